@@ -17,7 +17,7 @@ use errors::ErrorContext;
 use parsing::SyntaxKindEnum;
 use proc_macro2::TokenStream;
 use quote::{quote, quote_spanned};
-use syn::{parse_macro_input, spanned::Spanned, DeriveInput};
+use syn::{ext::IdentExt, parse_macro_input, spanned::Spanned, DeriveInput};
 
 mod errors;
 mod parsing;
@@ -25,7 +25,7 @@ mod symbols;
 
 use symbols::*;
 
-#[proc_macro_derive(Syntax, attributes(static_text))]
+#[proc_macro_derive(Syntax, attributes(static_text, error_token, class))]
 pub fn language(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let ast = parse_macro_input!(input as DeriveInput);
     expand_syntax(ast).unwrap_or_else(to_compile_errors).into()
@@ -60,9 +60,60 @@ fn expand_syntax(ast: DeriveInput) -> Result<TokenStream, Vec<syn::Error>> {
             #name :: #variant_name => #static_text,
         )
     });
+    let error_tokens: Vec<_> = syntax_kind_enum
+        .variants
+        .iter()
+        .filter(|variant| variant.error_token)
+        .map(|variant| {
+            let variant_name = &variant.name;
+            quote_spanned!(variant.source.span()=> #name :: #variant_name)
+        })
+        .collect();
+    // kinds without error tokens use the default implementation
+    let is_error_token = (!error_tokens.is_empty()).then(|| {
+        quote! {
+            fn is_error_token(self) -> bool {
+                ::core::matches!(self, #( #error_tokens )|*)
+            }
+        }
+    });
+    // kinds without explicit classes use the default implementation, which is based on `is_error_token`
+    let class = syntax_kind_enum
+        .variants
+        .iter()
+        .any(|variant| variant.class.is_some())
+        .then(|| {
+            let classes = syntax_kind_enum.variants.iter().map(|variant| {
+                let variant_name = &variant.name;
+                let class = match (&variant.class, variant.error_token) {
+                    (Some(class), _) => quote_spanned!(class.span()=> ::cstree::KindClass::#class),
+                    (None, true) => quote!(::cstree::KindClass::Error),
+                    (None, false) => quote!(::cstree::KindClass::Other),
+                };
+                quote_spanned!(variant.source.span()=> #name :: #variant_name => #class,)
+            });
+            quote! {
+                fn class(self) -> ::cstree::KindClass {
+                    match self {
+                        #( #classes )*
+                    }
+                }
+            }
+        });
+    let raw_kinds = match variant_count.checked_sub(1) {
+        Some(last) => quote!(0..=#last),
+        // no kind is valid
+        None => quote!(1..=0),
+    };
+    let kind_names = syntax_kind_enum
+        .variants
+        .iter()
+        .map(|variant| variant.name.unraw().to_string());
     let trait_impl = quote_spanned! { syntax_kind_enum.source.span()=>
         #[automatically_derived]
         impl ::cstree::Syntax for #name {
+            const RAW_KINDS: ::core::ops::RangeInclusive<u32> = #raw_kinds;
+
             fn from_raw(raw: ::cstree::RawSyntaxKind) -> Self {
                 assert!(raw.0 < #variant_count, "Invalid raw syntax kind: {}", raw.0);
                 // Safety: discriminant is valid by the assert above
@@ -78,6 +129,15 @@ fn expand_syntax(ast: DeriveInput) -> Result<TokenStream, Vec<syn::Error>> {
                     #( #static_texts )*
                 }
             }
+
+            #is_error_token
+
+            #class
+
+            fn kind_name(raw: ::cstree::RawSyntaxKind) -> ::core::option::Option<&'static str> {
+                const NAMES: &[&str] = &[ #( #kind_names ),* ];
+                NAMES.get(raw.0 as usize).copied()
+            }
         }
     };
     Ok(trait_impl)
@@ -58,6 +58,8 @@ impl<'i> SyntaxKindEnum<'i> {
 pub(crate) struct SyntaxKindVariant<'i> {
     pub(crate) name:        syn::Ident,
     pub(crate) static_text: Option<String>,
+    pub(crate) error_token: bool,
+    pub(crate) class:       Option<syn::Ident>,
     pub(crate) source:      &'i syn::Variant,
 }
 
@@ -89,9 +91,19 @@ impl<'i> SyntaxKindVariant<'i> {
         {
             static_text.set(&text, text.value());
         }
+        let mut error_token = Attr::none(error_handler, ERROR_TOKEN);
+        for attr in variant.attrs.iter().filter(|attr| is_error_token(error_handler, attr)) {
+            error_token.set(attr, ());
+        }
+        let mut class = Attr::none(error_handler, CLASS);
+        for ident in variant.attrs.iter().flat_map(|attr| get_class(error_handler, attr)) {
+            class.set(&ident, ident.clone());
+        }
         Self {
             name,
             static_text: static_text.get(),
+            error_token: error_token.get().is_some(),
+            class: class.get(),
             source: variant,
         }
     }
@@ -129,3 +141,29 @@ fn get_static_text(error_handler: &ErrorContext, attr: &syn::Attribute) -> Optio
         }
     }
 }
+
+fn is_error_token(error_handler: &ErrorContext, attr: &syn::Attribute) -> bool {
+    if attr.path() != ERROR_TOKEN {
+        return false;
+    }
+    if !matches!(attr.meta, syn::Meta::Path(_)) {
+        error_handler.error_at(attr, "`error_token` does not take any arguments: try `#[error_token]`");
+    }
+    true
+}
+
+fn get_class(error_handler: &ErrorContext, attr: &syn::Attribute) -> Option<syn::Ident> {
+    if attr.path() != CLASS {
+        return None;
+    }
+    match attr.meta.require_list().and_then(|list| list.parse_args()) {
+        Ok(class) => Some(class),
+        Err(_) => {
+            error_handler.error_at(
+                attr,
+                "`class` takes the name of a `KindClass` variant as a function argument: try `#[class(Keyword)]`",
+            );
+            None
+        }
+    }
+}
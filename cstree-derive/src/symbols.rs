@@ -5,6 +5,8 @@ use syn::{Ident, Path};
 pub struct Symbol(&'static str);
 
 pub const STATIC_TEXT: Symbol = Symbol("static_text");
+pub const ERROR_TOKEN: Symbol = Symbol("error_token");
+pub const CLASS: Symbol = Symbol("class");
 pub const REPR: Symbol = Symbol("repr");
 pub const U32: Symbol = Symbol("u32");
 
@@ -20,7 +22,7 @@ impl PartialEq<Symbol> for Ident {
     }
 }
 
-impl<'a> PartialEq<Symbol> for &'a Ident {
+impl PartialEq<Symbol> for &Ident {
     fn eq(&self, word: &Symbol) -> bool {
         *self == word.0
     }
@@ -32,7 +34,7 @@ impl PartialEq<Symbol> for Path {
     }
 }
 
-impl<'a> PartialEq<Symbol> for &'a Path {
+impl PartialEq<Symbol> for &Path {
     fn eq(&self, word: &Symbol) -> bool {
         self.is_ident(word.0)
     }
@@ -239,8 +239,8 @@ fn test_parser() {
 
 /// So far, we've been working with a homogeneous untyped tree.
 /// That tree is nice to provide generic tree operations, like traversals, but it's a bad fit for
-/// semantic analysis. cstree itself does not provide AST facilities directly, but it is possible to
-/// layer AST on top of `SyntaxNode` API. Let's write a function to evaluate S-expressions.
+/// semantic analysis. cstree itself does not provide AST types (beyond the `cstree::ast::AstNode` trait), but it is
+/// possible to layer AST on top of `SyntaxNode` API. Let's write a function to evaluate S-expressions.
 ///
 /// For that, let's define AST nodes.
 /// It'll be quite a bunch of repetitive code, so we'll use a macro.
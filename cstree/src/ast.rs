@@ -0,0 +1,221 @@
+//! Typed AST nodes layered on top of syntax nodes.
+//!
+//! A syntax tree is untyped: every node is a [`SyntaxNode`] and only its kind tells what it represents. Many
+//! frontends therefore define a typed wrapper for each kind of node they care about, which only exists for nodes of
+//! the right kind and provides accessors for the children of such a node. The [`AstNode`] trait describes such a
+//! wrapper, so that `cstree` can convert syntax nodes into it. In particular, [`SyntaxNode::ancestors_of_type`] and
+//! [`SyntaxNode::nearest_ancestor`] find the enclosing nodes of a given AST type, such as the function or block a
+//! node belongs to. The [`ast_node!`](crate::ast_node) macro generates such wrappers together with accessors for
+//! their children.
+//!
+//! # Example
+//! ```
+//! # use cstree::testing::*;
+//! use cstree::ast::AstNode;
+//!
+//! struct Operation(SyntaxNode<MySyntax>);
+//!
+//! impl AstNode<MySyntax> for Operation {
+//!     fn can_cast(kind: MySyntax) -> bool {
+//!         kind == MySyntax::Operation
+//!     }
+//!
+//!     fn cast(node: SyntaxNode<MySyntax>) -> Option<Self> {
+//!         Self::can_cast(node.kind()).then(|| Operation(node))
+//!     }
+//!
+//!     fn syntax(&self) -> &SyntaxNode<MySyntax> {
+//!         &self.0
+//!     }
+//! }
+//!
+//! let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+//! builder.start_node(Root);
+//! builder.start_node(MySyntax::Operation);
+//! builder.token(Int, "1");
+//! builder.static_token(Plus);
+//! builder.token(Int, "2");
+//! builder.finish_node();
+//! builder.finish_node();
+//! let (tree, _) = builder.finish();
+//! let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+//!
+//! let one = root.first_token().unwrap();
+//! let operation = one.nearest_ancestor::<Operation>().unwrap();
+//! assert_eq!(operation.syntax(), root.first_child().unwrap());
+//! assert!(root.nearest_ancestor::<Operation>().is_none());
+//! ```
+
+use crate::{syntax::SyntaxNode, Syntax};
+
+/// A typed view of syntax nodes of certain kinds.
+///
+/// Implementations usually wrap a [`SyntaxNode`] and only accept nodes of one kind (for example, function
+/// definitions) or a few related kinds (for example, all kinds of expressions).
+pub trait AstNode<S: Syntax, D: 'static = ()>: Sized {
+    /// Returns `true` if nodes of the given `kind` can be converted into `Self`.
+    ///
+    /// This is checked before calling [`cast`](AstNode::cast) when looking for nodes of this type, so that nodes of
+    /// other kinds do not have to be passed to it.
+    fn can_cast(kind: S) -> bool;
+
+    /// Converts `node` into `Self`, or returns `None` if it does not represent a `Self`.
+    fn cast(node: SyntaxNode<S, D>) -> Option<Self>;
+
+    /// The syntax node this value was created from.
+    fn syntax(&self) -> &SyntaxNode<S, D>;
+}
+
+/// Defines a typed [`AstNode`] wrapper for nodes of one kind, with accessors for the children that nodes of that kind
+/// are expected to have.
+///
+/// The wrapper is a tuple struct around a [`SyntaxNode`] that can only be created from nodes of the given kind.
+/// Each accessor is declared with the shape of the child it returns, followed by the kind or type of the child and an
+/// optional index, which selects the `n`-th matching child (starting at `0`) instead of the first one:
+///  - `node Kind[n]` returns the `n`-th child node of kind `Kind` as an `Option<&SyntaxNode<S>>`,
+///  - `token Kind[n]` returns the `n`-th child token of kind `Kind` as an `Option<&SyntaxToken<S>>`,
+///  - `child Type[n]` returns the `n`-th child node that can be cast to the `AstNode` type `Type`, as an
+///    `Option<Type>`, and
+///  - `children Type` returns an iterator over all child nodes that can be cast to `Type`.
+///
+/// Accessors only look at the direct children of the node and return `None` if there is no matching child, for
+/// example because the parser recovered from a syntax error. This keeps the positions of children in one place
+/// instead of spreading `children().nth(n)` over the code that consumes the tree.
+///
+/// The wrapper is an `AstNode<S>` for syntax nodes without data. For nodes that carry data of type `D`, write
+/// `struct Name: S, D = Kind` to make it an `AstNode<S, D>` instead.
+///
+/// The accessors are only as accurate as they are declared: the macro has no connection to a
+/// [`Schema`](crate::schema::Schema) and does not derive accessors from the shape of a node, nor check that the
+/// declared accessors agree with it.
+///
+/// # Examples
+/// ```
+/// # use cstree::testing::*;
+/// use cstree::ast::AstNode;
+///
+/// cstree::ast_node! {
+///     /// A binary operation.
+///     pub struct BinaryOperation: MySyntax = Operation {
+///         /// The left-hand side operand.
+///         pub fn lhs -> token Int;
+///         pub fn operator -> token Plus;
+///         /// The right-hand side operand.
+///         pub fn rhs -> token Int[1];
+///     }
+/// }
+///
+/// cstree::ast_node! {
+///     pub struct Program: MySyntax = Root {
+///         pub fn first -> child BinaryOperation;
+///         pub fn operations -> children BinaryOperation;
+///     }
+/// }
+///
+/// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+/// builder.start_node(Root);
+/// for rhs in ["2", "3"] {
+///     builder.start_node(Operation);
+///     builder.token(Int, "1");
+///     builder.static_token(Plus);
+///     builder.token(Int, rhs);
+///     builder.finish_node();
+/// }
+/// builder.finish_node();
+/// let (tree, cache) = builder.finish();
+/// let interner = cache.unwrap().into_interner().unwrap();
+/// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+///
+/// let program = Program::cast(root.clone()).unwrap();
+/// assert_eq!(program.operations().count(), 2);
+/// let operation = program.first().unwrap();
+/// assert_eq!(operation.lhs().unwrap().resolve_text(&interner), "1");
+/// assert_eq!(operation.rhs().unwrap().resolve_text(&interner), "2");
+/// assert!(operation.operator().is_some());
+/// assert!(BinaryOperation::cast(root).is_none());
+/// ```
+#[macro_export]
+macro_rules! ast_node {
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident : $syntax:ty, $data:ty = $kind:path {
+            $(
+                $(#[$fn_attr:meta])*
+                $fn_vis:vis fn $fn_name:ident -> $shape:ident $child:path $([$index:expr])?;
+            )*
+        }
+    ) => {
+        $(#[$attr])*
+        #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+        $vis struct $name($crate::syntax::SyntaxNode<$syntax, $data>);
+
+        impl $crate::ast::AstNode<$syntax, $data> for $name {
+            fn can_cast(kind: $syntax) -> bool {
+                kind == $kind
+            }
+
+            fn cast(node: $crate::syntax::SyntaxNode<$syntax, $data>) -> Option<Self> {
+                Self::can_cast(node.kind()).then(|| Self(node))
+            }
+
+            fn syntax(&self) -> &$crate::syntax::SyntaxNode<$syntax, $data> {
+                &self.0
+            }
+        }
+
+        impl $name {
+            $(
+                $crate::ast_node!(
+                    @accessor $syntax, $data, $(#[$fn_attr])* $fn_vis $fn_name $shape $child $([$index])?
+                );
+            )*
+        }
+    };
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident : $syntax:ty = $kind:path {
+            $($body:tt)*
+        }
+    ) => {
+        $crate::ast_node! {
+            $(#[$attr])*
+            $vis struct $name: $syntax, () = $kind {
+                $($body)*
+            }
+        }
+    };
+    (@accessor $syntax:ty, $data:ty, $(#[$attr:meta])* $vis:vis $name:ident node $kind:path $([$index:expr])?) => {
+        $(#[$attr])*
+        $vis fn $name(&self) -> Option<&$crate::syntax::SyntaxNode<$syntax, $data>> {
+            self.0.children().filter(|node| node.kind() == $kind).nth(0 $(+ $index)?)
+        }
+    };
+    (@accessor $syntax:ty, $data:ty, $(#[$attr:meta])* $vis:vis $name:ident token $kind:path $([$index:expr])?) => {
+        $(#[$attr])*
+        $vis fn $name(&self) -> Option<&$crate::syntax::SyntaxToken<$syntax, $data>> {
+            self.0
+                .children_with_tokens()
+                .filter_map(|child| child.into_token())
+                .filter(|token| token.kind() == $kind)
+                .nth(0 $(+ $index)?)
+        }
+    };
+    (@accessor $syntax:ty, $data:ty, $(#[$attr:meta])* $vis:vis $name:ident child $child:path $([$index:expr])?) => {
+        $(#[$attr])*
+        $vis fn $name(&self) -> Option<$child> {
+            self.0
+                .children()
+                .filter(|node| <$child as $crate::ast::AstNode<$syntax, $data>>::can_cast(node.kind()))
+                .nth(0 $(+ $index)?)
+                .and_then(|node| <$child as $crate::ast::AstNode<$syntax, $data>>::cast(node.clone()))
+        }
+    };
+    (@accessor $syntax:ty, $data:ty, $(#[$attr:meta])* $vis:vis $name:ident children $child:path) => {
+        $(#[$attr])*
+        $vis fn $name(&self) -> impl Iterator<Item = $child> + '_ {
+            self.0
+                .children()
+                .filter_map(|node| <$child as $crate::ast::AstNode<$syntax, $data>>::cast(node.clone()))
+        }
+    };
+}
@@ -0,0 +1,904 @@
+//! A compact binary format for persisting syntax trees.
+//!
+//! Unlike the `serde` implementations behind the `serialize` feature, this format does not depend on a
+//! serialization framework and is meant for storing trees outside of the program that built them, for example in
+//! on-disk caches. Every serialized tree starts with a header that records
+//!  - the version of the format,
+//!  - a hash identifying the [`Syntax`] the tree was written with (computed from [`Syntax::RAW_KINDS`] and the [kind
+//!    names](Syntax::kind_name)),
+//!  - the byte order of the machine that wrote the tree, and
+//!  - the length and a checksum of the rest of the data.
+//!
+//! Reading a tree that was written by an incompatible version of `cstree` or for a different `Syntax`, or that was
+//! cut off or otherwise damaged, returns a [`DecodeError`] instead of panicking or producing a wrong tree.
+//!
+//! The format stores the kinds and text of all elements in the tree, but not any [node data](SyntaxNode::get_data).
+//!
+//! Nodes in the format record the length of their text and of their serialized subtree. This lets
+//! [`from_slice_range`] skip over subtrees outside of a given [`TextRange`] and only build the part of a tree that
+//! covers a region of the text.
+//!
+//! With the `zstd` feature, trees can also be written compressed with [`write_compressed`] and
+//! [`to_vec_compressed`]. Since most of a tree consists of repetitive structure and token text, this usually makes it
+//! a lot smaller. Compressed trees are recognized by [`read`] and [`from_slice`] without further configuration, and
+//! [`read`] decompresses the tree while reading it.
+//!
+//! Trees can also be written in a _mapped layout_ with [`to_vec_mapped`] and [`write_mapped`], in which every node
+//! and token is a record of the same size. A [`MappedTree`] can be traversed directly in the serialized data, for
+//! example after memory-mapping it from a file, without building the tree first. Mapped trees are larger than trees in
+//! the default layout, but are also understood by [`read`], [`from_slice`] and [`from_slice_range`].
+//!
+//! # Example
+//! ```
+//! # use cstree::testing::*;
+//! use cstree::{binary, syntax::ResolvedNode};
+//!
+//! let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+//! builder.start_node(Root);
+//! builder.token(Int, "1");
+//! builder.static_token(Plus);
+//! builder.token(Int, "2");
+//! builder.finish_node();
+//! let (tree, cache) = builder.finish();
+//! let interner = cache.unwrap().into_interner().unwrap();
+//! let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+//!
+//! let bytes = binary::to_vec(&root, &interner);
+//! let restored: ResolvedNode<MySyntax> = binary::from_slice(&bytes).unwrap();
+//! assert_eq!(restored.text(), "1+2");
+//!
+//! let error = binary::from_slice::<MySyntax, ()>(&bytes[..bytes.len() - 1]).unwrap_err();
+//! assert!(matches!(error, binary::DecodeError::Truncated));
+//! ```
+
+use std::{fmt, io};
+
+use fxhash::FxHashMap;
+
+use crate::{
+    build::GreenNodeBuilder,
+    green::GreenNode,
+    interning::{Interner, Resolver, TokenKey},
+    syntax::{ResolvedNode, SyntaxNode},
+    text::{TextRange, TextSize},
+    util::NodeOrToken,
+    RawSyntaxKind, Syntax,
+};
+
+mod mapped;
+pub use mapped::{to_vec_mapped, write_mapped, MappedChildren, MappedElement, MappedNode, MappedToken, MappedTree};
+
+/// The version of the format written by this version of `cstree`.
+///
+/// Trees written with a different version cannot be read and return [`DecodeError::UnsupportedVersion`].
+pub const FORMAT_VERSION: u16 = 1;
+
+const MAGIC: [u8; 4] = *b"CSTR";
+const HEADER_LEN: usize = 32;
+
+// Event tags in the payload.
+const NODE: u8 = 0;
+const TOKEN: u8 = 1;
+const LEAVE: u8 = 2;
+
+/// Header flag for trees whose payload is compressed with zstd.
+const COMPRESSED: u8 = 1;
+/// Header flag for trees in the [mapped layout](MappedTree).
+const MAPPED: u8 = 2;
+
+/// Takes the place of the text index of tokens that do not store text, because the text is the
+/// [`static_text`](Syntax::static_text) of their kind.
+const STATIC_TEXT: u32 = u32::MAX;
+
+/// The error returned when reading a tree in the [binary format](self) fails.
+#[derive(Debug)]
+pub enum DecodeError {
+    /// The input does not start with the header of a serialized tree.
+    NotATree,
+    /// The tree was written in a different version of the format (the contained version).
+    UnsupportedVersion(u16),
+    /// The tree was written for a different [`Syntax`] than the one it is being read as.
+    SyntaxMismatch,
+    /// The input ended before the end of the tree.
+    Truncated,
+    /// The checksum of the tree does not match its data.
+    ChecksumMismatch,
+    /// The tree is compressed, but the `zstd` feature that is required to decompress it is not enabled.
+    Compressed,
+    /// The data of the tree is malformed, for the given reason.
+    Corrupted(&'static str),
+    /// Reading from the underlying reader failed.
+    Io(io::Error),
+}
+
+impl DecodeError {
+    fn from_io(error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::UnexpectedEof => DecodeError::Truncated,
+            _ => DecodeError::Io(error),
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::NotATree => write!(f, "input is not a serialized syntax tree"),
+            DecodeError::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported format version {version} (expected version {FORMAT_VERSION})"
+            ),
+            DecodeError::SyntaxMismatch => write!(f, "tree was serialized for a different syntax"),
+            DecodeError::Truncated => write!(f, "serialized tree is truncated"),
+            DecodeError::ChecksumMismatch => write!(f, "checksum mismatch"),
+            DecodeError::Compressed => write!(f, "tree is compressed, which requires the `zstd` feature"),
+            DecodeError::Corrupted(reason) => write!(f, "corrupted tree: {reason}"),
+            DecodeError::Io(error) => write!(f, "failed to read tree: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DecodeError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Writes the subtree rooted in `node` to `writer`, resolving the text of its tokens with `resolver`.
+pub fn write<S, D, R, W>(node: &SyntaxNode<S, D>, resolver: &R, mut writer: W) -> io::Result<()>
+where
+    S: Syntax,
+    R: Resolver<TokenKey> + ?Sized,
+    W: io::Write,
+{
+    let payload = encode_payload(node.green(), resolver);
+    Header::new::<S>(&payload, false).write(&mut writer)?;
+    writer.write_all(&payload)
+}
+
+/// Returns the subtree rooted in `node` in the binary format, resolving the text of its tokens with `resolver`.
+pub fn to_vec<S, D, R>(node: &SyntaxNode<S, D>, resolver: &R) -> Vec<u8>
+where
+    S: Syntax,
+    R: Resolver<TokenKey> + ?Sized,
+{
+    let payload = encode_payload(node.green(), resolver);
+    let mut res = Vec::with_capacity(HEADER_LEN + payload.len());
+    // NOTE: `io::Write` methods on `Vec<u8>` never fail
+    Header::new::<S>(&payload, false).write(&mut res).unwrap();
+    res.extend_from_slice(&payload);
+    res
+}
+
+/// Like [`write`], but compresses the tree with zstd at the given compression `level`.
+///
+/// Levels range from 1 to 22, with 0 selecting the zstd default (currently 3). Levels outside of the supported range
+/// are clamped.
+#[cfg(feature = "zstd")]
+#[cfg_attr(doc_cfg, doc(cfg(feature = "zstd")))]
+pub fn write_compressed<S, D, R, W>(node: &SyntaxNode<S, D>, resolver: &R, level: i32, mut writer: W) -> io::Result<()>
+where
+    S: Syntax,
+    R: Resolver<TokenKey> + ?Sized,
+    W: io::Write,
+{
+    let body = compress(&encode_payload(node.green(), resolver), level)?;
+    Header::new::<S>(&body, true).write(&mut writer)?;
+    writer.write_all(&body)
+}
+
+/// Like [`to_vec`], but compresses the tree with zstd at the given compression `level`, see [`write_compressed`].
+#[cfg(feature = "zstd")]
+#[cfg_attr(doc_cfg, doc(cfg(feature = "zstd")))]
+pub fn to_vec_compressed<S, D, R>(node: &SyntaxNode<S, D>, resolver: &R, level: i32) -> Vec<u8>
+where
+    S: Syntax,
+    R: Resolver<TokenKey> + ?Sized,
+{
+    let mut res = Vec::new();
+    // NOTE: compressing into memory can only fail for invalid parameters, but zstd clamps the level
+    write_compressed(node, resolver, level, &mut res).unwrap();
+    res
+}
+
+/// Reads a tree that was written for the syntax `S` from `bytes`, which must contain exactly one serialized tree.
+///
+/// The checksum is verified before the tree is built, so damaged data is reported as
+/// [`ChecksumMismatch`](DecodeError::ChecksumMismatch).
+pub fn from_slice<S: Syntax, D: 'static>(bytes: &[u8]) -> Result<ResolvedNode<S, D>, DecodeError> {
+    let (header, body) = split_slice::<S>(bytes)?;
+    if header.mapped {
+        let tree = MappedTree::<S>::from_body(&header, body)?;
+        tree.verify()?;
+        return Ok(tree.root().to_tree());
+    }
+    if header.compressed {
+        // Reading from a slice cannot fail, so any I/O error is an error in the compressed data.
+        return decompress(body, header.big_endian).map_err(|error| match error {
+            DecodeError::Io(_) => DecodeError::Corrupted("invalid compressed data"),
+            error => error,
+        });
+    }
+    decode(Payload {
+        reader:     body,
+        remaining:  header.len,
+        big_endian: header.big_endian,
+    })
+}
+
+/// Reads only the deepest subtree covering `range` from `bytes`, which must contain exactly one tree that was written
+/// for the syntax `S`, and returns its root together with its offset in the full tree.
+///
+/// Subtrees that do not contain the range are skipped without being built, so this is much cheaper than reading
+/// the full tree with [`from_slice`] if the range is small. Like in [`from_slice`], the checksum of the entire data is
+/// verified, and compressed trees are decompressed in full.
+///
+/// If the range is empty and lies on the boundary of two nodes, either one can be returned.
+///
+/// # Panics
+/// If `range` is not contained in the text range of the tree.
+///
+/// # Example
+/// ```
+/// # use cstree::testing::*;
+/// use cstree::{binary, syntax::ResolvedNode, text::TextRange};
+///
+/// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+/// builder.start_node(Root);
+/// builder.start_node(Operation);
+/// builder.token(Int, "1");
+/// builder.static_token(Plus);
+/// builder.token(Int, "2");
+/// builder.finish_node();
+/// builder.token(Whitespace, " ");
+/// builder.start_node(Operation);
+/// builder.token(Float, "3.5");
+/// builder.finish_node();
+/// builder.finish_node();
+/// let (tree, cache) = builder.finish();
+/// let interner = cache.unwrap().into_interner().unwrap();
+/// let bytes = binary::to_vec(&SyntaxNode::<MySyntax>::new_root(tree), &interner);
+///
+/// let (node, offset): (ResolvedNode<MySyntax>, _) =
+///     binary::from_slice_range(&bytes, TextRange::new(4.into(), 6.into())).unwrap();
+/// assert_eq!(node.kind(), Operation);
+/// assert_eq!(node.text(), "3.5");
+/// assert_eq!(offset, 4.into());
+/// ```
+pub fn from_slice_range<S: Syntax, D: 'static>(
+    bytes: &[u8],
+    range: TextRange,
+) -> Result<(ResolvedNode<S, D>, TextSize), DecodeError> {
+    let (header, body) = split_slice::<S>(bytes)?;
+    if header.mapped {
+        let tree = MappedTree::<S>::from_body(&header, body)?;
+        tree.verify()?;
+        let node = tree.root().covering_node(range);
+        return Ok((node.to_tree(), node.text_range().start()));
+    }
+    let decompressed;
+    let payload = if header.compressed {
+        decompressed = decompress_slice(body, header.big_endian)?;
+        &decompressed[..]
+    } else {
+        body
+    };
+    let mut payload = Payload {
+        reader:     payload,
+        remaining:  payload.len() as u64,
+        big_endian: header.big_endian,
+    };
+
+    let count = payload.u32()?;
+    // Every text takes up at least 4 bytes for its length.
+    payload.check_len(u64::from(count) * 4)?;
+    let mut texts = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let len = payload.u32()?;
+        let text = payload.take(u64::from(len))?;
+        texts.push(std::str::from_utf8(text).map_err(|_| DecodeError::Corrupted("token text is not UTF-8"))?);
+    }
+    let text = |index: u32| {
+        texts
+            .get(index as usize)
+            .copied()
+            .ok_or(DecodeError::Corrupted("invalid token text index"))
+    };
+
+    // Descend from the root to the deepest node covering `range`, skipping over the subtrees of all other nodes.
+    let mut node = payload.reader;
+    if payload.u8()? != NODE {
+        return Err(DecodeError::Corrupted("tree does not start with a node"));
+    }
+    let (_, len, _) = payload.node_record::<S>()?;
+    assert!(
+        TextRange::up_to(len).contains_range(range),
+        "Bad range: tree range {:?}, range {:?}",
+        TextRange::up_to(len),
+        range,
+    );
+    let mut offset = TextSize::from(0);
+    'descend: loop {
+        let mut child_offset = offset;
+        loop {
+            let child = payload.reader;
+            let child_range = match payload.u8()? {
+                NODE => {
+                    let (_, len, events_len) = payload.node_record::<S>()?;
+                    let child_range = text_range_at(child_offset, len)?;
+                    if child_range.contains_range(range) {
+                        node = child;
+                        offset = child_offset;
+                        continue 'descend;
+                    }
+                    payload.take(events_len)?;
+                    child_range
+                }
+                TOKEN => {
+                    let kind = payload.kind::<S>()?;
+                    let len = match payload.u32()? {
+                        STATIC_TEXT => S::static_text(kind)
+                            .ok_or(DecodeError::Corrupted("token without text"))?
+                            .len(),
+                        _ if S::static_text(kind).is_some() => {
+                            return Err(DecodeError::Corrupted("static-text kind with text"));
+                        }
+                        index => text(index)?.len(),
+                    };
+                    let child_range = text_range_at(child_offset, TextSize::from(len as u32))?;
+                    if child_range.contains_range(range) {
+                        break 'descend;
+                    }
+                    child_range
+                }
+                LEAVE => break 'descend,
+                _ => return Err(DecodeError::Corrupted("unknown event")),
+            };
+            child_offset = child_range.end();
+        }
+    }
+
+    let mut builder: GreenNodeBuilder<S> = GreenNodeBuilder::new();
+    let mut subtree = Payload {
+        reader:     node,
+        remaining:  node.len() as u64,
+        big_endian: header.big_endian,
+    };
+    decode_events(&mut subtree, &mut builder, |builder, kind, index| {
+        builder.token(kind, text(index)?);
+        Ok(())
+    })?;
+    Ok((finish_tree(builder), offset))
+}
+
+/// Reads a tree that was written for the syntax `S` from `reader`.
+///
+/// The tree is decoded (and decompressed, if necessary) while it is read, and the checksum is verified once the
+/// entire tree has been read. Data that follows the tree in `reader` is not consumed.
+pub fn read<S: Syntax, D: 'static>(mut reader: impl io::Read) -> Result<ResolvedNode<S, D>, DecodeError> {
+    let header = Header::read::<S>(&mut reader)?;
+    let mut body = Body {
+        reader,
+        remaining: header.len,
+        checksum: Fnv::new(),
+        failed: false,
+    };
+    let tree = if header.mapped {
+        // Mapped trees are not read incrementally, but this only reads as much as the header says.
+        let mut bytes = Vec::new();
+        io::Read::read_to_end(&mut body, &mut bytes).map_err(DecodeError::from_io)?;
+        if (bytes.len() as u64) < header.len {
+            return Err(DecodeError::Truncated);
+        }
+        MappedTree::<S>::from_body(&header, &bytes).and_then(|tree| {
+            tree.verify()?;
+            Ok(tree.root().to_tree())
+        })
+    } else if header.compressed {
+        decompress(io::BufReader::new(&mut body), header.big_endian)
+    } else {
+        decode(Payload {
+            reader:     &mut body,
+            remaining:  header.len,
+            big_endian: header.big_endian,
+        })
+    };
+    let tree = tree.map_err(|error| match error {
+        DecodeError::Io(_) if header.compressed && !body.failed => DecodeError::Corrupted("invalid compressed data"),
+        error => error,
+    })?;
+    if body.checksum.0 != header.checksum {
+        return Err(DecodeError::ChecksumMismatch);
+    }
+    Ok(tree)
+}
+
+/// Splits `bytes` into the header and the data of the tree, and verifies the checksum of the data.
+fn split_slice<S: Syntax>(bytes: &[u8]) -> Result<(Header, &[u8]), DecodeError> {
+    let (header, body) = split_header::<S>(bytes)?;
+    if checksum(body) != header.checksum {
+        return Err(DecodeError::ChecksumMismatch);
+    }
+    Ok((header, body))
+}
+
+/// Splits `bytes` into the header and the data of the tree, without looking at the data.
+fn split_header<S: Syntax>(bytes: &[u8]) -> Result<(Header, &[u8]), DecodeError> {
+    let mut input = bytes;
+    let header = Header::read::<S>(&mut input)?;
+    let body = usize::try_from(header.len)
+        .ok()
+        .and_then(|len| input.get(..len))
+        .ok_or(DecodeError::Truncated)?;
+    if body.len() < input.len() {
+        return Err(DecodeError::Corrupted("trailing data after the end of the tree"));
+    }
+    Ok((header, body))
+}
+
+struct Header {
+    big_endian: bool,
+    compressed: bool,
+    mapped:     bool,
+    version:    u16,
+    syntax:     u64,
+    /// The length of the data following the header.
+    len:        u64,
+    checksum:   u64,
+}
+
+impl Header {
+    fn new<S: Syntax>(body: &[u8], compressed: bool) -> Self {
+        Self {
+            big_endian: cfg!(target_endian = "big"),
+            compressed,
+            mapped: false,
+            version: FORMAT_VERSION,
+            syntax: syntax_hash::<S>(),
+            len: body.len() as u64,
+            checksum: checksum(body),
+        }
+    }
+
+    /// Marks the tree as being in the mapped layout.
+    fn mapped(self) -> Self {
+        Self { mapped: true, ..self }
+    }
+
+    /// Writes the header in native byte order.
+    fn write(&self, writer: &mut impl io::Write) -> io::Result<()> {
+        let mut bytes = [0; HEADER_LEN];
+        bytes[0..4].copy_from_slice(&MAGIC);
+        bytes[4] = self.big_endian as u8;
+        if self.compressed {
+            bytes[5] |= COMPRESSED;
+        }
+        if self.mapped {
+            bytes[5] |= MAPPED;
+        }
+        bytes[6..8].copy_from_slice(&self.version.to_ne_bytes());
+        bytes[8..16].copy_from_slice(&self.syntax.to_ne_bytes());
+        bytes[16..24].copy_from_slice(&self.len.to_ne_bytes());
+        bytes[24..32].copy_from_slice(&self.checksum.to_ne_bytes());
+        writer.write_all(&bytes)
+    }
+
+    /// Reads a header and checks that the tree following it can be read as `S`.
+    fn read<S: Syntax>(reader: &mut impl io::Read) -> Result<Self, DecodeError> {
+        let mut bytes = [0; HEADER_LEN];
+        reader.read_exact(&mut bytes).map_err(DecodeError::from_io)?;
+        if bytes[0..4] != MAGIC {
+            return Err(DecodeError::NotATree);
+        }
+        let big_endian = match bytes[4] {
+            0 => false,
+            1 => true,
+            _ => return Err(DecodeError::Corrupted("invalid byte order")),
+        };
+        let u64_at = |at: usize| {
+            let bytes = bytes[at..at + 8].try_into().unwrap();
+            if big_endian {
+                u64::from_be_bytes(bytes)
+            } else {
+                u64::from_le_bytes(bytes)
+            }
+        };
+        let version = [bytes[6], bytes[7]];
+        let header = Self {
+            big_endian,
+            compressed: bytes[5] & COMPRESSED != 0,
+            mapped: bytes[5] & MAPPED != 0,
+            version: if big_endian {
+                u16::from_be_bytes(version)
+            } else {
+                u16::from_le_bytes(version)
+            },
+            syntax: u64_at(8),
+            len: u64_at(16),
+            checksum: u64_at(24),
+        };
+        if header.version != FORMAT_VERSION {
+            return Err(DecodeError::UnsupportedVersion(header.version));
+        }
+        if bytes[5] & !(COMPRESSED | MAPPED) != 0 {
+            return Err(DecodeError::Corrupted("unknown flags"));
+        }
+        if header.compressed && header.mapped {
+            return Err(DecodeError::Corrupted("mapped trees cannot be compressed"));
+        }
+        if header.syntax != syntax_hash::<S>() {
+            return Err(DecodeError::SyntaxMismatch);
+        }
+        Ok(header)
+    }
+}
+
+/// Encodes the tree as a table of all distinct token texts, followed by the events of a preorder traversal of the
+/// tree. Node events are followed by the length of the node's text and the length of the events of its subtree
+/// (including the event that leaves the node). All numbers are written in native byte order.
+fn encode_payload<R>(root: &GreenNode, resolver: &R) -> Vec<u8>
+where
+    R: Resolver<TokenKey> + ?Sized,
+{
+    let mut indices: FxHashMap<TokenKey, u32> = FxHashMap::default();
+    let mut texts = Vec::new();
+    let mut events = Vec::new();
+    // The length of a subtree is only known once it is left, so space for it is reserved until then.
+    let enter = |events: &mut Vec<u8>, node: &GreenNode| {
+        events.push(NODE);
+        events.extend_from_slice(&node.kind().0.to_ne_bytes());
+        events.extend_from_slice(&u32::from(node.text_len()).to_ne_bytes());
+        events.extend_from_slice(&[0; 8]);
+        events.len()
+    };
+    let mut stack = vec![(root.children(), enter(&mut events, root))];
+    while let Some((children, start)) = stack.last_mut() {
+        match children.next() {
+            None => {
+                let start = *start;
+                stack.pop();
+                events.push(LEAVE);
+                let len = (events.len() - start) as u64;
+                events[start - 8..start].copy_from_slice(&len.to_ne_bytes());
+            }
+            Some(NodeOrToken::Node(node)) => {
+                let start = enter(&mut events, node);
+                stack.push((node.children(), start));
+            }
+            Some(NodeOrToken::Token(token)) => {
+                let index = match token.text_key() {
+                    Some(key) => {
+                        let next = indices.len() as u32;
+                        *indices.entry(key).or_insert_with(|| {
+                            let text = resolver.resolve(key);
+                            texts.extend_from_slice(&(text.len() as u32).to_ne_bytes());
+                            texts.extend_from_slice(text.as_bytes());
+                            next
+                        })
+                    }
+                    None => STATIC_TEXT,
+                };
+                events.push(TOKEN);
+                events.extend_from_slice(&token.kind().0.to_ne_bytes());
+                events.extend_from_slice(&index.to_ne_bytes());
+            }
+        }
+    }
+
+    let mut payload = Vec::with_capacity(4 + texts.len() + events.len());
+    payload.extend_from_slice(&(indices.len() as u32).to_ne_bytes());
+    payload.append(&mut texts);
+    payload.append(&mut events);
+    payload
+}
+
+/// Compresses the `payload` of a tree, prefixed by its uncompressed length.
+#[cfg(feature = "zstd")]
+fn compress(payload: &[u8], level: i32) -> io::Result<Vec<u8>> {
+    let mut body = (payload.len() as u64).to_ne_bytes().to_vec();
+    zstd::stream::copy_encode(payload, &mut body, level)?;
+    Ok(body)
+}
+
+#[cfg(feature = "zstd")]
+fn decompress<S, D, R>(mut body: R, big_endian: bool) -> Result<ResolvedNode<S, D>, DecodeError>
+where
+    S: Syntax,
+    D: 'static,
+    R: io::BufRead,
+{
+    let mut len = [0; 8];
+    body.read_exact(&mut len).map_err(DecodeError::from_io)?;
+    let len = if big_endian {
+        u64::from_be_bytes(len)
+    } else {
+        u64::from_le_bytes(len)
+    };
+    let mut decoder = zstd::stream::read::Decoder::with_buffer(body)
+        .map_err(DecodeError::Io)?
+        .single_frame();
+    let tree = decode(Payload {
+        reader: &mut decoder,
+        remaining: len,
+        big_endian,
+    })?;
+    // Read the rest of the frame, so that its end is checked and the entire body is included in the checksum.
+    if io::copy(&mut decoder, &mut io::sink()).map_err(DecodeError::from_io)? != 0 {
+        return Err(DecodeError::Corrupted("data after the end of the root node"));
+    }
+    Ok(tree)
+}
+
+/// Decompresses the entire payload of a compressed tree.
+#[cfg(feature = "zstd")]
+fn decompress_slice(mut body: &[u8], big_endian: bool) -> Result<Vec<u8>, DecodeError> {
+    let mut len = [0; 8];
+    io::Read::read_exact(&mut body, &mut len).map_err(DecodeError::from_io)?;
+    let len = if big_endian {
+        u64::from_be_bytes(len)
+    } else {
+        u64::from_le_bytes(len)
+    };
+    let mut payload = Vec::new();
+    zstd::stream::copy_decode(body, &mut payload).map_err(|_| DecodeError::Corrupted("invalid compressed data"))?;
+    if payload.len() as u64 != len {
+        return Err(DecodeError::Corrupted("invalid compressed data"));
+    }
+    Ok(payload)
+}
+
+#[cfg(not(feature = "zstd"))]
+fn decompress_slice(_body: &[u8], _big_endian: bool) -> Result<Vec<u8>, DecodeError> {
+    Err(DecodeError::Compressed)
+}
+
+#[cfg(not(feature = "zstd"))]
+fn decompress<S, D, R>(_body: R, _big_endian: bool) -> Result<ResolvedNode<S, D>, DecodeError>
+where
+    S: Syntax,
+    D: 'static,
+{
+    Err(DecodeError::Compressed)
+}
+
+/// The data following the header of a tree that is being read from an [`io::Read`], which keeps track of the
+/// checksum of the data that was read.
+struct Body<R> {
+    reader:    R,
+    remaining: u64,
+    checksum:  Fnv,
+    /// Whether reading from `reader` returned an error, to tell it apart from errors in decompression.
+    failed:    bool,
+}
+
+impl<R: io::Read> io::Read for Body<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(usize::try_from(self.remaining).unwrap_or(usize::MAX));
+        let read = match self.reader.read(&mut buf[..len]) {
+            Ok(read) => read,
+            Err(error) => {
+                self.failed = true;
+                return Err(error);
+            }
+        };
+        self.remaining -= read as u64;
+        self.checksum.write(&buf[..read]);
+        Ok(read)
+    }
+}
+
+/// The uncompressed payload of a tree.
+struct Payload<R> {
+    reader:     R,
+    remaining:  u64,
+    big_endian: bool,
+}
+
+impl<R: io::Read> Payload<R> {
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), DecodeError> {
+        self.reader.read_exact(buf).map_err(DecodeError::from_io)?;
+        self.remaining -= buf.len() as u64;
+        Ok(())
+    }
+
+    /// Checks that `len` more bytes are part of the tree. This must be done before allocating space for them, so
+    /// that corrupt lengths cannot cause huge allocations.
+    fn check_len(&self, len: u64) -> Result<(), DecodeError> {
+        if len > self.remaining {
+            return Err(DecodeError::Corrupted("data extends past the end of the tree"));
+        }
+        Ok(())
+    }
+
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        self.check_len(1)?;
+        let mut bytes = [0];
+        self.read_exact(&mut bytes)?;
+        Ok(bytes[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, DecodeError> {
+        self.check_len(4)?;
+        let mut bytes = [0; 4];
+        self.read_exact(&mut bytes)?;
+        Ok(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    fn u64(&mut self) -> Result<u64, DecodeError> {
+        self.check_len(8)?;
+        let mut bytes = [0; 8];
+        self.read_exact(&mut bytes)?;
+        Ok(if self.big_endian {
+            u64::from_be_bytes(bytes)
+        } else {
+            u64::from_le_bytes(bytes)
+        })
+    }
+
+    /// Reads the rest of a node event after its tag: the kind of the node, the length of its text and the length of
+    /// the events of its subtree.
+    fn node_record<S: Syntax>(&mut self) -> Result<(S, TextSize, u64), DecodeError> {
+        let kind = self.kind::<S>()?;
+        let text_len = self.u32()?;
+        let events_len = self.u64()?;
+        Ok((kind, text_len.into(), events_len))
+    }
+
+    fn kind<S: Syntax>(&mut self) -> Result<S, DecodeError> {
+        let raw = self.u32()?;
+        S::try_from_raw(RawSyntaxKind(raw)).ok_or(DecodeError::Corrupted("invalid syntax kind"))
+    }
+}
+
+impl<'a> Payload<&'a [u8]> {
+    /// Skips the next `len` bytes, returning them without copying.
+    fn take(&mut self, len: u64) -> Result<&'a [u8], DecodeError> {
+        self.check_len(len)?;
+        // NOTE: `remaining` is the length of the slice, so `check_len` ensures this is in bounds
+        let (bytes, rest) = self.reader.split_at(len as usize);
+        self.reader = rest;
+        self.remaining -= len;
+        Ok(bytes)
+    }
+}
+
+fn decode<S, D, R>(mut payload: Payload<R>) -> Result<ResolvedNode<S, D>, DecodeError>
+where
+    S: Syntax,
+    D: 'static,
+    R: io::Read,
+{
+    let mut builder: GreenNodeBuilder<S> = GreenNodeBuilder::new();
+
+    let count = payload.u32()?;
+    // Every text takes up at least 4 bytes for its length.
+    payload.check_len(u64::from(count) * 4)?;
+    let mut keys = Vec::with_capacity(count as usize);
+    let mut buf = Vec::new();
+    for _ in 0..count {
+        let len = payload.u32()?;
+        payload.check_len(u64::from(len))?;
+        buf.resize(len as usize, 0);
+        payload.read_exact(&mut buf)?;
+        let text = std::str::from_utf8(&buf).map_err(|_| DecodeError::Corrupted("token text is not UTF-8"))?;
+        keys.push(builder.interner_mut().get_or_intern(text));
+    }
+
+    decode_events(&mut payload, &mut builder, |builder, kind, index| {
+        let key = *keys
+            .get(index as usize)
+            .ok_or(DecodeError::Corrupted("invalid token text index"))?;
+        builder.tokens_with_keys(&[(kind, key)]);
+        Ok(())
+    })?;
+    if payload.remaining > 0 {
+        return Err(DecodeError::Corrupted("data after the end of the root node"));
+    }
+    Ok(finish_tree(builder))
+}
+
+/// Decodes the events of the subtree starting at the current position of `payload` into `builder`. Tokens with text
+/// are added by `token`, which is given the index of their text.
+fn decode_events<S, R>(
+    payload: &mut Payload<R>,
+    builder: &mut GreenNodeBuilder<S>,
+    mut token: impl FnMut(&mut GreenNodeBuilder<S>, S, u32) -> Result<(), DecodeError>,
+) -> Result<(), DecodeError>
+where
+    S: Syntax,
+    R: io::Read,
+{
+    if payload.u8()? != NODE {
+        return Err(DecodeError::Corrupted("tree does not start with a node"));
+    }
+    let (kind, _, _) = payload.node_record::<S>()?;
+    builder.start_node(kind);
+    let mut depth = 1_usize;
+    while depth > 0 {
+        match payload.u8()? {
+            NODE => {
+                let (kind, _, _) = payload.node_record::<S>()?;
+                builder.start_node(kind);
+                depth += 1;
+            }
+            TOKEN => {
+                let kind = payload.kind::<S>()?;
+                match payload.u32()? {
+                    STATIC_TEXT if S::static_text(kind).is_some() => builder.static_token(kind),
+                    STATIC_TEXT => return Err(DecodeError::Corrupted("token without text")),
+                    _ if S::static_text(kind).is_some() => {
+                        return Err(DecodeError::Corrupted("static-text kind with text"));
+                    }
+                    index => token(builder, kind, index)?,
+                }
+            }
+            LEAVE => {
+                builder.finish_node();
+                depth -= 1;
+            }
+            _ => return Err(DecodeError::Corrupted("unknown event")),
+        }
+    }
+    Ok(())
+}
+
+fn finish_tree<S: Syntax, D: 'static>(builder: GreenNodeBuilder<S>) -> ResolvedNode<S, D> {
+    let (tree, cache) = builder.finish();
+    ResolvedNode::new_root_with_resolver(tree, cache.unwrap().into_interner().unwrap())
+}
+
+fn text_range_at(offset: TextSize, len: TextSize) -> Result<TextRange, DecodeError> {
+    offset
+        .checked_add(len)
+        .map(|end| TextRange::new(offset, end))
+        .ok_or(DecodeError::Corrupted("text length out of bounds"))
+}
+
+fn checksum(bytes: &[u8]) -> u64 {
+    let mut hash = Fnv::new();
+    hash.write(bytes);
+    hash.0
+}
+
+/// Hash identifying `S`, computed from its range of raw kinds and the names of these kinds.
+fn syntax_hash<S: Syntax>() -> u64 {
+    let mut hash = Fnv::new();
+    let (start, end) = (*S::RAW_KINDS.start(), *S::RAW_KINDS.end());
+    hash.write(&start.to_le_bytes());
+    hash.write(&end.to_le_bytes());
+    // Names pin down the meaning of each raw kind, but are only looked up for syntaxes with a bounded number of kinds.
+    if end.saturating_sub(start) <= u32::from(u16::MAX) {
+        for raw in S::RAW_KINDS {
+            if let Some(name) = S::kind_name(RawSyntaxKind(raw)) {
+                hash.write(name.as_bytes());
+            }
+            hash.write(&[0xff]);
+        }
+    }
+    hash.0
+}
+
+/// 64-bit FNV-1a, which (unlike the hashers in `std`) is guaranteed to be the same on every platform and in every
+/// version.
+#[derive(Clone, Copy)]
+pub(crate) struct Fnv(pub(crate) u64);
+
+impl Fnv {
+    pub(crate) fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= u64::from(byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
@@ -0,0 +1,629 @@
+//! The mapped layout of the binary format, which can be traversed without decoding it.
+
+use std::{fmt, io, marker::PhantomData};
+
+use fxhash::FxHashMap;
+
+use super::{checksum, split_header, DecodeError, Header, STATIC_TEXT};
+use crate::{
+    build::GreenNodeBuilder,
+    green::GreenNode,
+    interning::{Resolver, TokenKey},
+    syntax::{ResolvedNode, SyntaxNode},
+    text::{TextRange, TextSize},
+    util::NodeOrToken,
+    RawSyntaxKind, Syntax,
+};
+
+/// The length of the counts at the start of the data of a mapped tree: the number of elements, the number of texts,
+/// the length of all texts and a reserved word.
+const COUNTS_LEN: usize = 16;
+/// The length of the record of an element, see [`encode_mapped`].
+const RECORD_LEN: usize = 24;
+/// The length of an entry of the text table: the start and length of the text.
+const TEXT_ENTRY_LEN: usize = 8;
+
+// Element tags.
+const NODE: u32 = 0;
+const TOKEN: u32 = 1;
+
+/// Marks the parent of the root.
+const NO_PARENT: u32 = u32::MAX;
+
+// Fields of an element record.
+const TAG: usize = 0;
+const KIND: usize = 1;
+const OFFSET: usize = 2;
+const LEN: usize = 3;
+/// For nodes, the index of the first element after the subtree of the node. For tokens, the index of their text.
+const LINK: usize = 4;
+const PARENT: usize = 5;
+
+/// Writes the subtree rooted in `node` to `writer` in the mapped layout, resolving the text of its tokens with
+/// `resolver`. See [`MappedTree`].
+pub fn write_mapped<S, D, R, W>(node: &SyntaxNode<S, D>, resolver: &R, mut writer: W) -> io::Result<()>
+where
+    S: Syntax,
+    R: Resolver<TokenKey> + ?Sized,
+    W: io::Write,
+{
+    let body = encode_mapped(node.green(), resolver);
+    Header::new::<S>(&body, false).mapped().write(&mut writer)?;
+    writer.write_all(&body)
+}
+
+/// Returns the subtree rooted in `node` in the mapped layout, resolving the text of its tokens with `resolver`. See
+/// [`MappedTree`].
+pub fn to_vec_mapped<S, D, R>(node: &SyntaxNode<S, D>, resolver: &R) -> Vec<u8>
+where
+    S: Syntax,
+    R: Resolver<TokenKey> + ?Sized,
+{
+    let body = encode_mapped(node.green(), resolver);
+    let mut res = Vec::with_capacity(super::HEADER_LEN + body.len());
+    // NOTE: `io::Write` methods on `Vec<u8>` never fail
+    Header::new::<S>(&body, false).mapped().write(&mut res).unwrap();
+    res.extend_from_slice(&body);
+    res
+}
+
+/// Encodes the tree as an array of fixed-size element records in preorder, followed by a table of all distinct token
+/// texts. Every record consists of six `u32`s: whether the element is a node or a token, its kind, the offset and
+/// length of its text, a link (for nodes, the index of the first element after their subtree, for tokens the index of
+/// their text) and the index of its parent. All numbers are written in native byte order, and all of them are
+/// aligned to 4 bytes relative to the start of the data.
+fn encode_mapped<R>(root: &GreenNode, resolver: &R) -> Vec<u8>
+where
+    R: Resolver<TokenKey> + ?Sized,
+{
+    let mut records: Vec<[u32; 6]> = Vec::new();
+    let mut indices: FxHashMap<TokenKey, u32> = FxHashMap::default();
+    let mut table = Vec::new();
+    let mut strings = Vec::new();
+
+    records.push([NODE, root.kind().0, 0, root.text_len().into(), 0, NO_PARENT]);
+    let mut stack = vec![(root.children(), 0_u32, 0_u32)];
+    while let Some((children, index, offset)) = stack.last_mut() {
+        let parent = *index;
+        match children.next() {
+            None => {
+                records[parent as usize][LINK] = records.len() as u32;
+                stack.pop();
+            }
+            Some(NodeOrToken::Node(node)) => {
+                let start = *offset;
+                *offset += u32::from(node.text_len());
+                let index = records.len() as u32;
+                records.push([NODE, node.kind().0, start, node.text_len().into(), 0, parent]);
+                stack.push((node.children(), index, start));
+            }
+            Some(NodeOrToken::Token(token)) => {
+                let start = *offset;
+                *offset += u32::from(token.text_len());
+                let text = match token.text_key() {
+                    Some(key) => {
+                        let next = indices.len() as u32;
+                        *indices.entry(key).or_insert_with(|| {
+                            let text = resolver.resolve(key);
+                            table.extend_from_slice(&(strings.len() as u32).to_ne_bytes());
+                            table.extend_from_slice(&(text.len() as u32).to_ne_bytes());
+                            strings.extend_from_slice(text.as_bytes());
+                            next
+                        })
+                    }
+                    None => STATIC_TEXT,
+                };
+                records.push([TOKEN, token.kind().0, start, token.text_len().into(), text, parent]);
+            }
+        }
+    }
+
+    let mut body = Vec::with_capacity(COUNTS_LEN + records.len() * RECORD_LEN + table.len() + strings.len());
+    for count in [records.len() as u32, indices.len() as u32, strings.len() as u32, 0] {
+        body.extend_from_slice(&count.to_ne_bytes());
+    }
+    for record in &records {
+        for field in record {
+            body.extend_from_slice(&field.to_ne_bytes());
+        }
+    }
+    body.append(&mut table);
+    body.append(&mut strings);
+    body
+}
+
+/// A tree in the mapped layout of the [binary format](super), which is traversed directly in the serialized data.
+///
+/// Trees written with [`to_vec_mapped`] or [`write_mapped`] store every element as a record of the same size, with
+/// all numbers aligned in the record, followed by a table of the token texts. The elements are stored in preorder,
+/// and every record links to the parent of the element and to the end of its subtree, so the tree can be navigated
+/// with [`MappedNode`] and [`MappedToken`] cursors, which are only an index into the data. This lets read-only
+/// consumers like indexers look at a tree that was memory-mapped from a file without reading or allocating anything
+/// up front.
+///
+/// Mapped trees can also be read into a regular tree with [`from_slice`](super::from_slice) and [`read`](super::read).
+///
+/// [`MappedTree::new`] only checks the header of the tree, so that creating it does not touch the rest of the data.
+/// The data is not trusted: accessing malformed data can panic, but never causes undefined behavior. Trees from
+/// untrusted sources should be checked with [`verify`](MappedTree::verify) first, after which no access panics.
+///
+/// # Example
+/// ```
+/// # use cstree::testing::*;
+/// use cstree::binary::{self, MappedTree};
+///
+/// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+/// builder.start_node(Root);
+/// builder.token(Int, "1");
+/// builder.static_token(Plus);
+/// builder.token(Int, "2");
+/// builder.finish_node();
+/// let (tree, cache) = builder.finish();
+/// let interner = cache.unwrap().into_interner().unwrap();
+/// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+///
+/// // Typically, these bytes are mapped from a file.
+/// let bytes = binary::to_vec_mapped(&root, &interner);
+/// let tree = MappedTree::<MySyntax>::new(&bytes).unwrap();
+/// tree.verify().unwrap();
+/// let root = tree.root();
+/// assert_eq!(root.kind(), Root);
+/// let two = root.children_with_tokens().last().unwrap().into_token().unwrap();
+/// assert_eq!(two.text(), "2");
+/// assert_eq!(u32::from(two.text_range().start()), 2);
+/// assert_eq!(two.parent(), root);
+/// ```
+#[derive(Clone, Copy)]
+pub struct MappedTree<'a, S: Syntax> {
+    /// The data following the header.
+    body:       &'a [u8],
+    checksum:   u64,
+    big_endian: bool,
+    _syntax:    PhantomData<fn() -> S>,
+}
+
+impl<'a, S: Syntax> MappedTree<'a, S> {
+    /// Checks that `bytes` contain exactly one tree in the mapped layout that was written for the syntax `S`, and
+    /// returns it.
+    ///
+    /// This only reads the header of the tree, see [`MappedTree`].
+    pub fn new(bytes: &'a [u8]) -> Result<Self, DecodeError> {
+        let (header, body) = split_header::<S>(bytes)?;
+        if !header.mapped {
+            return Err(DecodeError::Corrupted("tree is not in the mapped layout"));
+        }
+        Self::from_body(&header, body)
+    }
+
+    pub(super) fn from_body(header: &Header, body: &'a [u8]) -> Result<Self, DecodeError> {
+        let tree = Self {
+            body,
+            checksum: header.checksum,
+            big_endian: header.big_endian,
+            _syntax: PhantomData,
+        };
+        if body.len() < COUNTS_LEN {
+            return Err(DecodeError::Truncated);
+        }
+        let len = COUNTS_LEN as u64
+            + u64::from(tree.count(0)) * RECORD_LEN as u64
+            + u64::from(tree.count(1)) * TEXT_ENTRY_LEN as u64
+            + u64::from(tree.count(2));
+        if len != body.len() as u64 {
+            return Err(DecodeError::Corrupted("section lengths do not match the length of the tree"));
+        }
+        if tree.count(0) == 0 {
+            return Err(DecodeError::Corrupted("tree does not start with a node"));
+        }
+        Ok(tree)
+    }
+
+    /// Checks the checksum and the structure of the entire tree. If this succeeds, no access to the tree panics.
+    pub fn verify(&self) -> Result<(), DecodeError> {
+        if checksum(self.body) != self.checksum {
+            return Err(DecodeError::ChecksumMismatch);
+        }
+        let texts = self.count(1);
+        for index in 0..texts {
+            let start = self.text_entry(index, 0) as usize;
+            let len = self.text_entry(index, 1) as usize;
+            let text = start
+                .checked_add(len)
+                .and_then(|end| self.strings().get(start..end))
+                .ok_or(DecodeError::Corrupted("invalid token text"))?;
+            std::str::from_utf8(text).map_err(|_| DecodeError::Corrupted("token text is not UTF-8"))?;
+        }
+
+        // The nodes whose subtrees contain the current element, with the offset of their next child and of their end.
+        let mut stack: Vec<(u32, u32, u32)> = Vec::new();
+        let finish = |(_, next, end): (u32, u32, u32)| match next == end {
+            true => Ok(()),
+            false => Err(DecodeError::Corrupted("node length does not match its children")),
+        };
+        for index in 0..self.len() {
+            while let Some(&parent) = stack.last() {
+                if self.field(parent.0, LINK) != index {
+                    break;
+                }
+                finish(parent)?;
+                stack.pop();
+            }
+            let (tag, kind, offset, len, link, parent) = (
+                self.field(index, TAG),
+                self.field(index, KIND),
+                self.field(index, OFFSET),
+                self.field(index, LEN),
+                self.field(index, LINK),
+                self.field(index, PARENT),
+            );
+            let kind = S::try_from_raw(RawSyntaxKind(kind)).ok_or(DecodeError::Corrupted("invalid syntax kind"))?;
+            let end = offset
+                .checked_add(len)
+                .ok_or(DecodeError::Corrupted("text length out of bounds"))?;
+            match stack.last_mut() {
+                Some((expected, next, _)) => {
+                    if parent != *expected {
+                        return Err(DecodeError::Corrupted("invalid parent"));
+                    }
+                    if offset != *next {
+                        return Err(DecodeError::Corrupted("invalid text offset"));
+                    }
+                    *next = end;
+                }
+                None if index == 0 && parent == NO_PARENT && offset == 0 => {}
+                None => return Err(DecodeError::Corrupted("data after the end of the root node")),
+            }
+            match tag {
+                NODE => {
+                    if link <= index || link > self.len() {
+                        return Err(DecodeError::Corrupted("invalid subtree length"));
+                    }
+                    if let Some(&(parent, _, _)) = stack.last() {
+                        if link > self.field(parent, LINK) {
+                            return Err(DecodeError::Corrupted("subtree extends past its parent"));
+                        }
+                    }
+                    stack.push((index, offset, end));
+                }
+                TOKEN => {
+                    let text_len = match link {
+                        STATIC_TEXT => S::static_text(kind)
+                            .ok_or(DecodeError::Corrupted("token without text"))?
+                            .len(),
+                        _ if S::static_text(kind).is_some() => {
+                            return Err(DecodeError::Corrupted("static-text kind with text"));
+                        }
+                        text if text < texts => self.text_entry(text, 1) as usize,
+                        _ => return Err(DecodeError::Corrupted("invalid token text index")),
+                    };
+                    if text_len != len as usize {
+                        return Err(DecodeError::Corrupted("token length does not match its text"));
+                    }
+                    if stack.is_empty() {
+                        return Err(DecodeError::Corrupted("tree does not start with a node"));
+                    }
+                }
+                _ => return Err(DecodeError::Corrupted("unknown element")),
+            }
+        }
+        // Nodes whose subtrees end with the last element are still on the stack.
+        stack.into_iter().try_for_each(finish)?;
+        Ok(())
+    }
+
+    /// The root node of the tree.
+    #[inline]
+    pub fn root(&self) -> MappedNode<'a, S> {
+        MappedNode {
+            tree:  *self,
+            index: 0,
+        }
+    }
+
+    /// The number of nodes and tokens in the tree.
+    #[inline]
+    pub fn len(&self) -> u32 {
+        self.count(0)
+    }
+
+    /// Always `false`, since every tree has a root node.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// Reads the number at `at`. Numbers are read byte-wise, so the data does not have to be aligned in memory, but
+    /// since they are aligned in the data this compiles to a single load if it is.
+    fn read_u32(&self, at: usize) -> u32 {
+        let bytes = self.body[at..at + 4].try_into().unwrap();
+        if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+
+    fn count(&self, index: usize) -> u32 {
+        self.read_u32(index * 4)
+    }
+
+    fn field(&self, index: u32, field: usize) -> u32 {
+        self.read_u32(COUNTS_LEN + index as usize * RECORD_LEN + field * 4)
+    }
+
+    fn text_entry(&self, index: u32, field: usize) -> u32 {
+        let table = COUNTS_LEN + self.len() as usize * RECORD_LEN;
+        self.read_u32(table + index as usize * TEXT_ENTRY_LEN + field * 4)
+    }
+
+    fn strings(&self) -> &'a [u8] {
+        let start = COUNTS_LEN + self.len() as usize * RECORD_LEN + self.count(1) as usize * TEXT_ENTRY_LEN;
+        &self.body[start..]
+    }
+
+    fn kind(&self, index: u32) -> S {
+        S::try_from_raw(RawSyntaxKind(self.field(index, KIND))).expect("invalid syntax kind in mapped tree")
+    }
+
+    fn text_range(&self, index: u32) -> TextRange {
+        TextRange::at(self.field(index, OFFSET).into(), self.field(index, LEN).into())
+    }
+
+    fn element(&self, index: u32) -> MappedElement<'a, S> {
+        match self.field(index, TAG) {
+            NODE => NodeOrToken::Node(MappedNode { tree: *self, index }),
+            _ => NodeOrToken::Token(MappedToken { tree: *self, index }),
+        }
+    }
+
+    /// The index of the element after the subtree of the element at `index`.
+    fn subtree_end(&self, index: u32) -> u32 {
+        match self.field(index, TAG) {
+            NODE => self.field(index, LINK),
+            _ => index + 1,
+        }
+    }
+}
+
+impl<S: Syntax> fmt::Debug for MappedTree<'_, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MappedTree")
+            .field("len", &self.len())
+            .field("bytes", &self.body.len())
+            .finish()
+    }
+}
+
+/// An element of a [`MappedTree`].
+pub type MappedElement<'a, S> = NodeOrToken<MappedNode<'a, S>, MappedToken<'a, S>>;
+
+/// A cursor to a node of a [`MappedTree`]. Cursors are cheap to copy, and moving them does not allocate.
+#[derive(Clone, Copy)]
+pub struct MappedNode<'a, S: Syntax> {
+    tree:  MappedTree<'a, S>,
+    index: u32,
+}
+
+impl<'a, S: Syntax> MappedNode<'a, S> {
+    /// The tree this node belongs to.
+    #[inline]
+    pub fn tree(&self) -> MappedTree<'a, S> {
+        self.tree
+    }
+
+    /// The kind of this node.
+    #[inline]
+    pub fn kind(&self) -> S {
+        self.tree.kind(self.index)
+    }
+
+    /// The range of the text of this node, relative to the start of the tree.
+    #[inline]
+    pub fn text_range(&self) -> TextRange {
+        self.tree.text_range(self.index)
+    }
+
+    /// The parent of this node, or `None` for the root.
+    #[inline]
+    pub fn parent(&self) -> Option<MappedNode<'a, S>> {
+        match self.tree.field(self.index, PARENT) {
+            NO_PARENT => None,
+            index => Some(MappedNode { tree: self.tree, index }),
+        }
+    }
+
+    /// The first child node or token of this node.
+    #[inline]
+    pub fn first_child_or_token(&self) -> Option<MappedElement<'a, S>> {
+        self.children_with_tokens().next()
+    }
+
+    /// The node or token that follows this node in its parent.
+    #[inline]
+    pub fn next_sibling_or_token(&self) -> Option<MappedElement<'a, S>> {
+        next_sibling(self.tree, self.index)
+    }
+
+    /// The child nodes and tokens of this node, in order.
+    #[inline]
+    pub fn children_with_tokens(&self) -> MappedChildren<'a, S> {
+        MappedChildren {
+            tree: self.tree,
+            next: self.index + 1,
+            end:  self.tree.field(self.index, LINK),
+        }
+    }
+
+    /// The child nodes of this node, in order.
+    #[inline]
+    pub fn children(&self) -> impl Iterator<Item = MappedNode<'a, S>> {
+        self.children_with_tokens().filter_map(NodeOrToken::into_node)
+    }
+
+    /// The deepest node in the subtree of this node whose text range covers `range`, which is relative to the start of
+    /// the tree. Subtrees that do not contain `range` are skipped.
+    ///
+    /// If the range is empty and lies on the boundary of two nodes, either one can be returned.
+    ///
+    /// # Panics
+    /// If `range` is not contained in the text range of this node.
+    pub fn covering_node(&self, range: TextRange) -> MappedNode<'a, S> {
+        assert!(
+            self.text_range().contains_range(range),
+            "Bad range: node range {:?}, range {:?}",
+            self.text_range(),
+            range,
+        );
+        let mut node = *self;
+        while let Some(child) = node
+            .children()
+            .find(|child| child.text_range().contains_range(range))
+        {
+            node = child;
+        }
+        node
+    }
+
+    /// Builds a regular tree from the subtree rooted at this node.
+    pub fn to_tree<D: 'static>(&self) -> ResolvedNode<S, D> {
+        let mut builder: GreenNodeBuilder<S> = GreenNodeBuilder::new();
+        builder.start_node(self.kind());
+        let mut open = vec![self.tree.field(self.index, LINK)];
+        for index in self.index + 1..open[0] {
+            while open.last() == Some(&index) {
+                open.pop();
+                builder.finish_node();
+            }
+            match self.tree.element(index) {
+                NodeOrToken::Node(node) => {
+                    builder.start_node(node.kind());
+                    open.push(self.tree.field(index, LINK));
+                }
+                NodeOrToken::Token(token) if token.has_static_text() => builder.static_token(token.kind()),
+                NodeOrToken::Token(token) => builder.token(token.kind(), token.text()),
+            }
+        }
+        for _ in open {
+            builder.finish_node();
+        }
+        let (tree, cache) = builder.finish();
+        ResolvedNode::new_root_with_resolver(tree, cache.unwrap().into_interner().unwrap())
+    }
+}
+
+impl<S: Syntax> PartialEq for MappedNode<'_, S> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self.tree.body, other.tree.body) && self.index == other.index
+    }
+}
+
+impl<S: Syntax> Eq for MappedNode<'_, S> {}
+
+impl<S: Syntax> fmt::Debug for MappedNode<'_, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}@{:?}", self.kind(), self.text_range())
+    }
+}
+
+/// A cursor to a token of a [`MappedTree`]. Cursors are cheap to copy, and moving them does not allocate.
+#[derive(Clone, Copy)]
+pub struct MappedToken<'a, S: Syntax> {
+    tree:  MappedTree<'a, S>,
+    index: u32,
+}
+
+impl<'a, S: Syntax> MappedToken<'a, S> {
+    /// The kind of this token.
+    #[inline]
+    pub fn kind(&self) -> S {
+        self.tree.kind(self.index)
+    }
+
+    /// The range of the text of this token, relative to the start of the tree.
+    #[inline]
+    pub fn text_range(&self) -> TextRange {
+        self.tree.text_range(self.index)
+    }
+
+    /// The length of the text of this token.
+    #[inline]
+    pub fn text_len(&self) -> TextSize {
+        self.tree.field(self.index, LEN).into()
+    }
+
+    /// The text of this token, which is borrowed from the serialized data or is the
+    /// [`static_text`](Syntax::static_text) of its kind.
+    pub fn text(&self) -> &'a str {
+        match self.tree.field(self.index, LINK) {
+            STATIC_TEXT => S::static_text(self.kind()).expect("token without text in mapped tree"),
+            text => {
+                let start = self.tree.text_entry(text, 0) as usize;
+                let len = self.tree.text_entry(text, 1) as usize;
+                std::str::from_utf8(&self.tree.strings()[start..start + len]).expect("token text is not UTF-8")
+            }
+        }
+    }
+
+    fn has_static_text(&self) -> bool {
+        self.tree.field(self.index, LINK) == STATIC_TEXT
+    }
+
+    /// The node that contains this token.
+    #[inline]
+    pub fn parent(&self) -> MappedNode<'a, S> {
+        MappedNode {
+            tree:  self.tree,
+            index: self.tree.field(self.index, PARENT),
+        }
+    }
+
+    /// The node or token that follows this token in its parent.
+    #[inline]
+    pub fn next_sibling_or_token(&self) -> Option<MappedElement<'a, S>> {
+        next_sibling(self.tree, self.index)
+    }
+}
+
+impl<S: Syntax> PartialEq for MappedToken<'_, S> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self.tree.body, other.tree.body) && self.index == other.index
+    }
+}
+
+impl<S: Syntax> Eq for MappedToken<'_, S> {}
+
+impl<S: Syntax> fmt::Debug for MappedToken<'_, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}@{:?} {:?}", self.kind(), self.text_range(), self.text())
+    }
+}
+
+fn next_sibling<S: Syntax>(tree: MappedTree<'_, S>, index: u32) -> Option<MappedElement<'_, S>> {
+    let parent = match tree.field(index, PARENT) {
+        NO_PARENT => return None,
+        parent => parent,
+    };
+    let next = tree.subtree_end(index);
+    (next < tree.field(parent, LINK)).then(|| tree.element(next))
+}
+
+/// The children of a [`MappedNode`], see [`MappedNode::children_with_tokens`].
+#[derive(Debug, Clone)]
+pub struct MappedChildren<'a, S: Syntax> {
+    tree: MappedTree<'a, S>,
+    next: u32,
+    end:  u32,
+}
+
+impl<'a, S: Syntax> Iterator for MappedChildren<'a, S> {
+    type Item = MappedElement<'a, S>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.end {
+            return None;
+        }
+        let element = self.tree.element(self.next);
+        self.next = self.tree.subtree_end(self.next);
+        Some(element)
+    }
+}
@@ -3,21 +3,32 @@
 //! point to constructing [`GreenNode`]s and [`GreenToken`]s.
 
 pub(super) mod builder;
+pub(super) mod cancel;
 mod element;
 mod iter;
 mod node;
+pub(super) mod replay;
+mod report;
+pub(super) mod store;
+#[cfg(feature = "stable_hash")]
+mod stable_hash;
 mod token;
+mod visit;
 
 pub(crate) use self::element::GreenElementRef;
 use self::element::{GreenElement, PackedGreenElement};
 
-pub use self::{iter::GreenNodeChildren, node::GreenNode, token::GreenToken};
+pub use self::{
+    iter::{GreenChildrenFrom, GreenNodeChildren},
+    node::{GreenNode, GreenNodeHead, SliceError},
+    report::TreeReport,
+    token::{GreenToken, GreenTokenData},
+    visit::{GreenVisitor, Visit},
+};
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use node::GreenNodeHead;
-    use token::GreenTokenData;
 
     #[test]
     #[cfg_attr(miri, ignore)]
@@ -33,12 +44,31 @@ mod tests {
     #[cfg_attr(miri, ignore)]
     #[rustfmt::skip]
     fn assert_green_sizes() {
-        use std::mem::size_of;
+        use super::node::ChildHash;
+        use std::mem::{align_of, size_of};
+
+        // with `line_counts`, nodes and tokens store an additional `u32`
+        let newlines = if cfg!(feature = "line_counts") { size_of::<u32>() } else { 0 };
+        // with `char_counts`, tokens store an additional `u32`
+        let chars = if cfg!(feature = "char_counts") { size_of::<u32>() } else { 0 };
+        // with `error_messages`, tokens store an additional `u32`
+        let message = if cfg!(feature = "error_messages") { size_of::<u32>() } else { 0 };
+        // with `cooked_values`, tokens store an additional `Option<TokenKey>`
+        let cooked = if cfg!(feature = "cooked_values") { size_of::<u32>() } else { 0 };
+        // with `subtree_counts`, nodes store two additional `u32`s
+        let counts = if cfg!(feature = "subtree_counts") { size_of::<u32>() * 2 } else { 0 };
+        // with `key_filters`, nodes store an additional `u64`
+        let key_filter = if cfg!(feature = "key_filters") { size_of::<u64>() } else { 0 };
+        // with `node_payloads`, nodes store an additional `u32`
+        let payload = if cfg!(feature = "node_payloads") { size_of::<u32>() } else { 0 };
+        let head = size_of::<u32>() * 2 + counts + newlines + key_filter + payload + size_of::<ChildHash>();
+        let align = if cfg!(feature = "key_filters") { align_of::<u64>() } else { align_of::<ChildHash>() };
+        let head = (head + align - 1) / align * align;
 
         assert_eq!(size_of::<GreenNode>(),          size_of::<*const u8>());
         assert_eq!(size_of::<GreenToken>(),         size_of::<*const u8>());
-        assert_eq!(size_of::<GreenNodeHead>(),      size_of::<u32>() * 3);
-        assert_eq!(size_of::<GreenTokenData>(),     size_of::<u32>() * 3);
+        assert_eq!(size_of::<GreenNodeHead>(),      head);
+        assert_eq!(size_of::<GreenTokenData>(),     size_of::<u32>() * 3 + newlines + chars + message + cooked);
         assert_eq!(size_of::<GreenElement>(),       size_of::<*const u8>() * 2);
         assert_eq!(size_of::<PackedGreenElement>(), size_of::<*const u8>());
     }
@@ -1,17 +1,32 @@
-use std::hash::{Hash, Hasher};
+use std::{
+    fmt,
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 
-use fxhash::{FxHashMap, FxHasher32};
+use fxhash::FxHashMap;
+use parking_lot::Mutex;
+use smallvec::SmallVec;
 use text_size::TextSize;
 
 use crate::{
     green::{GreenElement, GreenNode, GreenToken},
-    interning::{new_interner, Interner, TokenInterner, TokenKey},
+    interning::{new_interner, Interner, KeyCounts, TokenInterner, TokenKey},
+    recovery::{RecoverySets, SyntaxKindSet},
+    schema::Schema,
     util::NodeOrToken,
     utility_types::MaybeOwned,
     RawSyntaxKind, Syntax,
 };
 
-use super::{node::GreenNodeHead, token::GreenTokenData};
+use super::{
+    cancel::{CancellationToken, Cancelled},
+    node::GreenNodeHead,
+    token::GreenTokenData,
+};
 
 /// If `node.children() <= CHILDREN_CACHE_THRESHOLD`, we will not create
 /// a new [`GreenNode`], but instead lookup in the cache if this node is
@@ -19,13 +34,671 @@ use super::{node::GreenNodeHead, token::GreenTokenData};
 /// this node into the cache.
 const CHILDREN_CACHE_THRESHOLD: usize = 3;
 
+/// The number of lookups of a class of nodes after which an [adaptive](DedupCache::adaptive) [`DedupCache`] decides
+/// whether to keep deduplicating it.
+const ADAPTIVE_DEDUP_SAMPLES: u32 = 1024;
+
+/// A strategy for creating the green nodes and tokens of a tree, which may return existing elements that are equal
+/// to the requested ones instead of allocating new ones.
+///
+/// Every [`NodeCache`] uses a `GreenCache` to create elements. By default, this is a [`DedupCache`], which
+/// deduplicates all tokens and all nodes with few children. [`NoCache`] always creates new elements, which is fastest
+/// for trees that are built once and do not need to share memory. Other strategies, like a cache with a bounded size
+/// or one that is shared between many builders over a long time, can be used by implementing this trait.
+///
+/// # Examples
+/// ```
+/// # use cstree::testing::*;
+/// # use cstree::interning::TokenInterner;
+/// use cstree::build::{NoCache, NodeCache};
+///
+/// let cache = NodeCache::new().with_green_cache(NoCache);
+/// let mut builder: GreenNodeBuilder<MySyntax, TokenInterner, NoCache> =
+///     GreenNodeBuilder::from_cache(cache);
+/// builder.start_node(Root);
+/// builder.token(Int, "1");
+/// builder.finish_node();
+/// let (tree, _) = builder.finish();
+/// assert_eq!(tree.kind(), MySyntax::into_raw(Root));
+/// ```
+pub trait GreenCache {
+    /// Returns a node with the given `head` and `children`, where `head` was computed from `children` with
+    /// [`GreenNodeHead::new`]. Children that are not needed because an existing node is returned can be dropped.
+    fn node<C>(&mut self, head: GreenNodeHead, children: C) -> GreenNode
+    where
+        C: IntoIterator<Item = GreenElement>,
+        C::IntoIter: ExactSizeIterator;
+
+    /// Returns a token with the given `data`.
+    fn token(&mut self, data: GreenTokenData) -> GreenToken;
+}
+
+/// The default [`GreenCache`], which deduplicates identical tokens and nodes with at most 3 children.
+#[derive(Debug, Default)]
+pub struct DedupCache {
+    nodes:     FxHashMap<GreenNodeHead, GreenNode>,
+    /// Nodes whose head is equal to the head of a different node in `nodes`.
+    colliding: FxHashMap<GreenNodeHead, Vec<GreenNode>>,
+    tokens:    FxHashMap<GreenTokenData, GreenToken>,
+    adaptive:  Option<AdaptiveDedup>,
+    /// Whether nodes with any number of children are deduplicated.
+    canonical: bool,
+}
+
+/// Hit rates of node lookups per kind and number of children, for [`DedupCache::adaptive`].
+#[derive(Debug)]
+struct AdaptiveDedup {
+    min_hit_rate: f64,
+    classes:      FxHashMap<(RawSyntaxKind, usize), DedupClass>,
+}
+
+#[derive(Debug, Default)]
+struct DedupClass {
+    lookups:  u32,
+    hits:     u32,
+    disabled: bool,
+}
+
+impl AdaptiveDedup {
+    fn should_dedup(&self, class: (RawSyntaxKind, usize)) -> bool {
+        self.classes.get(&class).map_or(true, |class| !class.disabled)
+    }
+
+    fn record(&mut self, class: (RawSyntaxKind, usize), hit: bool) {
+        let class = self.classes.entry(class).or_default();
+        class.lookups += 1;
+        class.hits += u32::from(hit);
+        if class.lookups == ADAPTIVE_DEDUP_SAMPLES {
+            class.disabled = f64::from(class.hits) < f64::from(class.lookups) * self.min_hit_rate;
+        }
+    }
+}
+
+impl DedupCache {
+    /// Constructs a new, empty cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Constructs a new, empty cache that stops deduplicating nodes that are rarely found in the cache.
+    ///
+    /// Nodes are grouped by their kind and number of children. After the first 1024 lookups of nodes of a group, the
+    /// cache stops looking up and storing nodes of that group if less than `min_hit_rate` of these lookups found an
+    /// existing node. On workloads where little structure is shared between nodes, this saves the time and memory
+    /// spent on nodes that will never be reused. Tokens are always deduplicated.
+    ///
+    /// Groups that were not deduplicated stay that way until the cache is [cleared](DedupCache::clear).
+    pub fn adaptive(min_hit_rate: f64) -> Self {
+        Self {
+            adaptive: Some(AdaptiveDedup {
+                min_hit_rate,
+                classes: FxHashMap::default(),
+            }),
+            ..Self::default()
+        }
+    }
+
+    /// Constructs a new, empty cache that deduplicates nodes with any number of children.
+    ///
+    /// Equal nodes created with this cache are always the same node, so this cache can be used to
+    /// [canonicalize](GreenNode::canonicalize) trees. Since large nodes are rarely equal while parsing, building trees
+    /// with it usually takes more time and memory than with a [`new`](DedupCache::new) cache.
+    pub fn canonical() -> Self {
+        Self {
+            canonical: true,
+            ..Self::default()
+        }
+    }
+
+    /// The number of nodes in the cache.
+    pub fn node_count(&self) -> usize {
+        self.nodes.len() + self.colliding.values().map(Vec::len).sum::<usize>()
+    }
+
+    /// The number of tokens in the cache.
+    pub fn token_count(&self) -> usize {
+        self.tokens.len()
+    }
+
+    /// Removes all cached nodes and tokens. Trees built before are not affected.
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.colliding.clear();
+        self.tokens.clear();
+        if let Some(adaptive) = &mut self.adaptive {
+            adaptive.classes.clear();
+        }
+    }
+
+    /// Returns the cached node with the given `head` and `children` and `true`, or caches and returns a new node and
+    /// `false`.
+    ///
+    /// The heads of different nodes are equal if the hashes of their children collide, so the children of a cached
+    /// node with an equal head are compared before it is returned. Equal children are usually the same allocation,
+    /// which makes comparing them cheap.
+    fn dedup_node<C>(&mut self, head: GreenNodeHead, children: C) -> (GreenNode, bool)
+    where
+        C: ExactSizeIterator<Item = GreenElement>,
+    {
+        let Some(cached) = self.nodes.get(&head) else {
+            let node = GreenNode::from_head_and_children(head, children);
+            self.nodes.insert(head, node.clone());
+            return (node, false);
+        };
+        let children: SmallVec<[GreenElement; 4]> = children.collect();
+        if has_children(cached, &children) {
+            return (cached.clone(), true);
+        }
+        let colliding = self.colliding.entry(head).or_default();
+        if let Some(node) = colliding.iter().find(|node| has_children(node, &children)) {
+            return (node.clone(), true);
+        }
+        let node = GreenNode::from_head_and_children(head, children);
+        colliding.push(node.clone());
+        (node, false)
+    }
+}
+
+/// Whether `node` has exactly the given `children`, for nodes found in a cache by a head that may be equal to theirs
+/// only because the hashes of the children collide.
+fn has_children(node: &GreenNode, children: &[GreenElement]) -> bool {
+    node.children().eq(children.iter().map(NodeOrToken::as_ref))
+}
+
+impl GreenCache for DedupCache {
+    fn node<C>(&mut self, head: GreenNodeHead, children: C) -> GreenNode
+    where
+        C: IntoIterator<Item = GreenElement>,
+        C::IntoIter: ExactSizeIterator,
+    {
+        // Green nodes are fully immutable, so it's ok to deduplicate them.
+        // This is the same optimization that Roslyn does
+        // https://github.com/KirillOsenkov/Bliki/wiki/Roslyn-Immutable-Trees
+        //
+        // For example, all `#[inline]` in this file share the same green node!
+        // For `libsyntax/parse/parser.rs`, measurements show that deduping saves
+        // 17% of the memory for green nodes!
+        let children = children.into_iter();
+        let class = (head.kind(), children.len());
+        let dedup = (self.canonical || children.len() <= CHILDREN_CACHE_THRESHOLD)
+            && self
+                .adaptive
+                .as_ref()
+                .map_or(true, |adaptive| adaptive.should_dedup(class));
+        if !dedup {
+            return GreenNode::from_head_and_children(head, children);
+        }
+
+        let (node, hit) = self.dedup_node(head, children);
+        if let Some(adaptive) = &mut self.adaptive {
+            adaptive.record(class, hit);
+        }
+        node
+    }
+
+    #[inline]
+    fn token(&mut self, data: GreenTokenData) -> GreenToken {
+        self.tokens
+            .entry(data)
+            .or_insert_with_key(|data| GreenToken::new(*data))
+            .clone()
+    }
+}
+
+/// A [`GreenCache`] that does not cache anything and creates a new element every time.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoCache;
+
+impl GreenCache for NoCache {
+    #[inline]
+    fn node<C>(&mut self, head: GreenNodeHead, children: C) -> GreenNode
+    where
+        C: IntoIterator<Item = GreenElement>,
+        C::IntoIter: ExactSizeIterator,
+    {
+        GreenNode::from_head_and_children(head, children)
+    }
+
+    #[inline]
+    fn token(&mut self, data: GreenTokenData) -> GreenToken {
+        GreenToken::new(data)
+    }
+}
+
+/// The number of independently locked shards of a [`ThreadedNodeCache`].
+const THREADED_CACHE_SHARDS: usize = 32;
+
+/// The maximum number of tokens a [`LocalNodeCache`] keeps in addition to its shared cache.
+const LOCAL_TOKEN_CACHE_CAPACITY: usize = 512;
+
+/// The number of local token hits after which a [`LocalNodeCache`] adds them to the stats of its shared cache.
+const LOCAL_STATS_SYNC_INTERVAL: u64 = 1024;
+
+/// A [`GreenCache`] that can be shared between builders on different threads, so that trees built in parallel share
+/// their tokens and small nodes like trees built with the same [`DedupCache`].
+///
+/// `GreenCache` is implemented for `&ThreadedNodeCache`, so each builder gets its own [`NodeCache`] that refers to
+/// the shared cache. The cached elements are distributed over a number of shards that are locked independently to
+/// reduce contention. For builders that create many tokens, [`local`](ThreadedNodeCache::local) can be used to
+/// further reduce contention for tokens. The cache also counts how often a lookup found an existing element, see
+/// [`stats`](ThreadedNodeCache::stats).
+///
+/// # Examples
+/// ```
+/// # use cstree::testing::*;
+/// # use cstree::interning::TokenInterner;
+/// use cstree::build::{NodeCache, ThreadedNodeCache};
+///
+/// let cache = ThreadedNodeCache::new();
+/// let trees: Vec<_> = std::thread::scope(|scope| {
+///     let threads: Vec<_> = (0..2)
+///         .map(|_| {
+///             scope.spawn(|| {
+///                 let cache = NodeCache::new().with_green_cache(&cache);
+///                 let mut builder: GreenNodeBuilder<MySyntax, TokenInterner, &ThreadedNodeCache> =
+///                     GreenNodeBuilder::from_cache(cache);
+///                 builder.start_node(Root);
+///                 builder.static_token(Plus);
+///                 builder.finish_node();
+///                 builder.finish().0
+///             })
+///         })
+///         .collect();
+///     threads
+///         .into_iter()
+///         .map(|thread| thread.join().unwrap())
+///         .collect()
+/// });
+/// assert_eq!(trees[0], trees[1]);
+/// assert_eq!(cache.stats().node_hits, 1);
+/// ```
+#[derive(Debug)]
+pub struct ThreadedNodeCache {
+    shards:       Box<[Mutex<DedupCache>]>,
+    node_hits:    AtomicU64,
+    node_misses:  AtomicU64,
+    token_hits:   AtomicU64,
+    token_misses: AtomicU64,
+}
+
+/// How often lookups in a [`ThreadedNodeCache`] found an existing element.
+///
+/// Nodes with too many children to be deduplicated are not looked up and are not counted.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CacheStats {
+    /// The number of nodes that were found in the cache.
+    pub node_hits:    u64,
+    /// The number of nodes that were newly created and added to the cache.
+    pub node_misses:  u64,
+    /// The number of tokens that were found in the cache.
+    pub token_hits:   u64,
+    /// The number of tokens that were newly created and added to the cache.
+    pub token_misses: u64,
+}
+
+impl CacheStats {
+    /// The fraction of node lookups that found an existing node, or `0.0` if no nodes were looked up.
+    pub fn node_hit_rate(&self) -> f64 {
+        hit_rate(self.node_hits, self.node_misses)
+    }
+
+    /// The fraction of token lookups that found an existing token, or `0.0` if no tokens were looked up.
+    pub fn token_hit_rate(&self) -> f64 {
+        hit_rate(self.token_hits, self.token_misses)
+    }
+}
+
+fn hit_rate(hits: u64, misses: u64) -> f64 {
+    match hits + misses {
+        0 => 0.0,
+        total => hits as f64 / total as f64,
+    }
+}
+
+impl Default for ThreadedNodeCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ThreadedNodeCache {
+    /// Constructs a new, empty cache.
+    pub fn new() -> Self {
+        Self {
+            shards:       (0..THREADED_CACHE_SHARDS).map(|_| Mutex::default()).collect(),
+            node_hits:    AtomicU64::new(0),
+            node_misses:  AtomicU64::new(0),
+            token_hits:   AtomicU64::new(0),
+            token_misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Removes all cached nodes and tokens and resets the [`stats`](ThreadedNodeCache::stats).
+    /// Trees built before are not affected.
+    pub fn clear(&mut self) {
+        for shard in self.shards.iter_mut() {
+            shard.get_mut().clear();
+        }
+        *self.node_hits.get_mut() = 0;
+        *self.node_misses.get_mut() = 0;
+        *self.token_hits.get_mut() = 0;
+        *self.token_misses.get_mut() = 0;
+    }
+
+    /// Returns how often lookups in this cache have found an existing element so far.
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            node_hits:    self.node_hits.load(Ordering::Relaxed),
+            node_misses:  self.node_misses.load(Ordering::Relaxed),
+            token_hits:   self.token_hits.load(Ordering::Relaxed),
+            token_misses: self.token_misses.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns a cache for a single builder that uses this cache, but first looks for tokens in a small cache of its
+    /// own that does not need to be locked.
+    ///
+    /// Tokens with common texts, like keywords and punctuation, are requested very often. When building trees in
+    /// parallel, using a local cache on each thread avoids that all threads contend for the same shard of the shared
+    /// cache to look them up. Tokens found in the shared cache are remembered by the local cache until it is full, and
+    /// local hits are added to the [`stats`](ThreadedNodeCache::stats) of this cache periodically and when the local
+    /// cache is dropped.
+    pub fn local(&self) -> LocalNodeCache<'_> {
+        LocalNodeCache {
+            shared:     self,
+            tokens:     FxHashMap::default(),
+            token_hits: 0,
+        }
+    }
+
+    fn shard<T: std::hash::Hash>(&self, key: &T) -> &Mutex<DedupCache> {
+        &self.shards[self.shard_index(key)]
+    }
+
+    fn shard_mut<T: std::hash::Hash>(&mut self, key: &T) -> &mut DedupCache {
+        let index = self.shard_index(key);
+        self.shards[index].get_mut()
+    }
+
+    fn shard_index<T: std::hash::Hash>(&self, key: &T) -> usize {
+        fxhash::hash(key) % self.shards.len()
+    }
+
+    fn count(hits: &AtomicU64, misses: &AtomicU64, hit: bool) {
+        if hit { hits } else { misses }.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl GreenCache for &ThreadedNodeCache {
+    fn node<C>(&mut self, head: GreenNodeHead, children: C) -> GreenNode
+    where
+        C: IntoIterator<Item = GreenElement>,
+        C::IntoIter: ExactSizeIterator,
+    {
+        let children = children.into_iter();
+        if children.len() > CHILDREN_CACHE_THRESHOLD {
+            return GreenNode::from_head_and_children(head, children);
+        }
+        let (node, hit) = self.shard(&head).lock().dedup_node(head, children);
+        ThreadedNodeCache::count(&self.node_hits, &self.node_misses, hit);
+        node
+    }
+
+    fn token(&mut self, data: GreenTokenData) -> GreenToken {
+        let mut shard = self.shard(&data).lock();
+        let hit = shard.tokens.contains_key(&data);
+        ThreadedNodeCache::count(&self.token_hits, &self.token_misses, hit);
+        shard
+            .tokens
+            .entry(data)
+            .or_insert_with_key(|data| GreenToken::new(*data))
+            .clone()
+    }
+}
+
+impl From<DedupCache> for ThreadedNodeCache {
+    /// Distributes the elements cached by `cache` over the shards of a new cache, so that trees built in parallel can
+    /// reuse them. Like any `ThreadedNodeCache`, the new cache only looks up nodes with at most 3 children.
+    fn from(cache: DedupCache) -> Self {
+        let mut threaded = Self::new();
+        for (head, node) in cache.nodes {
+            threaded.shard_mut(&head).nodes.insert(head, node);
+        }
+        for (head, nodes) in cache.colliding {
+            threaded.shard_mut(&head).colliding.insert(head, nodes);
+        }
+        for (data, token) in cache.tokens {
+            threaded.shard_mut(&data).tokens.insert(data, token);
+        }
+        threaded
+    }
+}
+
+impl From<ThreadedNodeCache> for DedupCache {
+    /// Collects the elements cached in all shards of `cache` into a single [`new`](DedupCache::new) cache, for
+    /// example to continue building trees on a single thread after building them in parallel. The
+    /// [`stats`](ThreadedNodeCache::stats) of `cache` are discarded.
+    fn from(cache: ThreadedNodeCache) -> Self {
+        let mut dedup = Self::new();
+        for shard in cache.shards.into_vec() {
+            let shard = shard.into_inner();
+            // Every head and token is cached by a single shard, so the shards never contain the same key.
+            dedup.nodes.extend(shard.nodes);
+            dedup.colliding.extend(shard.colliding);
+            dedup.tokens.extend(shard.tokens);
+        }
+        dedup
+    }
+}
+
+/// A [`GreenCache`] for a single builder that keeps frequently used tokens of a [`ThreadedNodeCache`] locally.
+///
+/// Created by [`ThreadedNodeCache::local`].
+#[derive(Debug)]
+pub struct LocalNodeCache<'c> {
+    shared:     &'c ThreadedNodeCache,
+    tokens:     FxHashMap<GreenTokenData, GreenToken>,
+    token_hits: u64,
+}
+
+impl LocalNodeCache<'_> {
+    /// The shared cache this cache uses.
+    pub fn shared(&self) -> &ThreadedNodeCache {
+        self.shared
+    }
+
+    fn sync_stats(&mut self) {
+        self.shared.token_hits.fetch_add(self.token_hits, Ordering::Relaxed);
+        self.token_hits = 0;
+    }
+}
+
+impl GreenCache for LocalNodeCache<'_> {
+    #[inline]
+    fn node<C>(&mut self, head: GreenNodeHead, children: C) -> GreenNode
+    where
+        C: IntoIterator<Item = GreenElement>,
+        C::IntoIter: ExactSizeIterator,
+    {
+        let mut shared = self.shared;
+        shared.node(head, children)
+    }
+
+    fn token(&mut self, data: GreenTokenData) -> GreenToken {
+        if let Some(token) = self.tokens.get(&data).cloned() {
+            self.token_hits += 1;
+            if self.token_hits >= LOCAL_STATS_SYNC_INTERVAL {
+                self.sync_stats();
+            }
+            return token;
+        }
+        let mut shared = self.shared;
+        let token = shared.token(data);
+        if self.tokens.len() < LOCAL_TOKEN_CACHE_CAPACITY {
+            self.tokens.insert(data, token.clone());
+        }
+        token
+    }
+}
+
+impl Drop for LocalNodeCache<'_> {
+    fn drop(&mut self) {
+        self.sync_stats();
+    }
+}
+
+/// A [`GreenCache`] that keeps recently used elements of another cache in a small hot tier of its own.
+///
+/// Large caches, like a [`DedupCache`] that is reused for many documents or a [`ThreadedNodeCache`] that is shared
+/// between threads, get slower to query as they grow and may have to be locked for every lookup, even though most
+/// lookups request the same few tokens and node shapes. A `TieredCache` looks up elements in its hot tier first,
+/// which is a small map owned by a single builder that does not need to be locked. Elements that are not in the hot
+/// tier are requested from the larger cold tier and promoted to the hot tier.
+///
+/// Once the hot tier reaches its capacity, elements that were not used since the tier was last full are evicted to
+/// make room for new ones. Evicted elements stay in the cold tier. Like with [`DedupCache`], nodes with more than 3
+/// children are not kept in the hot tier.
+///
+/// # Examples
+/// ```
+/// # use cstree::testing::*;
+/// # use cstree::interning::TokenInterner;
+/// use cstree::build::{DedupCache, NodeCache, TieredCache};
+///
+/// let mut cache = NodeCache::new().with_green_cache(TieredCache::new(DedupCache::new(), 256));
+/// for _ in 0..2 {
+///     let mut builder: GreenNodeBuilder<MySyntax, TokenInterner, TieredCache<DedupCache>> =
+///         GreenNodeBuilder::with_cache(&mut cache);
+///     builder.start_node(Root);
+///     builder.static_token(Plus);
+///     builder.finish_node();
+///     builder.finish();
+/// }
+/// let tiered = cache.green_cache();
+/// assert_eq!(tiered.hot_len(), 2);
+/// assert_eq!(tiered.hot_hits(), 2);
+/// assert_eq!(tiered.cold().node_count(), 1);
+/// ```
+#[derive(Debug)]
+pub struct TieredCache<C> {
+    cold:         C,
+    /// The elements of the hot tier, with whether they were used since the hot tier was last full.
+    hot_nodes:    FxHashMap<GreenNodeHead, (GreenNode, bool)>,
+    hot_tokens:   FxHashMap<GreenTokenData, (GreenToken, bool)>,
+    hot_capacity: usize,
+    hot_hits:     u64,
+}
+
+impl<C: GreenCache> TieredCache<C> {
+    /// Creates a cache that keeps up to `hot_capacity` recently used elements of `cold` in its hot tier.
+    pub fn new(cold: C, hot_capacity: usize) -> Self {
+        Self {
+            cold,
+            hot_nodes: FxHashMap::default(),
+            hot_tokens: FxHashMap::default(),
+            hot_capacity,
+            hot_hits: 0,
+        }
+    }
+
+    /// The cache that elements are requested from if they are not in the hot tier.
+    pub fn cold(&self) -> &C {
+        &self.cold
+    }
+
+    /// Mutable access to the cold tier.
+    pub fn cold_mut(&mut self) -> &mut C {
+        &mut self.cold
+    }
+
+    /// Returns the cold tier, dropping the hot tier.
+    pub fn into_cold(self) -> C {
+        self.cold
+    }
+
+    /// The number of nodes and tokens in the hot tier.
+    pub fn hot_len(&self) -> usize {
+        self.hot_nodes.len() + self.hot_tokens.len()
+    }
+
+    /// The number of elements that were found in the hot tier so far.
+    pub fn hot_hits(&self) -> u64 {
+        self.hot_hits
+    }
+
+    /// Removes all elements from the hot tier. The cold tier is not affected.
+    pub fn clear_hot(&mut self) {
+        self.hot_nodes.clear();
+        self.hot_tokens.clear();
+    }
+
+    /// Makes room for a new element in the hot tier if it is full.
+    fn evict(&mut self) {
+        if self.hot_len() < self.hot_capacity {
+            return;
+        }
+        // Give every element a second chance: evict the ones that were not used since the last eviction, and mark the
+        // others as unused.
+        self.hot_nodes.retain(|_, (_, used)| std::mem::take(used));
+        self.hot_tokens.retain(|_, (_, used)| std::mem::take(used));
+        if self.hot_len() >= self.hot_capacity {
+            self.clear_hot();
+        }
+    }
+}
+
+impl<C: GreenCache> GreenCache for TieredCache<C> {
+    fn node<I>(&mut self, head: GreenNodeHead, children: I) -> GreenNode
+    where
+        I: IntoIterator<Item = GreenElement>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        let children = children.into_iter();
+        if children.len() > CHILDREN_CACHE_THRESHOLD {
+            return self.cold.node(head, children);
+        }
+        let children: SmallVec<[GreenElement; 4]> = children.collect();
+        if let Some((node, used)) = self.hot_nodes.get_mut(&head) {
+            if has_children(node, &children) {
+                *used = true;
+                self.hot_hits += 1;
+                return node.clone();
+            }
+        }
+        // Nodes whose head collides with the head of a node in the hot tier replace that node.
+        let node = self.cold.node(head, children);
+        if self.hot_capacity > 0 {
+            self.evict();
+            self.hot_nodes.insert(head, (node.clone(), false));
+        }
+        node
+    }
+
+    fn token(&mut self, data: GreenTokenData) -> GreenToken {
+        if let Some((token, used)) = self.hot_tokens.get_mut(&data) {
+            *used = true;
+            self.hot_hits += 1;
+            return token.clone();
+        }
+        let token = self.cold.token(data);
+        if self.hot_capacity > 0 {
+            self.evict();
+            self.hot_tokens.insert(data, (token.clone(), false));
+        }
+        token
+    }
+}
+
 /// A `NodeCache` deduplicates identical tokens and small nodes during tree construction.
 /// You can re-use the same cache for multiple similar trees with [`GreenNodeBuilder::with_cache`].
+///
+/// Which elements are deduplicated is decided by the cache's [`GreenCache`], see
+/// [`with_green_cache`](NodeCache::with_green_cache).
 #[derive(Debug)]
-pub struct NodeCache<'i, I = TokenInterner> {
-    nodes:    FxHashMap<GreenNodeHead, GreenNode>,
-    tokens:   FxHashMap<GreenTokenData, GreenToken>,
+pub struct NodeCache<'i, I = TokenInterner, C = DedupCache> {
+    green:    C,
     interner: MaybeOwned<'i, I>,
+    counts:   Option<KeyCounts>,
 }
 
 impl NodeCache<'static> {
@@ -54,9 +727,9 @@ impl NodeCache<'static> {
     /// ```
     pub fn new() -> Self {
         Self {
-            nodes:    FxHashMap::default(),
-            tokens:   FxHashMap::default(),
+            green:    DedupCache::new(),
             interner: MaybeOwned::Owned(new_interner()),
+            counts:   None,
         }
     }
 }
@@ -101,9 +774,9 @@ where
     #[inline]
     pub fn with_interner(interner: &'i mut I) -> Self {
         Self {
-            nodes:    FxHashMap::default(),
-            tokens:   FxHashMap::default(),
+            green:    DedupCache::new(),
             interner: MaybeOwned::Borrowed(interner),
+            counts:   None,
         }
     }
 
@@ -138,38 +811,163 @@ where
     #[inline]
     pub fn from_interner(interner: I) -> Self {
         Self {
-            nodes:    FxHashMap::default(),
-            tokens:   FxHashMap::default(),
+            green:    DedupCache::new(),
             interner: MaybeOwned::Owned(interner),
+            counts:   None,
         }
     }
 
-    /// Get a reference to the interner used to deduplicate source text (strings).
+    /// Constructs a cache that uses the given interner and continues with the nodes and tokens cached by a
+    /// [`ThreadedNodeCache`], for example to hand a cache that was warmed up by building trees in parallel to a
+    /// single-threaded session. `interner` must be the interner that the tokens in `cache` were interned with.
     ///
-    /// See also [`interner_mut`](NodeCache::interner_mut).
-    #[inline]
-    pub fn interner(&self) -> &I {
-        &self.interner
+    /// See [`into_threaded`](NodeCache::into_threaded) for the opposite conversion and an example.
+    pub fn from_threaded(cache: ThreadedNodeCache, interner: I) -> Self {
+        Self {
+            green:    cache.into(),
+            interner: MaybeOwned::Owned(interner),
+            counts:   None,
+        }
     }
 
-    /// Get a mutable reference to the interner used to deduplicate source text (strings).
+    /// Converts the nodes and tokens cached so far into a [`ThreadedNodeCache`], and returns it together with the
+    /// interner if this cache owns it (see [`into_interner`](NodeCache::into_interner)). The
+    /// [key counts](NodeCache::key_counts) are discarded.
+    ///
+    /// To build trees in parallel, the interner usually has to be converted to one that can be shared between
+    /// threads with [`map_interner`](NodeCache::map_interner) first.
+    ///
     /// # Examples
     /// ```
-    /// # use cstree::*;
-    /// # use cstree::build::*;
-    /// # use cstree::interning::*;
+    /// # use cstree::testing::*;
+    /// use cstree::build::NodeCache;
+    ///
     /// let mut cache = NodeCache::new();
-    /// let interner = cache.interner_mut();
-    /// let key = interner.get_or_intern("foo");
-    /// assert_eq!(interner.resolve(key), "foo");
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::with_cache(&mut cache);
+    /// builder.start_node(Root);
+    /// builder.token(Int, "1");
+    /// builder.finish_node();
+    /// let (tree, _) = builder.finish();
+    ///
+    /// let (threaded, interner) = cache.into_threaded();
+    /// // ... build more trees in parallel with `&threaded` ...
+    /// let mut cache = NodeCache::from_threaded(threaded, interner.unwrap());
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::with_cache(&mut cache);
+    /// builder.start_node(Root);
+    /// builder.token(Int, "1");
+    /// builder.finish_node();
+    /// assert!(builder.finish().0.ptr_eq(&tree));
     /// ```
-    #[inline]
-    pub fn interner_mut(&mut self) -> &mut I {
-        &mut self.interner
+    pub fn into_threaded(self) -> (ThreadedNodeCache, Option<I>) {
+        (self.green.into(), self.interner.into_owned())
     }
+}
 
-    /// If this node cache was constructed with [`new`](NodeCache::new) or
-    /// [`from_interner`](NodeCache::from_interner), returns the interner used to deduplicate source
+impl<'i, I, C> NodeCache<'i, I, C>
+where
+    I: Interner<TokenKey>,
+    C: GreenCache,
+{
+    /// Returns a cache that uses `green` instead of this cache's [`GreenCache`] to create nodes and tokens, which
+    /// discards all nodes and tokens cached so far. The interner is kept.
+    ///
+    /// See [`GreenCache`] for an example.
+    pub fn with_green_cache<G: GreenCache>(self, green: G) -> NodeCache<'i, I, G> {
+        NodeCache {
+            green,
+            interner: self.interner,
+            counts: self.counts,
+        }
+    }
+
+    /// Get a reference to the [`GreenCache`] that creates nodes and tokens.
+    #[inline]
+    pub fn green_cache(&self) -> &C {
+        &self.green
+    }
+
+    /// Get a mutable reference to the [`GreenCache`] that creates nodes and tokens.
+    #[inline]
+    pub fn green_cache_mut(&mut self) -> &mut C {
+        &mut self.green
+    }
+
+    /// Returns a cache that counts how often the text of each key is used by the tokens that are added to builders
+    /// using the cache. Counting starts from zero if it was already enabled. See [`KeyCounts`].
+    pub fn with_key_counts(self) -> Self {
+        Self {
+            counts: Some(KeyCounts::new()),
+            ..self
+        }
+    }
+
+    /// The number of tokens with each key that were added to builders using this cache since counting was enabled
+    /// with [`with_key_counts`](NodeCache::with_key_counts), or `None` if it is not enabled.
+    ///
+    /// Tokens that are added as part of an existing node, for example with
+    /// [`GreenNodeBuilder::insert_node_at`], are not counted.
+    #[inline]
+    pub fn key_counts(&self) -> Option<&KeyCounts> {
+        self.counts.as_ref()
+    }
+
+    /// Get a mutable reference to the [key counts](NodeCache::key_counts) of this cache, for example to
+    /// [clear](KeyCounts::clear) them.
+    #[inline]
+    pub fn key_counts_mut(&mut self) -> Option<&mut KeyCounts> {
+        self.counts.as_mut()
+    }
+
+    /// Get a reference to the interner used to deduplicate source text (strings).
+    ///
+    /// See also [`interner_mut`](NodeCache::interner_mut).
+    #[inline]
+    pub fn interner(&self) -> &I {
+        &self.interner
+    }
+
+    /// Get a mutable reference to the interner used to deduplicate source text (strings).
+    /// # Examples
+    /// ```
+    /// # use cstree::*;
+    /// # use cstree::build::*;
+    /// # use cstree::interning::*;
+    /// let mut cache = NodeCache::new();
+    /// let interner = cache.interner_mut();
+    /// let key = interner.get_or_intern("foo");
+    /// assert_eq!(interner.resolve(key), "foo");
+    /// ```
+    #[inline]
+    pub fn interner_mut(&mut self) -> &mut I {
+        &mut self.interner
+    }
+
+    /// Replaces the interner owned by this cache with the result of `f`, keeping all cached nodes and tokens.
+    ///
+    /// This allows to switch between interner types, for example to continue building trees in a single thread
+    /// with a warm cache that was filled by building trees in parallel with a
+    /// [`MultiThreadedTokenInterner`](crate::interning::MultiThreadedTokenInterner). The interner returned by `f`
+    /// must resolve every key of the original interner to the same text, which is the case for the `From`
+    /// conversions between `cstree`'s interners.
+    ///
+    /// If this cache only borrows its interner (see [`with_interner`](NodeCache::with_interner)), the cache is
+    /// returned unchanged as an error.
+    pub fn map_interner<J, F>(self, f: F) -> Result<NodeCache<'static, J, C>, Self>
+    where
+        F: FnOnce(I) -> J,
+    {
+        match self.interner {
+            MaybeOwned::Owned(interner) => Ok(NodeCache {
+                green:    self.green,
+                interner: MaybeOwned::Owned(f(interner)),
+                counts:   self.counts,
+            }),
+            interner @ MaybeOwned::Borrowed(_) => Err(Self { interner, ..self }),
+        }
+    }
+
+    /// If this node cache was constructed with [`new`](NodeCache::new) or
+    /// [`from_interner`](NodeCache::from_interner), returns the interner used to deduplicate source
     /// text (strings) to allow resolving tree tokens back to text and re-using the interner to build
     /// additonal trees.
     #[inline]
@@ -177,30 +975,20 @@ where
         self.interner.into_owned()
     }
 
-    fn node<S: Syntax>(&mut self, kind: S, all_children: &mut Vec<GreenElement>, offset: usize) -> GreenNode {
+    fn node<S: Syntax>(
+        &mut self,
+        kind: S,
+        all_children: &mut Vec<GreenElement>,
+        offset: usize,
+        finish_head: impl FnOnce(GreenNodeHead) -> GreenNodeHead,
+    ) -> GreenNode {
         // NOTE: this fn must remove all children starting at `first_child` from `all_children` before returning
-        let kind = S::into_raw(kind);
-        let mut hasher = FxHasher32::default();
-        let mut text_len: TextSize = 0.into();
-        for child in &all_children[offset..] {
-            text_len += child.text_len();
-            child.hash(&mut hasher);
-        }
-        let child_hash = hasher.finish() as u32;
+        let head = finish_head(GreenNodeHead::new(
+            S::into_raw(kind),
+            all_children[offset..].iter().map(NodeOrToken::as_ref),
+        ));
 
-        // Green nodes are fully immutable, so it's ok to deduplicate them.
-        // This is the same optimization that Roslyn does
-        // https://github.com/KirillOsenkov/Bliki/wiki/Roslyn-Immutable-Trees
-        //
-        // For example, all `#[inline]` in this file share the same green node!
-        // For `libsyntax/parse/parser.rs`, measurements show that deduping saves
-        // 17% of the memory for green nodes!
-        let children = all_children.drain(offset..);
-        if children.len() <= CHILDREN_CACHE_THRESHOLD {
-            self.get_cached_node(kind, children, text_len, child_hash)
-        } else {
-            GreenNode::new_with_len_and_hash(kind, children, text_len, child_hash)
-        }
+        self.green.node(head, all_children.drain(offset..))
     }
 
     #[inline(always)]
@@ -208,41 +996,359 @@ where
         self.interner.get_or_intern(text)
     }
 
-    /// Creates a [`GreenNode`] by looking inside the cache or inserting
-    /// a new node into the cache if it's a cache miss.
+    /// Creates a token of the given `kind` with the given `text`, interning the text if `kind` has no static text.
+    /// If `kind` has static text, `text` is checked against it according to the `options`.
+    #[inline]
+    fn text_token<S: Syntax>(&mut self, kind: S, text: &str, options: &mut TokenOptions<S>) -> GreenToken {
+        match S::static_text(kind) {
+            Some(static_text) => self.static_text_token(kind, text, static_text, options),
+            None => {
+                let key = self.intern(text);
+                self.keyed_token(kind, key, text, options)
+            }
+        }
+    }
+
+    /// Creates the tokens for all `(kind, text)` pairs in `tokens` like [`text_token`](NodeCache::text_token) and
+    /// passes them to `f` in order, but interns the texts of all kinds without static text as a single batch.
+    fn text_tokens<S: Syntax>(
+        &mut self,
+        tokens: &[(S, &str)],
+        options: &mut TokenOptions<S>,
+        mut f: impl FnMut(GreenToken),
+    ) {
+        let texts: SmallVec<[&str; 16]> = tokens
+            .iter()
+            .filter(|(kind, _)| S::static_text(*kind).is_none())
+            .map(|&(_, text)| text)
+            .collect();
+        let mut keys = self.interner.get_or_intern_many(&texts).into_iter();
+        for &(kind, text) in tokens {
+            let token = match S::static_text(kind) {
+                Some(static_text) => self.static_text_token(kind, text, static_text, options),
+                // every kind without static text has a key
+                None => self.keyed_token(kind, keys.next().unwrap(), text, options),
+            };
+            f(token);
+        }
+    }
+
+    /// Creates a token of the given `kind` with its `static_text`, checking `text` against it according to the
+    /// `options`.
     #[inline]
-    fn get_cached_node(
+    fn static_text_token<S: Syntax>(
         &mut self,
-        kind: RawSyntaxKind,
-        children: std::vec::Drain<'_, GreenElement>,
-        text_len: TextSize,
-        child_hash: u32,
-    ) -> GreenNode {
-        let head = GreenNodeHead {
-            kind,
-            text_len,
-            child_hash,
+        kind: S,
+        text: &str,
+        static_text: &str,
+        options: &mut TokenOptions<S>,
+    ) -> GreenToken {
+        if static_text != text {
+            options.policy.mismatch(kind, text, static_text);
+        }
+        self.token::<S>(kind, None, static_text)
+    }
+
+    /// Creates a token of the given `kind`, which has no static text, with the given `text` that was interned as
+    /// `key`.
+    #[inline]
+    fn keyed_token<S: Syntax>(
+        &mut self,
+        kind: S,
+        key: TokenKey,
+        text: &str,
+        options: &mut TokenOptions<S>,
+    ) -> GreenToken {
+        let data = GreenTokenData::from_text(S::into_raw(kind), Some(key), text);
+        self.keyed_token_from_data(kind, key, data, options)
+    }
+
+    /// Creates a token of the given `kind` from `data` that was computed from the text interned as `key`, which allows
+    /// callers to compute `data` from text borrowed from the interner.
+    #[inline]
+    #[allow(unused_variables)]
+    fn keyed_token_from_data<S: Syntax>(
+        &mut self,
+        kind: S,
+        key: TokenKey,
+        data: GreenTokenData,
+        options: &mut TokenOptions<S>,
+    ) -> GreenToken {
+        #[cfg(feature = "cooked_values")]
+        let data = data.with_cooked_key(self.cook(kind, key, options));
+        self.count(key, 1);
+        self.green.token(data)
+    }
+
+    /// Records `n` additional occurrences of `key` if this cache counts keys.
+    #[inline]
+    fn count(&mut self, key: TokenKey, n: usize) {
+        if let Some(counts) = &mut self.counts {
+            counts.add(key, n as u64);
+        }
+    }
+
+    /// Returns the key of the cooked value of the text interned as `key` for tokens of the given `kind`, if the
+    /// `options` have a cooker and it returns a value.
+    #[cfg(feature = "cooked_values")]
+    fn cook<S: Syntax>(&mut self, kind: S, key: TokenKey, options: &mut TokenOptions<S>) -> Option<TokenKey> {
+        let cooker = options.cooker?;
+        let class = (S::into_raw(kind), key);
+        if let Some(&cooked) = options.cooked.get(&class) {
+            return cooked;
+        }
+        let cooked = cooker(kind, self.interner.resolve(key)).map(|value| self.intern(&value));
+        options.cooked.insert(class, cooked);
+        cooked
+    }
+
+    /// Creates an error token of the given `kind` with the given `text` and error message.
+    #[cfg(feature = "error_messages")]
+    fn error_token<S: Syntax>(&mut self, kind: S, text: &str, message: u32) -> GreenToken {
+        let data = match S::static_text(kind) {
+            Some(static_text) => GreenTokenData::from_text(S::into_raw(kind), None, static_text),
+            None => {
+                let key = self.intern(text);
+                self.count(key, 1);
+                GreenTokenData::from_text(S::into_raw(kind), Some(key), text)
+            }
         };
-        self.nodes
-            .entry(head)
-            .or_insert_with_key(|head| GreenNode::from_head_and_children(head.clone(), children))
-            .clone()
+        self.green.token(data.with_error_message(message))
     }
 
-    fn token<S: Syntax>(&mut self, kind: S, text: Option<TokenKey>, len: u32) -> GreenToken {
-        let text_len = TextSize::from(len);
-        let kind = S::into_raw(kind);
-        let data = GreenTokenData { kind, text, text_len };
-        self.tokens
-            .entry(data)
-            .or_insert_with_key(|data| GreenToken::new(*data))
-            .clone()
+    fn token<S: Syntax>(&mut self, kind: S, key: Option<TokenKey>, text: &str) -> GreenToken {
+        self.green.token(GreenTokenData::from_text(S::into_raw(kind), key, text))
+    }
+}
+
+/// What a [`GreenNodeBuilder`] does when it is given a token whose kind has [static text](Syntax::static_text), but
+/// whose text differs from that static text.
+///
+/// Such a mismatch usually means that the lexer and the [`Syntax`] implementation disagree about a kind. The token is
+/// always added with its static text. By default, builders [panic](StaticTextPolicy::Panic) on mismatches if debug
+/// assertions are enabled and [ignore](StaticTextPolicy::Ignore) them otherwise. Use
+/// [`GreenNodeBuilder::set_static_text_policy`] to choose a different policy, for example to report mismatches in
+/// production without crashing.
+///
+/// # Examples
+/// ```
+/// # use cstree::testing::*;
+/// use cstree::build::StaticTextPolicy;
+/// use std::sync::{Arc, Mutex};
+///
+/// let mismatches = Arc::new(Mutex::new(Vec::new()));
+/// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+/// let log = Arc::clone(&mismatches);
+/// builder.set_static_text_policy(StaticTextPolicy::Warn(Box::new(move |kind, text| {
+///     log.lock().unwrap().push((kind, text.to_string()));
+/// })));
+/// builder.start_node(Root);
+/// builder.token(Int, "1");
+/// builder.token(Plus, "-");
+/// builder.token(Int, "2");
+/// builder.finish_node();
+/// let (tree, _) = builder.finish();
+/// assert_eq!(*mismatches.lock().unwrap(), [(Plus, "-".to_string())]);
+/// assert_eq!(tree.text_len(), 3.into());
+/// ```
+pub enum StaticTextPolicy<S> {
+    /// Silently use the static text.
+    Ignore,
+    /// Call the given function with the kind of the token and the text that was given for it.
+    #[allow(clippy::type_complexity)]
+    Warn(Box<dyn FnMut(S, &str) + Send + Sync>),
+    /// Panic with a message that describes the mismatch.
+    Panic,
+}
+
+impl<S: Syntax> StaticTextPolicy<S> {
+    fn mismatch(&mut self, kind: S, text: &str, static_text: &str) {
+        match self {
+            Self::Ignore => {}
+            Self::Warn(f) => f(kind, text),
+            Self::Panic => {
+                panic!(r#"Received `{kind:?}` token which should have text "{static_text}", but "{text}" was given."#)
+            }
+        }
+    }
+
+    fn is_ignore(&self) -> bool {
+        matches!(self, Self::Ignore)
+    }
+}
+
+impl<S> Default for StaticTextPolicy<S> {
+    fn default() -> Self {
+        if cfg!(debug_assertions) {
+            Self::Panic
+        } else {
+            Self::Ignore
+        }
+    }
+}
+
+impl<S> fmt::Debug for StaticTextPolicy<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ignore => f.write_str("Ignore"),
+            Self::Warn(_) => f.write_str("Warn(..)"),
+            Self::Panic => f.write_str("Panic"),
+        }
+    }
+}
+
+/// How [`GreenNodeBuilder::finish_node`] creates a node after its children were processed by a
+/// [node hook](GreenNodeBuilder::set_node_hook).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NodeCaching {
+    /// Create the node through the builder's [`GreenCache`], like nodes that are finished without a hook.
+    Cache,
+    /// Create a new node without looking it up in or adding it to the builder's [`GreenCache`].
+    Bypass,
+}
+
+/// A function that processes the children of every node before it is created, see
+/// [`GreenNodeBuilder::set_node_hook`].
+struct NodeHook<S, I>(Box<NodeHookFn<S, I>>);
+
+type NodeHookFn<S, I> = dyn FnMut(S, &mut Vec<GreenElement>, &mut I) -> NodeCaching + Send + Sync;
+
+impl<S, I> fmt::Debug for NodeHook<S, I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("NodeHook(..)")
+    }
+}
+
+/// The combined length and number of tokens of `elements`.
+fn text_and_tokens(elements: &[GreenElement]) -> (TextSize, usize) {
+    elements.iter().fold((TextSize::from(0), 0), |(len, tokens), element| {
+        let element_tokens = match element {
+            NodeOrToken::Node(node) => node.token_count() as usize,
+            NodeOrToken::Token(_) => 1,
+        };
+        (len + element.text_len(), tokens + element_tokens)
+    })
+}
+
+/// The settings of a [`GreenNodeBuilder`] for creating tokens from text.
+struct TokenOptions<S> {
+    policy:  StaticTextPolicy<S>,
+    #[cfg(feature = "cooked_values")]
+    cooker:  Option<fn(S, &str) -> Option<String>>,
+    /// The keys of the cooked values of all texts that were cooked so far, by the kind and key of the text.
+    #[cfg(feature = "cooked_values")]
+    cooked:  FxHashMap<(RawSyntaxKind, TokenKey), Option<TokenKey>>,
+}
+
+impl<S> Default for TokenOptions<S> {
+    fn default() -> Self {
+        Self {
+            policy:  StaticTextPolicy::default(),
+            #[cfg(feature = "cooked_values")]
+            cooker:  None,
+            #[cfg(feature = "cooked_values")]
+            cooked:  FxHashMap::default(),
+        }
+    }
+}
+
+impl<S> fmt::Debug for TokenOptions<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenOptions")
+            .field("policy", &self.policy)
+            .finish_non_exhaustive()
     }
 }
 
 /// A checkpoint for maybe wrapping a node. See [`GreenNodeBuilder::checkpoint`] for details.
+///
+/// In debug mode, checkpoints remember the builder and [generation](GreenNodeBuilder::reset) they were created in,
+/// so that using a checkpoint with a different builder, or after the builder was reset, panics instead of silently
+/// corrupting the structure of the tree.
 #[derive(Clone, Copy, Debug)]
-pub struct Checkpoint(usize);
+pub struct Checkpoint {
+    children:   usize,
+    offset:     TextSize,
+    tokens:     usize,
+    name:       Option<&'static str>,
+    #[cfg(debug_assertions)]
+    generation: u64,
+}
+
+impl Checkpoint {
+    /// The name given to this checkpoint by [`GreenNodeBuilder::named_checkpoint`], if any.
+    #[inline]
+    pub fn name(&self) -> Option<&'static str> {
+        self.name
+    }
+
+    /// Describes this checkpoint for panic messages.
+    fn context(&self) -> String {
+        match self.name {
+            Some(name) => format!(" (checkpoint `{name}`)"),
+            None => String::new(),
+        }
+    }
+}
+
+/// The source of the generations of [`GreenNodeBuilder`]s, which identify a builder between two resets in debug
+/// mode.
+#[cfg(debug_assertions)]
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+#[cfg(debug_assertions)]
+fn next_generation() -> u64 {
+    NEXT_GENERATION.fetch_add(1, Ordering::Relaxed)
+}
+
+/// A guard for a node started with [`GreenNodeBuilder::enter_node`], which finishes the node when dropped.
+///
+/// Dereferences to the [`GreenNodeBuilder`] the node belongs to.
+#[derive(Debug)]
+pub struct NodeGuard<
+    'b,
+    'cache,
+    'interner,
+    S: Syntax,
+    I: Interner<TokenKey> = TokenInterner,
+    C: GreenCache = DedupCache,
+> {
+    builder: &'b mut GreenNodeBuilder<'cache, 'interner, S, I, C>,
+    /// The number of open nodes when the guarded node was started, including the node itself.
+    depth:   usize,
+}
+
+impl<'cache, 'interner, S: Syntax, I: Interner<TokenKey>, C: GreenCache> NodeGuard<'_, 'cache, 'interner, S, I, C> {
+    /// Add the node's contents with `f`, then finish the node.
+    #[inline]
+    pub fn with<R>(mut self, f: impl FnOnce(&mut GreenNodeBuilder<'cache, 'interner, S, I, C>) -> R) -> R {
+        f(&mut self)
+    }
+}
+
+impl<'cache, 'interner, S: Syntax, I: Interner<TokenKey>, C: GreenCache> Deref
+    for NodeGuard<'_, 'cache, 'interner, S, I, C>
+{
+    type Target = GreenNodeBuilder<'cache, 'interner, S, I, C>;
+
+    fn deref(&self) -> &Self::Target {
+        self.builder
+    }
+}
+
+impl<S: Syntax, I: Interner<TokenKey>, C: GreenCache> DerefMut for NodeGuard<'_, '_, '_, S, I, C> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.builder
+    }
+}
+
+impl<S: Syntax, I: Interner<TokenKey>, C: GreenCache> Drop for NodeGuard<'_, '_, '_, S, I, C> {
+    fn drop(&mut self) {
+        while self.builder.parents.len() >= self.depth {
+            self.builder.finish_node();
+        }
+    }
+}
 
 /// A builder for green trees.
 /// Construct with [`new`](GreenNodeBuilder::new), [`with_cache`](GreenNodeBuilder::with_cache), or
@@ -269,19 +1375,41 @@ pub struct Checkpoint(usize);
 /// assert_eq!(int.as_token().unwrap().text(&resolver), Some("42"));
 /// ```
 #[derive(Debug)]
-pub struct GreenNodeBuilder<'cache, 'interner, S: Syntax, I = TokenInterner> {
-    cache:    MaybeOwned<'cache, NodeCache<'interner, I>>,
-    parents:  Vec<(S, usize)>,
-    children: Vec<GreenElement>,
+pub struct GreenNodeBuilder<'cache, 'interner, S: Syntax, I = TokenInterner, C = DedupCache> {
+    cache:      MaybeOwned<'cache, NodeCache<'interner, I, C>>,
+    parents:    Vec<(S, usize)>,
+    children:   Vec<GreenElement>,
+    /// The combined length of all tokens added so far.
+    offset:     TextSize,
+    /// The number of tokens added so far.
+    tokens:     usize,
+    options:    TokenOptions<S>,
+    hook:       Option<NodeHook<S, I>>,
+    /// Reused buffer for the children of nodes that are processed by `hook`.
+    hooked:     Vec<GreenElement>,
+    cancel:     Option<CancellationToken>,
+    schema:     Option<Arc<Schema<S>>>,
+    /// Identifies this builder and the number of times it was reset, to validate checkpoints.
+    #[cfg(debug_assertions)]
+    generation: u64,
 }
 
 impl<S: Syntax> GreenNodeBuilder<'static, 'static, S> {
     /// Creates new builder with an empty [`NodeCache`].
     pub fn new() -> Self {
         Self {
-            cache:    MaybeOwned::Owned(NodeCache::new()),
-            parents:  Vec::with_capacity(8),
-            children: Vec::with_capacity(8),
+            cache:      MaybeOwned::Owned(NodeCache::new()),
+            parents:    Vec::with_capacity(8),
+            children:   Vec::with_capacity(8),
+            offset:     0.into(),
+            tokens:     0,
+            options:    TokenOptions::default(),
+            hook:       None,
+            hooked:     Vec::new(),
+            cancel:     None,
+            schema:     None,
+            #[cfg(debug_assertions)]
+            generation: next_generation(),
         }
     }
 }
@@ -292,18 +1420,28 @@ impl<S: Syntax> Default for GreenNodeBuilder<'static, 'static, S> {
     }
 }
 
-impl<'cache, 'interner, S, I> GreenNodeBuilder<'cache, 'interner, S, I>
+impl<'cache, 'interner, S, I, C> GreenNodeBuilder<'cache, 'interner, S, I, C>
 where
     S: Syntax,
     I: Interner<TokenKey>,
+    C: GreenCache,
 {
     /// Reusing a [`NodeCache`] between multiple builders saves memory, as it allows to structurally
     /// share underlying trees.
-    pub fn with_cache(cache: &'cache mut NodeCache<'interner, I>) -> Self {
+    pub fn with_cache(cache: &'cache mut NodeCache<'interner, I, C>) -> Self {
         Self {
-            cache:    MaybeOwned::Borrowed(cache),
-            parents:  Vec::with_capacity(8),
-            children: Vec::with_capacity(8),
+            cache:      MaybeOwned::Borrowed(cache),
+            parents:    Vec::with_capacity(8),
+            children:   Vec::with_capacity(8),
+            offset:     0.into(),
+            tokens:     0,
+            options:    TokenOptions::default(),
+            hook:       None,
+            hooked:     Vec::new(),
+            cancel:     None,
+            schema:     None,
+            #[cfg(debug_assertions)]
+            generation: next_generation(),
         }
     }
 
@@ -332,14 +1470,29 @@ where
     /// assert_eq!(int.kind(), MySyntax::into_raw(Int));
     /// assert_eq!(int.as_token().unwrap().text(&interner), Some("42"));
     /// ```
-    pub fn from_cache(cache: NodeCache<'interner, I>) -> Self {
+    pub fn from_cache(cache: NodeCache<'interner, I, C>) -> Self {
         Self {
-            cache:    MaybeOwned::Owned(cache),
-            parents:  Vec::with_capacity(8),
-            children: Vec::with_capacity(8),
+            cache:      MaybeOwned::Owned(cache),
+            parents:    Vec::with_capacity(8),
+            children:   Vec::with_capacity(8),
+            offset:     0.into(),
+            tokens:     0,
+            options:    TokenOptions::default(),
+            hook:       None,
+            hooked:     Vec::new(),
+            cancel:     None,
+            schema:     None,
+            #[cfg(debug_assertions)]
+            generation: next_generation(),
         }
     }
+}
 
+impl<'cache, 'interner, S, I> GreenNodeBuilder<'cache, 'interner, S, I>
+where
+    S: Syntax,
+    I: Interner<TokenKey>,
+{
     /// Shortcut to construct a builder that uses an existing interner.
     ///
     /// This is equivalent to using [`from_cache`](GreenNodeBuilder::from_cache) with a node cache
@@ -359,7 +1512,14 @@ where
         let cache = NodeCache::from_interner(interner);
         Self::from_cache(cache)
     }
+}
 
+impl<'cache, 'interner, S, I, C> GreenNodeBuilder<'cache, 'interner, S, I, C>
+where
+    S: Syntax,
+    I: Interner<TokenKey>,
+    C: GreenCache,
+{
     /// Get a reference to the interner used to deduplicate source text (strings).
     ///
     /// This is the same interner as used by the underlying [`NodeCache`].
@@ -387,29 +1547,196 @@ where
         &mut self.cache.interner
     }
 
+    /// The [`StaticTextPolicy`] that determines what happens if a token is added whose text does not match the
+    /// static text of its kind.
+    #[inline]
+    pub fn static_text_policy(&self) -> &StaticTextPolicy<S> {
+        &self.options.policy
+    }
+
+    /// Set the [`StaticTextPolicy`] that determines what happens if a token is added whose text does not match the
+    /// static text of its kind.
+    #[inline]
+    pub fn set_static_text_policy(&mut self, policy: StaticTextPolicy<S>) {
+        self.options.policy = policy;
+    }
+
+    /// Set a function that computes the cooked value of tokens, such as the unescaped contents of string and
+    /// character literals, which is stored with the tokens added to this builder afterwards. The cooked value is
+    /// interned with the builder's interner and can be retrieved with [`GreenToken::cooked_text`] and
+    /// [`SyntaxToken::cooked_text`], so that consumers of the tree do not have to process escapes again.
+    ///
+    /// `cooker` is called with the kind and text of tokens without static text, and returns `None` for kinds that
+    /// have no cooked value or if the value of the token is its text. It is only called once for each combination
+    /// of a kind and a text.
+    ///
+    /// [`SyntaxToken::cooked_text`]: crate::syntax::SyntaxToken::cooked_text
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.set_cooker(|kind, text| match kind {
+    ///     Identifier if text.starts_with("r#") => Some(text[2..].to_string()),
+    ///     _ => None,
+    /// });
+    /// builder.start_node(Root);
+    /// builder.token(Identifier, "r#type");
+    /// builder.token(Identifier, "x");
+    /// builder.finish_node();
+    /// let (tree, cache) = builder.finish();
+    /// let interner = cache.unwrap().into_interner().unwrap();
+    /// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+    /// let mut tokens = root.children_with_tokens().map(|child| child.into_token().unwrap());
+    /// assert_eq!(tokens.next().unwrap().cooked_text(&interner), "type");
+    /// assert_eq!(tokens.next().unwrap().cooked_text(&interner), "x");
+    /// ```
+    #[cfg(feature = "cooked_values")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "cooked_values")))]
+    pub fn set_cooker(&mut self, cooker: fn(S, &str) -> Option<String>) {
+        self.options.cooker = Some(cooker);
+        self.options.cooked.clear();
+    }
+
     /// Add a new token with the given `text` to the current node.
     ///
+    /// If `kind` has [static text](Syntax::static_text), the token is added like with
+    /// [`static_token`](GreenNodeBuilder::static_token), without interning `text`. Parsers can therefore pass the
+    /// text of every token from the lexer to this method without checking for static text themselves. A `text` that
+    /// does not match the static text is handled according to the builder's [`StaticTextPolicy`].
+    ///
     /// ## Panics
-    /// In debug mode, if `kind` has static text, this function will verify that `text` matches that text.
+    /// If `kind` has static text that does not match `text` and the builder's [`StaticTextPolicy`] is
+    /// [`Panic`](StaticTextPolicy::Panic), which is the default in debug mode.
     #[inline]
     pub fn token(&mut self, kind: S, text: &str) {
-        let token = match S::static_text(kind) {
-            Some(static_text) => {
-                debug_assert_eq!(
-                    static_text, text,
-                    r#"Received `{kind:?}` token which should have text "{static_text}", but "{text}" was given."#
-                );
-                self.cache.token::<S>(kind, None, static_text.len() as u32)
-            }
-            None => {
-                let len = text.len() as u32;
-                let text = self.cache.intern(text);
-                self.cache.token::<S>(kind, Some(text), len)
-            }
-        };
+        let token = self.cache.text_token(kind, text, &mut self.options);
+        self.offset += token.text_len();
+        self.tokens += 1;
+        self.children.push(token.into());
+    }
+
+    /// Add a new [error token](Syntax::is_error_token) with the given `text` and the id of its error `message` to
+    /// the current node. The message id can be read back with
+    /// [`SyntaxToken::error_message`](crate::syntax::SyntaxToken::error_message).
+    ///
+    /// Since the message is part of the token, error tokens with the same text but different messages are not
+    /// deduplicated.
+    ///
+    /// ## Panics
+    /// If `kind` is not an error token kind.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// use cstree::text::TextRange;
+    ///
+    /// const UNTERMINATED_STRING: u32 = 1;
+    ///
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// builder.token(Identifier, "x");
+    /// builder.error_token(Error, "\"abc", UNTERMINATED_STRING);
+    /// builder.finish_node();
+    /// let (tree, _) = builder.finish();
+    /// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+    /// let errors: Vec<_> = root
+    ///     .error_tokens()
+    ///     .map(|token| (token.text_range(), token.error_message()))
+    ///     .collect();
+    /// assert_eq!(errors, [(TextRange::new(1.into(), 5.into()), Some(UNTERMINATED_STRING))]);
+    /// ```
+    #[cfg(feature = "error_messages")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "error_messages")))]
+    pub fn error_token(&mut self, kind: S, text: &str, message: u32) {
+        assert!(kind.is_error_token(), "`{}` is not an error token kind", crate::utility_types::KindName(kind));
+        let token = self.cache.error_token(kind, text, message);
+        self.offset += token.text_len();
+        self.tokens += 1;
         self.children.push(token.into());
     }
 
+    /// Add all `tokens` to the current node, in order.
+    ///
+    /// This is equivalent to calling [`token`](GreenNodeBuilder::token) for each `(kind, text)` pair, but interns the
+    /// texts of all tokens as a single batch with [`get_or_intern_many`](Interner::get_or_intern_many) and avoids
+    /// repeated bookkeeping, which makes it faster for a lexer that produces many tokens at once.
+    ///
+    /// ## Panics
+    /// If a `kind` has static text that does not match its `text` and the builder's [`StaticTextPolicy`] is
+    /// [`Panic`](StaticTextPolicy::Panic), which is the default in debug mode.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// builder.tokens(&[(Int, "1"), (Plus, "+"), (Int, "2")]);
+    /// builder.finish_node();
+    /// let (tree, _) = builder.finish();
+    /// assert_eq!(tree.children().count(), 3);
+    /// assert_eq!(tree.text_len(), 3.into());
+    /// ```
+    pub fn tokens(&mut self, tokens: &[(S, &str)]) {
+        let offset = &mut self.offset;
+        let children = &mut self.children;
+        self.tokens += tokens.len();
+        children.reserve(tokens.len());
+        self.cache.text_tokens(tokens, &mut self.options, |token| {
+            *offset += token.text_len();
+            children.push(token.into());
+        });
+    }
+
+    /// Add all `tokens` to the current node, in order, where the text of each token is given by a key that was
+    /// already interned with this builder's [`interner`](GreenNodeBuilder::interner).
+    ///
+    /// Kinds with static text are added with their static text. Unless the builder's [`StaticTextPolicy`] is
+    /// [`Ignore`](StaticTextPolicy::Ignore), their key is resolved and checked against the static text.
+    ///
+    /// ## Panics
+    /// If a key cannot be resolved by the interner, or if the text of a key does not match the static text of its
+    /// kind and the builder's [`StaticTextPolicy`] is [`Panic`](StaticTextPolicy::Panic).
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// # use cstree::interning::Interner;
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// let x = builder.interner_mut().get_or_intern("x");
+    /// builder.start_node(Root);
+    /// builder.tokens_with_keys(&[(Identifier, x), (Identifier, x)]);
+    /// builder.finish_node();
+    /// let (tree, _) = builder.finish();
+    /// assert_eq!(tree.text_len(), 2.into());
+    /// ```
+    pub fn tokens_with_keys(&mut self, tokens: &[(S, TokenKey)]) {
+        let cache = &mut *self.cache;
+        let options = &mut self.options;
+        let offset = &mut self.offset;
+        self.tokens += tokens.len();
+        self.children.reserve(tokens.len());
+        self.children.extend(tokens.iter().map(|&(kind, key)| {
+            let token = match S::static_text(kind) {
+                Some(static_text) => {
+                    if !options.policy.is_ignore() {
+                        let text = cache.interner.resolve(key);
+                        if text != static_text {
+                            options.policy.mismatch(kind, text, static_text);
+                        }
+                    }
+                    cache.token::<S>(kind, None, static_text)
+                }
+                None => {
+                    let data = GreenTokenData::from_text(S::into_raw(kind), Some(key), cache.interner.resolve(key));
+                    cache.keyed_token_from_data(kind, key, data, options)
+                }
+            };
+            *offset += token.text_len();
+            GreenElement::from(token)
+        }));
+    }
+
     /// Add a new token to the current node without storing an explicit section of text.
     /// This is be useful if the text can always be inferred from the token's `kind`, for example
     /// when using kinds for specific operators or punctuation.
@@ -422,26 +1749,380 @@ where
     #[inline]
     pub fn static_token(&mut self, kind: S) {
         let static_text = S::static_text(kind).unwrap_or_else(|| panic!("Missing static text for '{kind:?}'"));
-        let token = self.cache.token::<S>(kind, None, static_text.len() as u32);
+        let token = self.cache.token::<S>(kind, None, static_text);
+        self.offset += token.text_len();
+        self.tokens += 1;
         self.children.push(token.into());
     }
 
+    /// Add `n` tokens of the given `kind` to the current node, like calling
+    /// [`static_token`](GreenNodeBuilder::static_token) `n` times.
+    ///
+    /// ## Panics
+    /// If `kind` does not have static text, i.e., `L::static_text(kind)` returns `None`, or if the total length of the
+    /// `n` tokens does not fit into a `u32`.
+    #[inline]
+    pub fn static_token_n(&mut self, kind: S, n: usize) {
+        let static_text = S::static_text(kind).unwrap_or_else(|| panic!("Missing static text for '{kind:?}'"));
+        let token = self.cache.token::<S>(kind, None, static_text);
+        self.push_repeated(token, n);
+    }
+
+    /// Add `n` tokens of the given `kind` with the given `text` to the current node, like calling
+    /// [`token`](GreenNodeBuilder::token) `n` times.
+    ///
+    /// The token is only created and looked up in the cache once, and all `n` children share it. This is useful for
+    /// runs of identical tokens, such as the indentation levels of indentation-sensitive languages.
+    ///
+    /// ## Panics
+    /// If `kind` has static text that does not match `text` and the builder's [`StaticTextPolicy`] is
+    /// [`Panic`](StaticTextPolicy::Panic), which is the default in debug mode, or if the total length of the `n` tokens
+    /// does not fit into a `u32`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// builder.token_n(Whitespace, "    ", 3);
+    /// builder.token(Identifier, "x");
+    /// builder.static_token_n(Plus, 2);
+    /// builder.finish_node();
+    /// let (tree, _) = builder.finish();
+    /// assert_eq!(tree.children().count(), 6);
+    /// assert_eq!(tree.text_len(), 15.into());
+    /// ```
+    #[inline]
+    pub fn token_n(&mut self, kind: S, text: &str, n: usize) {
+        if n == 0 {
+            return;
+        }
+        let token = self.cache.text_token(kind, text, &mut self.options);
+        if let Some(key) = token.text_key() {
+            // `text_token` counted the first occurrence
+            self.cache.count(key, n - 1);
+        }
+        self.push_repeated(token, n);
+    }
+
+    fn push_repeated(&mut self, token: GreenToken, n: usize) {
+        let len = u32::try_from(n)
+            .ok()
+            .and_then(|n| u32::from(token.text_len()).checked_mul(n))
+            .expect("repeated token run exceeds u32 text length");
+        self.offset += TextSize::from(len);
+        self.tokens += n;
+        self.children.extend(std::iter::repeat(token).take(n).map(GreenElement::from));
+    }
+
     /// Start new node of the given `kind` and make it current.
+    ///
+    /// ## Panics
+    /// Throws [`Cancelled`] if the builder's [cancellation token](GreenNodeBuilder::set_cancellation_token) was
+    /// cancelled.
     #[inline]
     pub fn start_node(&mut self, kind: S) {
+        self.cancellation_point();
         let len = self.children.len();
         self.parents.push((kind, len));
     }
 
     /// Finish the current branch and restore the previous branch as current.
+    ///
+    /// If a [node hook](GreenNodeBuilder::set_node_hook) is set, it is called with the node's kind and children
+    /// before the node is created.
+    ///
+    /// ## Panics
+    /// Throws [`Cancelled`] if the builder's [cancellation token](GreenNodeBuilder::set_cancellation_token) was
+    /// cancelled.
     #[inline]
     pub fn finish_node(&mut self) {
+        self.finish_node_with_head(|head| head);
+    }
+
+    /// Finish the current branch like [`finish_node`](GreenNodeBuilder::finish_node), but create the node with the
+    /// given `kind` instead of the kind it was started with.
+    ///
+    /// This is useful if the kind of a node is only known after all of its children were parsed, like when telling a
+    /// parenthesized expression from a tuple, and avoids creating a [checkpoint](GreenNodeBuilder::checkpoint) for
+    /// it. The [node hook](GreenNodeBuilder::set_node_hook) and the [schema](GreenNodeBuilder::set_schema) see the
+    /// new kind.
+    ///
+    /// ## Panics
+    /// If there is no current branch, or if the builder's
+    /// [cancellation token](GreenNodeBuilder::set_cancellation_token) was cancelled.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// let tokens = [(Int, "1"), (Plus, "+"), (Int, "2")];
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// // the kind of the operand is not known yet
+    /// builder.start_node(Int);
+    /// builder.tokens(&tokens);
+    /// let is_operation = tokens.iter().any(|&(kind, _)| kind == Plus);
+    /// builder.finish_node_as(if is_operation { Operation } else { Int });
+    /// builder.finish_node();
+    /// let (tree, _) = builder.finish();
+    /// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+    /// assert_eq!(root.first_child().unwrap().kind(), Operation);
+    /// ```
+    #[inline]
+    pub fn finish_node_as(&mut self, kind: S) {
+        let (current, _) = self.parents.last_mut().expect("no node to finish");
+        *current = kind;
+        self.finish_node();
+    }
+
+    /// Finish the current branch like [`finish_node`](GreenNodeBuilder::finish_node), storing `payload` in the head
+    /// of the new node.
+    ///
+    /// The payload can be any small piece of per-node metadata, like the precedence of an operator or a set of flags,
+    /// and is read back with [`SyntaxNode::payload`](crate::syntax::SyntaxNode::payload). Since it is part of the
+    /// head, nodes are only deduplicated with nodes that have the same payload. Nodes finished with
+    /// [`finish_node`](GreenNodeBuilder::finish_node) have a payload of `0`.
+    ///
+    /// Payloads are kept when a tree is edited, but are not stored by the serialization formats of `cstree`.
+    ///
+    /// ## Panics
+    /// Throws [`Cancelled`] if the builder's [cancellation token](GreenNodeBuilder::set_cancellation_token) was
+    /// cancelled.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// builder.start_node(Operation);
+    /// builder.token(Int, "1");
+    /// builder.static_token(Plus);
+    /// builder.token(Int, "2");
+    /// builder.finish_node_with_payload(10);
+    /// builder.finish_node();
+    /// let (tree, _) = builder.finish();
+    /// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+    /// assert_eq!(root.payload(), 0);
+    /// assert_eq!(root.first_child().unwrap().payload(), 10);
+    /// ```
+    #[cfg(feature = "node_payloads")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "node_payloads")))]
+    #[inline]
+    pub fn finish_node_with_payload(&mut self, payload: u32) {
+        self.finish_node_with_head(|head| head.with_payload(payload));
+    }
+
+    #[inline]
+    fn finish_node_with_head(&mut self, finish_head: impl FnOnce(GreenNodeHead) -> GreenNodeHead) {
+        self.cancellation_point();
         let (kind, first_child) = self.parents.pop().unwrap();
-        // NOTE: we rely on the node cache to remove all children starting at `first_child` from `self.children`
-        let node = self.cache.node::<S>(kind, &mut self.children, first_child);
+        // The node starts where its first child started, which a hook cannot change.
+        let start = self.schema.as_ref().map(|_| {
+            let len: TextSize = self.children[first_child..].iter().map(GreenElement::text_len).sum();
+            self.offset - len
+        });
+        let node = match &mut self.hook {
+            // NOTE: we rely on the node cache to remove all children starting at `first_child` from `self.children`
+            None => self
+                .cache
+                .node::<S>(kind, &mut self.children, first_child, finish_head),
+            Some(hook) => {
+                let mut children = std::mem::take(&mut self.hooked);
+                children.extend(self.children.drain(first_child..));
+                let (len, tokens) = text_and_tokens(&children);
+                let caching = (hook.0)(kind, &mut children, &mut self.cache.interner);
+                let (new_len, new_tokens) = text_and_tokens(&children);
+                self.offset = self.offset - len + new_len;
+                self.tokens = self.tokens - tokens + new_tokens;
+                let node = match caching {
+                    NodeCaching::Cache => self.cache.node::<S>(kind, &mut children, 0, finish_head),
+                    NodeCaching::Bypass => {
+                        let head = GreenNodeHead::new(S::into_raw(kind), children.iter().map(NodeOrToken::as_ref));
+                        let head = finish_head(head);
+                        GreenNode::from_head_and_children(head, children.drain(..))
+                    }
+                };
+                self.hooked = children;
+                node
+            }
+        };
+        if let (Some(schema), Some(start)) = (&self.schema, start) {
+            // NOTE: no checks while unwinding, since a second panic in a `NodeGuard` drop would abort
+            if !std::thread::panicking() {
+                if let Some(violation) = schema.validate_green(&node, start) {
+                    panic!("node does not match the schema: {violation}");
+                }
+            }
+        }
         self.children.push(node.into());
     }
 
+    /// Set a function that is called by [`finish_node`](GreenNodeBuilder::finish_node) with the kind and the
+    /// children of every node before the node is created, replacing any previously set hook.
+    ///
+    /// The hook may modify the children, for example to normalize the tree while it is built instead of in a second
+    /// pass. New tokens can be created with the interner that is passed to the hook. Its return value determines
+    /// whether the node is created through the builder's [`GreenCache`] or bypasses it, which is useful for nodes
+    /// that are unlikely to be shared. If the hook changes the text of the node, [`text_since`] and
+    /// [`current_offset`] account for the new text.
+    ///
+    /// [`text_since`]: GreenNodeBuilder::text_since
+    /// [`current_offset`]: GreenNodeBuilder::current_offset
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// use cstree::{
+    ///     build::NodeCaching,
+    ///     green::{GreenToken, GreenTokenData},
+    ///     interning::Interner,
+    ///     util::NodeOrToken,
+    /// };
+    ///
+    /// // Merge adjacent whitespace tokens.
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.set_node_hook(|_kind, children, interner| {
+    ///     let mut merged: Vec<NodeOrToken<GreenNode, GreenToken>> = Vec::with_capacity(children.len());
+    ///     for child in children.drain(..) {
+    ///         let ws = MySyntax::into_raw(Whitespace);
+    ///         match (merged.last_mut(), &child) {
+    ///             (Some(NodeOrToken::Token(last)), NodeOrToken::Token(token))
+    ///                 if last.kind() == ws && token.kind() == ws =>
+    ///             {
+    ///                 let text = format!("{}{}", last.text(&*interner).unwrap(), token.text(&*interner).unwrap());
+    ///                 let key = interner.get_or_intern(&text);
+    ///                 *last = GreenToken::new(GreenTokenData::from_text(ws, Some(key), &text));
+    ///             }
+    ///             _ => merged.push(child),
+    ///         }
+    ///     }
+    ///     *children = merged;
+    ///     NodeCaching::Cache
+    /// });
+    /// builder.start_node(Root);
+    /// builder.token(Int, "1");
+    /// builder.token(Whitespace, " ");
+    /// builder.token(Whitespace, "\n");
+    /// builder.static_token(Plus);
+    /// builder.finish_node();
+    /// let (tree, _) = builder.finish();
+    /// assert_eq!(tree.children().count(), 3);
+    /// assert_eq!(tree.text_len(), 4.into());
+    /// ```
+    pub fn set_node_hook<F>(&mut self, hook: F)
+    where
+        F: FnMut(S, &mut Vec<NodeOrToken<GreenNode, GreenToken>>, &mut I) -> NodeCaching + Send + Sync + 'static,
+    {
+        self.hook = Some(NodeHook(Box::new(hook)));
+    }
+
+    /// Remove the hook that was set with [`set_node_hook`](GreenNodeBuilder::set_node_hook), if any.
+    #[inline]
+    pub fn remove_node_hook(&mut self) {
+        self.hook = None;
+    }
+
+    /// The kind of the node that is currently being built, i.e. the innermost node that was started but not yet
+    /// finished. Returns `None` if no node has been started.
+    #[inline]
+    pub fn current_node_kind(&self) -> Option<S> {
+        self.parents.last().map(|&(kind, _)| kind)
+    }
+
+    /// The number of nodes that have been started but not yet finished.
+    ///
+    /// This is `0` before the root node is started and after it has been finished.
+    #[inline]
+    pub fn depth(&self) -> usize {
+        self.parents.len()
+    }
+
+    /// The offset in the source text at which the next token will be added, which is the combined length of all
+    /// tokens added so far.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// builder.token(Int, "42");
+    /// builder.start_node(Operation);
+    /// assert_eq!(builder.current_node_kind(), Some(Operation));
+    /// assert_eq!(builder.depth(), 2);
+    /// assert_eq!(builder.current_offset(), 2.into());
+    /// ```
+    #[inline]
+    pub fn current_offset(&self) -> TextSize {
+        self.offset
+    }
+
+    /// Start a new node of the given `kind`, build its contents with `f` and finish it.
+    ///
+    /// The node is finished even if `f` returns early, for example with `?`. Any nodes started within `f` that were
+    /// left unfinished are finished as well, so the builder is always balanced after this call.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// # use cstree::build::GreenNodeBuilder;
+    /// fn parse_operation(
+    ///     builder: &mut GreenNodeBuilder<MySyntax>,
+    ///     input: &[&str],
+    /// ) -> Result<(), String> {
+    ///     builder.node(Operation, |builder| {
+    ///         for &part in input {
+    ///             match part {
+    ///                 "+" => builder.static_token(Plus),
+    ///                 _ if part.parse::<u32>().is_ok() => builder.token(Int, part),
+    ///                 _ => return Err(format!("unexpected `{part}`")),
+    ///             }
+    ///         }
+    ///         Ok(())
+    ///     })
+    /// }
+    ///
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// assert!(parse_operation(&mut builder, &["1", "+", "x"]).is_err());
+    /// builder.finish_node();
+    /// let (tree, _) = builder.finish();
+    /// let operation = tree.children().next().unwrap();
+    /// assert_eq!(operation.kind(), MySyntax::into_raw(Operation));
+    /// ```
+    #[inline]
+    pub fn node<R>(&mut self, kind: S, f: impl FnOnce(&mut Self) -> R) -> R {
+        self.enter_node(kind).with(f)
+    }
+
+    /// Start a new node of the given `kind` and return a guard that finishes it when dropped.
+    ///
+    /// The guard dereferences to this builder, so the node's contents can be added through it. When the guard is
+    /// dropped, including due to an early return or a panic, the node and any nodes started inside of it that were
+    /// left unfinished are finished.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// # use cstree::build::GreenNodeBuilder;
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// {
+    ///     let mut root = builder.enter_node(Root);
+    ///     root.token(Int, "1");
+    ///     // an unfinished node
+    ///     root.start_node(Operation);
+    ///     root.static_token(Plus);
+    /// }
+    /// let (tree, _) = builder.finish();
+    /// assert_eq!(tree.kind(), MySyntax::into_raw(Root));
+    /// assert_eq!(tree.children().count(), 2);
+    /// ```
+    #[inline]
+    pub fn enter_node(&mut self, kind: S) -> NodeGuard<'_, 'cache, 'interner, S, I, C> {
+        self.start_node(kind);
+        let depth = self.parents.len();
+        NodeGuard { builder: self, depth }
+    }
+
     /// Prepare for maybe wrapping the next node with a surrounding node.
     ///
     /// The way wrapping works is that you first get a checkpoint, then you add nodes and tokens as
@@ -469,27 +2150,306 @@ where
     /// ```
     #[inline]
     pub fn checkpoint(&self) -> Checkpoint {
-        Checkpoint(self.children.len())
+        Checkpoint {
+            children:   self.children.len(),
+            offset:     self.offset,
+            tokens:     self.tokens,
+            name:       None,
+            #[cfg(debug_assertions)]
+            generation: self.generation,
+        }
+    }
+
+    /// Like [`checkpoint`](GreenNodeBuilder::checkpoint), but tags the checkpoint with a `name`, which is included
+    /// in the message if using the checkpoint panics. This helps to find the production that misused a checkpoint in
+    /// large parsers.
+    ///
+    /// # Examples
+    /// ```should_panic
+    /// # use cstree::testing::*;
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// let checkpoint = builder.named_checkpoint("implied operator");
+    /// assert_eq!(checkpoint.name(), Some("implied operator"));
+    /// builder.token(Int, "1");
+    /// builder.start_node(Operation);
+    /// // panics with "checkpoint is not in the current node (checkpoint `implied operator`)"
+    /// builder.insert_token_at(checkpoint, Plus, "+");
+    /// ```
+    #[inline]
+    pub fn named_checkpoint(&self, name: &'static str) -> Checkpoint {
+        Checkpoint {
+            name: Some(name),
+            ..self.checkpoint()
+        }
+    }
+
+    /// Discard all nodes and tokens that were added to this builder, so that it can be used to build a new tree
+    /// with the same cache. The builder's settings, like its [`StaticTextPolicy`] and node hook, are kept.
+    ///
+    /// In debug mode, checkpoints that were created before the reset can no longer be used with this builder.
+    pub fn reset(&mut self) {
+        self.parents.clear();
+        self.children.clear();
+        self.offset = 0.into();
+        self.tokens = 0;
+        #[cfg(debug_assertions)]
+        {
+            self.generation = next_generation();
+        }
+    }
+
+    /// Sets a token that stops this builder when it is cancelled, replacing any previously set token. See
+    /// [`CancellationToken`].
+    pub fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self.cancel = Some(token);
+    }
+
+    /// Checks every node against `schema` when it is [finished](GreenNodeBuilder::finish_node), replacing any
+    /// previously set schema.
+    ///
+    /// If the children of a node do not match the shape of its kind, `finish_node` panics with the
+    /// [`Violation`](crate::schema::Violation), which includes the range of the offending child in the text built so
+    /// far. This catches parser bugs when the wrong node is built, with the parser's stack still available in the
+    /// backtrace, instead of when the tree is validated later. Since every node is checked, this is meant for debug
+    /// builds and tests, for example by only setting the schema if `cfg!(debug_assertions)`.
+    ///
+    /// Nodes are checked after a [node hook](GreenNodeBuilder::set_node_hook) has modified their children.
+    ///
+    /// # Examples
+    /// ```should_panic
+    /// # use cstree::testing::*;
+    /// # use std::sync::Arc;
+    /// use cstree::schema::{Child, Schema};
+    ///
+    /// let schema = Schema::new().shape(Operation, [Child::kind(Int), Child::kind(Plus), Child::kind(Int)]);
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.set_schema(Arc::new(schema));
+    /// builder.start_node(Root);
+    /// builder.start_node(Operation);
+    /// builder.token(Int, "1");
+    /// builder.static_token(Plus);
+    /// builder.token(Float, "2.5");
+    /// // panics with "node does not match the schema: `Operation` at 0..5 has unexpected `Float` at 2..5"
+    /// builder.finish_node();
+    /// ```
+    pub fn set_schema(&mut self, schema: Arc<Schema<S>>) {
+        self.schema = Some(schema);
+    }
+
+    /// Returns [`Cancelled`] if the builder's [cancellation token](GreenNodeBuilder::set_cancellation_token) was
+    /// cancelled.
+    ///
+    /// The builder already checks for cancellation whenever a node is started or finished. Parsers can call this in
+    /// loops that may add many tokens without creating nodes, and either return the error or
+    /// [throw](Cancelled::throw) it.
+    #[inline]
+    pub fn check_cancelled(&self) -> Result<(), Cancelled> {
+        self.cancel.as_ref().map_or(Ok(()), CancellationToken::check)
+    }
+
+    /// Throws [`Cancelled`] if the builder was cancelled, unless the thread is already unwinding, for example because
+    /// a [`NodeGuard`] finishes its node while a cancellation is thrown through it.
+    #[inline]
+    fn cancellation_point(&self) {
+        if self.check_cancelled().is_err() && !std::thread::panicking() {
+            Cancelled::throw();
+        }
+    }
+
+    /// In debug mode, panics if `checkpoint` was not created by this builder since it was last reset.
+    #[inline]
+    #[allow(unused_variables)]
+    fn validate(&self, checkpoint: &Checkpoint) {
+        #[cfg(debug_assertions)]
+        assert!(
+            checkpoint.generation == self.generation,
+            "checkpoint is from a different builder, or from before the builder was reset{}",
+            checkpoint.context()
+        );
+    }
+
+    /// The combined length of all tokens that were added since `checkpoint` was created.
+    ///
+    /// Parsers can use this to check whether a production consumed any input, for example to avoid looping forever
+    /// during error recovery.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// let checkpoint = builder.checkpoint();
+    /// assert_eq!(builder.text_since(checkpoint), 0.into());
+    /// builder.token(Int, "42");
+    /// builder.token(Whitespace, "");
+    /// assert_eq!(builder.text_since(checkpoint), 2.into());
+    /// assert_eq!(builder.tokens_since(checkpoint), 2);
+    /// ```
+    #[inline]
+    pub fn text_since(&self, checkpoint: Checkpoint) -> TextSize {
+        self.validate(&checkpoint);
+        self.offset - checkpoint.offset
+    }
+
+    /// The number of tokens that were added since `checkpoint` was created.
+    ///
+    /// Unlike [`text_since`](GreenNodeBuilder::text_since), this also counts tokens with empty text.
+    #[inline]
+    pub fn tokens_since(&self, checkpoint: Checkpoint) -> usize {
+        self.validate(&checkpoint);
+        self.tokens - checkpoint.tokens
+    }
+
+    /// The union of the FOLLOW sets in `sets` of all nodes that are currently open, which are the kinds of the tokens
+    /// at which error recovery should stop. See the [`recovery`](crate::recovery) module for an example.
+    pub fn recovery_set(&self, sets: &RecoverySets<S>) -> SyntaxKindSet<S> {
+        sets.recovery_set(self.parents.iter().map(|&(kind, _)| kind))
+    }
+
+    /// Skips the `tokens` before the first token whose kind is in `recovery`, adding them to a new node of kind
+    /// `error`. Returns the number of skipped tokens, which the parser should advance its position by.
+    ///
+    /// If the first token is in `recovery`, or if `tokens` is empty, no node is created and this returns `0`. If
+    /// none of the tokens is in `recovery`, all of them are skipped.
+    ///
+    /// ## Panics
+    /// Like [`tokens`](GreenNodeBuilder::tokens) and [`finish_node`](GreenNodeBuilder::finish_node).
+    pub fn recover(&mut self, error: S, tokens: &[(S, &str)], recovery: &SyntaxKindSet<S>) -> usize {
+        let skipped = tokens.iter().take_while(|&&(kind, _)| !recovery.contains(kind)).count();
+        if skipped > 0 {
+            self.start_node(error);
+            self.tokens(&tokens[..skipped]);
+            self.finish_node();
+        }
+        skipped
     }
 
     /// Wrap the previous branch marked by [`checkpoint`](GreenNodeBuilder::checkpoint) in a new
     /// branch and make it current.
     #[inline]
     pub fn start_node_at(&mut self, checkpoint: Checkpoint, kind: S) {
-        let Checkpoint(checkpoint) = checkpoint;
+        self.validate(&checkpoint);
+        let index = checkpoint.children;
         assert!(
-            checkpoint <= self.children.len(),
-            "checkpoint no longer valid, was finish_node called early?"
+            index <= self.children.len(),
+            "checkpoint no longer valid, was finish_node called early?{}",
+            checkpoint.context()
         );
 
         if let Some(&(_, first_child)) = self.parents.last() {
             assert!(
-                checkpoint >= first_child,
-                "checkpoint no longer valid, was an unmatched start_node_at called?"
+                index >= first_child,
+                "checkpoint no longer valid, was an unmatched start_node_at called?{}",
+                checkpoint.context()
+            );
+        }
+
+        self.parents.push((kind, index));
+    }
+
+    /// Insert a token with the given `text` into the current node at the position marked by `checkpoint`, before all
+    /// elements that were added since the checkpoint was created.
+    ///
+    /// This allows a parser to add a token that it only later finds to be needed, such as an inferred separator,
+    /// without restructuring the order in which it adds the other elements. `checkpoint` must have been created while
+    /// building the current node.
+    ///
+    /// Since the inserted token moves all elements after it, checkpoints that were created after `checkpoint` in the
+    /// current node no longer mark the same position.
+    ///
+    /// Like with [`token`](GreenNodeBuilder::token), a `text` that does not match the [static
+    /// text](Syntax::static_text) of `kind` is handled according to the builder's [`StaticTextPolicy`].
+    ///
+    /// ## Panics
+    /// If `checkpoint` is not in the current node, or if `kind` has static text that does not match `text` and the
+    /// builder's [`StaticTextPolicy`] is [`Panic`](StaticTextPolicy::Panic), which is the default in debug mode.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// builder.token(Int, "1");
+    /// let checkpoint = builder.checkpoint();
+    /// builder.token(Int, "2");
+    /// // turns out the `Plus` was implied
+    /// builder.insert_token_at(checkpoint, Plus, "+");
+    /// builder.finish_node();
+    /// let (tree, _) = builder.finish();
+    /// let kinds: Vec<_> = tree.children().map(|child| child.kind()).collect();
+    /// assert_eq!(kinds, [Int, Plus, Int].map(MySyntax::into_raw));
+    /// assert_eq!(tree.text_len(), 3.into());
+    /// ```
+    #[inline]
+    pub fn insert_token_at(&mut self, checkpoint: Checkpoint, kind: S, text: &str) {
+        let token = self.cache.text_token(kind, text, &mut self.options);
+        self.insert_at(checkpoint, token.into(), 1);
+    }
+
+    /// Insert an existing green `node` into the current node at the position marked by `checkpoint`, before all
+    /// elements that were added since the checkpoint was created.
+    ///
+    /// See [`insert_token_at`](GreenNodeBuilder::insert_token_at) for details. The text of the tokens in `node` must
+    /// be resolvable by this builder's interner.
+    ///
+    /// ## Panics
+    /// If `checkpoint` is not in the current node.
+    pub fn insert_node_at(&mut self, checkpoint: Checkpoint, node: GreenNode) {
+        let tokens = node.token_count() as usize;
+        self.insert_at(checkpoint, node.into(), tokens);
+    }
+
+    fn insert_at(&mut self, checkpoint: Checkpoint, element: GreenElement, tokens: usize) {
+        self.validate(&checkpoint);
+        let index = checkpoint.children;
+        assert!(
+            index <= self.children.len(),
+            "checkpoint no longer valid, was finish_node called early?{}",
+            checkpoint.context()
+        );
+        if let Some(&(_, first_child)) = self.parents.last() {
+            assert!(
+                index >= first_child,
+                "checkpoint is not in the current node{}",
+                checkpoint.context()
             );
         }
+        self.offset += element.text_len();
+        self.tokens += tokens;
+        self.children.insert(index, element);
+    }
 
-        self.parents.push((kind, checkpoint));
+    /// Wrap everything that was built so far in a new node of the given `kind`, which becomes the new root.
+    ///
+    /// This lets frontends that parse the parts of a file first add a root for the whole file afterwards. Call it
+    /// after all nodes have been finished and before [`finish`](GreenNodeBuilder::finish). For finished trees, see
+    /// [`GreenNode::wrap_in`].
+    ///
+    /// ## Panics
+    /// If there are nodes that were started, but not finished.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Operation);
+    /// builder.token(Int, "1");
+    /// builder.finish_node();
+    /// builder.token(Whitespace, " ");
+    /// builder.wrap_root(Root);
+    /// let (tree, _) = builder.finish();
+    /// assert_eq!(tree.kind(), Root.into_raw());
+    /// assert_eq!(tree.children().count(), 2);
+    /// ```
+    #[inline]
+    pub fn wrap_root(&mut self, kind: S) {
+        assert!(
+            self.parents.is_empty(),
+            "called `wrap_root` on a `GreenNodeBuilder` with unfinished nodes"
+        );
+        self.parents.push((kind, 0));
+        self.finish_node();
     }
 
     /// Complete building the tree.
@@ -504,7 +2464,7 @@ where
     ///  as its second return value to allow re-using the cache or extracting the underlying string
     ///  [`Interner`]. See also [`NodeCache::into_interner`].
     #[inline]
-    pub fn finish(mut self) -> (GreenNode, Option<NodeCache<'interner, I>>) {
+    pub fn finish(mut self) -> (GreenNode, Option<NodeCache<'interner, I, C>>) {
         assert_eq!(self.children.len(), 1);
         let cache = self.cache.into_owned();
         match self.children.pop().unwrap() {
@@ -512,4 +2472,54 @@ where
             NodeOrToken::Token(_) => panic!("called `finish` on a `GreenNodeBuilder` which only contained a token"),
         }
     }
+
+    /// Builds a flat tree with a single node of kind `root` that contains all `tokens` in order, and completes the
+    /// tree like [`finish`](GreenNodeBuilder::finish).
+    ///
+    /// This is a fast path for tools that only need a lossless stream of tokens with their ranges, like syntax
+    /// highlighters or simple formatters, which can pass the output of a lexer directly to this method. Tokens are
+    /// created and interned like with [`token`](GreenNodeBuilder::token), but without tracking any nodes while they
+    /// are added. A [node hook](GreenNodeBuilder::set_node_hook) is called for the root node.
+    ///
+    /// ## Panics
+    /// If any elements were already added to this builder, or if a `kind` has static text that does not match its
+    /// `text` and the builder's [`StaticTextPolicy`] is [`Panic`](StaticTextPolicy::Panic).
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// use cstree::text::TextRange;
+    ///
+    /// let lexer = [(Int, "1"), (Whitespace, " "), (Plus, "+"), (Whitespace, " "), (Int, "2")].into_iter();
+    /// let builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// let (tree, cache) = builder.build_flat(Root, lexer);
+    /// let interner = cache.unwrap().into_interner().unwrap();
+    /// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+    /// let tokens: Vec<_> = root.tokens().map(|token| (token.kind(), token.text_range())).collect();
+    /// assert_eq!(tokens.len(), 5);
+    /// assert_eq!(tokens[2], (Plus, TextRange::new(2.into(), 3.into())));
+    /// assert_eq!(root.resolve_text(&interner), "1 + 2");
+    /// ```
+    pub fn build_flat<'t>(
+        mut self,
+        root: S,
+        tokens: impl IntoIterator<Item = (S, &'t str)>,
+    ) -> (GreenNode, Option<NodeCache<'interner, I, C>>) {
+        assert!(
+            self.parents.is_empty() && self.children.is_empty(),
+            "called `build_flat` on a `GreenNodeBuilder` that already contains elements"
+        );
+        let cache = &mut *self.cache;
+        let options = &mut self.options;
+        let offset = &mut self.offset;
+        self.children.extend(tokens.into_iter().map(|(kind, text)| {
+            let token = cache.text_token(kind, text, options);
+            *offset += token.text_len();
+            GreenElement::from(token)
+        }));
+        self.tokens = self.children.len();
+        self.parents.push((root, 0));
+        self.finish_node();
+        self.finish()
+    }
 }
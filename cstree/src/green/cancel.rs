@@ -0,0 +1,117 @@
+//! Cooperative cancellation of tree building.
+
+use std::{
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+/// A flag that tells a [`GreenNodeBuilder`] to stop building its tree, for example because a language server received
+/// a new version of the document that is being parsed.
+///
+/// Tokens are cheap to clone, and all clones share the same flag, so one clone can be given to the builder with
+/// [`GreenNodeBuilder::set_cancellation_token`] while another one is kept to [`cancel`](CancellationToken::cancel)
+/// the build from a different thread. Once the token is cancelled, the builder [throws](Cancelled::throw) the next
+/// time it starts or finishes a node, which unwinds out of the parser without changing any of its code. Parsers
+/// that run for a long time without creating nodes can check for cancellation themselves with
+/// [`GreenNodeBuilder::check_cancelled`]. The parse is run with [`Cancelled::catch`] to turn the unwinding into a
+/// [`Cancelled`] error.
+///
+/// [`GreenNodeBuilder`]: crate::build::GreenNodeBuilder
+/// [`GreenNodeBuilder::set_cancellation_token`]: crate::build::GreenNodeBuilder::set_cancellation_token
+/// [`GreenNodeBuilder::check_cancelled`]: crate::build::GreenNodeBuilder::check_cancelled
+///
+/// # Examples
+/// ```
+/// # use cstree::testing::*;
+/// use cstree::build::{CancellationToken, Cancelled};
+///
+/// let token = CancellationToken::new();
+/// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+/// builder.set_cancellation_token(token.clone());
+///
+/// let result = Cancelled::catch(|| {
+///     builder.start_node(Root);
+///     for i in 0.. {
+///         if i == 100 {
+///             // The document changed, for example on another thread.
+///             token.cancel();
+///         }
+///         builder.start_node(Operation);
+///         builder.token(Int, "1");
+///         builder.finish_node();
+///     }
+/// });
+/// assert_eq!(result, Err(Cancelled));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// Creates a token that is not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels this token and all of its clones.
+    #[inline]
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns `true` if this token or one of its clones was cancelled.
+    #[inline]
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Returns [`Cancelled`] if this token was cancelled.
+    #[inline]
+    pub fn check(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            Err(Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// The error of a build that was stopped by a [`CancellationToken`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Cancelled;
+
+impl Cancelled {
+    /// Unwinds the stack with `Cancelled` as the payload, to be caught by [`catch`](Cancelled::catch).
+    ///
+    /// Unlike a panic, this does not call the panic hook, so nothing is printed.
+    pub fn throw() -> ! {
+        panic::resume_unwind(Box::new(Cancelled))
+    }
+
+    /// Runs `f` and returns its result, or `Err(Cancelled)` if it was [thrown](Cancelled::throw) by `f`. Other panics
+    /// are propagated.
+    ///
+    /// Since cancellation unwinds the stack, it does not work if the program is compiled with `panic = "abort"`.
+    pub fn catch<T>(f: impl FnOnce() -> T) -> Result<T, Cancelled> {
+        match panic::catch_unwind(AssertUnwindSafe(f)) {
+            Ok(result) => Ok(result),
+            Err(payload) => match payload.downcast::<Cancelled>() {
+                Ok(_) => Err(Cancelled),
+                Err(payload) => panic::resume_unwind(payload),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "building the tree was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
@@ -75,6 +75,14 @@ impl GreenElement {
     pub fn text_len(&self) -> TextSize {
         self.as_ref().text_len()
     }
+
+    /// Returns the number of newlines (`\n`) in the text covered by this element.
+    #[cfg(feature = "line_counts")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "line_counts")))]
+    #[inline]
+    pub fn newline_count(&self) -> u32 {
+        self.as_ref().newline_count()
+    }
 }
 
 impl GreenElementRef<'_> {
@@ -95,6 +103,17 @@ impl GreenElementRef<'_> {
             NodeOrToken::Token(it) => it.text_len(),
         }
     }
+
+    /// Returns the number of newlines (`\n`) in the text covered by this element.
+    #[cfg(feature = "line_counts")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "line_counts")))]
+    #[inline]
+    pub fn newline_count(self) -> u32 {
+        match self {
+            NodeOrToken::Node(it) => it.newline_count(),
+            NodeOrToken::Token(it) => it.newline_count(),
+        }
+    }
 }
 
 impl From<GreenElement> for PackedGreenElement {
@@ -118,7 +137,7 @@ impl From<PackedGreenElement> for GreenElement {
 
 impl PackedGreenElement {
     pub(crate) fn is_node(&self) -> bool {
-        self.ptr.addr() & super::token::IS_TOKEN_TAG == 0
+        Strict::addr(self.ptr) & super::token::IS_TOKEN_TAG == 0
     }
 
     pub(crate) fn as_node(&self) -> Option<&GreenNode> {
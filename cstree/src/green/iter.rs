@@ -2,6 +2,8 @@
 
 use std::{iter::FusedIterator, slice};
 
+use text_size::TextSize;
+
 use super::{element::PackedGreenElement, GreenElementRef};
 
 /// An iterator over a [`GreenNode`](crate::green::GreenNode)'s children.
@@ -90,3 +92,49 @@ impl<'a> DoubleEndedIterator for GreenNodeChildren<'a> {
 }
 
 impl FusedIterator for GreenNodeChildren<'_> {}
+
+/// An iterator over the children of a [`GreenNode`](crate::green::GreenNode) with their offsets relative to the
+/// start of the node, returned by [`GreenNode::children_from_offset`](crate::green::GreenNode::children_from_offset).
+///
+/// The iterator can be kept to continue scanning from where it stopped, and [`offset`](GreenChildrenFrom::offset)
+/// tells where the next child starts.
+#[derive(Debug, Clone)]
+pub struct GreenChildrenFrom<'a> {
+    pub(super) inner:  GreenNodeChildren<'a>,
+    pub(super) offset: TextSize,
+}
+
+impl GreenChildrenFrom<'_> {
+    /// The offset of the next child relative to the start of the node, or the length of the node if all children
+    /// were visited.
+    #[inline]
+    pub fn offset(&self) -> TextSize {
+        self.offset
+    }
+}
+
+impl ExactSizeIterator for GreenChildrenFrom<'_> {
+    #[inline(always)]
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+}
+
+impl<'a> Iterator for GreenChildrenFrom<'a> {
+    type Item = (GreenElementRef<'a>, TextSize);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let child = self.inner.next()?;
+        let offset = self.offset;
+        self.offset += child.text_len();
+        Some((child, offset))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl FusedIterator for GreenChildrenFrom<'_> {}
@@ -1,23 +1,249 @@
 use std::{
+    fmt,
     hash::{Hash, Hasher},
+    marker::PhantomData,
     slice,
 };
 
-use fxhash::FxHasher32;
+use fxhash::FxHashMap;
 
 use crate::{
-    green::{iter::GreenNodeChildren, GreenElement, PackedGreenElement},
-    text::TextSize,
-    RawSyntaxKind,
+    build::{GreenCache, NodeCache},
+    green::{
+        iter::{GreenChildrenFrom, GreenNodeChildren},
+        GreenElement, GreenElementRef, GreenToken, GreenTokenData, PackedGreenElement,
+    },
+    interning::{Interner, KeyMap, Resolver, TokenKey},
+    text::{TextRange, TextSize},
+    util::NodeOrToken,
+    utility_types::KindName,
+    RawSyntaxKind, Syntax,
 };
 use triomphe::{Arc, HeaderWithLength, ThinArc};
 
+/// The type of the hash of the children of a green node that is stored in its head.
+#[cfg(not(feature = "wide_child_hash"))]
+pub(super) type ChildHash = u32;
+#[cfg(feature = "wide_child_hash")]
+pub(super) type ChildHash = u64;
+
+#[cfg(not(feature = "wide_child_hash"))]
+type ChildHasher = fxhash::FxHasher32;
+#[cfg(feature = "wide_child_hash")]
+type ChildHasher = fxhash::FxHasher64;
+
+/// The header of a [`GreenNode`], which identifies the node by its kind and a hash of its children.
+///
+/// Two nodes with equal heads are very likely, though not guaranteed, to be equal. The [`NodeCache`] uses this
+/// to find identical nodes without comparing their children, and embedders can do the same to implement their own
+/// caching of green nodes with [`GreenNode::from_head_and_children`]. Since the hash of the children is computed
+/// from the contents of the children (and not, for example, their addresses), it is the same for equal nodes
+/// across trees and caches.
+///
+/// With the `subtree_counts` feature, the head also records how many tokens and nodes the subtree of the node
+/// contains, and, with the `line_counts` feature, how many newlines its text contains. With the `key_filters` feature,
+/// it contains a small Bloom filter of the keys of the tokens in the subtree of the node (see
+/// [`may_contain_key`](GreenNodeHead::may_contain_key)). With the `node_payloads` feature, it carries a small
+/// [payload](GreenNodeHead::payload) that is given to the builder.
+///
+/// [`NodeCache`]: crate::build::NodeCache
 #[repr(align(2))] //to use 1 bit for pointer tagging. NB: this is an at-least annotation
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub(super) struct GreenNodeHead {
-    pub(super) kind:       RawSyntaxKind,
-    pub(super) text_len:   TextSize,
-    pub(super) child_hash: u32,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GreenNodeHead {
+    pub(super) kind:        RawSyntaxKind,
+    pub(super) text_len:    TextSize,
+    pub(super) child_hash:  ChildHash,
+    #[cfg(feature = "subtree_counts")]
+    pub(super) token_count: u32,
+    #[cfg(feature = "subtree_counts")]
+    pub(super) node_count:  u32,
+    #[cfg(feature = "line_counts")]
+    pub(super) newlines:    u32,
+    #[cfg(feature = "key_filters")]
+    pub(super) key_filter:  u64,
+    #[cfg(feature = "node_payloads")]
+    pub(super) payload:     u32,
+}
+
+/// Accumulates the parts of a [`GreenNodeHead`] that are computed from the children of a node.
+#[derive(Default)]
+struct HeadBuilder {
+    hasher:      ChildHasher,
+    text_len:    TextSize,
+    #[cfg(feature = "subtree_counts")]
+    token_count: u32,
+    #[cfg(feature = "subtree_counts")]
+    node_count:  u32,
+    #[cfg(feature = "line_counts")]
+    newlines:    u32,
+    #[cfg(feature = "key_filters")]
+    key_filter:  u64,
+}
+
+impl HeadBuilder {
+    fn add(&mut self, child: GreenElementRef<'_>) {
+        self.text_len += child.text_len();
+        child.hash(&mut self.hasher);
+        #[cfg(feature = "subtree_counts")]
+        {
+            let (tokens, nodes) = match child {
+                NodeOrToken::Node(node) => (node.token_count(), node.node_count()),
+                NodeOrToken::Token(_) => (1, 0),
+            };
+            self.token_count = add_count(self.token_count, tokens, "tokens");
+            self.node_count = add_count(self.node_count, nodes, "nodes");
+        }
+        #[cfg(feature = "line_counts")]
+        {
+            self.newlines += child.newline_count();
+        }
+        #[cfg(feature = "key_filters")]
+        {
+            self.key_filter |= match child {
+                NodeOrToken::Node(node) => node.head().key_filter,
+                NodeOrToken::Token(token) => token.text_key().map_or(0, key_filter_bits),
+            };
+        }
+    }
+
+    fn finish(self, kind: RawSyntaxKind) -> GreenNodeHead {
+        GreenNodeHead {
+            kind,
+            text_len: self.text_len,
+            child_hash: self.hasher.finish() as ChildHash,
+            #[cfg(feature = "subtree_counts")]
+            token_count: self.token_count,
+            #[cfg(feature = "subtree_counts")]
+            node_count: add_count(self.node_count, 1, "nodes"),
+            #[cfg(feature = "line_counts")]
+            newlines: self.newlines,
+            #[cfg(feature = "key_filters")]
+            key_filter: self.key_filter,
+            #[cfg(feature = "node_payloads")]
+            payload: 0,
+        }
+    }
+}
+
+/// Adds `more` to the number of `what` in a subtree. Since green nodes can share subtrees, the count can exceed the
+/// size of the tree in memory.
+fn add_count(count: u32, more: u32, what: &str) -> u32 {
+    count
+        .checked_add(more)
+        .unwrap_or_else(|| panic!("number of {what} in the subtree of a green node exceeds u32"))
+}
+
+impl GreenNodeHead {
+    /// Computes the head of a node of the given `kind` with the given `children`.
+    pub fn new<'a>(kind: RawSyntaxKind, children: impl IntoIterator<Item = GreenElementRef<'a>>) -> Self {
+        let mut head = HeadBuilder::default();
+        for child in children {
+            head.add(child);
+        }
+        head.finish(kind)
+    }
+
+    /// Computes the head of a node of the given `kind` with the given `children`, keeping the payload of this head.
+    fn derive<'a>(&self, kind: RawSyntaxKind, children: impl IntoIterator<Item = GreenElementRef<'a>>) -> Self {
+        GreenNodeHead {
+            #[cfg(feature = "node_payloads")]
+            payload: self.payload,
+            ..GreenNodeHead::new(kind, children)
+        }
+    }
+
+    /// [`RawSyntaxKind`] of the node.
+    #[inline]
+    pub fn kind(&self) -> RawSyntaxKind {
+        self.kind
+    }
+
+    /// The length of text covered by the node.
+    #[inline]
+    pub fn text_len(&self) -> TextSize {
+        self.text_len
+    }
+
+    /// A hash of the children of the node.
+    ///
+    /// The head only stores 32 bits of the hash by default, which keeps it small, and this returns them zero-extended.
+    /// Caches look up nodes by their head, so if the hashes of the children of different nodes collide, the
+    /// [`GreenCache`]s provided by `cstree` have to compare the children of all cached nodes with that head. Collisions
+    /// become frequent in caches that hold millions of nodes of the same kinds, so with the `wide_child_hash` feature
+    /// the head stores all 64 bits instead.
+    #[inline]
+    pub fn child_hash(&self) -> u64 {
+        #[cfg(not(feature = "wide_child_hash"))]
+        return u64::from(self.child_hash);
+        #[cfg(feature = "wide_child_hash")]
+        return self.child_hash;
+    }
+
+    /// The number of tokens in the subtree of the node.
+    #[cfg(feature = "subtree_counts")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "subtree_counts")))]
+    #[inline]
+    pub fn token_count(&self) -> u32 {
+        self.token_count
+    }
+
+    /// The number of nodes in the subtree of the node, including the node itself.
+    #[cfg(feature = "subtree_counts")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "subtree_counts")))]
+    #[inline]
+    pub fn node_count(&self) -> u32 {
+        self.node_count
+    }
+
+    /// The number of newlines (`\n`) in the text of the node.
+    #[cfg(feature = "line_counts")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "line_counts")))]
+    #[inline]
+    pub fn newline_count(&self) -> u32 {
+        self.newlines
+    }
+
+    /// Returns `false` if no token in the subtree of the node has the text `key`. If this returns `true`, such a
+    /// token may or may not exist.
+    ///
+    /// The head stores a Bloom filter with 64 bits that sets two bits for each interned text of a token in the
+    /// subtree. Tokens with [static text](crate::Syntax::static_text) are not included, since they have no key.
+    #[cfg(feature = "key_filters")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "key_filters")))]
+    #[inline]
+    pub fn may_contain_key(&self, key: TokenKey) -> bool {
+        let bits = key_filter_bits(key);
+        self.key_filter & bits == bits
+    }
+
+    /// The payload of the node, which is `0` unless the node was finished with
+    /// [`finish_node_with_payload`](crate::build::GreenNodeBuilder::finish_node_with_payload).
+    #[cfg(feature = "node_payloads")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "node_payloads")))]
+    #[inline]
+    pub fn payload(&self) -> u32 {
+        self.payload
+    }
+
+    /// Returns a copy of this head with the given `payload`.
+    ///
+    /// Since the payload is part of the head, nodes that only differ in their payload are not deduplicated.
+    #[cfg(feature = "node_payloads")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "node_payloads")))]
+    #[inline]
+    pub fn with_payload(self, payload: u32) -> Self {
+        Self { payload, ..self }
+    }
+}
+
+/// The bits that are set in the [key filter](GreenNodeHead::may_contain_key) of a node for a token with text `key`.
+#[cfg(feature = "key_filters")]
+#[inline]
+fn key_filter_bits(key: TokenKey) -> u64 {
+    use crate::interning::InternKey;
+
+    let hash = (key.into_u32() as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    (1 << (hash >> 58)) | (1 << ((hash >> 52) & 63))
 }
 
 /// Internal node in the immutable "green" tree.
@@ -41,79 +267,654 @@ impl GreenNode {
         I: IntoIterator<Item = GreenElement>,
         I::IntoIter: ExactSizeIterator,
     {
-        let mut hasher = FxHasher32::default();
-        let mut text_len: TextSize = 0.into();
+        let mut head = HeadBuilder::default();
         let children = children
             .into_iter()
-            .inspect(|it| {
-                text_len += it.text_len();
-                it.hash(&mut hasher);
-            })
+            .inspect(|it| head.add(it.as_ref()))
             .map(PackedGreenElement::from);
-        let header = HeaderWithLength::new(
-            GreenNodeHead {
-                kind,
-                text_len: 0.into(),
-                child_hash: 0,
-            },
-            children.len(),
-        );
+        let header = HeaderWithLength::new(HeadBuilder::default().finish(kind), children.len());
         let mut data = Arc::from_header_and_iter(header, children);
 
-        // XXX: fixup `text_len` and `child_hash` after construction, because
+        // XXX: fixup the parts of the head that depend on the children after construction, because
         // we can't iterate `children` twice.
-        let header = &mut Arc::get_mut(&mut data).unwrap().header.header;
-        header.text_len = text_len;
-        header.child_hash = hasher.finish() as u32;
+        Arc::get_mut(&mut data).unwrap().header.header = head.finish(kind);
         GreenNode {
             data: Arc::into_thin(data),
         }
     }
 
-    /// Creates a new Node.
+    /// Creates a new node of the given `kind` with the given `children`, outside of a
+    /// [`GreenNodeBuilder`](crate::build::GreenNodeBuilder). This is useful to synthesize nodes for edits and
+    /// refactorings, together with [`GreenToken::new_detached`].
+    ///
+    /// The children are shared with the new node, but the node itself is not deduplicated with equal nodes, like
+    /// nodes created with [`new`](GreenNode::new).
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// use cstree::interning::new_interner;
+    ///
+    /// let mut interner = new_interner();
+    /// let one = GreenToken::new_detached(Int, "1", &mut interner);
+    /// let plus = GreenToken::new_detached(Plus, "+", &mut interner);
+    /// let operation = GreenNode::new_detached(Operation, [one.clone().into(), plus.into(), one.into()]);
+    /// let root = GreenNode::new_detached(Root, [operation.into()]);
+    /// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(root);
+    /// assert_eq!(root.resolve_text(&interner), "1+1");
+    /// ```
+    pub fn new_detached<S: Syntax>(kind: S, children: impl IntoIterator<Item = GreenElement>) -> GreenNode {
+        let children: Vec<_> = children.into_iter().collect();
+        GreenNode::new(S::into_raw(kind), children)
+    }
+
+    /// Creates a new node from its `head` and its `children`, without computing the head again.
+    ///
+    /// This is meant for custom caches of green nodes, which compute the [`GreenNodeHead`] of a node to look it up
+    /// before creating it.
+    ///
+    /// `head` must be the [head of a node](GreenNodeHead::new) with these `children`, otherwise the text offsets of
+    /// the node and all trees containing it will be wrong. This is checked in debug builds.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// use cstree::{green::GreenNodeHead, util::NodeOrToken};
+    ///
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Operation);
+    /// builder.token(Int, "1");
+    /// builder.finish_node();
+    /// let (operation, _) = builder.finish();
+    /// let head = GreenNodeHead::new(Root.into_raw(), [NodeOrToken::Node(&operation)]);
+    /// let root = GreenNode::from_head_and_children(head, [NodeOrToken::Node(operation.clone())]);
+    /// assert_eq!(root.head(), &head);
+    /// assert_eq!(root, operation.wrap_in(Root.into_raw()));
+    /// ```
     #[inline]
-    pub(super) fn new_with_len_and_hash<I>(
-        kind: RawSyntaxKind,
-        children: I,
-        text_len: TextSize,
-        child_hash: u32,
-    ) -> GreenNode
+    pub fn from_head_and_children<I>(head: GreenNodeHead, children: I) -> GreenNode
     where
         I: IntoIterator<Item = GreenElement>,
         I::IntoIter: ExactSizeIterator,
     {
         let children = children.into_iter().map(PackedGreenElement::from);
-        let header = HeaderWithLength::new(
-            GreenNodeHead {
-                kind,
-                text_len: 0.into(),
-                child_hash: 0,
-            },
-            children.len(),
+        let header = HeaderWithLength::new(head, children.len());
+        let node = GreenNode {
+            data: Arc::into_thin(Arc::from_header_and_iter(header, children)),
+        };
+        debug_assert_eq!(
+            node.head(),
+            &head.derive(head.kind, node.children()),
+            "`head` does not match the children of the node"
         );
-        let mut data = Arc::from_header_and_iter(header, children);
+        node
+    }
 
-        // XXX: fixup `text_len` and `child_hash` after construction, because
-        // we can't iterate `children` twice.
-        let header = &mut Arc::get_mut(&mut data).unwrap().header.header;
-        header.text_len = text_len;
-        header.child_hash = child_hash;
-        GreenNode {
-            data: Arc::into_thin(data),
-        }
+    /// The [`GreenNodeHead`] of this node.
+    #[inline]
+    pub fn head(&self) -> &GreenNodeHead {
+        &self.data.header.header
+    }
+
+    /// Creates a new node of the given `kind` with this node as its only child.
+    ///
+    /// This is useful to add a synthetic root to a tree that was built without one. To do so while building the
+    /// tree, see [`GreenNodeBuilder::wrap_root`](crate::build::GreenNodeBuilder::wrap_root).
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Operation);
+    /// builder.token(Int, "1");
+    /// builder.finish_node();
+    /// let (operation, _) = builder.finish();
+    /// let root = operation.wrap_in(Root.into_raw());
+    /// assert_eq!(root.kind(), Root.into_raw());
+    /// assert_eq!(root.text_len(), operation.text_len());
+    /// assert_eq!(
+    ///     root.children().next().unwrap().into_node(),
+    ///     Some(&operation)
+    /// );
+    /// ```
+    #[inline]
+    pub fn wrap_in(&self, kind: RawSyntaxKind) -> GreenNode {
+        GreenNode::new(kind, [NodeOrToken::Node(self.clone())])
     }
 
+    /// Creates a new node of the given `kind` whose children are the given `parts`, in order.
+    ///
+    /// This joins trees that were built separately, for example the cells of a notebook-style document or the chunks
+    /// of a file that was parsed in parallel, into a single tree. The parts are shared with the new tree, not copied,
+    /// and the text offsets of their elements in the new tree follow from the lengths of the parts before them. All
+    /// parts must have been built with the same interner, or with interners that agree on all keys.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// use cstree::{build::NodeCache, text::TextRange};
+    ///
+    /// let mut cache = NodeCache::new();
+    /// let mut parts = Vec::new();
+    /// for text in ["1", "23"] {
+    ///     let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::with_cache(&mut cache);
+    ///     builder.start_node(Operation);
+    ///     builder.token(Int, text);
+    ///     builder.finish_node();
+    ///     parts.push(builder.finish().0);
+    /// }
+    /// let document = GreenNode::concat(Root.into_raw(), &parts);
+    /// assert_eq!(document.text_len(), 3.into());
+    ///
+    /// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(document);
+    /// let second = root.children().nth(1).unwrap();
+    /// assert_eq!(second.green(), &parts[1]);
+    /// assert_eq!(second.text_range(), TextRange::new(1.into(), 3.into()));
+    /// ```
+    pub fn concat(kind: RawSyntaxKind, parts: &[GreenNode]) -> GreenNode {
+        GreenNode::new(kind, parts.iter().map(|part| NodeOrToken::Node(part.clone())))
+    }
+
+    /// Returns a copy of this node with the given `kind`, which shares all children with this node.
+    ///
+    /// To change the kind of a node anywhere in a tree and get the new root, see
+    /// [`SyntaxNode::with_kind`](crate::syntax::SyntaxNode::with_kind).
     #[inline]
-    pub(super) fn from_head_and_children<I>(header: GreenNodeHead, children: I) -> GreenNode
+    pub fn with_kind(&self, kind: RawSyntaxKind) -> GreenNode {
+        self.rebuild(kind, self.children().map(|child| child.cloned()))
+    }
+
+    /// Creates a node of the given `kind` with the given `children`, which keeps the payload of this node.
+    pub(crate) fn rebuild(&self, kind: RawSyntaxKind, children: impl IntoIterator<Item = GreenElement>) -> GreenNode {
+        let children: Vec<GreenElement> = children.into_iter().collect();
+        let head = self.head().derive(kind, children.iter().map(NodeOrToken::as_ref));
+        GreenNode::from_head_and_children(head, children)
+    }
+
+    /// Returns a copy of this node in which the child node at `index` is replaced by that node's own children.
+    ///
+    /// To flatten a node anywhere in a tree and get the new root, see
+    /// [`SyntaxNode::flatten`](crate::syntax::SyntaxNode::flatten).
+    ///
+    /// ## Panics
+    /// If this node has no child at `index`, or if that child is a token.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// builder.start_node(Operation);
+    /// builder.token(Int, "1");
+    /// builder.static_token(Plus);
+    /// builder.finish_node();
+    /// builder.token(Int, "2");
+    /// builder.finish_node();
+    /// let (tree, _) = builder.finish();
+    /// let flat = tree.flatten_child(0);
+    /// assert_eq!(flat.kind(), Root.into_raw());
+    /// assert_eq!(flat.text_len(), tree.text_len());
+    /// assert_eq!(flat.children().count(), 3);
+    /// ```
+    pub fn flatten_child(&self, index: usize) -> GreenNode {
+        let child = self
+            .children()
+            .nth(index)
+            .and_then(NodeOrToken::into_node)
+            .unwrap_or_else(|| panic!("no child node at index {index}"));
+        let children: Vec<GreenElement> = self
+            .children()
+            .take(index)
+            .chain(child.children())
+            .chain(self.children().skip(index + 1))
+            .map(|child| child.cloned())
+            .collect();
+        self.rebuild(self.kind(), children)
+    }
+
+    /// Returns a new tree with the same kind as this node that contains only the elements of this node's subtree that
+    /// overlap `range`, where `range` is relative to the start of this node. The text of the new tree is the text of
+    /// the subtree in `range`, and nodes that lie completely inside `range` are shared with this node.
+    ///
+    /// Nodes that only partially overlap `range` are sliced recursively. If a token only partially overlaps `range`,
+    /// a [`SliceError`] is returned. To split such tokens instead, see [`slice_splitting`](GreenNode::slice_splitting).
+    ///
+    /// ## Panics
+    /// If `range` is not contained in `0..self.text_len()`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// use cstree::text::TextRange;
+    ///
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// builder.start_node(Operation);
+    /// builder.token(Int, "1");
+    /// builder.static_token(Plus);
+    /// builder.token(Int, "23");
+    /// builder.finish_node();
+    /// builder.finish_node();
+    /// let (tree, _) = builder.finish();
+    ///
+    /// let slice = tree.slice(TextRange::new(1.into(), 4.into())).unwrap();
+    /// assert_eq!(slice.kind(), Root.into_raw());
+    /// assert_eq!(slice.text_len(), 3.into());
+    /// let operation = slice.children().next().unwrap().into_node().unwrap();
+    /// assert_eq!(operation.children().count(), 2);
+    ///
+    /// let error = tree.slice(TextRange::new(0.into(), 3.into())).unwrap_err();
+    /// assert_eq!(error.token, TextRange::new(2.into(), 4.into()));
+    /// ```
+    pub fn slice(&self, range: TextRange) -> Result<GreenNode, SliceError> {
+        self.slice_with(range, 0.into(), &mut |_, _| None)
+    }
+
+    /// Like [`slice`](GreenNode::slice), but tokens that only partially overlap `range` are split, keeping only
+    /// their text in `range`. The text of split tokens is resolved and interned with `interner`.
+    ///
+    /// Tokens without interned text, which are tokens of kinds with [static text](crate::Syntax::static_text),
+    /// cannot be split, since the new token would not have the static text of its kind. If such a token only
+    /// partially overlaps `range`, a [`SliceError`] is returned.
+    ///
+    /// ## Panics
+    /// If `range` is not contained in `0..self.text_len()`, or if the text of a token that has to be split cannot be
+    /// resolved by `interner`.
+    pub fn slice_splitting<I>(&self, range: TextRange, interner: &mut I) -> Result<GreenNode, SliceError>
     where
-        I: IntoIterator<Item = GreenElement>,
-        I::IntoIter: ExactSizeIterator,
+        I: Interner<TokenKey> + ?Sized,
     {
-        let children = children.into_iter().map(PackedGreenElement::from);
-        let header = HeaderWithLength::new(header, children.len());
-        GreenNode {
-            data: Arc::into_thin(Arc::from_header_and_iter(header, children)),
+        self.slice_with(range, 0.into(), &mut |token, range| {
+            let text = interner.resolve(token.text_key()?)[range].to_string();
+            let key = interner.get_or_intern(&text);
+            Some(token.with_text(key, &text))
+        })
+    }
+
+    /// Slices this node, which starts at `offset` in the tree that is being sliced. `split` returns the part of a
+    /// token in the given range relative to the token, or `None` if the token cannot be split.
+    fn slice_with<F>(&self, range: TextRange, offset: TextSize, split: &mut F) -> Result<GreenNode, SliceError>
+    where
+        F: FnMut(&GreenToken, TextRange) -> Option<GreenToken>,
+    {
+        assert!(
+            TextRange::up_to(self.text_len()).contains_range(range),
+            "slice range {range:?} out of bounds of node with length {:?}",
+            self.text_len()
+        );
+        let mut children: Vec<GreenElement> = Vec::new();
+        let mut start = TextSize::from(0);
+        for child in self.children() {
+            let child_range = TextRange::at(start, child.text_len());
+            start = child_range.end();
+            if range.contains_range(child_range) {
+                children.push(child.cloned());
+                continue;
+            }
+            let Some(overlap) = child_range.intersect(range).filter(|overlap| !overlap.is_empty()) else {
+                continue;
+            };
+            let relative = overlap - child_range.start();
+            match child {
+                NodeOrToken::Node(node) => {
+                    let node = node.slice_with(relative, offset + child_range.start(), split)?;
+                    children.push(node.into());
+                }
+                NodeOrToken::Token(token) => {
+                    let token = split(token, relative).ok_or(SliceError {
+                        token: child_range + offset,
+                    })?;
+                    children.push(token.into());
+                }
+            }
+        }
+        Ok(self.rebuild(self.kind(), children))
+    }
+
+    /// Returns a copy of this tree in which every token whose text was interned as `old` gets the text of `new`
+    /// instead. `resolver` is only used to look up the length of the new text.
+    ///
+    /// Subtrees that contain no such token are shared with this tree. If there are no such tokens at all, the result
+    /// is this tree itself. Since only keys are compared, this renames all uses of a name in a file without looking at
+    /// the text of any token.
+    ///
+    /// To replace keys in a subtree and get the new root of the whole tree, see
+    /// [`SyntaxNode::replace_token_keys`](crate::syntax::SyntaxNode::replace_token_keys).
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// # use cstree::interning::Interner;
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// builder.token(Identifier, "x");
+    /// builder.static_token(Plus);
+    /// builder.token(Identifier, "x");
+    /// builder.finish_node();
+    /// let (tree, cache) = builder.finish();
+    /// let mut interner = cache.unwrap().into_interner().unwrap();
+    /// let x = interner.get_or_intern("x");
+    /// let total = interner.get_or_intern("total");
+    /// let renamed = tree.replace_key(x, total, &interner);
+    /// assert_eq!(renamed.text_len(), 11.into());
+    /// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(renamed);
+    /// assert_eq!(root.resolve_text(&interner), "total+total");
+    /// ```
+    pub fn replace_key<R>(&self, old: TokenKey, new: TokenKey, resolver: &R) -> GreenNode
+    where
+        R: Resolver<TokenKey> + ?Sized,
+    {
+        let text = resolver.resolve(new);
+        self.map_tokens(&mut |token| (token.text_key() == Some(old)).then(|| token.with_text(new, text)))
+            .unwrap_or_else(|| self.clone())
+    }
+
+    /// Returns an iterator over the keys of the texts of all tokens in this tree, in order. Tokens with
+    /// [static text](Syntax::static_text) have no key and are skipped. With the `cooked_values` feature, the key of
+    /// the cooked value of a token follows the key of its text.
+    ///
+    /// Keys of tokens that occur multiple times are returned once for each occurrence.
+    pub fn token_keys(&self) -> impl Iterator<Item = TokenKey> + '_ {
+        let mut stack = vec![self.children()];
+        #[cfg(feature = "cooked_values")]
+        let mut cooked = None;
+        std::iter::from_fn(move || loop {
+            #[cfg(feature = "cooked_values")]
+            if let Some(key) = cooked.take() {
+                return Some(key);
+            }
+            let Some(child) = stack.last_mut()?.next() else {
+                stack.pop();
+                continue;
+            };
+            match child {
+                NodeOrToken::Node(node) => stack.push(node.children()),
+                NodeOrToken::Token(token) => {
+                    #[cfg(feature = "cooked_values")]
+                    {
+                        cooked = token.cooked_key();
+                    }
+                    if let Some(key) = token.text_key() {
+                        return Some(key);
+                    }
+                }
+            }
+        })
+    }
+
+    /// Returns a copy of this tree in which the text key of every token is replaced by the key it is mapped to in
+    /// `map`, for example to move a tree that was built with a scratch interner to a shared interner. See [`KeyMap`]
+    /// for an example.
+    ///
+    /// Unlike [`clone_into`](GreenNode::clone_into), this does not resolve or intern any text, since all texts were
+    /// already interned when `map` was created. Tokens that are shared within this tree stay shared in the copy.
+    ///
+    /// ## Panics
+    /// If `map` does not contain the key of a token in this tree.
+    pub fn remap_keys(&self, map: &KeyMap) -> GreenNode {
+        let mut tokens: FxHashMap<GreenToken, GreenToken> = FxHashMap::default();
+        self.map_tokens(&mut |token| {
+            token.text_key()?;
+            Some(
+                tokens
+                    .entry(token.clone())
+                    .or_insert_with(|| {
+                        token.map_keys(|key| {
+                            map.get(key)
+                                .unwrap_or_else(|| panic!("no mapping for {key:?} in `KeyMap`"))
+                        })
+                    })
+                    .clone(),
+            )
+        })
+        .unwrap_or_else(|| self.clone())
+    }
+
+    /// Returns a copy of this tree in which the kind of every node and token is replaced by `map(kind)`.
+    ///
+    /// This migrates trees that were created with an older version of a [`Syntax`] definition to a newer one in which
+    /// kinds were added or reordered, without parsing the source again. For example, a tree that was stored in the
+    /// [binary format](crate::binary) can be read with the old definition and then remapped. Subtrees and tokens that
+    /// are shared within this tree stay shared in the copy.
+    ///
+    /// Tokens with [static text](Syntax::static_text) do not store their text, so `map` must map their kinds to kinds
+    /// with the same static text, and tokens with interned text to kinds without static text.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// builder.token(Int, "1");
+    /// builder.finish_node();
+    /// let (tree, _) = builder.finish();
+    ///
+    /// // A new version of the syntax inserted a kind before `Root`.
+    /// let root = MySyntax::into_raw(Root);
+    /// let migrated = tree.remap_kinds(|kind| match kind {
+    ///     kind if kind < root => kind,
+    ///     RawSyntaxKind(n) => RawSyntaxKind(n + 1),
+    /// });
+    /// assert_eq!(migrated.kind(), RawSyntaxKind(root.0 + 1));
+    /// let int = migrated.children().next().unwrap();
+    /// assert_eq!(int.kind(), MySyntax::into_raw(Int));
+    /// assert_eq!(int.as_token().unwrap().text_key(), tree.children().next().unwrap().as_token().unwrap().text_key());
+    /// ```
+    pub fn remap_kinds(&self, map: impl Fn(RawSyntaxKind) -> RawSyntaxKind) -> GreenNode {
+        fn remap(
+            node: &GreenNode,
+            map: &dyn Fn(RawSyntaxKind) -> RawSyntaxKind,
+            nodes: &mut FxHashMap<GreenNode, GreenNode>,
+            tokens: &mut FxHashMap<GreenToken, GreenToken>,
+        ) -> GreenNode {
+            if let Some(copy) = nodes.get(node) {
+                return copy.clone();
+            }
+            let children: Vec<GreenElement> = node
+                .children()
+                .map(|child| match child {
+                    NodeOrToken::Node(child) => remap(child, map, nodes, tokens).into(),
+                    NodeOrToken::Token(token) => tokens
+                        .entry(token.clone())
+                        .or_insert_with(|| {
+                            let data = token.data();
+                            GreenToken::new(GreenTokenData {
+                                kind: map(data.kind),
+                                ..*data
+                            })
+                        })
+                        .clone()
+                        .into(),
+                })
+                .collect();
+            let copy = node.rebuild(map(node.kind()), children);
+            nodes.insert(node.clone(), copy.clone());
+            copy
+        }
+
+        remap(self, &map, &mut FxHashMap::default(), &mut FxHashMap::default())
+    }
+
+    /// Returns a copy of this tree that is built with `cache`, where the text of all tokens is resolved with `from`
+    /// and interned with the interner of `cache`.
+    ///
+    /// This moves a tree to a different interner, for example to continue working with it in a new session or to
+    /// combine it with trees that were built with another interner. Like a tree built with `cache` from scratch, the
+    /// copy shares nodes and tokens with other trees built with `cache`. Subtrees that are shared within this tree are
+    /// only copied once.
+    ///
+    /// ## Panics
+    /// If `from` cannot resolve the text of a token.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// use cstree::build::NodeCache;
+    ///
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// builder.token(Int, "42");
+    /// builder.finish_node();
+    /// let (tree, cache) = builder.finish();
+    /// let old_interner = cache.unwrap().into_interner().unwrap();
+    ///
+    /// let mut cache = NodeCache::new();
+    /// let copy = tree.clone_into(&mut cache, &old_interner);
+    /// drop(old_interner);
+    /// let new_interner = cache.into_interner().unwrap();
+    /// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(copy);
+    /// assert_eq!(root.resolve_text(&new_interner), "42");
+    /// ```
+    pub fn clone_into<I, C, R>(&self, cache: &mut NodeCache<'_, I, C>, from: &R) -> GreenNode
+    where
+        I: Interner<TokenKey>,
+        C: GreenCache,
+        R: Resolver<TokenKey> + ?Sized,
+    {
+        struct Copier<'c, 'i, 'r, I, C, R: ?Sized> {
+            cache: &'c mut NodeCache<'i, I, C>,
+            from:  &'r R,
+            nodes: FxHashMap<GreenNode, GreenNode>,
+            keys:  FxHashMap<TokenKey, TokenKey>,
+        }
+
+        impl<I, C, R> Copier<'_, '_, '_, I, C, R>
+        where
+            I: Interner<TokenKey>,
+            C: GreenCache,
+            R: Resolver<TokenKey> + ?Sized,
+        {
+            fn node(&mut self, node: &GreenNode) -> GreenNode {
+                if let Some(copy) = self.nodes.get(node) {
+                    return copy.clone();
+                }
+                let children: Vec<GreenElement> = node
+                    .children()
+                    .map(|child| match child {
+                        NodeOrToken::Node(node) => self.node(node).into(),
+                        NodeOrToken::Token(token) => self.token(token).into(),
+                    })
+                    .collect();
+                let head = node.head().derive(node.kind(), children.iter().map(NodeOrToken::as_ref));
+                let copy = self.cache.green_cache_mut().node(head, children);
+                self.nodes.insert(node.clone(), copy.clone());
+                copy
+            }
+
+            fn token(&mut self, token: &GreenToken) -> GreenToken {
+                let mut copy_key = |key: Option<TokenKey>| {
+                    key.map(|key| {
+                        *self.keys.entry(key).or_insert_with(|| {
+                            let text = self.from.resolve(key);
+                            self.cache.interner_mut().get_or_intern(text)
+                        })
+                    })
+                };
+                let data = GreenTokenData {
+                    text: copy_key(token.text_key()),
+                    #[cfg(feature = "cooked_values")]
+                    cooked: copy_key(token.cooked_key()),
+                    ..*token.data()
+                };
+                self.cache.green_cache_mut().token(data)
+            }
+        }
+
+        Copier {
+            cache,
+            from,
+            nodes: FxHashMap::default(),
+            keys: FxHashMap::default(),
+        }
+        .node(self)
+    }
+
+    /// Rebuilds this tree through `cache`, returning the representative of the tree that `cache` holds.
+    ///
+    /// Every node and token of the tree is looked up in `cache` from the bottom up, so the result shares all subtrees
+    /// that `cache` already contains, and equal subtrees within this tree are only stored once. With a cache that
+    /// deduplicates all nodes, like [`DedupCache::canonical`], canonicalizing equal trees with the same cache returns
+    /// the same node, so they can be compared with [`ptr_eq`](GreenNode::ptr_eq) instead of comparing their
+    /// contents, for example to look up the results of an analysis of a tree.
+    ///
+    /// The keys of the tokens are not changed, so all trees canonicalized with a cache must use the same interner.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// use cstree::build::{DedupCache, GreenCache, NoCache, NodeCache};
+    ///
+    /// let mut cache = NodeCache::new().with_green_cache(NoCache);
+    /// let mut build = |cache: &mut NodeCache<_, NoCache>| {
+    ///     let mut builder: GreenNodeBuilder<MySyntax, _, NoCache> = GreenNodeBuilder::with_cache(cache);
+    ///     builder.start_node(Root);
+    ///     for _ in 0..4 {
+    ///         builder.token(Int, "1");
+    ///     }
+    ///     builder.finish_node();
+    ///     builder.finish().0
+    /// };
+    /// let (a, b) = (build(&mut cache), build(&mut cache));
+    /// assert!(a == b && !a.ptr_eq(&b));
+    ///
+    /// let mut canonical = DedupCache::canonical();
+    /// let (a, b) = (a.canonicalize(&mut canonical), b.canonicalize(&mut canonical));
+    /// assert!(a.ptr_eq(&b));
+    /// ```
+    ///
+    /// [`DedupCache::canonical`]: crate::build::DedupCache::canonical
+    pub fn canonicalize<C: GreenCache>(&self, cache: &mut C) -> GreenNode {
+        fn canonical<C>(node: &GreenNode, cache: &mut C, nodes: &mut FxHashMap<GreenNode, GreenNode>) -> GreenNode
+        where
+            C: GreenCache,
+        {
+            if let Some(copy) = nodes.get(node) {
+                return copy.clone();
+            }
+            let children: Vec<GreenElement> = node
+                .children()
+                .map(|child| match child {
+                    NodeOrToken::Node(child) => canonical(child, cache, nodes).into(),
+                    NodeOrToken::Token(token) => cache.token(*token.data()).into(),
+                })
+                .collect();
+            let head = node.head().derive(node.kind(), children.iter().map(NodeOrToken::as_ref));
+            let copy = cache.node(head, children);
+            nodes.insert(node.clone(), copy.clone());
+            copy
+        }
+
+        canonical(self, cache, &mut FxHashMap::default())
+    }
+
+    /// Returns `true` if both nodes are the same allocation, which implies that they are equal.
+    ///
+    /// Unlike comparing nodes with `==`, this does not compare the contents of different nodes, which takes time
+    /// proportional to the size of their subtrees if the nodes are equal. See
+    /// [`canonicalize`](GreenNode::canonicalize).
+    #[inline]
+    pub fn ptr_eq(&self, other: &GreenNode) -> bool {
+        std::ptr::eq(&*self.data, &*other.data)
+    }
+
+    /// Replaces all tokens in this tree for which `f` returns a replacement, or returns `None` if there are none.
+    /// Subtrees without replacements are shared with this tree.
+    pub(crate) fn map_tokens(&self, f: &mut impl FnMut(&GreenToken) -> Option<GreenToken>) -> Option<GreenNode> {
+        let replacements: Vec<_> = self
+            .children()
+            .map(|child| match child {
+                NodeOrToken::Node(node) => node.map_tokens(f).map(NodeOrToken::Node),
+                NodeOrToken::Token(token) => f(token).map(NodeOrToken::Token),
+            })
+            .collect();
+        if replacements.iter().all(Option::is_none) {
+            return None;
         }
+        let children = self
+            .children()
+            .zip(replacements)
+            .map(|(child, replacement)| replacement.unwrap_or_else(|| child.cloned()));
+        Some(self.rebuild(self.kind(), children))
     }
 
     /// [`RawSyntaxKind`] of this node.
@@ -128,6 +929,78 @@ impl GreenNode {
         self.data.header.header.text_len
     }
 
+    /// Returns the number of tokens in the subtree of this node. With the `subtree_counts` feature, this is stored in
+    /// the node and does not require walking the subtree.
+    ///
+    /// ## Panics
+    /// If the subtree contains more than `u32::MAX` tokens, counting shared subtrees once for every occurrence.
+    #[inline]
+    pub fn token_count(&self) -> u32 {
+        #[cfg(feature = "subtree_counts")]
+        return self.data.header.header.token_count;
+        #[cfg(not(feature = "subtree_counts"))]
+        return self.count_subtree().0;
+    }
+
+    /// Returns the number of nodes in the subtree of this node, including this node. With the `subtree_counts`
+    /// feature, this is stored in the node and does not require walking the subtree.
+    ///
+    /// ## Panics
+    /// If the subtree contains more than `u32::MAX` nodes, counting shared subtrees once for every occurrence.
+    #[inline]
+    pub fn node_count(&self) -> u32 {
+        #[cfg(feature = "subtree_counts")]
+        return self.data.header.header.node_count;
+        #[cfg(not(feature = "subtree_counts"))]
+        return self.count_subtree().1;
+    }
+
+    /// Walks the subtree of this node to count its tokens and nodes.
+    #[cfg(not(feature = "subtree_counts"))]
+    fn count_subtree(&self) -> (u32, u32) {
+        let (mut tokens, mut nodes) = (0, 1);
+        let mut stack = vec![self.children()];
+        while let Some(children) = stack.last_mut() {
+            match children.next() {
+                Some(NodeOrToken::Node(node)) => {
+                    nodes = add_count(nodes, 1, "nodes");
+                    stack.push(node.children());
+                }
+                Some(NodeOrToken::Token(_)) => tokens = add_count(tokens, 1, "tokens"),
+                None => {
+                    stack.pop();
+                }
+            }
+        }
+        (tokens, nodes)
+    }
+
+    /// Returns `false` if no token in the subtree of this node has the text `key`. If this returns `true`, such a
+    /// token may or may not exist. See [`GreenNodeHead::may_contain_key`].
+    #[cfg(feature = "key_filters")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "key_filters")))]
+    #[inline]
+    pub fn may_contain_key(&self, key: TokenKey) -> bool {
+        self.data.header.header.may_contain_key(key)
+    }
+
+    /// Returns the number of newlines (`\n`) in the text of this node. This is stored in the node and does not
+    /// require resolving the text of its tokens.
+    #[cfg(feature = "line_counts")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "line_counts")))]
+    #[inline]
+    pub fn newline_count(&self) -> u32 {
+        self.data.header.header.newlines
+    }
+
+    /// Returns the payload of this node, see [`GreenNodeHead::payload`].
+    #[cfg(feature = "node_payloads")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "node_payloads")))]
+    #[inline]
+    pub fn payload(&self) -> u32 {
+        self.data.header.header.payload
+    }
+
     #[inline]
     pub(crate) fn iter(&self) -> slice::Iter<'_, PackedGreenElement> {
         self.data.slice.iter()
@@ -140,6 +1013,99 @@ impl GreenNode {
             inner: self.data.slice.iter(),
         }
     }
+
+    /// Iterator over the children of this node with their offsets relative to the start of this node, starting at
+    /// the child whose range contains `offset`.
+    ///
+    /// Empty children at `offset` are skipped. If `offset` is at or after the end of this node, the iterator is
+    /// empty. Finding the first child takes time proportional to the number of children before it, but the returned
+    /// [`GreenChildrenFrom`] can be kept to resume the scan, so code that repeatedly moves forward through the
+    /// children does not have to start from the first child again.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// builder.token(Int, "12");
+    /// builder.static_token(Plus);
+    /// builder.token(Int, "345");
+    /// builder.finish_node();
+    /// let (tree, _) = builder.finish();
+    ///
+    /// let mut children = tree.children_from_offset(1.into());
+    /// let (first, offset) = children.next().unwrap();
+    /// assert_eq!((first.kind(), u32::from(offset)), (MySyntax::into_raw(Int), 0));
+    /// assert_eq!(children.offset(), 2.into());
+    /// let offsets: Vec<_> = children.map(|(_, offset)| u32::from(offset)).collect();
+    /// assert_eq!(offsets, [2, 3]);
+    /// ```
+    pub fn children_from_offset(&self, offset: TextSize) -> GreenChildrenFrom<'_> {
+        let mut children = GreenChildrenFrom {
+            inner:  self.children(),
+            offset: TextSize::from(0),
+        };
+        loop {
+            let mut rest = children.clone();
+            match rest.next() {
+                Some((child, start)) if start + child.text_len() <= offset => children = rest,
+                _ => return children,
+            }
+        }
+    }
+
+    /// Returns a [`Debug`](fmt::Debug) representation of this node that shows kinds as `S`, using
+    /// [`Syntax::kind_name`] if available.
+    ///
+    /// Only this node's kind and length are written, unless formatted in alternate mode (`{:#?}`), which prints the
+    /// entire subtree with ranges relative to the start of this node. Since green trees do not have access to a
+    /// resolver, only the text of tokens with a [`static_text`](Syntax::static_text) is shown.
+    pub fn debug_kinds<'n, S: Syntax + 'n>(&'n self) -> impl fmt::Debug + 'n {
+        DebugKinds::<S> {
+            node:    self,
+            _syntax: PhantomData,
+        }
+    }
+}
+
+struct DebugKinds<'n, S> {
+    node:    &'n GreenNode,
+    _syntax: PhantomData<S>,
+}
+
+impl<S: Syntax> fmt::Debug for DebugKinds<'_, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let root = TextRange::up_to(self.node.text_len());
+        write!(f, "{}@{:?}", KindName(S::from_raw(self.node.kind())), root)?;
+        if !f.alternate() {
+            return Ok(());
+        }
+        writeln!(f)?;
+        let mut stack = vec![(self.node.children(), TextSize::from(0))];
+        while let Some((children, offset)) = stack.last_mut() {
+            let Some(child) = children.next() else {
+                stack.pop();
+                continue;
+            };
+            let range = TextRange::at(*offset, child.text_len());
+            *offset = range.end();
+            for _ in 0..stack.len() {
+                write!(f, "  ")?;
+            }
+            let kind = S::from_raw(child.kind());
+            write!(f, "{}@{:?}", KindName(kind), range)?;
+            match child {
+                NodeOrToken::Node(node) => stack.push((node.children(), range.start())),
+                NodeOrToken::Token(_) => {
+                    if let Some(text) = kind.static_text() {
+                        write!(f, " {:?}", text)?;
+                    }
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
 }
 
 impl Hash for GreenNode {
@@ -156,3 +1122,19 @@ impl PartialEq for GreenNode {
 }
 
 impl Eq for GreenNode {}
+
+/// The error returned by [`GreenNode::slice`] and [`GreenNode::slice_splitting`] if the slice range would have to
+/// split a token that cannot be split.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SliceError {
+    /// The range of the token, relative to the start of the node that was sliced.
+    pub token: TextRange,
+}
+
+impl fmt::Display for SliceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "slice range splits the token at {:?}", self.token)
+    }
+}
+
+impl std::error::Error for SliceError {}
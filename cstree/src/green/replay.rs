@@ -0,0 +1,85 @@
+//! Building trees from recorded events in bounded slices.
+
+use super::builder::{GreenCache, GreenNodeBuilder};
+use crate::{
+    interning::{Interner, TokenKey},
+    Syntax,
+};
+
+/// An operation of a [`GreenNodeBuilder`], as recorded by parsers that produce a list of events first and build the
+/// tree from it afterwards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BuildEvent<'t, S> {
+    /// [Starts](GreenNodeBuilder::start_node) a node of the given kind.
+    StartNode(S),
+    /// Adds a [token](GreenNodeBuilder::token) of the given kind and text.
+    Token(S, &'t str),
+    /// [Finishes](GreenNodeBuilder::finish_node) the current node.
+    FinishNode,
+}
+
+/// Whether [`GreenNodeBuilder::replay`] applied all events.
+#[must_use]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Progress {
+    /// The budget ran out before all events were applied. Replaying the rest of the events continues the tree.
+    Paused,
+    /// All events were applied.
+    Done,
+}
+
+impl<S, I, C> GreenNodeBuilder<'_, '_, S, I, C>
+where
+    S: Syntax,
+    I: Interner<TokenKey>,
+    C: GreenCache,
+{
+    /// Applies a single `event` to this builder.
+    #[inline]
+    pub fn apply(&mut self, event: BuildEvent<'_, S>) {
+        match event {
+            BuildEvent::StartNode(kind) => self.start_node(kind),
+            BuildEvent::Token(kind, text) => self.token(kind, text),
+            BuildEvent::FinishNode => self.finish_node(),
+        }
+    }
+
+    /// Applies at most `budget` of the remaining `events` to this builder, and returns whether all events were
+    /// applied.
+    ///
+    /// The builder and the iterator together are the state of a build that can be resumed, so an interactive host can
+    /// build a large tree in slices of bounded length, doing other work in between, without an async runtime or
+    /// a second thread. Once this returns [`Progress::Done`], the tree can be [finished](GreenNodeBuilder::finish).
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// use cstree::build::{BuildEvent, Progress};
+    ///
+    /// let mut events = vec![BuildEvent::StartNode(Root)];
+    /// for _ in 0..100 {
+    ///     events.push(BuildEvent::Token(Int, "1"));
+    /// }
+    /// events.push(BuildEvent::FinishNode);
+    ///
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// let mut events = events.into_iter();
+    /// let mut slices = 1;
+    /// while builder.replay(&mut events, 16) == Progress::Paused {
+    ///     // Handle user input or repaint here.
+    ///     slices += 1;
+    /// }
+    /// assert_eq!(slices, 7);
+    /// let (tree, _) = builder.finish();
+    /// assert_eq!(tree.children().count(), 100);
+    /// ```
+    pub fn replay<'t>(&mut self, events: &mut impl Iterator<Item = BuildEvent<'t, S>>, budget: usize) -> Progress {
+        for _ in 0..budget {
+            match events.next() {
+                Some(event) => self.apply(event),
+                None => return Progress::Done,
+            }
+        }
+        Progress::Paused
+    }
+}
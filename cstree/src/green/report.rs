@@ -0,0 +1,162 @@
+//! Statistics about the shape and size of green trees.
+
+use std::{collections::BTreeMap, mem};
+
+use fxhash::FxHashSet;
+
+use super::{GreenNode, GreenNodeHead, GreenTokenData, PackedGreenElement};
+use crate::{
+    interning::{Resolver, TokenKey},
+    util::NodeOrToken,
+    RawSyntaxKind,
+};
+
+/// Statistics about a green tree, returned by [`GreenNode::report`].
+///
+/// With the `serialize` feature, reports implement `serde::Serialize`, so they can be written as JSON or another
+/// format for tools that track the health of a parser over time.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize))]
+pub struct TreeReport {
+    /// The length of the text of the tree in bytes.
+    pub text_len:         u64,
+    /// The number of nodes in the tree, including the root. Nodes that appear in the tree more than once are counted
+    /// each time.
+    pub nodes:            u64,
+    /// The number of tokens in the tree, counting tokens that appear more than once each time.
+    pub tokens:           u64,
+    /// The number of distinct node allocations in the tree. Deduplicated nodes are only counted once.
+    pub unique_nodes:     u64,
+    /// The number of distinct token allocations in the tree. Deduplicated tokens are only counted once.
+    pub unique_tokens:    u64,
+    /// The number of nodes of each kind.
+    pub node_kinds:       BTreeMap<RawSyntaxKind, u64>,
+    /// The number of tokens of each kind.
+    pub token_kinds:      BTreeMap<RawSyntaxKind, u64>,
+    /// The number of nodes at each depth, starting with the root at depth 0.
+    pub depth_histogram:  Vec<u64>,
+    /// The number of distinct token texts in the tree that are stored in the interner, which excludes the
+    /// [static text](crate::Syntax::static_text) of tokens.
+    pub interned_texts:   u64,
+    /// The total length in bytes of the distinct token texts in the tree that are stored in the interner.
+    pub interned_bytes:   u64,
+    /// An estimate of the memory in bytes used by the distinct nodes and tokens of the tree, excluding the memory
+    /// used by the interner.
+    pub estimated_memory: u64,
+}
+
+impl TreeReport {
+    /// The depth of the deepest node of the tree, where the root has depth 0.
+    pub fn max_depth(&self) -> usize {
+        self.depth_histogram.len().saturating_sub(1)
+    }
+
+    /// The fraction of nodes in the tree that share their allocation with another node, or `0.0` for a tree without
+    /// nodes.
+    pub fn node_dedup_ratio(&self) -> f64 {
+        dedup_ratio(self.unique_nodes, self.nodes)
+    }
+
+    /// The fraction of tokens in the tree that share their allocation with another token, or `0.0` for a tree
+    /// without tokens.
+    pub fn token_dedup_ratio(&self) -> f64 {
+        dedup_ratio(self.unique_tokens, self.tokens)
+    }
+}
+
+fn dedup_ratio(unique: u64, total: u64) -> f64 {
+    match total {
+        0 => 0.0,
+        total => total.saturating_sub(unique) as f64 / total as f64,
+    }
+}
+
+impl GreenNode {
+    /// Collects statistics about this tree, like the number of its nodes and tokens, how many of them are
+    /// deduplicated, and how much memory they use. The length of token texts is resolved with `resolver`.
+    ///
+    /// The report visits every node of the tree, including every occurrence of nodes that are shared.
+    ///
+    /// ## Panics
+    /// If `resolver` cannot resolve the text of a token.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// for _ in 0..2 {
+    ///     builder.start_node(Operation);
+    ///     builder.token(Int, "12");
+    ///     builder.static_token(Plus);
+    ///     builder.token(Int, "3");
+    ///     builder.finish_node();
+    /// }
+    /// builder.finish_node();
+    /// let (tree, cache) = builder.finish();
+    /// let interner = cache.unwrap().into_interner().unwrap();
+    ///
+    /// let report = tree.report(&interner);
+    /// assert_eq!((report.nodes, report.unique_nodes), (3, 2));
+    /// assert_eq!(report.depth_histogram, [1, 2]);
+    /// assert_eq!((report.interned_texts, report.interned_bytes), (2, 3));
+    /// assert_eq!(report.node_dedup_ratio(), 1.0 / 3.0);
+    /// ```
+    pub fn report<R>(&self, resolver: &R) -> TreeReport
+    where
+        R: Resolver<TokenKey> + ?Sized,
+    {
+        let mut report = TreeReport {
+            text_len: u32::from(self.text_len()).into(),
+            ..TreeReport::default()
+        };
+        let mut nodes: FxHashSet<*const GreenNodeHead> = FxHashSet::default();
+        let mut tokens: FxHashSet<*const GreenTokenData> = FxHashSet::default();
+        let mut texts: FxHashSet<TokenKey> = FxHashSet::default();
+        let mut stack = vec![(self, 0)];
+        while let Some((node, depth)) = stack.pop() {
+            report.nodes += 1;
+            *report.node_kinds.entry(node.kind()).or_default() += 1;
+            if report.depth_histogram.len() <= depth {
+                report.depth_histogram.resize(depth + 1, 0);
+            }
+            report.depth_histogram[depth] += 1;
+            if nodes.insert(&node.data.header.header) {
+                report.estimated_memory += node_size(node.children().len());
+            }
+            for child in node.children() {
+                match child {
+                    NodeOrToken::Node(node) => stack.push((node, depth + 1)),
+                    NodeOrToken::Token(token) => {
+                        report.tokens += 1;
+                        *report.token_kinds.entry(token.kind()).or_default() += 1;
+                        if tokens.insert(token.data()) {
+                            report.estimated_memory += token_size();
+                        }
+                        if let Some(key) = token.text_key() {
+                            if texts.insert(key) {
+                                report.interned_texts += 1;
+                                report.interned_bytes += resolver.resolve(key).len() as u64;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        report.unique_nodes = nodes.len() as u64;
+        report.unique_tokens = tokens.len() as u64;
+        report
+    }
+}
+
+/// The size of the allocation of a node with `children` children: the reference count, the header, the number of
+/// children and the children themselves.
+fn node_size(children: usize) -> u64 {
+    (mem::size_of::<usize>() * 2 + mem::size_of::<GreenNodeHead>() + children * mem::size_of::<PackedGreenElement>())
+        as u64
+}
+
+/// The size of the allocation of a token: the reference count and the token data.
+fn token_size() -> u64 {
+    (mem::size_of::<usize>() + mem::size_of::<GreenTokenData>()) as u64
+}
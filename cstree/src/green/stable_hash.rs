@@ -0,0 +1,97 @@
+//! Hashes of green trees that do not depend on the interner or the process that built them.
+
+use super::{GreenNode, GreenToken};
+use crate::{
+    binary::Fnv,
+    interning::{Resolver, TokenKey},
+    util::NodeOrToken,
+};
+
+const NODE: u8 = 0;
+const TOKEN: u8 = 1;
+const STATIC_TOKEN: u8 = 2;
+
+impl GreenNode {
+    /// A 64-bit hash of this tree that is computed from the kinds of its nodes and tokens and the text of its tokens,
+    /// which is resolved with `resolver`.
+    ///
+    /// The [`Hash`] implementation of green nodes is fast, but it hashes the keys of the tokens' texts, which depend on
+    /// the order in which texts were interned, and it uses a hasher chosen by the caller. The stable hash is the same
+    /// for equal trees regardless of the interner they were built with, on every platform, and in every run of the
+    /// program, so it can be used as the key of a memoization table that is persisted between runs. The algorithm
+    /// will only change in a breaking release of `cstree`, and such a change is noted in the changelog.
+    ///
+    /// Tokens with [static text](crate::Syntax::static_text) are hashed by their kind and the length of their text.
+    /// Computing the hash takes time proportional to the size of the tree and the length of its text.
+    ///
+    /// ## Panics
+    /// If `resolver` cannot resolve the text of a token.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// use cstree::{build::NodeCache, interning::Interner};
+    ///
+    /// let build = |cache: &mut NodeCache| {
+    ///     let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::with_cache(cache);
+    ///     builder.start_node(Root);
+    ///     builder.token(Int, "1");
+    ///     builder.finish_node();
+    ///     builder.finish().0
+    /// };
+    /// let mut first = NodeCache::new();
+    /// let mut second = NodeCache::new();
+    /// second.interner_mut().get_or_intern("something else");
+    /// let (a, b) = (build(&mut first), build(&mut second));
+    /// assert_ne!(a, b);
+    /// assert_eq!(a.stable_hash(first.interner()), b.stable_hash(second.interner()));
+    /// ```
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "stable_hash")))]
+    pub fn stable_hash<R>(&self, resolver: &R) -> u64
+    where
+        R: Resolver<TokenKey> + ?Sized,
+    {
+        let mut hash = Fnv::new();
+        hash.write(&[NODE]);
+        hash.write(&self.kind().0.to_le_bytes());
+        hash.write(&(self.children().len() as u64).to_le_bytes());
+        for child in self.children() {
+            let child = match child {
+                NodeOrToken::Node(node) => node.stable_hash(resolver),
+                NodeOrToken::Token(token) => token.stable_hash(resolver),
+            };
+            hash.write(&child.to_le_bytes());
+        }
+        hash.0
+    }
+}
+
+impl GreenToken {
+    /// A 64-bit hash of the kind and the text of this token, which is resolved with `resolver`. See
+    /// [`GreenNode::stable_hash`].
+    ///
+    /// ## Panics
+    /// If `resolver` cannot resolve the text of this token.
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "stable_hash")))]
+    pub fn stable_hash<R>(&self, resolver: &R) -> u64
+    where
+        R: Resolver<TokenKey> + ?Sized,
+    {
+        let mut hash = Fnv::new();
+        match self.text_key() {
+            Some(key) => {
+                let text = resolver.resolve(key);
+                hash.write(&[TOKEN]);
+                hash.write(&self.kind().0.to_le_bytes());
+                hash.write(&(text.len() as u64).to_le_bytes());
+                hash.write(text.as_bytes());
+            }
+            None => {
+                hash.write(&[STATIC_TOKEN]);
+                hash.write(&self.kind().0.to_le_bytes());
+                hash.write(&u32::from(self.text_len()).to_le_bytes());
+            }
+        }
+        hash.0
+    }
+}
@@ -0,0 +1,160 @@
+use std::sync::OnceLock;
+
+use fxhash::{FxHashMap, FxHashSet};
+use parking_lot::Mutex;
+
+use crate::{
+    build::GreenCache,
+    green::{GreenElement, GreenNode, GreenNodeHead, GreenToken, GreenTokenData},
+    util::NodeOrToken,
+};
+
+/// The number of independently locked shards of a [`NodeStore`].
+const STORE_SHARDS: usize = 32;
+
+/// A content-addressed store of green nodes and tokens, which shares identical subtrees between all trees that are
+/// added to it, independent of the [`NodeCache`](crate::build::NodeCache) or builder they were created with.
+///
+/// Nodes in the store are identified by their full contents rather than only by their [`GreenNodeHead`], so nodes
+/// with many children are shared too. This is useful for subtrees that repeat across many files, like license headers
+/// or generated code, which a cache of small nodes does not deduplicate.
+///
+/// Trees can be added to a store after they were built with [`intern`](NodeStore::intern), or while they are built by
+/// using the store as the [`GreenCache`] of a builder, since `GreenCache` is implemented for `&NodeStore`.
+/// [`NodeStore::global`] returns a store that is shared by the whole process. Note that nodes and tokens are only
+/// identical if their texts were interned with the same keys, so trees added to the same store should also use the
+/// same interner.
+///
+/// A store keeps all of its elements alive until it is [cleared](NodeStore::clear).
+///
+/// # Examples
+/// ```
+/// # use cstree::testing::*;
+/// use cstree::build::NodeStore;
+///
+/// let build = |builder: &mut GreenNodeBuilder<MySyntax>| {
+///     builder.start_node(Root);
+///     for _ in 0..8 {
+///         builder.token(Int, "1");
+///     }
+///     builder.finish_node();
+/// };
+/// let mut first = GreenNodeBuilder::new();
+/// build(&mut first);
+/// let (first, cache) = first.finish();
+/// let mut second = GreenNodeBuilder::from_cache(cache.unwrap());
+/// build(&mut second);
+/// let (second, _) = second.finish();
+///
+/// let store = NodeStore::new();
+/// let first = store.intern(&first);
+/// let second = store.intern(&second);
+/// assert_eq!(first, second);
+/// assert_eq!(store.node_count(), 1);
+/// ```
+#[derive(Debug)]
+pub struct NodeStore {
+    shards: Box<[Mutex<StoreShard>]>,
+}
+
+#[derive(Debug, Default)]
+struct StoreShard {
+    nodes:  FxHashSet<GreenNode>,
+    tokens: FxHashMap<GreenTokenData, GreenToken>,
+}
+
+impl Default for NodeStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NodeStore {
+    /// Constructs a new, empty store.
+    pub fn new() -> Self {
+        Self {
+            shards: (0..STORE_SHARDS).map(|_| Mutex::default()).collect(),
+        }
+    }
+
+    /// Returns the store that is shared by the whole process. It is created empty on first use.
+    pub fn global() -> &'static NodeStore {
+        static GLOBAL: OnceLock<NodeStore> = OnceLock::new();
+        GLOBAL.get_or_init(NodeStore::new)
+    }
+
+    /// Returns the node in the store that is identical to `node`, adding `node` if there is none.
+    ///
+    /// All subtrees of a node that is added are also added to or replaced by identical subtrees from the store, so that
+    /// they are shared with all other trees in the store.
+    pub fn intern(&self, node: &GreenNode) -> GreenNode {
+        if let Some(stored) = self.shard(node).lock().nodes.get(node) {
+            return stored.clone();
+        }
+        let children: Vec<GreenElement> = node
+            .children()
+            .map(|child| match child {
+                NodeOrToken::Node(node) => self.intern(node).into(),
+                NodeOrToken::Token(token) => self.intern_token(*token.data()).into(),
+            })
+            .collect();
+        self.insert(GreenNode::from_head_and_children(*node.head(), children))
+    }
+
+    /// The number of nodes in the store.
+    pub fn node_count(&self) -> usize {
+        self.shards.iter().map(|shard| shard.lock().nodes.len()).sum()
+    }
+
+    /// The number of tokens in the store.
+    pub fn token_count(&self) -> usize {
+        self.shards.iter().map(|shard| shard.lock().tokens.len()).sum()
+    }
+
+    /// Removes all nodes and tokens from the store. Trees that use them are not affected.
+    pub fn clear(&self) {
+        for shard in self.shards.iter() {
+            let mut shard = shard.lock();
+            shard.nodes.clear();
+            shard.tokens.clear();
+        }
+    }
+
+    fn shard<T: std::hash::Hash>(&self, key: &T) -> &Mutex<StoreShard> {
+        &self.shards[fxhash::hash(key) % self.shards.len()]
+    }
+
+    fn insert(&self, node: GreenNode) -> GreenNode {
+        let mut shard = self.shard(&node).lock();
+        if let Some(stored) = shard.nodes.get(&node) {
+            return stored.clone();
+        }
+        shard.nodes.insert(node.clone());
+        node
+    }
+
+    fn intern_token(&self, data: GreenTokenData) -> GreenToken {
+        self.shard(&data)
+            .lock()
+            .tokens
+            .entry(data)
+            .or_insert_with_key(|data| GreenToken::new(*data))
+            .clone()
+    }
+}
+
+impl GreenCache for &NodeStore {
+    fn node<C>(&mut self, head: GreenNodeHead, children: C) -> GreenNode
+    where
+        C: IntoIterator<Item = GreenElement>,
+        C::IntoIter: ExactSizeIterator,
+    {
+        // The children come from the same builder and are already in the store.
+        self.insert(GreenNode::from_head_and_children(head, children))
+    }
+
+    #[inline]
+    fn token(&mut self, data: GreenTokenData) -> GreenToken {
+        self.intern_token(data)
+    }
+}
@@ -1,19 +1,152 @@
 use std::{fmt, hash, mem::ManuallyDrop, ptr::NonNull};
 
 use crate::{
-    interning::{Resolver, TokenKey},
+    interning::{Interner, Resolver, TokenKey},
     text::TextSize,
-    RawSyntaxKind,
+    RawSyntaxKind, Syntax,
 };
 use sptr::Strict;
 use triomphe::Arc;
 
+/// The data of a [`GreenToken`], which fully determines the token.
+///
+/// Equal tokens have equal data, so custom caches of green tokens can use it as their key and create tokens that
+/// are not yet cached with [`GreenToken::new`].
 #[repr(align(2))] // to use 1 bit for pointer tagging. NB: this is an at-least annotation
 #[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
-pub(super) struct GreenTokenData {
+pub struct GreenTokenData {
     pub(super) kind:     RawSyntaxKind,
     pub(super) text:     Option<TokenKey>,
     pub(super) text_len: TextSize,
+    #[cfg(feature = "line_counts")]
+    pub(super) newlines: u32,
+    #[cfg(feature = "char_counts")]
+    pub(super) chars:    u32,
+    #[cfg(feature = "error_messages")]
+    pub(super) message:  u32,
+    #[cfg(feature = "cooked_values")]
+    pub(super) cooked:   Option<TokenKey>,
+}
+
+impl GreenTokenData {
+    /// Creates the data of a token of the given `kind`, with text that was interned as `text` and has length
+    /// `text_len`. Tokens with [static text](Syntax::static_text) have no interned text.
+    ///
+    /// With the `line_counts` feature, the data created by this function does not count any newlines, since the
+    /// text of the token is not known. Use [`from_text`](GreenTokenData::from_text) for tokens that may contain
+    /// newlines. Similarly, with the `char_counts` feature, the text is assumed to consist of ASCII characters, so
+    /// that its length in characters is its length in bytes.
+    #[inline]
+    pub fn new(kind: RawSyntaxKind, text: Option<TokenKey>, text_len: TextSize) -> Self {
+        Self {
+            kind,
+            text,
+            text_len,
+            #[cfg(feature = "line_counts")]
+            newlines: 0,
+            #[cfg(feature = "char_counts")]
+            chars: text_len.into(),
+            #[cfg(feature = "error_messages")]
+            message: 0,
+            #[cfg(feature = "cooked_values")]
+            cooked: None,
+        }
+    }
+
+    /// Creates the data of a token of the given `kind` with the given `text`, which was interned as `key`. Tokens
+    /// with [static text](Syntax::static_text) have no interned text, but should still pass their static text.
+    ///
+    /// This computes the length of the token from its text, as well as the number of newlines in it if the
+    /// `line_counts` feature is enabled and the number of characters in it if the `char_counts` feature is enabled.
+    #[inline]
+    pub fn from_text(kind: RawSyntaxKind, key: Option<TokenKey>, text: &str) -> Self {
+        Self {
+            kind,
+            text: key,
+            text_len: TextSize::of(text),
+            #[cfg(feature = "line_counts")]
+            newlines: count_newlines(text),
+            #[cfg(feature = "char_counts")]
+            chars: text.chars().count() as u32,
+            #[cfg(feature = "error_messages")]
+            message: 0,
+            #[cfg(feature = "cooked_values")]
+            cooked: None,
+        }
+    }
+
+    /// [`RawSyntaxKind`] of the token.
+    #[inline]
+    pub fn kind(&self) -> RawSyntaxKind {
+        self.kind
+    }
+
+    /// The interned key of the text of the token, if it has any.
+    #[inline]
+    pub fn text_key(&self) -> Option<TokenKey> {
+        self.text
+    }
+
+    /// The length of text covered by the token.
+    #[inline]
+    pub fn text_len(&self) -> TextSize {
+        self.text_len
+    }
+
+    /// The number of newlines (`\n`) in the text of the token.
+    #[cfg(feature = "line_counts")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "line_counts")))]
+    #[inline]
+    pub fn newline_count(&self) -> u32 {
+        self.newlines
+    }
+
+    /// The length of the text of the token in `char`s, i.e., in Unicode scalar values.
+    #[cfg(feature = "char_counts")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "char_counts")))]
+    #[inline]
+    pub fn text_char_len(&self) -> u32 {
+        self.chars
+    }
+
+    /// The id of the error message of the token, or `0` if the token was not created with
+    /// [`GreenNodeBuilder::error_token`](crate::build::GreenNodeBuilder::error_token).
+    #[cfg(feature = "error_messages")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "error_messages")))]
+    #[inline]
+    pub fn error_message(&self) -> u32 {
+        self.message
+    }
+
+    /// Returns a copy of this data with the error message `message`.
+    #[cfg(feature = "error_messages")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "error_messages")))]
+    #[inline]
+    pub fn with_error_message(self, message: u32) -> Self {
+        Self { message, ..self }
+    }
+
+    /// The interned key of the cooked value of the token, if it has one. See
+    /// [`GreenToken::cooked_text`].
+    #[cfg(feature = "cooked_values")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "cooked_values")))]
+    #[inline]
+    pub fn cooked_key(&self) -> Option<TokenKey> {
+        self.cooked
+    }
+
+    /// Returns a copy of this data with the cooked value interned as `cooked`.
+    #[cfg(feature = "cooked_values")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "cooked_values")))]
+    #[inline]
+    pub fn with_cooked_key(self, cooked: Option<TokenKey>) -> Self {
+        Self { cooked, ..self }
+    }
+}
+
+#[cfg(feature = "line_counts")]
+fn count_newlines(text: &str) -> u32 {
+    memchr::memchr_iter(b'\n', text.as_bytes()).count() as u32
 }
 
 /// Leaf node in the immutable "green" tree.
@@ -28,25 +161,32 @@ pub(super) const IS_TOKEN_TAG: usize = 0x1;
 impl GreenToken {
     fn add_tag(ptr: NonNull<GreenTokenData>) -> NonNull<GreenTokenData> {
         unsafe {
-            let ptr = ptr.as_ptr().map_addr(|addr| addr | IS_TOKEN_TAG);
+            let ptr = Strict::map_addr(ptr.as_ptr(), |addr| addr | IS_TOKEN_TAG);
             NonNull::new_unchecked(ptr)
         }
     }
 
     fn remove_tag(ptr: NonNull<GreenTokenData>) -> NonNull<GreenTokenData> {
         unsafe {
-            let ptr = ptr.as_ptr().map_addr(|addr| addr & !IS_TOKEN_TAG);
+            let ptr = Strict::map_addr(ptr.as_ptr(), |addr| addr & !IS_TOKEN_TAG);
             NonNull::new_unchecked(ptr)
         }
     }
 
-    fn data(&self) -> &GreenTokenData {
+    /// The [`GreenTokenData`] of this token.
+    #[inline]
+    pub fn data(&self) -> &GreenTokenData {
         unsafe { &*Self::remove_tag(self.ptr).as_ptr() }
     }
 
-    /// Creates a new Token.
+    /// Creates a new token from its `data`.
+    ///
+    /// This always allocates a new token. To share tokens with equal data, create them through a [`NodeCache`] or a
+    /// custom cache keyed by [`GreenTokenData`].
+    ///
+    /// [`NodeCache`]: crate::build::NodeCache
     #[inline]
-    pub(super) fn new(data: GreenTokenData) -> GreenToken {
+    pub fn new(data: GreenTokenData) -> GreenToken {
         let ptr = Arc::into_raw(Arc::new(data));
         let ptr = NonNull::new(ptr as *mut _).unwrap();
         GreenToken {
@@ -54,6 +194,50 @@ impl GreenToken {
         }
     }
 
+    /// Creates a new token of the given `kind` with the given `text`, outside of a
+    /// [`GreenNodeBuilder`](crate::build::GreenNodeBuilder). This is useful to synthesize tokens for edits and
+    /// refactorings.
+    ///
+    /// If `kind` has [static text](Syntax::static_text), the token uses that text. In debug builds, this checks
+    /// that `text` matches the static text, like the builder does by default. Otherwise, `text` is interned with
+    /// `interner`, which must be the interner of the tree the token is inserted into. Unlike tokens created by a
+    /// builder, the token is not deduplicated with equal tokens.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// builder.token(Identifier, "x");
+    /// builder.finish_node();
+    /// let (tree, cache) = builder.finish();
+    /// let mut interner = cache.unwrap().into_interner().unwrap();
+    /// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+    ///
+    /// let y = GreenToken::new_detached(Identifier, "y", &mut interner);
+    /// let new_root: SyntaxNode<MySyntax> = SyntaxNode::new_root(root.first_token().unwrap().replace_with(y));
+    /// assert_eq!(new_root.resolve_text(&interner), "y");
+    /// let plus = GreenToken::new_detached(Plus, "+", &mut interner);
+    /// assert_eq!(plus.text_key(), None);
+    /// ```
+    pub fn new_detached<S, I>(kind: S, text: &str, interner: &mut I) -> GreenToken
+    where
+        S: Syntax,
+        I: Interner<TokenKey> + ?Sized,
+    {
+        let data = match S::static_text(kind) {
+            Some(static_text) => {
+                debug_assert_eq!(
+                    static_text, text,
+                    r#"Tried to create a `{kind:?}` token, which should have text "{static_text}", with text "{text}"."#
+                );
+                GreenTokenData::from_text(S::into_raw(kind), None, static_text)
+            }
+            None => GreenTokenData::from_text(S::into_raw(kind), Some(interner.get_or_intern(text)), text),
+        };
+        GreenToken::new(data)
+    }
+
     /// [`RawSyntaxKind`] of this Token.
     #[inline]
     pub fn kind(&self) -> RawSyntaxKind {
@@ -69,6 +253,32 @@ impl GreenToken {
         self.data().text.map(|key| resolver.resolve(key))
     }
 
+    /// If this token was created without explicit text because its kind, interpreted as `S`, has a
+    /// [`static_text`](Syntax::static_text), returns that text.
+    ///
+    /// Unlike [`text`](GreenToken::text), this does not require a resolver.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// builder.static_token(Plus);
+    /// builder.token(Int, "1");
+    /// builder.finish_node();
+    /// let (tree, _) = builder.finish();
+    /// let mut tokens = tree.children().filter_map(|child| child.into_token());
+    /// assert_eq!(tokens.next().unwrap().static_text::<MySyntax>(), Some("+"));
+    /// assert_eq!(tokens.next().unwrap().static_text::<MySyntax>(), None);
+    /// ```
+    #[inline]
+    pub fn static_text<S: Syntax>(&self) -> Option<&'static str> {
+        match self.data().text {
+            Some(_) => None,
+            None => S::static_text(S::from_raw(self.kind())),
+        }
+    }
+
     /// Returns the length of text covered by this token.
     #[inline]
     pub fn text_len(&self) -> TextSize {
@@ -83,6 +293,77 @@ impl GreenToken {
     pub fn text_key(&self) -> Option<TokenKey> {
         self.data().text
     }
+
+    /// Returns the number of newlines (`\n`) in the text of this token.
+    #[cfg(feature = "line_counts")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "line_counts")))]
+    #[inline]
+    pub fn newline_count(&self) -> u32 {
+        self.data().newlines
+    }
+
+    /// Returns the length of the text of this token in `char`s. This is computed when the token is created, so it
+    /// does not require decoding its text.
+    #[cfg(feature = "char_counts")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "char_counts")))]
+    #[inline]
+    pub fn text_char_len(&self) -> u32 {
+        self.data().chars
+    }
+
+    /// Returns the id of the error message of this token, see [`GreenTokenData::error_message`].
+    #[cfg(feature = "error_messages")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "error_messages")))]
+    #[inline]
+    pub fn error_message(&self) -> u32 {
+        self.data().message
+    }
+
+    /// Returns a copy of this token with the same kind, but the given `text`, which was interned as `key`.
+    #[inline]
+    pub(crate) fn with_text(&self, key: TokenKey, text: &str) -> GreenToken {
+        GreenToken::new(GreenTokenData::from_text(self.kind(), Some(key), text))
+    }
+
+    /// Returns the interned key of the cooked value of this token, if it has one. See
+    /// [`cooked_text`](GreenToken::cooked_text).
+    #[cfg(feature = "cooked_values")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "cooked_values")))]
+    #[inline]
+    pub fn cooked_key(&self) -> Option<TokenKey> {
+        self.data().cooked
+    }
+
+    /// The cooked value of this token, such as the unescaped contents of a string literal, which was recorded when
+    /// the token was created by a builder with a [cooker](crate::build::GreenNodeBuilder::set_cooker).
+    ///
+    /// Returns the [`text`](GreenToken::text) of the token if no cooked value was recorded, i.e., if the cooker
+    /// returned `None` for the token because its value is its text.
+    #[cfg(feature = "cooked_values")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "cooked_values")))]
+    #[inline]
+    pub fn cooked_text<'i, I>(&self, resolver: &'i I) -> Option<&'i str>
+    where
+        I: Resolver<TokenKey> + ?Sized,
+    {
+        match self.data().cooked {
+            Some(key) => Some(resolver.resolve(key)),
+            None => self.text(resolver),
+        }
+    }
+
+    /// Returns a copy of this token with the same kind and text, where all interned keys of the token are replaced
+    /// by `map`.
+    #[inline]
+    pub(crate) fn map_keys(&self, mut map: impl FnMut(TokenKey) -> TokenKey) -> GreenToken {
+        let data = self.data();
+        GreenToken::new(GreenTokenData {
+            text: data.text.map(&mut map),
+            #[cfg(feature = "cooked_values")]
+            cooked: data.cooked.map(&mut map),
+            ..*data
+        })
+    }
 }
 
 impl fmt::Debug for GreenToken {
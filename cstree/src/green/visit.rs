@@ -0,0 +1,124 @@
+//! Internal iteration over green trees.
+
+use std::ops::ControlFlow;
+
+use super::{GreenNode, GreenToken};
+use crate::util::NodeOrToken;
+
+/// What [`GreenNode::visit`] does after a [`GreenVisitor`] entered a node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Visit {
+    /// Visit the children of the node, then leave it.
+    Continue,
+    /// Leave the node without visiting its children.
+    SkipChildren,
+    /// Stop the traversal without leaving the node or any of its ancestors.
+    Stop,
+}
+
+/// A pass over a green tree that is driven by [`GreenNode::visit`].
+///
+/// Unlike the traversals of the syntax tree, visiting a green tree does not create any syntax nodes and does not
+/// allocate, which makes it well suited for passes that only need the kinds, lengths and text keys of the elements
+/// of a tree, such as computing metrics or serializing the tree. All methods have default implementations that
+/// continue the traversal, so visitors only need to implement the methods they are interested in.
+///
+/// # Examples
+/// ```
+/// # use cstree::testing::*;
+/// use cstree::green::{GreenVisitor, Visit};
+/// use std::ops::ControlFlow;
+///
+/// #[derive(Default)]
+/// struct Metrics {
+///     depth:     usize,
+///     max_depth: usize,
+///     plus:      usize,
+/// }
+///
+/// impl GreenVisitor for Metrics {
+///     fn enter_node(&mut self, _node: &GreenNode) -> Visit {
+///         self.depth += 1;
+///         self.max_depth = self.max_depth.max(self.depth);
+///         Visit::Continue
+///     }
+///
+///     fn leave_node(&mut self, _node: &GreenNode) -> ControlFlow<()> {
+///         self.depth -= 1;
+///         ControlFlow::Continue(())
+///     }
+///
+///     fn token(&mut self, token: &GreenToken) -> ControlFlow<()> {
+///         if token.kind() == MySyntax::into_raw(Plus) {
+///             self.plus += 1;
+///         }
+///         ControlFlow::Continue(())
+///     }
+/// }
+///
+/// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+/// builder.start_node(Root);
+/// builder.start_node(Operation);
+/// builder.token(Int, "1");
+/// builder.static_token(Plus);
+/// builder.token(Int, "2");
+/// builder.finish_node();
+/// builder.finish_node();
+/// let (tree, _) = builder.finish();
+///
+/// let mut metrics = Metrics::default();
+/// assert!(tree.visit(&mut metrics).is_continue());
+/// assert_eq!((metrics.depth, metrics.max_depth, metrics.plus), (0, 2, 1));
+/// ```
+pub trait GreenVisitor {
+    /// Called before the children of `node` are visited.
+    fn enter_node(&mut self, _node: &GreenNode) -> Visit {
+        Visit::Continue
+    }
+
+    /// Called after the children of `node` were visited or [skipped](Visit::SkipChildren).
+    fn leave_node(&mut self, _node: &GreenNode) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// Called for every token.
+    fn token(&mut self, _token: &GreenToken) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+}
+
+impl<V: GreenVisitor + ?Sized> GreenVisitor for &mut V {
+    fn enter_node(&mut self, node: &GreenNode) -> Visit {
+        (**self).enter_node(node)
+    }
+
+    fn leave_node(&mut self, node: &GreenNode) -> ControlFlow<()> {
+        (**self).leave_node(node)
+    }
+
+    fn token(&mut self, token: &GreenToken) -> ControlFlow<()> {
+        (**self).token(token)
+    }
+}
+
+impl GreenNode {
+    /// Visits this node and all elements in its subtree in preorder with `visitor`.
+    ///
+    /// Returns [`ControlFlow::Break`] if the visitor stopped the traversal early and [`ControlFlow::Continue`]
+    /// otherwise. See [`GreenVisitor`] for an example.
+    pub fn visit(&self, visitor: &mut impl GreenVisitor) -> ControlFlow<()> {
+        match visitor.enter_node(self) {
+            Visit::Continue => {
+                for child in self.children() {
+                    match child {
+                        NodeOrToken::Node(node) => node.visit(visitor)?,
+                        NodeOrToken::Token(token) => visitor.token(token)?,
+                    }
+                }
+            }
+            Visit::SkipChildren => {}
+            Visit::Stop => return ControlFlow::Break(()),
+        }
+        visitor.leave_node(self)
+    }
+}
@@ -108,9 +108,19 @@ of the `GreenNodeBuilder` appropriately.
 
 mod traits;
 pub use self::traits::*;
+pub use smallvec::SmallVec;
 
 mod default_interner;
 
+mod key_map;
+pub use key_map::KeyMap;
+
+mod key_counts;
+pub use key_counts::KeyCounts;
+
+mod spill;
+pub use spill::{FileSpillStore, SpillError, SpillStore, SpillingInterner};
+
 #[cfg(not(feature = "lasso_compat"))]
 #[doc(inline)]
 pub use default_interner::TokenInterner;
@@ -4,6 +4,7 @@ use core::fmt;
 
 use fxhash::FxBuildHasher as Hasher;
 use indexmap::IndexSet;
+use smallvec::SmallVec;
 
 use super::{InternKey, Interner, Resolver, TokenKey};
 
@@ -67,4 +68,10 @@ impl Interner<TokenKey> for TokenInterner {
         let raw_key = u32::try_from(index).unwrap_or_else(|_| panic!("interned `{index}` despite keyspace exhaustion"));
         TokenKey::try_from_u32(raw_key).ok_or(InternerError::KeySpaceExhausted)
     }
+
+    fn try_get_or_intern_many(&mut self, texts: &[&str]) -> Result<SmallVec<[TokenKey; 16]>, Self::Error> {
+        // Make room for the case that every text is new up front, so that the set grows at most once per batch.
+        self.id_set.reserve(texts.len());
+        texts.iter().map(|text| self.try_get_or_intern(text)).collect()
+    }
 }
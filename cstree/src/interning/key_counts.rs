@@ -0,0 +1,121 @@
+use crate::{green::GreenNode, util::NodeOrToken};
+
+use super::{InternKey, TokenKey};
+
+/// The number of occurrences of each [`TokenKey`] in the tokens of one or more trees.
+///
+/// Interners only know which texts exist, not how often they are used. Counting occurrences lets tooling answer
+/// questions like "what are the most common identifiers?", and lets passes decide whether some transformation of the
+/// interned texts is worth it. Counts can be collected while building trees, by creating the builder's cache with
+/// [`NodeCache::with_key_counts`], or from finished trees with [`add_tree`](KeyCounts::add_tree). Only the keys of
+/// token texts are counted, tokens with [static text](crate::Syntax::static_text) have no key.
+///
+/// Like [`KeyMap`](super::KeyMap), the counts are indexed by key, so they are most compact for interners that hand
+/// out consecutive keys.
+///
+/// # Examples
+/// ```
+/// # use cstree::testing::*;
+/// use cstree::{build::NodeCache, interning::Resolver};
+///
+/// let mut cache = NodeCache::new().with_key_counts();
+/// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::with_cache(&mut cache);
+/// builder.start_node(Root);
+/// for name in ["x", "y", "x", "z", "x", "y"] {
+///     builder.token(Identifier, name);
+///     builder.static_token(Plus);
+/// }
+/// builder.finish_node();
+/// builder.finish();
+///
+/// let counts = cache.key_counts().unwrap();
+/// assert_eq!(counts.total(), 6);
+/// let common: Vec<_> = counts
+///     .most_common(2)
+///     .into_iter()
+///     .map(|(key, count)| (cache.interner().resolve(key), count))
+///     .collect();
+/// assert_eq!(common, [("x", 3), ("y", 2)]);
+/// ```
+///
+/// [`NodeCache::with_key_counts`]: crate::build::NodeCache::with_key_counts
+#[derive(Debug, Clone, Default)]
+pub struct KeyCounts {
+    /// The number of occurrences of each key, indexed by the key.
+    counts: Vec<u64>,
+    total:  u64,
+}
+
+impl KeyCounts {
+    /// Creates empty counts.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records `n` occurrences of `key`.
+    pub fn add(&mut self, key: TokenKey, n: u64) {
+        let index = key.into_u32() as usize;
+        if index >= self.counts.len() {
+            self.counts.resize(index + 1, 0);
+        }
+        self.counts[index] += n;
+        self.total += n;
+    }
+
+    /// Records the keys of all tokens in `tree`.
+    pub fn add_tree(&mut self, tree: &GreenNode) {
+        let mut stack = vec![tree.children()];
+        while let Some(children) = stack.last_mut() {
+            match children.next() {
+                Some(NodeOrToken::Node(node)) => stack.push(node.children()),
+                Some(NodeOrToken::Token(token)) => {
+                    if let Some(key) = token.text_key() {
+                        self.add(key, 1);
+                    }
+                }
+                None => {
+                    stack.pop();
+                }
+            }
+        }
+    }
+
+    /// The number of recorded occurrences of `key`.
+    #[inline]
+    pub fn get(&self, key: TokenKey) -> u64 {
+        self.counts.get(key.into_u32() as usize).copied().unwrap_or(0)
+    }
+
+    /// The number of recorded occurrences of all keys.
+    #[inline]
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Returns an iterator over all keys that occurred at least once and their number of occurrences, ordered by key.
+    pub fn iter(&self) -> impl Iterator<Item = (TokenKey, u64)> + '_ {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, &count)| count > 0)
+            .map(|(index, &count)| {
+                let key = TokenKey::try_from_u32(index as u32).expect("counted keys are valid");
+                (key, count)
+            })
+    }
+
+    /// Returns the (at most) `n` keys with the most occurrences and their number of occurrences, most common first.
+    /// Keys with the same number of occurrences are ordered by key.
+    pub fn most_common(&self, n: usize) -> Vec<(TokenKey, u64)> {
+        let mut keys: Vec<_> = self.iter().collect();
+        keys.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.into_u32().cmp(&b.into_u32())));
+        keys.truncate(n);
+        keys
+    }
+
+    /// Removes all recorded occurrences.
+    pub fn clear(&mut self) {
+        self.counts.clear();
+        self.total = 0;
+    }
+}
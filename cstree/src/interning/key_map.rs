@@ -0,0 +1,100 @@
+use super::{InternKey, Interner, Resolver, TokenKey};
+
+/// A mapping from the [`TokenKey`]s of one interner to the keys of the same texts in another interner.
+///
+/// This allows building trees against separate scratch interners, for example to parse files in parallel without
+/// contending on a shared interner, and to move them to a shared master interner afterwards. Computing the map with
+/// [`KeyMap::reintern`] only interns each distinct text once, after which [`GreenNode::remap_keys`] rewrites a tree
+/// in a single pass without resolving any text.
+///
+/// The map is indexed by the old keys, so it is most compact for interners that hand out consecutive keys, like the
+/// interners provided by `cstree`.
+///
+/// # Examples
+/// ```
+/// # use cstree::testing::*;
+/// use cstree::interning::{new_interner, KeyMap, Resolver};
+///
+/// let mut master = new_interner();
+/// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+/// builder.start_node(Root);
+/// builder.token(Identifier, "x");
+/// builder.static_token(Plus);
+/// builder.token(Identifier, "x");
+/// builder.finish_node();
+/// let (tree, cache) = builder.finish();
+/// let scratch = cache.unwrap().into_interner().unwrap();
+///
+/// let map = KeyMap::reintern(tree.token_keys(), &scratch, &mut master);
+/// assert_eq!(map.len(), 1);
+/// let tree = tree.remap_keys(&map);
+/// let x = tree.children().next().unwrap().into_token().unwrap();
+/// assert_eq!(x.text(&master), Some("x"));
+/// ```
+///
+/// [`GreenNode::remap_keys`]: crate::green::GreenNode::remap_keys
+#[derive(Debug, Clone, Default)]
+pub struct KeyMap {
+    /// The new key of each old key, indexed by the old key.
+    keys: Vec<Option<TokenKey>>,
+    len: usize,
+}
+
+impl KeyMap {
+    /// Creates an empty map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a map from all `keys`, which are resolved with `from`, to the keys of the same texts in `into`,
+    /// interning all texts that `into` does not contain yet.
+    ///
+    /// `keys` may contain duplicates, each distinct key is only resolved and interned once.
+    ///
+    /// ## Panics
+    /// If a key cannot be resolved by `from`, or if interning a text into `into` fails.
+    pub fn reintern<R, I>(keys: impl IntoIterator<Item = TokenKey>, from: &R, into: &mut I) -> Self
+    where
+        R: Resolver<TokenKey> + ?Sized,
+        I: Interner<TokenKey> + ?Sized,
+    {
+        let mut map = Self::new();
+        for key in keys {
+            if map.get(key).is_none() {
+                map.insert(key, into.get_or_intern(from.resolve(key)));
+            }
+        }
+        map
+    }
+
+    /// Maps `from` to `to`, returning the key `from` was previously mapped to, if any.
+    pub fn insert(&mut self, from: TokenKey, to: TokenKey) -> Option<TokenKey> {
+        let index = from.into_u32() as usize;
+        if index >= self.keys.len() {
+            self.keys.resize(index + 1, None);
+        }
+        let previous = self.keys[index].replace(to);
+        if previous.is_none() {
+            self.len += 1;
+        }
+        previous
+    }
+
+    /// Returns the key that `from` is mapped to, if any.
+    #[inline]
+    pub fn get(&self, from: TokenKey) -> Option<TokenKey> {
+        self.keys.get(from.into_u32() as usize).copied().flatten()
+    }
+
+    /// The number of keys in the map.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the map does not contain any keys.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
@@ -4,8 +4,9 @@
 
 use std::{hash::BuildHasher, num::NonZeroUsize};
 
-use fxhash::FxBuildHasher as Hasher;
+use fxhash::{FxBuildHasher as Hasher, FxHashMap};
 use lasso::{Capacity, Rodeo, ThreadedRodeo};
+use smallvec::SmallVec;
 
 use crate::interning::{Interner, Resolver, TokenKey};
 
@@ -45,10 +46,39 @@ macro_rules! impl_traits {
             fn get_or_intern(&mut self, text: &str) -> TokenKey {
                 self.rodeo.get_or_intern(text)
             }
+
+            fn try_get_or_intern_many(&mut self, texts: &[&str]) -> Result<SmallVec<[TokenKey; 16]>, Self::Error> {
+                intern_many(texts, |text| self.rodeo.try_get_or_intern(text))
+            }
         }
     };
 }
 
+/// Interns a batch of `texts` with `intern`, which is only called once for every distinct text in the batch.
+///
+/// Lexers produce the same identifiers and punctuation over and over, so batches contain many repeated texts. Looking
+/// them up in a small map local to the batch avoids hashing them into the interner again, and for the
+/// [`MultiThreadedTokenInterner`] avoids locking a shard of the shared map for each of them.
+fn intern_many<'t, E>(
+    texts: &[&'t str],
+    mut intern: impl FnMut(&str) -> Result<TokenKey, E>,
+) -> Result<SmallVec<[TokenKey; 16]>, E> {
+    let mut batch: FxHashMap<&'t str, TokenKey> = FxHashMap::default();
+    texts
+        .iter()
+        .map(|&text| {
+            if let Some(&key) = batch.get(text) {
+                return Ok(key);
+            }
+            #[cfg(test)]
+            tests::INTERNED.with(|interned| interned.set(interned.get() + 1));
+            let key = intern(text)?;
+            batch.insert(text, key);
+            Ok(key)
+        })
+        .collect()
+}
+
 /// The default [`Interner`] used to deduplicate green token strings.
 #[derive(Debug)]
 pub struct TokenInterner {
@@ -101,9 +131,113 @@ mod multi_threaded {
                 ),
             }
         }
+
+        /// Converts this interner into a single-threaded [`TokenInterner`], which is cheaper to use once no more
+        /// concurrent access is needed.
+        ///
+        /// All keys of this interner remain valid and resolve to the same text, so trees built with it can be
+        /// resolved with the returned interner.
+        ///
+        /// # Examples
+        /// ```
+        /// # use cstree::interning::*;
+        /// let interner = new_threaded_interner();
+        /// let key = (&interner).get_or_intern("foo");
+        /// let interner = interner.into_single_threaded();
+        /// assert_eq!(interner.resolve(key), "foo");
+        /// let interner = interner.into_multi_threaded();
+        /// assert_eq!(interner.resolve(key), "foo");
+        /// ```
+        #[inline]
+        pub fn into_single_threaded(self) -> TokenInterner {
+            self.into()
+        }
+    }
+
+    impl TokenInterner {
+        /// Converts this interner into a [`MultiThreadedTokenInterner`] that can be shared between threads.
+        ///
+        /// All keys of this interner remain valid and resolve to the same text, so trees built with it can be
+        /// resolved with the returned interner.
+        #[cfg_attr(doc_cfg, doc(cfg(feature = "multi_threaded_interning")))]
+        #[inline]
+        pub fn into_multi_threaded(self) -> MultiThreadedTokenInterner {
+            self.into()
+        }
+    }
+
+    /// Converts a single-threaded interner into a threadsafe one.
+    ///
+    /// All keys of `interner` remain valid and resolve to the same text, so trees built with the single-threaded
+    /// interner can be resolved with the new one.
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "multi_threaded_interning")))]
+    impl From<TokenInterner> for MultiThreadedTokenInterner {
+        fn from(interner: TokenInterner) -> Self {
+            let threaded = Self::new();
+            // `Rodeo` iterates in key order and `ThreadedRodeo` assigns keys sequentially
+            for (key, text) in interner.rodeo.iter() {
+                let new_key = threaded.rodeo.get_or_intern(text);
+                debug_assert_eq!(key, new_key);
+            }
+            threaded
+        }
+    }
+
+    /// Converts a threadsafe interner into a single-threaded one.
+    ///
+    /// All keys of `interner` remain valid and resolve to the same text, so trees built with the threadsafe interner
+    /// can be resolved with the new one.
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "multi_threaded_interning")))]
+    impl From<MultiThreadedTokenInterner> for TokenInterner {
+        fn from(interner: MultiThreadedTokenInterner) -> Self {
+            let mut single = Self::new();
+            // `ThreadedRodeo` does not iterate in key order, so keys are re-interned in order explicitly
+            for index in 0..interner.rodeo.len() {
+                let key = <TokenKey as lasso::Key>::try_from_usize(index).unwrap();
+                let new_key = single.rodeo.get_or_intern(interner.rodeo.resolve(&key));
+                debug_assert_eq!(key, new_key);
+            }
+            single
+        }
     }
 
     impl_traits!(for MultiThreadedTokenInterner, if #[cfg(feature = "multi_threaded_interning")]);
 
     impl_traits!(for &MultiThreadedTokenInterner, if #[cfg(feature = "multi_threaded_interning")]);
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    thread_local! {
+        /// The number of texts that [`intern_many`] passed to the interner on this thread.
+        pub(super) static INTERNED: Cell<usize> = const { Cell::new(0) };
+    }
+
+    fn check_batch(mut interner: impl Interner<TokenKey>) {
+        let texts = ["let", "x", "=", "x", "+", "x", ";", "let"];
+        INTERNED.with(|interned| interned.set(0));
+        let keys = interner.get_or_intern_many(&texts);
+        // every distinct text was interned exactly once
+        assert_eq!(INTERNED.with(Cell::get), 5);
+        assert_eq!(keys[1], keys[3]);
+        assert_eq!(keys[0], keys[7]);
+        for (key, text) in keys.iter().zip(texts) {
+            assert_eq!(interner.resolve(*key), text);
+            assert_eq!(interner.get_or_intern(text), *key);
+        }
+    }
+
+    #[test]
+    fn intern_many_once_per_text() {
+        check_batch(TokenInterner::new());
+        #[cfg(feature = "multi_threaded_interning")]
+        {
+            check_batch(MultiThreadedTokenInterner::new());
+            check_batch(&MultiThreadedTokenInterner::new());
+        }
+    }
+}
@@ -0,0 +1,347 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use fxhash::FxHashMap;
+use parking_lot::Mutex;
+use smallvec::SmallVec;
+
+use super::{InternKey, Interner, Resolver, TokenKey};
+
+/// Secondary storage for the texts that a [`SpillingInterner`] moves out of memory.
+///
+/// Stores are append-only: texts are never removed, and the ids returned by [`store`](SpillStore::store) stay valid for
+/// the lifetime of the store. Besides the [file-based store](FileSpillStore), implementations might, for example, keep
+/// the texts in a compressed in-memory arena.
+pub trait SpillStore {
+    /// Writes `text` to the store and returns an id to [`load`](SpillStore::load) it with.
+    fn store(&mut self, text: &str) -> io::Result<u64>;
+
+    /// Reads the text that was stored with `id`.
+    fn load(&self, id: u64) -> io::Result<String>;
+}
+
+/// A [`SpillStore`] that appends the texts to a file.
+///
+/// Each text is written with a length prefix at the end of the file, and its offset is used as its id. The file is
+/// not deleted when the store is dropped, so it is usually a temporary file that has been opened for reading and
+/// writing.
+#[derive(Debug)]
+pub struct FileSpillStore {
+    file: Mutex<File>,
+    len:  u64,
+}
+
+impl FileSpillStore {
+    /// Creates a store that writes to the end of `file`, which has to be opened for reading and writing.
+    pub fn new(mut file: File) -> io::Result<Self> {
+        let len = file.seek(SeekFrom::End(0))?;
+        Ok(Self {
+            file: Mutex::new(file),
+            len,
+        })
+    }
+
+    /// The number of bytes written to the file, including the bytes that were already in it.
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns `true` if nothing has been written to the file.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl SpillStore for FileSpillStore {
+    fn store(&mut self, text: &str) -> io::Result<u64> {
+        let len = u32::try_from(text.len()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "text too long"))?;
+        let file = self.file.get_mut();
+        file.seek(SeekFrom::Start(self.len))?;
+        file.write_all(&len.to_le_bytes())?;
+        file.write_all(text.as_bytes())?;
+        let id = self.len;
+        self.len += 4 + u64::from(len);
+        Ok(id)
+    }
+
+    fn load(&self, id: u64) -> io::Result<String> {
+        let mut file = self.file.lock();
+        file.seek(SeekFrom::Start(id))?;
+        let mut len = [0; 4];
+        file.read_exact(&mut len)?;
+        let mut bytes = vec![0; u32::from_le_bytes(len) as usize];
+        file.read_exact(&mut bytes)?;
+        String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// The errors that can occur when interning text with a [`SpillingInterner`].
+#[derive(Debug)]
+pub enum SpillError {
+    /// All keys have been handed out.
+    KeySpaceExhausted,
+    /// The [`SpillStore`] failed to store or load a text.
+    Io(io::Error),
+}
+
+impl fmt::Display for SpillError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpillError::KeySpaceExhausted => write!(f, "key space exhausted"),
+            SpillError::Io(e) => write!(f, "spill store error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for SpillError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SpillError::KeySpaceExhausted => None,
+            SpillError::Io(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for SpillError {
+    fn from(e: io::Error) -> Self {
+        SpillError::Io(e)
+    }
+}
+
+#[derive(Debug)]
+enum Text {
+    Resident(Box<str>),
+    Spilled(u64),
+}
+
+#[derive(Debug)]
+struct Entry {
+    text:      Text,
+    /// The tick of the interner when the text was last interned or resolved.
+    last_used: AtomicU64,
+}
+
+/// An [`Interner`] that caps the memory used by long texts, by moving the least recently used of them to a
+/// [`SpillStore`].
+///
+/// Texts of at least [`min_len`](SpillingInterner::with_min_len) bytes count towards the memory budget of the
+/// interner, shorter texts are always kept in memory. When interning a new long text would exceed the budget, the long
+/// texts that have not been interned or resolved for the longest time are written to the store and dropped from
+/// memory, until the resident long texts take up at most three quarters of the budget. Keys stay valid when their text
+/// is spilled, and resolving them reads the text back from the store transparently.
+///
+/// Since [`Resolver::try_resolve`] returns text that lives as long as the shared borrow of the interner, texts that are
+/// read back are kept in memory until the next call that takes the interner mutably, like interning another text or
+/// [`trim`](SpillingInterner::trim). They count towards the budget and are dropped first when it is exceeded.
+/// If the store fails to read a text, it cannot be resolved.
+///
+/// # Examples
+/// ```
+/// use cstree::interning::{FileSpillStore, Interner, Resolver, SpillingInterner};
+/// # let path = std::env::temp_dir().join(format!("cstree-spill-doctest-{}", std::process::id()));
+/// let file = std::fs::OpenOptions::new()
+///     .read(true)
+///     .write(true)
+///     .create(true)
+///     .truncate(true)
+///     .open(&path)?;
+///
+/// // Keep at most 100 bytes of texts that are 16 bytes or longer in memory.
+/// let mut interner = SpillingInterner::new(FileSpillStore::new(file)?, 100).with_min_len(16);
+/// let keys: Vec<_> = (0..10)
+///     .map(|i| interner.get_or_intern(&format!("a_long_identifier_{i}")))
+///     .collect();
+/// assert!(interner.resident_bytes() <= 100);
+/// assert!(interner.spilled() > 0);
+/// assert_eq!(interner.resolve(keys[0]), "a_long_identifier_0");
+/// assert_eq!(interner.get_or_intern("a_long_identifier_0"), keys[0]);
+/// # std::fs::remove_file(&path)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct SpillingInterner<St = FileSpillStore> {
+    store:          St,
+    entries:        Vec<Entry>,
+    /// The keys of all texts with the same hash.
+    lookup:         FxHashMap<u64, SmallVec<[u32; 1]>>,
+    /// Spilled texts that have been read back from the store, by key.
+    reloaded:       Mutex<FxHashMap<u32, Box<str>>>,
+    reloaded_bytes: AtomicU64,
+    resident_bytes: usize,
+    spilled:        usize,
+    budget:         usize,
+    min_len:        usize,
+    tick:           AtomicU64,
+}
+
+// `TokenKey` can represent `u32::MAX` different keys, see the default interner.
+const N_INDICES: usize = u32::MAX as usize;
+
+impl<St: SpillStore> SpillingInterner<St> {
+    /// Creates an interner that keeps at most `budget` bytes of long texts in memory and spills the rest to `store`.
+    /// Texts are considered long if they have at least 64 bytes.
+    pub fn new(store: St, budget: usize) -> Self {
+        Self {
+            store,
+            entries: Vec::new(),
+            lookup: FxHashMap::default(),
+            reloaded: Mutex::new(FxHashMap::default()),
+            reloaded_bytes: AtomicU64::new(0),
+            resident_bytes: 0,
+            spilled: 0,
+            budget,
+            min_len: 64,
+            tick: AtomicU64::new(0),
+        }
+    }
+
+    /// Sets the length in bytes from which texts count towards the budget and may be spilled.
+    pub fn with_min_len(mut self, min_len: usize) -> Self {
+        self.min_len = min_len;
+        self
+    }
+
+    /// The number of bytes of long texts that are currently in memory, including texts read back from the store.
+    pub fn resident_bytes(&self) -> usize {
+        self.resident_bytes + self.reloaded_bytes.load(Ordering::Relaxed) as usize
+    }
+
+    /// The number of texts that have been moved to the store.
+    #[inline]
+    pub fn spilled(&self) -> usize {
+        self.spilled
+    }
+
+    /// The store that spilled texts are written to.
+    #[inline]
+    pub fn store(&self) -> &St {
+        &self.store
+    }
+
+    /// Drops all texts that have been read back from the store from memory.
+    pub fn trim(&mut self) {
+        self.reloaded.get_mut().clear();
+        *self.reloaded_bytes.get_mut() = 0;
+    }
+
+    fn touch(&self, entry: &Entry) {
+        entry
+            .last_used
+            .store(self.tick.fetch_add(1, Ordering::Relaxed), Ordering::Relaxed);
+    }
+
+    fn text(&self, index: u32) -> io::Result<&str> {
+        let entry = &self.entries[index as usize];
+        self.touch(entry);
+        let id = match entry.text {
+            Text::Resident(ref text) => return Ok(text),
+            Text::Spilled(id) => id,
+        };
+        let mut reloaded = self.reloaded.lock();
+        let text: &str = match reloaded.get(&index) {
+            Some(text) => text,
+            None => {
+                let text = self.store.load(id)?.into_boxed_str();
+                self.reloaded_bytes.fetch_add(text.len() as u64, Ordering::Relaxed);
+                reloaded.entry(index).or_insert(text)
+            }
+        };
+        let text: *const str = text;
+        // Safety: the text is boxed, so it does not move when the map grows, and it is only removed from the map
+        // through `&mut self`, which cannot exist while the returned `&str` borrows `self`.
+        Ok(unsafe { &*text })
+    }
+
+    /// Spills the least recently used long texts until `additional` more bytes fit into three quarters of the budget.
+    fn make_room(&mut self, additional: usize) -> io::Result<()> {
+        if self.resident_bytes() + additional <= self.budget {
+            return Ok(());
+        }
+        self.trim();
+        let target = (self.budget / 4 * 3).saturating_sub(additional);
+        if self.resident_bytes <= target {
+            return Ok(());
+        }
+        let mut candidates: Vec<_> = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter_map(|(index, entry)| match &entry.text {
+                Text::Resident(text) if text.len() >= self.min_len => {
+                    Some((entry.last_used.load(Ordering::Relaxed), index))
+                }
+                _ => None,
+            })
+            .collect();
+        candidates.sort_unstable();
+        for (_, index) in candidates {
+            if self.resident_bytes <= target {
+                break;
+            }
+            let entry = &mut self.entries[index];
+            let Text::Resident(text) = &entry.text else {
+                unreachable!("only resident texts are spilled")
+            };
+            let len = text.len();
+            entry.text = Text::Spilled(self.store.store(text)?);
+            self.resident_bytes -= len;
+            self.spilled += 1;
+        }
+        Ok(())
+    }
+}
+
+impl<St: SpillStore> Resolver<TokenKey> for SpillingInterner<St> {
+    fn try_resolve(&self, key: TokenKey) -> Option<&str> {
+        let index = key.into_u32();
+        if index as usize >= self.entries.len() {
+            return None;
+        }
+        self.text(index).ok()
+    }
+}
+
+impl<St: SpillStore> Interner<TokenKey> for SpillingInterner<St> {
+    type Error = SpillError;
+
+    fn try_get_or_intern(&mut self, text: &str) -> Result<TokenKey, Self::Error> {
+        let hash = fxhash::hash64(text);
+        for &index in self.lookup.get(&hash).into_iter().flatten() {
+            if self.text(index)? == text {
+                return Ok(TokenKey::try_from_u32(index).expect("interned keys are valid"));
+            }
+        }
+        if self.entries.len() >= N_INDICES {
+            return Err(SpillError::KeySpaceExhausted);
+        }
+
+        if text.len() >= self.min_len {
+            self.make_room(text.len())?;
+            self.resident_bytes += text.len();
+        }
+        let index = self.entries.len() as u32;
+        self.entries.push(Entry {
+            text:      Text::Resident(text.into()),
+            last_used: AtomicU64::new(self.tick.fetch_add(1, Ordering::Relaxed)),
+        });
+        self.lookup.entry(hash).or_default().push(index);
+        TokenKey::try_from_u32(index).ok_or(SpillError::KeySpaceExhausted)
+    }
+}
+
+impl<St> fmt::Debug for SpillingInterner<St> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SpillingInterner")
+            .field("len", &self.entries.len())
+            .field("resident_bytes", &self.resident_bytes)
+            .field("spilled", &self.spilled)
+            .field("budget", &self.budget)
+            .field("min_len", &self.min_len)
+            .finish_non_exhaustive()
+    }
+}
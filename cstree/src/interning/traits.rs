@@ -1,5 +1,7 @@
 use core::fmt;
 
+use smallvec::SmallVec;
+
 use super::TokenKey;
 
 /// Common interface for all intern keys via conversion to and from `u32`.
@@ -64,4 +66,34 @@ pub trait Interner<Key: InternKey = TokenKey>: Resolver<Key> {
         self.try_get_or_intern(text)
             .unwrap_or_else(|_| panic!("failed to intern `{text:?}`"))
     }
+
+    /// Interns all `texts` and returns their intern keys, in the same order.
+    ///
+    /// This is useful for lexers that produce many tokens at once. The default implementation interns each text
+    /// separately, but interners may override it to intern the whole batch more efficiently. `cstree`'s own interners
+    /// only intern each distinct text of the batch once. Interning stops at the first text that fails to be interned.
+    fn try_get_or_intern_many(&mut self, texts: &[&str]) -> Result<SmallVec<[Key; 16]>, Self::Error> {
+        texts.iter().map(|text| self.try_get_or_intern(text)).collect()
+    }
+
+    /// Interns all `texts` and returns their intern keys, in the same order.
+    ///
+    /// See also [`try_get_or_intern_many`](Interner::try_get_or_intern_many).
+    ///
+    /// # Panics
+    /// Panics if the internment process raises an [`Error`](Interner::Error) for any of the `texts`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::interning::*;
+    /// let mut interner = new_interner();
+    /// let keys = interner.get_or_intern_many(&["let", "x", "=", "x"]);
+    /// assert_eq!(keys.len(), 4);
+    /// assert_eq!(keys[1], keys[3]);
+    /// assert_eq!(interner.resolve(keys[0]), "let");
+    /// ```
+    fn get_or_intern_many(&mut self, texts: &[&str]) -> SmallVec<[Key; 16]> {
+        self.try_get_or_intern_many(texts)
+            .unwrap_or_else(|_| panic!("failed to intern {texts:?}"))
+    }
 }
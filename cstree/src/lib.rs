@@ -96,6 +96,7 @@
 #![doc(html_root_url = "https://docs.rs/cstree/0.12.1")]
 #![cfg_attr(doc_cfg, feature(doc_cfg))]
 
+pub mod binary;
 pub mod getting_started;
 
 #[allow(unsafe_code)]
@@ -106,21 +107,111 @@ pub mod syntax;
 #[allow(unsafe_code)]
 pub mod interning;
 
+pub mod ast;
+pub mod lower;
+#[cfg(feature = "multi_threaded_interning")]
+#[cfg_attr(doc_cfg, doc(cfg(feature = "multi_threaded_interning")))]
+pub mod parallel;
+pub mod pretty;
+pub mod recovery;
+pub mod schema;
+
+mod line_index;
 #[cfg(feature = "serialize")]
 mod serde_impls;
+mod text_edit;
 #[allow(missing_docs)]
 mod utility_types;
 
-use std::fmt;
+use std::{fmt, ops::RangeInclusive};
 
 /// `RawSyntaxKind` is a type tag for each token or node.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct RawSyntaxKind(pub u32);
 
+impl RawSyntaxKind {
+    /// Creates a `RawSyntaxKind` from `value` if it is in the range of valid kinds of `S`, see
+    /// [`Syntax::RAW_KINDS`].
+    #[inline]
+    pub fn try_from_u32<S: Syntax>(value: u32) -> Option<Self> {
+        S::RAW_KINDS.contains(&value).then_some(Self(value))
+    }
+
+    /// Returns `true` if this kind is in the range of valid kinds of `S`, see [`Syntax::RAW_KINDS`].
+    #[inline]
+    pub fn is_valid_for<S: Syntax>(self) -> bool {
+        S::RAW_KINDS.contains(&self.0)
+    }
+}
+
+/// A coarse classification of syntax kinds, returned by [`Syntax::class`].
+///
+/// Generic tools like syntax highlighters, formatters and tree viewers can ask for the class of a kind instead of
+/// keeping their own table of kinds. The classes of tokens and nodes are mixed in one enum, so that a single function
+/// can classify all kinds of a syntax.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum KindClass {
+    /// Any kind that does not belong to one of the other classes.
+    Other,
+    /// Whitespace tokens, including line breaks.
+    Whitespace,
+    /// Comment tokens.
+    Comment,
+    /// Documentation comment tokens.
+    DocComment,
+    /// Keyword tokens.
+    Keyword,
+    /// Identifier tokens.
+    Identifier,
+    /// Literal tokens, like numbers and strings.
+    Literal,
+    /// Punctuation and operator tokens.
+    Punctuation,
+    /// Tokens and nodes that represent errors, see [`Syntax::is_error_token`].
+    Error,
+    /// Expression nodes.
+    Expression,
+    /// Statement nodes.
+    Statement,
+    /// Item nodes, like function and type definitions.
+    Item,
+}
+
+impl KindClass {
+    /// Returns the [`TriviaKind`](syntax::TriviaKind) of the kinds of this class, or `None` if they are not trivia.
+    #[inline]
+    pub fn trivia_kind(self) -> Option<syntax::TriviaKind> {
+        match self {
+            Self::Whitespace => Some(syntax::TriviaKind::Whitespace),
+            Self::Comment => Some(syntax::TriviaKind::Comment),
+            Self::DocComment => Some(syntax::TriviaKind::DocComment),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` for whitespace and comments.
+    #[inline]
+    pub fn is_trivia(self) -> bool {
+        self.trivia_kind().is_some()
+    }
+}
+
 /// Typesafe representations of text ranges and sizes.
 pub mod text {
-    pub use crate::syntax::SyntaxText;
+    pub use crate::{
+        line_index::{ColumnUnit, LineCol, LineIndex},
+        syntax::SyntaxText,
+        text_edit::TextEdit,
+    };
     pub use text_size::{TextLen, TextRange, TextSize};
+
+    #[cfg(feature = "arbitrary")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "arbitrary")))]
+    pub use arbitrary;
+
+    #[cfg(feature = "regex")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "regex")))]
+    pub use regex_automata;
 }
 
 /// A tree builder for the construction of syntax trees.
@@ -129,7 +220,15 @@ pub mod text {
 /// section](../index.html#getting-started) from the top-level documentation for an introduction to how to build a
 /// syntax tree.
 pub mod build {
-    pub use crate::green::builder::{Checkpoint, GreenNodeBuilder, NodeCache};
+    pub use crate::green::{
+        builder::{
+            CacheStats, Checkpoint, DedupCache, GreenCache, GreenNodeBuilder, LocalNodeCache, NoCache, NodeCache,
+            NodeCaching, NodeGuard, StaticTextPolicy, ThreadedNodeCache, TieredCache,
+        },
+        cancel::{CancellationToken, Cancelled},
+        replay::{BuildEvent, Progress},
+        store::NodeStore,
+    };
 }
 
 /// A convenient collection of the most used parts of `cstree`.
@@ -190,9 +289,26 @@ pub mod sync {
 ///
 /// [`SyntaxNode`]: crate::syntax::SyntaxNode
 pub trait Syntax: Sized + Copy + fmt::Debug + Eq {
+    /// The range of raw kinds that represent a valid kind of this syntax.
+    ///
+    /// [`from_raw`](Syntax::from_raw) may panic for raw kinds outside of this range, so `cstree` checks that all kinds
+    /// are in range when reading syntax trees from untrusted sources, such as when deserializing a tree. Kinds
+    /// outside of this range can also be used as reserved kinds that are never part of a valid tree.
+    ///
+    /// By default, all raw kinds are considered valid. The derive macro sets this to the range of the enum's
+    /// variants.
+    const RAW_KINDS: RangeInclusive<u32> = 0..=u32::MAX;
+
     /// Construct a semantic item kind from the compact representation.
     fn from_raw(raw: RawSyntaxKind) -> Self;
 
+    /// Construct a semantic item kind from the compact representation if `raw` is in the range of valid kinds
+    /// ([`RAW_KINDS`](Syntax::RAW_KINDS)).
+    #[inline]
+    fn try_from_raw(raw: RawSyntaxKind) -> Option<Self> {
+        raw.is_valid_for::<Self>().then(|| Self::from_raw(raw))
+    }
+
     /// Convert a semantic item kind into a more compact representation.
     fn into_raw(self) -> RawSyntaxKind;
 
@@ -204,6 +320,44 @@ pub trait Syntax: Sized + Copy + fmt::Debug + Eq {
     /// it faster to add them to a syntax tree and to look up their text. Since there can often be many occurrences
     /// of these tokens inside a file, doing so will improve the performance of using `cstree`.
     fn static_text(self) -> Option<&'static str>;
+
+    /// Whether tokens of this kind represent lexical errors, like an unterminated string literal or a character that
+    /// cannot start any token.
+    ///
+    /// Keeping such tokens in the tree lets [`SyntaxNode::error_tokens`](syntax::SyntaxNode::error_tokens) find all
+    /// lexical errors of a tree, instead of keeping a separate list of errors in sync with the tree. With the
+    /// `error_messages` feature, error tokens can also carry the id of an error message, see
+    /// [`GreenNodeBuilder::error_token`](build::GreenNodeBuilder::error_token). The derive macro returns `true` for
+    /// variants marked with `#[error_token]`. Returns `false` by default.
+    fn is_error_token(self) -> bool {
+        false
+    }
+
+    /// The [class](KindClass) of this kind, which generic tools use to treat kinds of different syntaxes alike.
+    ///
+    /// For example, [`TriviaPolicy::from_classes`](syntax::TriviaPolicy::from_classes) and the default
+    /// [`Format::trivia_kind`](pretty::Format::trivia_kind) treat whitespace and comments as trivia, and
+    /// [`SyntaxNode::tokens_of_class`](syntax::SyntaxNode::tokens_of_class) finds all tokens of a class. The derive
+    /// macro returns the class given with `#[class(...)]`, like `#[class(Keyword)]`. By default, returns
+    /// [`KindClass::Error`] for [error tokens](Syntax::is_error_token) and [`KindClass::Other`] for all other kinds.
+    fn class(self) -> KindClass {
+        if self.is_error_token() {
+            KindClass::Error
+        } else {
+            KindClass::Other
+        }
+    }
+
+    /// A human-readable name for the kind represented by `raw`.
+    ///
+    /// If provided, this is used in place of the kind's `Debug` representation when printing syntax nodes, tokens and
+    /// [`GreenNode::debug_kinds`](green::GreenNode::debug_kinds), which lets you write `println!("{node:?}")`
+    /// without a [`Resolver`](interning::Resolver) in scope. The derive macro implements this with the names of the
+    /// enum variants. Returns `None` by default.
+    fn kind_name(raw: RawSyntaxKind) -> Option<&'static str> {
+        let _ = raw;
+        None
+    }
 }
 
 #[cfg(feature = "derive")]
@@ -215,12 +369,57 @@ extern crate cstree_derive;
 /// Derive macro available if `cstree` is built with `features = ["derive"]`.
 pub use cstree_derive::Syntax;
 
-#[doc(hidden)]
+/// Utilities for testing and benchmarking code that works with `cstree` syntax trees.
 #[allow(unsafe_code, unused)]
 pub mod testing {
+    #[doc(hidden)]
     pub use crate::prelude::*;
+    use crate::KindClass;
+
+    #[doc(hidden)]
     pub fn parse<S: Syntax, I>(_b: &mut GreenNodeBuilder<S, I>, _s: &str) {}
 
+    /// A [`Syntax`] in which every raw kind is a valid kind.
+    ///
+    /// This lets tests and benchmarks build syntax trees with arbitrary kinds, without having to define a syntax kind
+    /// enum and implement `Syntax` for it first. No kind has a [`static_text`](Syntax::static_text).
+    ///
+    /// # Example
+    /// ```
+    /// use cstree::{build::GreenNodeBuilder, syntax::SyntaxNode, testing::AnyKind};
+    ///
+    /// let mut builder: GreenNodeBuilder<AnyKind> = GreenNodeBuilder::new();
+    /// builder.start_node(AnyKind(0));
+    /// builder.token(AnyKind(1), "hello");
+    /// builder.finish_node();
+    /// let (green, _) = builder.finish();
+    /// let root: SyntaxNode<AnyKind> = SyntaxNode::new_root(green);
+    /// assert_eq!(root.first_token().unwrap().kind(), AnyKind(1));
+    /// ```
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct AnyKind(pub u32);
+
+    impl Syntax for AnyKind {
+        fn from_raw(raw: RawSyntaxKind) -> Self {
+            Self(raw.0)
+        }
+
+        fn into_raw(self) -> RawSyntaxKind {
+            RawSyntaxKind(self.0)
+        }
+
+        fn static_text(self) -> Option<&'static str> {
+            None
+        }
+    }
+
+    impl From<u32> for AnyKind {
+        fn from(raw: u32) -> Self {
+            Self(raw)
+        }
+    }
+
+    #[doc(hidden)]
     #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     #[repr(u32)]
     #[allow(non_camel_case_types)]
@@ -232,9 +431,12 @@ pub mod testing {
         Operation,
         Root,
         Whitespace,
+        Error,
         __LAST,
     }
+    #[doc(hidden)]
     pub type MySyntax = TestSyntaxKind;
+    #[doc(hidden)]
     pub use TestSyntaxKind::*;
 
     impl Syntax for TestSyntaxKind {
@@ -253,5 +455,21 @@ pub mod testing {
                 _ => None,
             }
         }
+
+        fn is_error_token(self) -> bool {
+            self == TestSyntaxKind::Error
+        }
+
+        fn class(self) -> KindClass {
+            match self {
+                TestSyntaxKind::Plus => KindClass::Punctuation,
+                TestSyntaxKind::Identifier => KindClass::Identifier,
+                TestSyntaxKind::Int | TestSyntaxKind::Float => KindClass::Literal,
+                TestSyntaxKind::Operation => KindClass::Expression,
+                TestSyntaxKind::Whitespace => KindClass::Whitespace,
+                TestSyntaxKind::Error => KindClass::Error,
+                TestSyntaxKind::Root | TestSyntaxKind::__LAST => KindClass::Other,
+            }
+        }
     }
 }
@@ -0,0 +1,618 @@
+//! Conversion between text offsets and line/column positions.
+
+use crate::text::{TextRange, TextSize};
+
+/// A zero-based line and column position in a text.
+///
+/// The unit in which `col` is counted depends on the context the position is used in, see [`ColumnUnit`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LineCol {
+    /// The zero-based line number.
+    pub line: u32,
+    /// The zero-based column within the line.
+    pub col:  u32,
+}
+
+/// The unit in which columns of a [`LineCol`] are counted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColumnUnit {
+    /// Columns are counted in bytes of the UTF-8 encoded text. This is how [`TextSize`] measures text.
+    Utf8,
+    /// Columns are counted in UTF-16 code units, as is the default for the Language Server Protocol.
+    Utf16,
+    /// Columns are counted in Unicode scalar values, i.e. Rust `char`s. This is the same as counting UTF-32 code
+    /// units.
+    Char,
+    /// Columns are counted in extended grapheme clusters, which is closest to what a user perceives as a single
+    /// character.
+    ///
+    /// Since lines are split at `\n`, a `\r` before the end of a line always counts as its own grapheme.
+    #[cfg(feature = "unicode_graphemes")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "unicode_graphemes")))]
+    Grapheme,
+}
+
+impl ColumnUnit {
+    /// The length of `c` in this unit, where graphemes are counted as `char`s.
+    fn char_len(self, c: char) -> u32 {
+        match self {
+            ColumnUnit::Utf8 => c.len_utf8() as u32,
+            ColumnUnit::Utf16 => c.len_utf16() as u32,
+            _ => 1,
+        }
+    }
+
+    #[cfg(feature = "unicode_graphemes")]
+    fn is_grapheme(self) -> bool {
+        self == ColumnUnit::Grapheme
+    }
+}
+
+/// Maps between byte offsets into a text and line/column positions.
+///
+/// Lines are terminated by `\n`. A `\r` preceding the `\n` is considered part of the line's content.
+///
+/// # Example
+/// ```
+/// # use cstree::text::{ColumnUnit, LineCol, LineIndex, TextSize};
+/// let index = LineIndex::new("let x = 1;\nlet ü = 2;\n");
+/// assert_eq!(index.line_count(), 3);
+/// assert_eq!(
+///     index.line_col(TextSize::from(15)),
+///     LineCol { line: 1, col: 4 }
+/// );
+///
+/// let equals = LineCol { line: 1, col: 7 };
+/// assert_eq!(index.offset(equals), Some(TextSize::from(18)));
+/// assert_eq!(
+///     index.line_col_in(18.into(), ColumnUnit::Utf16),
+///     LineCol { line: 1, col: 6 }
+/// );
+/// assert_eq!(
+///     index.offset_in(LineCol { line: 1, col: 6 }, ColumnUnit::Utf16),
+///     Some(18.into())
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineIndex {
+    /// The offset at which each line starts. The first line always starts at offset 0.
+    line_starts: Vec<TextSize>,
+    /// All characters that are encoded with more than one byte in UTF-8, in order.
+    wide_chars:  Vec<WideChar>,
+    /// All grapheme clusters that consist of more than one character, in order.
+    #[cfg(feature = "unicode_graphemes")]
+    clusters:    Vec<Cluster>,
+    len:         TextSize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct WideChar {
+    offset: TextSize,
+    c:      char,
+}
+
+impl WideChar {
+    fn range(self) -> TextRange {
+        TextRange::at(self.offset, TextSize::of(self.c))
+    }
+}
+
+#[cfg(feature = "unicode_graphemes")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cluster {
+    range: TextRange,
+    /// The number of `char`s in this cluster.
+    chars: u32,
+}
+
+impl LineIndex {
+    /// Computes the line index of `text`.
+    pub fn new(text: &str) -> Self {
+        let mut builder = LineIndexBuilder::default();
+        builder.push(text);
+        builder.finish()
+    }
+
+    /// Updates the index after the text in `deleted` was replaced by `inserted_len` bytes of new text, resulting in
+    /// `new_text`.
+    ///
+    /// Only the lines touched by the edit are re-indexed, which is much cheaper than computing a new index for
+    /// the whole text after every small change.
+    ///
+    /// # Panics
+    /// If `deleted` is out of bounds for the indexed text or the length of `new_text` does not match the edit.
+    ///
+    /// # Example
+    /// ```
+    /// # use cstree::text::{LineCol, LineIndex, TextRange, TextSize};
+    /// let mut index = LineIndex::new("let x = 1;\nlet y = 2;\n");
+    /// // replace `1` with `{\n    1\n}`
+    /// index.edit(
+    ///     "let x = {\n    1\n};\nlet y = 2;\n",
+    ///     TextRange::at(8.into(), 1.into()),
+    ///     9.into(),
+    /// );
+    /// assert_eq!(index.line_count(), 5);
+    /// assert_eq!(
+    ///     index.line_col(TextSize::from(19)),
+    ///     LineCol { line: 3, col: 0 }
+    /// );
+    /// ```
+    pub fn edit(&mut self, new_text: &str, deleted: TextRange, inserted_len: TextSize) {
+        assert!(
+            deleted.end() <= self.len,
+            "edit range {:?} is out of bounds for text of length {:?}",
+            deleted,
+            self.len
+        );
+        let new_len = self.len - deleted.len() + inserted_len;
+        assert_eq!(TextSize::of(new_text), new_len, "new text does not match the edit");
+        let shift = |offset: TextSize| offset + inserted_len - deleted.len();
+
+        // Re-index all lines that overlap the edit, up to and including the newline of the last one.
+        let first_line = self.line_starts.partition_point(|&start| start <= deleted.start()) - 1;
+        let last_line = self.line_starts.partition_point(|&start| start <= deleted.end()) - 1;
+        let start = self.line_starts[first_line];
+        let old_end = self.line_starts.get(last_line + 1).copied().unwrap_or(self.len);
+        let new_end = shift(old_end);
+        let mut builder = LineIndexBuilder::default();
+        builder.push(&new_text[usize::from(start)..usize::from(new_end)]);
+        let lines = builder.finish();
+
+        let tail = (last_line + 2).min(self.line_starts.len());
+        let line_starts = lines.line_starts.iter().map(|&offset| start + offset);
+        self.line_starts.splice(first_line..tail, line_starts);
+        for offset in &mut self.line_starts[first_line + lines.line_starts.len()..] {
+            *offset = shift(*offset);
+        }
+
+        let first = self.wide_chars.partition_point(|wide| wide.offset < start);
+        let tail = self.wide_chars.partition_point(|wide| wide.offset < old_end);
+        let wide_chars = lines.wide_chars.iter().map(|&wide| WideChar {
+            offset: start + wide.offset,
+            ..wide
+        });
+        self.wide_chars.splice(first..tail, wide_chars);
+        for wide in &mut self.wide_chars[first + lines.wide_chars.len()..] {
+            wide.offset = shift(wide.offset);
+        }
+
+        #[cfg(feature = "unicode_graphemes")]
+        {
+            let first = self.clusters.partition_point(|cluster| cluster.range.start() < start);
+            let tail = self.clusters.partition_point(|cluster| cluster.range.start() < old_end);
+            let clusters = lines.clusters.iter().map(|&cluster| Cluster {
+                range: cluster.range + start,
+                ..cluster
+            });
+            self.clusters.splice(first..tail, clusters);
+            for cluster in &mut self.clusters[first + lines.clusters.len()..] {
+                cluster.range = TextRange::at(shift(cluster.range.start()), cluster.range.len());
+            }
+        }
+
+        self.len = new_len;
+    }
+
+    /// The length of the indexed text, in bytes.
+    pub fn len(&self) -> TextSize {
+        self.len
+    }
+
+    /// Returns `true` if the indexed text is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0.into()
+    }
+
+    /// The number of lines in the indexed text.
+    ///
+    /// This is always at least one. A text that ends with a newline has an additional, empty line at its end.
+    pub fn line_count(&self) -> u32 {
+        self.line_starts.len() as u32
+    }
+
+    /// The range of the given `line`, excluding the terminating newline.
+    /// Returns `None` if there is no such line.
+    pub fn line_range(&self, line: u32) -> Option<TextRange> {
+        let start = *self.line_starts.get(line as usize)?;
+        let end = match self.line_starts.get(line as usize + 1) {
+            Some(&next) => next - TextSize::from(1),
+            None => self.len,
+        };
+        Some(TextRange::new(start, end))
+    }
+
+    /// Returns the line and column of `offset`, with the column counted in UTF-8 bytes.
+    ///
+    /// # Panics
+    /// If `offset` is larger than the length of the indexed text.
+    pub fn line_col(&self, offset: TextSize) -> LineCol {
+        self.line_col_in(offset, ColumnUnit::Utf8)
+    }
+
+    /// Returns the line and column of `offset`, with the column counted in `unit`.
+    ///
+    /// If `offset` lies inside of a character, the column after that character is returned.
+    ///
+    /// # Panics
+    /// If `offset` is larger than the length of the indexed text.
+    pub fn line_col_in(&self, offset: TextSize, unit: ColumnUnit) -> LineCol {
+        assert!(
+            offset <= self.len,
+            "offset {:?} is out of bounds for text of length {:?}",
+            offset,
+            self.len
+        );
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        let line_start = self.line_starts[line];
+        let mut col = u32::from(offset - line_start);
+        if unit != ColumnUnit::Utf8 {
+            for wide in self.wide_chars_in(TextRange::new(line_start, offset)) {
+                let len = u32::from(TextSize::of(wide.c));
+                if wide.offset + TextSize::from(len) > offset {
+                    // `offset` is inside of this character
+                    col += len - u32::from(offset - wide.offset);
+                }
+                col = col - len + unit.char_len(wide.c);
+            }
+        }
+        #[cfg(feature = "unicode_graphemes")]
+        if unit.is_grapheme() {
+            col = self.char_col_to_grapheme_col(line_start, offset, col);
+        }
+        LineCol { line: line as u32, col }
+    }
+
+    /// Returns the offset of the given line and column, with the column counted in UTF-8 bytes.
+    ///
+    /// Returns `None` if the position does not exist in the indexed text.
+    pub fn offset(&self, position: LineCol) -> Option<TextSize> {
+        self.offset_in(position, ColumnUnit::Utf8)
+    }
+
+    /// Returns the offset of the given line and column, with the column counted in `unit`.
+    ///
+    /// Returns `None` if the position does not exist in the indexed text, including if it points into the
+    /// middle of a character.
+    pub fn offset_in(&self, position: LineCol, unit: ColumnUnit) -> Option<TextSize> {
+        let line = self.line_range(position.line)?;
+        let mut offset = line.start();
+        let mut remaining = position.col;
+        #[cfg(feature = "unicode_graphemes")]
+        if unit.is_grapheme() {
+            remaining = self.grapheme_col_to_char_col(line, remaining);
+        }
+        for wide in self.wide_chars_in(line) {
+            let before = u32::from(wide.offset - offset);
+            if remaining <= before {
+                break;
+            }
+            remaining -= before;
+            let len = unit.char_len(wide.c);
+            if remaining < len {
+                return None;
+            }
+            remaining -= len;
+            offset = wide.range().end();
+        }
+        let offset = offset.checked_add(remaining.into())?;
+        line.contains_inclusive(offset).then_some(offset)
+    }
+
+    /// Converts the column of `position` from the unit `from` to the unit `to`.
+    ///
+    /// Returns `None` if the position does not exist in the indexed text, including if it points into the
+    /// middle of a character in `from`. If the position points into the middle of a character in `to`, the column
+    /// after that character is returned.
+    pub fn convert(&self, position: LineCol, from: ColumnUnit, to: ColumnUnit) -> Option<LineCol> {
+        let offset = self.offset_in(position, from)?;
+        Some(self.line_col_in(offset, to))
+    }
+
+    /// Given the `char` column `col` of `offset` in the line starting at `line_start`, computes the grapheme
+    /// column of `offset`.
+    #[cfg(feature = "unicode_graphemes")]
+    fn char_col_to_grapheme_col(&self, line_start: TextSize, offset: TextSize, col: u32) -> u32 {
+        let range = TextRange::new(line_start, offset);
+        let mut char_cols = self.char_cols(range);
+        let mut grapheme_col = col;
+        for cluster in self.clusters_in(range) {
+            if cluster.range.end() > offset {
+                // `offset` is inside of this cluster, which is counted as a whole
+                grapheme_col += cluster.chars - (col - char_cols.col(cluster.range.start()));
+            }
+            grapheme_col -= cluster.chars - 1;
+        }
+        grapheme_col
+    }
+
+    /// Computes the `char` column of the grapheme column `col` in `line`.
+    #[cfg(feature = "unicode_graphemes")]
+    fn grapheme_col_to_char_col(&self, line: TextRange, col: u32) -> u32 {
+        let mut char_cols = self.char_cols(line);
+        let mut extra = 0;
+        for cluster in self.clusters_in(line) {
+            let cluster_col = char_cols.col(cluster.range.start()) - extra;
+            if col <= cluster_col {
+                break;
+            }
+            extra += cluster.chars - 1;
+        }
+        col + extra
+    }
+
+    /// Returns a cursor that computes the `char` columns of offsets within `range`, which must start at the start of
+    /// a line.
+    #[cfg(feature = "unicode_graphemes")]
+    fn char_cols(&self, range: TextRange) -> CharCols<impl Iterator<Item = WideChar> + '_> {
+        CharCols {
+            line_start: range.start(),
+            wide_chars: self.wide_chars_in(range).peekable(),
+            extra:      0,
+        }
+    }
+
+    /// The grapheme clusters that start within `range`.
+    #[cfg(feature = "unicode_graphemes")]
+    fn clusters_in(&self, range: TextRange) -> impl Iterator<Item = Cluster> + '_ {
+        let start = self
+            .clusters
+            .partition_point(|cluster| cluster.range.start() < range.start());
+        self.clusters[start..]
+            .iter()
+            .take_while(move |cluster| cluster.range.start() < range.end())
+            .copied()
+    }
+
+    /// The wide characters that start within `range`.
+    fn wide_chars_in(&self, range: TextRange) -> impl Iterator<Item = WideChar> + '_ {
+        let start = self.wide_chars.partition_point(|wide| wide.offset < range.start());
+        self.wide_chars[start..]
+            .iter()
+            .take_while(move |wide| wide.offset < range.end())
+            .copied()
+    }
+}
+
+/// Computes the `char` columns of increasing offsets in a line in a single pass over the wide characters of the line.
+#[cfg(feature = "unicode_graphemes")]
+struct CharCols<I: Iterator<Item = WideChar>> {
+    line_start: TextSize,
+    wide_chars: std::iter::Peekable<I>,
+    /// The number of bytes by which the wide characters before the last offset are longer than one byte.
+    extra:      u32,
+}
+
+#[cfg(feature = "unicode_graphemes")]
+impl<I: Iterator<Item = WideChar>> CharCols<I> {
+    /// The `char` column of `offset`, which must be at a character boundary and must not be before any offset this
+    /// was called with before.
+    fn col(&mut self, offset: TextSize) -> u32 {
+        while let Some(wide) = self.wide_chars.next_if(|wide| wide.offset < offset) {
+            self.extra += u32::from(TextSize::of(wide.c)) - 1;
+        }
+        u32::from(offset - self.line_start) - self.extra
+    }
+}
+
+/// Incrementally computes a [`LineIndex`] from text chunks.
+#[derive(Debug)]
+pub(crate) struct LineIndexBuilder {
+    index: LineIndex,
+    /// The text of the current line so far, for grapheme segmentation.
+    #[cfg(feature = "unicode_graphemes")]
+    line:  String,
+}
+
+impl Default for LineIndexBuilder {
+    fn default() -> Self {
+        Self {
+            index: LineIndex {
+                line_starts: vec![0.into()],
+                wide_chars: Vec::new(),
+                #[cfg(feature = "unicode_graphemes")]
+                clusters: Vec::new(),
+                len: 0.into(),
+            },
+            #[cfg(feature = "unicode_graphemes")]
+            line: String::new(),
+        }
+    }
+}
+
+impl LineIndexBuilder {
+    pub(crate) fn push(&mut self, chunk: &str) {
+        #[cfg(feature = "unicode_graphemes")]
+        let mut line_start = 0;
+        for pos in memchr::memchr_iter(b'\n', chunk.as_bytes()) {
+            #[cfg(feature = "unicode_graphemes")]
+            {
+                self.line.push_str(&chunk[line_start..pos]);
+                self.finish_line();
+                line_start = pos + 1;
+            }
+            let offset = self.index.len + TextSize::from(pos as u32 + 1);
+            self.index.line_starts.push(offset);
+        }
+        // Checking for non-ASCII text first is vectorized and much faster than decoding every `char`.
+        if !chunk.is_ascii() {
+            let wide_chars = chunk
+                .char_indices()
+                .filter(|(_, c)| !c.is_ascii())
+                .map(|(pos, c)| WideChar {
+                    offset: self.index.len + TextSize::from(pos as u32),
+                    c,
+                });
+            self.index.wide_chars.extend(wide_chars);
+        }
+        #[cfg(feature = "unicode_graphemes")]
+        self.line.push_str(&chunk[line_start..]);
+        self.index.len += TextSize::of(chunk);
+    }
+
+    #[cfg(feature = "unicode_graphemes")]
+    fn finish_line(&mut self) {
+        use unicode_segmentation::UnicodeSegmentation;
+
+        // ASCII text only has single-`char` graphemes, except for `\r\n`, which never occurs within a line.
+        if !self.line.is_ascii() {
+            let line_start = *self.index.line_starts.last().unwrap();
+            for (pos, grapheme) in self.line.grapheme_indices(true) {
+                let chars = grapheme.chars().count() as u32;
+                if chars > 1 {
+                    let range = TextRange::at(line_start + TextSize::from(pos as u32), TextSize::of(grapheme));
+                    self.index.clusters.push(Cluster { range, chars });
+                }
+            }
+        }
+        self.line.clear();
+    }
+
+    #[allow(unused_mut)]
+    pub(crate) fn finish(mut self) -> LineIndex {
+        #[cfg(feature = "unicode_graphemes")]
+        self.finish_line();
+        self.index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UNITS: &[ColumnUnit] = &[
+        ColumnUnit::Utf8,
+        ColumnUnit::Utf16,
+        ColumnUnit::Char,
+        #[cfg(feature = "unicode_graphemes")]
+        ColumnUnit::Grapheme,
+    ];
+
+    #[test]
+    fn line_col_roundtrip() {
+        let text = "aä\r\n\nü𐐷b\n";
+        let index = LineIndex::new(text);
+        assert_eq!(index.line_count(), 4);
+        assert_eq!(index.line_range(0), Some(TextRange::new(0.into(), 4.into())));
+        assert_eq!(index.line_range(1), Some(TextRange::empty(5.into())));
+        assert_eq!(index.line_range(3), Some(TextRange::empty(14.into())));
+        assert_eq!(index.line_range(4), None);
+
+        for &unit in UNITS {
+            for (offset, _) in text.char_indices().chain([(text.len(), ' ')]) {
+                let offset = TextSize::from(offset as u32);
+                let position = index.line_col_in(offset, unit);
+                assert_eq!(
+                    index.offset_in(position, unit),
+                    Some(offset),
+                    "{:?} ({:?})",
+                    position,
+                    unit
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn columns_in_units() {
+        let index = LineIndex::new("x\nü𐐷b");
+        let b = TextSize::from(8);
+        assert_eq!(index.line_col_in(b, ColumnUnit::Utf8), LineCol { line: 1, col: 6 });
+        assert_eq!(index.line_col_in(b, ColumnUnit::Utf16), LineCol { line: 1, col: 3 });
+        // inside of `ü`
+        assert_eq!(index.offset(LineCol { line: 1, col: 1 }), None);
+        // inside of the surrogate pair of `𐐷`
+        assert_eq!(index.offset_in(LineCol { line: 1, col: 2 }, ColumnUnit::Utf16), None);
+        // past the end of the line
+        assert_eq!(index.offset_in(LineCol { line: 1, col: 5 }, ColumnUnit::Utf16), None);
+        assert_eq!(index.offset(LineCol { line: 0, col: 2 }), None);
+    }
+
+    #[test]
+    fn convert_between_units() {
+        let index = LineIndex::new("x\nü𐐷b");
+        let b = LineCol { line: 1, col: 6 };
+        assert_eq!(
+            index.convert(b, ColumnUnit::Utf8, ColumnUnit::Char),
+            Some(LineCol { line: 1, col: 2 })
+        );
+        assert_eq!(
+            index.offset_in(LineCol { line: 1, col: 2 }, ColumnUnit::Char),
+            Some(8.into())
+        );
+        for &from in UNITS {
+            for &to in UNITS {
+                let position = index.line_col_in(8.into(), from);
+                assert_eq!(index.convert(position, from, to), Some(index.line_col_in(8.into(), to)));
+            }
+        }
+    }
+
+    #[cfg(feature = "unicode_graphemes")]
+    #[test]
+    fn grapheme_columns() {
+        use unicode_segmentation::UnicodeSegmentation;
+
+        // `e` + combining acute accent, a family emoji made of 5 `char`s and an `x`
+        let text = "e\u{301}👨\u{200d}👩\u{200d}👧x\r\ne\u{301}";
+        let index = LineIndex::new(text);
+        let x = TextSize::from(text.find('x').unwrap() as u32);
+        assert_eq!(index.line_col_in(x, ColumnUnit::Char), LineCol { line: 0, col: 7 });
+        assert_eq!(index.line_col_in(x, ColumnUnit::Grapheme), LineCol { line: 0, col: 2 });
+        assert_eq!(
+            index.offset_in(LineCol { line: 0, col: 2 }, ColumnUnit::Grapheme),
+            Some(x)
+        );
+        // the `\r` is its own grapheme
+        assert_eq!(
+            index.line_col_in(x + TextSize::from(2), ColumnUnit::Grapheme),
+            LineCol { line: 0, col: 4 }
+        );
+        // inside of the emoji
+        assert_eq!(
+            index.line_col_in(4.into(), ColumnUnit::Grapheme),
+            LineCol { line: 0, col: 2 }
+        );
+        assert_eq!(
+            index.line_col_in(TextSize::of(text), ColumnUnit::Grapheme),
+            LineCol { line: 1, col: 1 }
+        );
+
+        // many clusters and wide characters on the same line
+        let text = "aé".to_owned() + &"e\u{301}x👨\u{200d}👩ü".repeat(20);
+        let index = LineIndex::new(&text);
+        for (col, (offset, _)) in text.grapheme_indices(true).chain([(text.len(), "")]).enumerate() {
+            let position = LineCol { line: 0, col: col as u32 };
+            let offset = TextSize::from(offset as u32);
+            assert_eq!(index.line_col_in(offset, ColumnUnit::Grapheme), position);
+            assert_eq!(index.offset_in(position, ColumnUnit::Grapheme), Some(offset));
+        }
+    }
+
+    #[test]
+    fn incremental_edits() {
+        let mut text = String::from("fn main() {\n    let ü = 1;\r\n}\n");
+        let mut index = LineIndex::new(&text);
+        let edits: &[(usize, usize, &str)] = &[
+            (16, 18, "x"),
+            (0, 0, "// 𐐷\n"),
+            (10, 14, ""),
+            (7, 30, "\n\n\n"),
+            (0, 0, "e"),
+            (1, 1, "\u{301}👨\u{200d}👩"),
+            (3, 3, "y\n"),
+        ];
+        for &(start, end, insert) in edits {
+            text.replace_range(start..end, insert);
+            let deleted = TextRange::new((start as u32).into(), (end as u32).into());
+            index.edit(&text, deleted, TextSize::of(insert));
+            assert_eq!(
+                index,
+                LineIndex::new(&text),
+                "after replacing {:?} with {:?}",
+                deleted,
+                insert
+            );
+        }
+    }
+}
@@ -0,0 +1,197 @@
+//! Lowering a syntax tree into typed AST values, collecting errors along the way.
+//!
+//! A lossless syntax tree keeps everything the parser saw, including malformed input. Most compiler frontends convert
+//! it into their own AST types before further analysis, and in doing so have to report everything that does not have
+//! a valid AST representation with the right source range. This module provides the plumbing for that conversion:
+//! implement [`Lower`] for each of your AST types and use a [`Lowering`] context to lower child nodes, look up token
+//! texts and record errors. Every lowered value is returned together with the range of the node it was lowered from
+//! as [`Spanned`], and errors are recorded with the range of the node that was being lowered when they occurred.
+//!
+//! # Example
+//! ```
+//! # use cstree::testing::*;
+//! use cstree::lower::{Lower, Lowering};
+//!
+//! #[derive(Debug, PartialEq)]
+//! struct Sum(Vec<i64>);
+//!
+//! impl Lower<MySyntax> for Sum {
+//!     type Error = String;
+//!
+//!     fn lower(node: &SyntaxNode<MySyntax>, cx: &mut Lowering<'_, String>) -> Option<Self> {
+//!         let mut summands = Vec::new();
+//!         for token in node
+//!             .children_with_tokens()
+//!             .filter_map(|child| child.into_token())
+//!         {
+//!             match token.kind() {
+//!                 Int => summands.push(cx.text(token).parse().unwrap()),
+//!                 Plus => {}
+//!                 kind => cx.error_at(token.text_range(), format!("unexpected {kind:?}")),
+//!             }
+//!         }
+//!         Some(Sum(summands))
+//!     }
+//! }
+//!
+//! let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+//! builder.start_node(Operation);
+//! builder.token(Int, "1");
+//! builder.static_token(Plus);
+//! builder.token(Float, "2.5");
+//! builder.static_token(Plus);
+//! builder.token(Int, "3");
+//! builder.finish_node();
+//! let (tree, cache) = builder.finish();
+//! let interner = cache.unwrap().into_interner().unwrap();
+//! let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+//!
+//! let mut cx = Lowering::new(&interner);
+//! let sum = cx.lower::<Sum, _, _>(&root).unwrap();
+//! assert_eq!(sum.value, Sum(vec![1, 3]));
+//! assert_eq!(sum.range, root.text_range());
+//! let errors = cx.finish();
+//! assert_eq!(errors.len(), 1);
+//! assert_eq!(errors[0].error, "unexpected Float");
+//! assert_eq!(u32::from(errors[0].range.start()), 2);
+//! ```
+
+use std::fmt;
+
+use crate::{
+    interning::{Resolver, TokenKey},
+    syntax::{SyntaxNode, SyntaxToken},
+    text::TextRange,
+    Syntax,
+};
+
+/// A type that can be created from a [`SyntaxNode`] by a [`Lowering`].
+pub trait Lower<S: Syntax, D: 'static = ()>: Sized {
+    /// The type of errors reported while lowering. All types lowered with the same [`Lowering`] share it.
+    type Error;
+
+    /// Converts `node` into `Self`, using `cx` to lower children and report errors.
+    ///
+    /// Returning `None` signals that `node` cannot be represented at all. In that case, an error explaining why
+    /// should usually have been reported to `cx`. Problems that still allow a (partial) value to be produced should
+    /// be reported to `cx` while returning `Some`, so that as many errors as possible are found in one pass.
+    fn lower(node: &SyntaxNode<S, D>, cx: &mut Lowering<'_, Self::Error>) -> Option<Self>;
+}
+
+/// A value lowered from the source text in `range`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Spanned<T> {
+    /// The lowered value.
+    pub value: T,
+    /// The range of the node the value was lowered from.
+    pub range: TextRange,
+}
+
+impl<T> Spanned<T> {
+    /// Applies `f` to the value, keeping the range.
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Spanned<U> {
+        Spanned {
+            value: f(self.value),
+            range: self.range,
+        }
+    }
+}
+
+/// An error reported while lowering, together with the range of source text it refers to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LowerError<E> {
+    /// The error reported to the [`Lowering`].
+    pub error: E,
+    /// The range the error was reported for.
+    pub range: TextRange,
+}
+
+/// The context in which a tree is lowered, which collects all errors reported while lowering.
+///
+/// See the [module documentation](self) for an example.
+pub struct Lowering<'r, E> {
+    resolver: &'r dyn Resolver<TokenKey>,
+    errors:   Vec<LowerError<E>>,
+    /// The ranges of the nodes that are currently being lowered, innermost last.
+    ranges:   Vec<TextRange>,
+}
+
+impl<'r, E> Lowering<'r, E> {
+    /// Creates a new context, which resolves token texts with `resolver`.
+    pub fn new(resolver: &'r dyn Resolver<TokenKey>) -> Self {
+        Self {
+            resolver,
+            errors: Vec::new(),
+            ranges: Vec::new(),
+        }
+    }
+
+    /// Lowers `node` into a `T`, returning it together with the range of `node`.
+    pub fn lower<T, S, D>(&mut self, node: &SyntaxNode<S, D>) -> Option<Spanned<T>>
+    where
+        T: Lower<S, D, Error = E>,
+        S: Syntax,
+    {
+        let range = node.text_range();
+        self.ranges.push(range);
+        let value = T::lower(node, self);
+        self.ranges.pop();
+        Some(Spanned { value: value?, range })
+    }
+
+    /// Lowers each of `nodes` into a `T`, leaving out those that cannot be lowered.
+    pub fn lower_all<'n, T, S, D>(&mut self, nodes: impl IntoIterator<Item = &'n SyntaxNode<S, D>>) -> Vec<Spanned<T>>
+    where
+        T: Lower<S, D, Error = E>,
+        S: Syntax + 'n,
+        D: 'static,
+    {
+        nodes.into_iter().filter_map(|node| self.lower(node)).collect()
+    }
+
+    /// The resolver used by this context.
+    pub fn resolver(&self) -> &'r dyn Resolver<TokenKey> {
+        self.resolver
+    }
+
+    /// Returns the text of `token`.
+    pub fn text<S: Syntax, D>(&self, token: &SyntaxToken<S, D>) -> &'r str {
+        token.resolve_text(self.resolver)
+    }
+
+    /// Reports `error` for the node that is currently being lowered.
+    ///
+    /// ## Panics
+    /// If no node is being lowered, that is, if this is not called from [`Lower::lower`].
+    pub fn error(&mut self, error: E) {
+        let range = *self
+            .ranges
+            .last()
+            .expect("`Lowering::error` called outside of `Lower::lower`, use `error_at` instead");
+        self.error_at(range, error);
+    }
+
+    /// Reports `error` for the given `range`.
+    pub fn error_at(&mut self, range: TextRange, error: E) {
+        self.errors.push(LowerError { error, range });
+    }
+
+    /// All errors reported so far, in the order they were reported.
+    pub fn errors(&self) -> &[LowerError<E>] {
+        &self.errors
+    }
+
+    /// Consumes the context and returns all reported errors, in the order they were reported.
+    pub fn finish(self) -> Vec<LowerError<E>> {
+        self.errors
+    }
+}
+
+impl<E: fmt::Debug> fmt::Debug for Lowering<'_, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Lowering")
+            .field("errors", &self.errors)
+            .field("ranges", &self.ranges)
+            .finish_non_exhaustive()
+    }
+}
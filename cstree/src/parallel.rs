@@ -0,0 +1,137 @@
+//! Building many syntax trees in parallel with a shared cache and interner.
+//!
+//! Parsing a large number of independent inputs, like all files of a project, is easily parallelized. To still get
+//! the memory savings of deduplication across all resulting trees, the builders on all threads have to share a
+//! [`ThreadedNodeCache`] and an interner that supports interning through a shared reference, like the one returned by
+//! [`new_threaded_interner`](crate::interning::new_threaded_interner). [`parse_all`] sets this up, distributes the
+//! inputs over a number of threads and collects the resulting trees in the order of the inputs. Each thread looks up
+//! tokens in a [`LocalNodeCache`] first to reduce contention on the shared cache.
+//!
+//! # Example
+//! ```
+//! # use cstree::testing::*;
+//! use cstree::{
+//!     build::ThreadedNodeCache,
+//!     interning::new_threaded_interner,
+//!     parallel::{parse_all, ParallelBuilder},
+//! };
+//!
+//! fn parse_sum(input: &&str, builder: &mut ParallelBuilder<'_, MySyntax>) -> usize {
+//!     builder.start_node(Operation);
+//!     let summands: Vec<_> = input.split('+').collect();
+//!     for (i, summand) in summands.iter().enumerate() {
+//!         if i > 0 {
+//!             builder.static_token(Plus);
+//!         }
+//!         builder.token(Int, summand);
+//!     }
+//!     builder.finish_node();
+//!     summands.len()
+//! }
+//!
+//! let inputs = ["1+2", "3+4+5", "1+2"];
+//! let cache = ThreadedNodeCache::new();
+//! let interner = new_threaded_interner();
+//! let parsed = parse_all(&inputs, &cache, &interner, parse_sum);
+//!
+//! let (tree, summands) = &parsed.trees[1];
+//! assert_eq!(*summands, 3);
+//! let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree.clone());
+//! assert_eq!(root.resolve_text(&interner), "3+4+5");
+//! // The trees for both "1+2" are the same node
+//! assert_eq!(parsed.trees[0].0, parsed.trees[2].0);
+//! assert!(parsed.stats.token_hits > 0);
+//! ```
+
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+use crate::{
+    build::{CacheStats, GreenNodeBuilder, LocalNodeCache, NodeCache, ThreadedNodeCache},
+    green::GreenNode,
+    interning::{Interner, MultiThreadedTokenInterner, TokenKey},
+    Syntax,
+};
+
+/// The builder that [`parse_all`] passes to the parse function for each input.
+pub type ParallelBuilder<'a, S, I = MultiThreadedTokenInterner> =
+    GreenNodeBuilder<'a, 'a, S, &'a I, LocalNodeCache<'a>>;
+
+/// The result of [`parse_all`].
+#[derive(Debug, Clone)]
+pub struct ParallelParse<R> {
+    /// The tree built for each input together with the value returned by the parse function, in the order of the
+    /// inputs.
+    pub trees: Vec<(GreenNode, R)>,
+    /// The lookups in the shared cache made while building the trees.
+    ///
+    /// This is computed from the [stats](ThreadedNodeCache::stats) of the cache before and after parsing, so it also
+    /// includes lookups from other builders that use the cache at the same time.
+    pub stats: CacheStats,
+}
+
+/// Runs `parse` for each of the `inputs` on as many threads as the system supports, building the trees with a
+/// shared `cache` and `interner`.
+///
+/// `parse` has to build exactly one root node for each input with the builder it is given, like with a
+/// [`GreenNodeBuilder`] that is used on its own. Whatever else it returns, like a list of parse errors, is returned
+/// together with the tree. See the [module documentation](self) for an example.
+///
+/// ## Panics
+/// If `parse` panics for any of the inputs, or does not build a single complete root node.
+pub fn parse_all<'a, T, S, I, R, F>(
+    inputs: &[T],
+    cache: &'a ThreadedNodeCache,
+    interner: &'a I,
+    parse: F,
+) -> ParallelParse<R>
+where
+    T: Sync,
+    S: Syntax,
+    I: ?Sized + Sync,
+    &'a I: Interner<TokenKey>,
+    R: Send,
+    F: Fn(&T, &mut ParallelBuilder<'a, S, I>) -> R + Sync,
+{
+    let before = cache.stats();
+    let threads = thread::available_parallelism()
+        .map_or(1, |threads| threads.get())
+        .min(inputs.len());
+    let next = AtomicUsize::new(0);
+    let work = || {
+        let mut trees = Vec::new();
+        loop {
+            let index = next.fetch_add(1, Ordering::Relaxed);
+            let Some(input) = inputs.get(index) else {
+                break trees;
+            };
+            let mut builder: ParallelBuilder<'a, S, I> =
+                GreenNodeBuilder::from_cache(NodeCache::from_interner(interner).with_green_cache(cache.local()));
+            let output = parse(input, &mut builder);
+            let (tree, _) = builder.finish();
+            trees.push((index, tree, output));
+        }
+    };
+
+    let mut trees: Vec<_> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads).map(|_| scope.spawn(work)).collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+            .collect()
+    });
+    trees.sort_unstable_by_key(|&(index, ..)| index);
+
+    let after = cache.stats();
+    ParallelParse {
+        trees: trees.into_iter().map(|(_, tree, output)| (tree, output)).collect(),
+        stats: CacheStats {
+            node_hits:    after.node_hits - before.node_hits,
+            node_misses:  after.node_misses - before.node_misses,
+            token_hits:   after.token_hits - before.token_hits,
+            token_misses: after.token_misses - before.token_misses,
+        },
+    }
+}
@@ -0,0 +1,439 @@
+//! A pretty-printing backend for formatters.
+//!
+//! A formatter describes the layout it wants as a [`Doc`]: text, line breaks that are only taken if a
+//! [group](Doc::group) does not fit on the current line, and [indentation](Doc::nest), in the style of Wadler's
+//! "prettier printer". The document is then [rendered](Doc::render) for a maximum line width, which picks the line
+//! breaks. Parts of the document can be [tagged](Doc::source) with the range of the input they were produced from, and
+//! the renderer reports where each tagged part ended up in the output, so diagnostics and cursor positions can be
+//! carried over to the formatted text.
+//!
+//! [`Doc::from_tree`] produces a document from a syntax tree by calling a [`Format`] implementation for every node and
+//! token, and tags every element with its range. With the default implementation of `Format`, rendering the document
+//! reproduces the text of the tree, so formatters only need to override what they want to change.
+//!
+//! # Example
+//! ```
+//! # use cstree::testing::*;
+//! use cstree::{
+//!     pretty::{Doc, Format},
+//!     syntax::TriviaKind,
+//! };
+//!
+//! struct Rules;
+//!
+//! impl Format<MySyntax> for Rules {
+//!     fn trivia_kind(&self, kind: MySyntax) -> Option<TriviaKind> {
+//!         (kind == Whitespace).then_some(TriviaKind::Whitespace)
+//!     }
+//!
+//!     // Whitespace is replaced by the line breaks of the document.
+//!     fn trivia(&mut self, _: &SyntaxToken<MySyntax>, _: TriviaKind, _: &str) -> Option<Doc> {
+//!         None
+//!     }
+//!
+//!     fn node(&mut self, node: &SyntaxNode<MySyntax>, children: Vec<Doc>) -> Doc {
+//!         match node.kind() {
+//!             Operation => Doc::intersperse(children, Doc::line()).nest(2).group(),
+//!             _ => Doc::concat(children),
+//!         }
+//!     }
+//! }
+//!
+//! let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+//! builder.start_node(Operation);
+//! builder.token(Int, "1");
+//! builder.token(Whitespace, "   ");
+//! builder.static_token(Plus);
+//! builder.token(Whitespace, "\n");
+//! builder.token(Int, "23");
+//! builder.finish_node();
+//! let (tree, cache) = builder.finish();
+//! let interner = cache.unwrap().into_interner().unwrap();
+//! let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+//!
+//! let doc = Doc::from_tree(&root, &mut Rules, &interner);
+//! assert_eq!(doc.render(80).text(), "1 + 23");
+//! let narrow = doc.render(4);
+//! assert_eq!(narrow.text(), "1\n  +\n  23");
+//!
+//! // `23` was at 6..8 in the input and is at 8..10 in the output.
+//! let int = narrow.source_at(8.into()).unwrap();
+//! assert_eq!(int.input, root.last_token().unwrap().text_range());
+//! assert_eq!(u32::from(int.output.start()), 8);
+//! ```
+
+use text_size::{TextRange, TextSize};
+
+use crate::{
+    interning::{Resolver, TokenKey},
+    syntax::{SyntaxNode, SyntaxToken, TriviaKind},
+    traversal::WalkEvent,
+    util::NodeOrToken,
+    Syntax,
+};
+
+/// A document that describes the layout of text, to be [rendered](Doc::render) for a maximum line width.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Doc {
+    /// Text that is printed as-is. Text should not contain line breaks, except for text that is preserved from the
+    /// input, like comments. Text with a line break never fits in a group that is printed on a single line.
+    Text(String),
+    /// A space if the enclosing group is printed on a single line, otherwise a line break.
+    Line,
+    /// Nothing if the enclosing group is printed on a single line, otherwise a line break.
+    SoftLine,
+    /// A line break, which is always taken. The enclosing groups are never printed on a single line.
+    HardLine,
+    /// The documents printed one after the other.
+    Concat(Vec<Doc>),
+    /// A document whose line breaks are followed by the given number of additional spaces of indentation.
+    Nest(u32, Box<Doc>),
+    /// A document that is printed on a single line if it fits, and with all of its own line breaks otherwise. Line
+    /// breaks of groups nested in it are decided separately.
+    Group(Box<Doc>),
+    /// A document that was produced from the given range of the input, see [`Rendered::mappings`].
+    Source(TextRange, Box<Doc>),
+}
+
+impl Doc {
+    /// The empty document.
+    #[inline]
+    pub fn nil() -> Self {
+        Doc::Concat(Vec::new())
+    }
+
+    /// See [`Doc::Text`].
+    #[inline]
+    pub fn text(text: impl Into<String>) -> Self {
+        Doc::Text(text.into())
+    }
+
+    /// See [`Doc::Line`].
+    #[inline]
+    pub fn line() -> Self {
+        Doc::Line
+    }
+
+    /// See [`Doc::SoftLine`].
+    #[inline]
+    pub fn softline() -> Self {
+        Doc::SoftLine
+    }
+
+    /// See [`Doc::HardLine`].
+    #[inline]
+    pub fn hardline() -> Self {
+        Doc::HardLine
+    }
+
+    /// See [`Doc::Concat`].
+    pub fn concat(docs: impl IntoIterator<Item = Doc>) -> Self {
+        Doc::Concat(docs.into_iter().collect())
+    }
+
+    /// Concatenates `docs` with a clone of `separator` between each pair of them.
+    pub fn intersperse(docs: impl IntoIterator<Item = Doc>, separator: Doc) -> Self {
+        let mut result = Vec::new();
+        for doc in docs {
+            if !result.is_empty() {
+                result.push(separator.clone());
+            }
+            result.push(doc);
+        }
+        Doc::Concat(result)
+    }
+
+    /// Indents the line breaks in this document by `indent` additional spaces. See [`Doc::Nest`].
+    #[inline]
+    pub fn nest(self, indent: u32) -> Self {
+        Doc::Nest(indent, Box::new(self))
+    }
+
+    /// Makes this document a [group](Doc::Group).
+    #[inline]
+    pub fn group(self) -> Self {
+        Doc::Group(Box::new(self))
+    }
+
+    /// Tags this document with the range of the input it was produced from. See [`Doc::Source`].
+    #[inline]
+    pub fn source(self, range: TextRange) -> Self {
+        Doc::Source(range, Box::new(self))
+    }
+
+    /// Returns `true` if this document prints nothing.
+    pub fn is_nil(&self) -> bool {
+        match self {
+            Doc::Text(text) => text.is_empty(),
+            Doc::Concat(docs) => docs.iter().all(Doc::is_nil),
+            Doc::Nest(_, doc) | Doc::Group(doc) | Doc::Source(_, doc) => doc.is_nil(),
+            Doc::Line | Doc::SoftLine | Doc::HardLine => false,
+        }
+    }
+
+    /// Produces a document from the tree rooted at `node` by calling `format` for each of its elements, children
+    /// first. Token texts are resolved with `resolver`. The document of every element is
+    /// [tagged](Doc::source) with the range of the element.
+    pub fn from_tree<S, D, F, R>(node: &SyntaxNode<S, D>, format: &mut F, resolver: &R) -> Self
+    where
+        S: Syntax,
+        F: Format<S, D> + ?Sized,
+        R: Resolver<TokenKey> + ?Sized,
+    {
+        // the documents of the children of each node that is being visited
+        let mut stack: Vec<Vec<Doc>> = vec![Vec::new()];
+        for event in node.preorder_with_tokens() {
+            match event {
+                WalkEvent::Enter(NodeOrToken::Node(_)) => stack.push(Vec::new()),
+                WalkEvent::Enter(NodeOrToken::Token(token)) => {
+                    let text = token.resolve_text(resolver);
+                    let doc = match format.trivia_kind(token.kind()) {
+                        Some(kind) => format.trivia(token, kind, text),
+                        None => Some(format.token(token, text)),
+                    };
+                    if let Some(doc) = doc {
+                        stack.last_mut().unwrap().push(doc.source(token.text_range()));
+                    }
+                }
+                WalkEvent::Leave(NodeOrToken::Node(node)) => {
+                    let children = stack.pop().unwrap();
+                    let doc = format.node(node, children).source(node.text_range());
+                    stack.last_mut().unwrap().push(doc);
+                }
+                WalkEvent::Leave(NodeOrToken::Token(_)) => {}
+            }
+        }
+        stack.pop().unwrap().pop().unwrap()
+    }
+
+    /// Renders this document, taking line breaks so that lines are at most `width` chars long where possible.
+    pub fn render(&self, width: u32) -> Rendered {
+        let mut printer = Printer {
+            text:           String::new(),
+            column:         0,
+            pending_indent: None,
+            mappings:       Vec::new(),
+            pending_starts: Vec::new(),
+        };
+        let mut stack = vec![Command::Doc(0, Mode::Break, self)];
+        while let Some(command) = stack.pop() {
+            let (indent, mode, doc) = match command {
+                Command::Doc(indent, mode, doc) => (indent, mode, doc),
+                Command::EndSource(index) => {
+                    printer.pending_starts.retain(|&i| i != index);
+                    let mapping = &mut printer.mappings[index];
+                    mapping.output = TextRange::new(mapping.output.start(), TextSize::of(printer.text.as_str()));
+                    continue;
+                }
+            };
+            match doc {
+                Doc::Text(text) => printer.write(text),
+                Doc::Line if mode == Mode::Flat => printer.write(" "),
+                Doc::SoftLine if mode == Mode::Flat => {}
+                Doc::Line | Doc::SoftLine | Doc::HardLine => printer.newline(indent),
+                Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|doc| Command::Doc(indent, mode, doc))),
+                Doc::Nest(extra, doc) => stack.push(Command::Doc(indent + extra, mode, doc)),
+                Doc::Group(doc) => {
+                    let remaining = i64::from(width) - i64::from(printer.column);
+                    let mode = if mode == Mode::Flat || fits(remaining, doc, &stack) {
+                        Mode::Flat
+                    } else {
+                        Mode::Break
+                    };
+                    stack.push(Command::Doc(indent, mode, doc));
+                }
+                Doc::Source(range, doc) => {
+                    let index = printer.mappings.len();
+                    printer.mappings.push(Mapping {
+                        output: TextRange::empty(TextSize::of(printer.text.as_str())),
+                        input:  *range,
+                    });
+                    if printer.pending_indent.is_some() {
+                        printer.pending_starts.push(index);
+                    }
+                    stack.push(Command::EndSource(index));
+                    stack.push(Command::Doc(indent, mode, doc));
+                }
+            }
+        }
+        Rendered {
+            text:     printer.text,
+            mappings: printer.mappings,
+        }
+    }
+}
+
+impl From<&str> for Doc {
+    fn from(text: &str) -> Self {
+        Doc::text(text)
+    }
+}
+
+impl From<String> for Doc {
+    fn from(text: String) -> Self {
+        Doc::Text(text)
+    }
+}
+
+/// How a [`Doc`] is produced from the elements of a syntax tree by [`Doc::from_tree`].
+///
+/// Every method has a default implementation that preserves the input, so that the document of a tree renders to the
+/// text of the tree unless some of the methods are overridden.
+pub trait Format<S: Syntax, D: 'static = ()> {
+    /// Returns the [`TriviaKind`] of tokens of the given kind, or `None` if they are not trivia. Trivia tokens are
+    /// passed to [`trivia`](Format::trivia) instead of [`token`](Format::token). By default, tokens are trivia if
+    /// their [class](Syntax::class) is, see [`KindClass::trivia_kind`](crate::KindClass::trivia_kind).
+    fn trivia_kind(&self, kind: S) -> Option<TriviaKind> {
+        kind.class().trivia_kind()
+    }
+
+    /// Produces the document of a token that is not trivia from its `text`. By default, the text is kept.
+    fn token(&mut self, token: &SyntaxToken<S, D>, text: &str) -> Doc {
+        let _ = token;
+        Doc::text(text)
+    }
+
+    /// Produces the document of a trivia token from its `text`, or returns `None` to drop the token. By default, the
+    /// text is kept.
+    fn trivia(&mut self, token: &SyntaxToken<S, D>, kind: TriviaKind, text: &str) -> Option<Doc> {
+        let _ = (token, kind);
+        Some(Doc::text(text))
+    }
+
+    /// Produces the document of a node from the documents of its children, in order. Dropped trivia are not
+    /// included in `children`. By default, the children are [concatenated](Doc::concat).
+    fn node(&mut self, node: &SyntaxNode<S, D>, children: Vec<Doc>) -> Doc {
+        let _ = node;
+        Doc::concat(children)
+    }
+}
+
+/// Where a [tagged](Doc::source) part of a document ended up in the [rendered](Doc::render) text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Mapping {
+    /// The range of the part in the rendered text.
+    pub output: TextRange,
+    /// The range of the input that the part was produced from.
+    pub input:  TextRange,
+}
+
+/// The text of a [rendered](Doc::render) document and where its [tagged](Doc::source) parts ended up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rendered {
+    text:     String,
+    mappings: Vec<Mapping>,
+}
+
+impl Rendered {
+    /// The rendered text.
+    #[inline]
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The rendered text.
+    #[inline]
+    pub fn into_text(self) -> String {
+        self.text
+    }
+
+    /// The [`Mapping`]s of all tagged parts of the document, in the order in which they start in the document. A part
+    /// that is nested in another one comes after it.
+    #[inline]
+    pub fn mappings(&self) -> &[Mapping] {
+        &self.mappings
+    }
+
+    /// The mapping of the innermost tagged part that contains `offset` in the rendered text. Parts that end at
+    /// `offset` do not contain it.
+    pub fn source_at(&self, offset: TextSize) -> Option<&Mapping> {
+        self.mappings
+            .iter()
+            .filter(|mapping| mapping.output.contains(offset))
+            .min_by_key(|mapping| mapping.output.len())
+    }
+
+    /// The range in the rendered text of the first part that was tagged with the `input` range.
+    pub fn output_range(&self, input: TextRange) -> Option<TextRange> {
+        self.mappings
+            .iter()
+            .find(|mapping| mapping.input == input)
+            .map(|mapping| mapping.output)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Command<'d> {
+    Doc(u32, Mode, &'d Doc),
+    EndSource(usize),
+}
+
+struct Printer {
+    text:           String,
+    /// The column of the next char, including indentation that was not written yet.
+    column:         u32,
+    /// Indentation is only written before the next text, so that empty lines do not end in spaces.
+    pending_indent: Option<u32>,
+    mappings:       Vec<Mapping>,
+    /// Mappings that were started while indentation was pending, which should start after the indentation instead.
+    pending_starts: Vec<usize>,
+}
+
+impl Printer {
+    fn write(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        if let Some(indent) = self.pending_indent.take() {
+            self.text.extend((0..indent).map(|_| ' '));
+            let start = TextSize::of(self.text.as_str());
+            for index in self.pending_starts.drain(..) {
+                self.mappings[index].output = TextRange::empty(start);
+            }
+        }
+        self.text.push_str(text);
+        match text.rfind('\n') {
+            Some(newline) => self.column = text[newline + 1..].chars().count() as u32,
+            None => self.column += text.chars().count() as u32,
+        }
+    }
+
+    fn newline(&mut self, indent: u32) {
+        self.text.push('\n');
+        self.column = indent;
+        self.pending_indent = Some(indent);
+    }
+}
+
+/// Whether `doc` fits in the `remaining` width if printed flat, together with the rest of the line that follows it.
+fn fits(mut remaining: i64, doc: &Doc, rest: &[Command<'_>]) -> bool {
+    let mut stack = vec![(Mode::Flat, doc)];
+    let mut rest = rest.iter().rev().filter_map(|command| match command {
+        Command::Doc(_, mode, doc) => Some((*mode, *doc)),
+        Command::EndSource(_) => None,
+    });
+    while let Some((mode, doc)) = stack.pop().or_else(|| rest.next()) {
+        match doc {
+            Doc::Text(text) => match text.find('\n') {
+                Some(newline) => return mode == Mode::Break && text[..newline].chars().count() as i64 <= remaining,
+                None => remaining -= text.chars().count() as i64,
+            },
+            Doc::Line | Doc::SoftLine | Doc::HardLine if mode == Mode::Break => return true,
+            Doc::Line => remaining -= 1,
+            Doc::SoftLine => {}
+            Doc::HardLine => return false,
+            Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|doc| (mode, doc))),
+            Doc::Nest(_, doc) | Doc::Group(doc) | Doc::Source(_, doc) => stack.push((mode, doc)),
+        }
+        if remaining < 0 {
+            return false;
+        }
+    }
+    true
+}
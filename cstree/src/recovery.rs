@@ -0,0 +1,230 @@
+//! Sets of syntax kinds and the recovery sets of node kinds, for parsers that recover from syntax errors.
+//!
+//! A resilient parser that finds an unexpected token skips tokens until it reaches one that can continue the input,
+//! and wraps the skipped tokens in an error node. Which tokens can continue the input depends on the nodes that are
+//! currently open: a token that may follow any of them ends the skipped range. [`RecoverySets`] stores the tokens
+//! that may follow each node kind (their FOLLOW sets) as [`SyntaxKindSet`]s, and
+//! [`GreenNodeBuilder::recovery_set`](crate::build::GreenNodeBuilder::recovery_set) combines them for the nodes that
+//! the builder has open. [`GreenNodeBuilder::recover`](crate::build::GreenNodeBuilder::recover) then skips the
+//! tokens that are not in the combined set.
+//!
+//! # Example
+//! ```
+//! # use cstree::testing::*;
+//! use cstree::{
+//!     recovery::{RecoverySets, SyntaxKindSet},
+//!     text::TextRange,
+//! };
+//!
+//! let sets = RecoverySets::new()
+//!     .follow(Root, [])
+//!     .follow(Operation, [Whitespace]);
+//!
+//! let tokens = [(Identifier, "x"), (Plus, "+"), (Whitespace, " "), (Int, "1")];
+//! let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+//! builder.start_node(Root);
+//! builder.start_node(Operation);
+//! let recovery = builder.recovery_set(&sets);
+//! assert_eq!(recovery, SyntaxKindSet::from_iter([Whitespace]));
+//! // `x+` cannot start an operation, so it is skipped
+//! assert_eq!(builder.recover(Float, &tokens, &recovery), 2);
+//! builder.finish_node();
+//! builder.tokens(&tokens[2..]);
+//! builder.finish_node();
+//! let (tree, _) = builder.finish();
+//! let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+//! let error = root.first_child().unwrap().first_child().unwrap();
+//! assert_eq!(error.kind(), Float);
+//! assert_eq!(error.text_range(), TextRange::up_to(2.into()));
+//! ```
+
+use std::{fmt, marker::PhantomData};
+
+use fxhash::FxHashMap;
+
+use crate::{utility_types::KindName, RawSyntaxKind, Syntax};
+
+/// A set of syntax kinds, stored as a bit set over their [raw](RawSyntaxKind) values.
+///
+/// The set grows to the largest raw kind it contains, so it is best suited for kinds with small raw values, like the
+/// ones of `#[derive(Syntax)]` enums.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct SyntaxKindSet<S: Syntax> {
+    /// The bits of the set, without trailing zero words.
+    words:   Vec<u64>,
+    _syntax: PhantomData<S>,
+}
+
+impl<S: Syntax> Default for SyntaxKindSet<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: Syntax> SyntaxKindSet<S> {
+    /// Creates an empty set.
+    pub fn new() -> Self {
+        Self {
+            words:   Vec::new(),
+            _syntax: PhantomData,
+        }
+    }
+
+    fn position(kind: S) -> (usize, u64) {
+        let RawSyntaxKind(raw) = kind.into_raw();
+        ((raw / 64) as usize, 1 << (raw % 64))
+    }
+
+    /// Adds `kind` to the set. Returns `true` if it was not in the set before.
+    pub fn insert(&mut self, kind: S) -> bool {
+        let (word, bit) = Self::position(kind);
+        if word >= self.words.len() {
+            self.words.resize(word + 1, 0);
+        }
+        let added = self.words[word] & bit == 0;
+        self.words[word] |= bit;
+        added
+    }
+
+    /// Removes `kind` from the set. Returns `true` if it was in the set.
+    pub fn remove(&mut self, kind: S) -> bool {
+        let (word, bit) = Self::position(kind);
+        let Some(bits) = self.words.get_mut(word) else {
+            return false;
+        };
+        let removed = *bits & bit != 0;
+        *bits &= !bit;
+        self.trim();
+        removed
+    }
+
+    /// Returns `true` if `kind` is in the set.
+    #[inline]
+    pub fn contains(&self, kind: S) -> bool {
+        let (word, bit) = Self::position(kind);
+        self.words.get(word).is_some_and(|bits| bits & bit != 0)
+    }
+
+    /// Returns `true` if the set contains no kinds.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    /// The number of kinds in the set.
+    pub fn len(&self) -> usize {
+        self.words.iter().map(|bits| bits.count_ones() as usize).sum()
+    }
+
+    /// Adds all kinds in `other` to this set.
+    pub fn union_with(&mut self, other: &Self) {
+        if other.words.len() > self.words.len() {
+            self.words.resize(other.words.len(), 0);
+        }
+        for (bits, other) in self.words.iter_mut().zip(&other.words) {
+            *bits |= other;
+        }
+    }
+
+    /// Returns a set with the kinds that are in this set, in `other`, or in both.
+    pub fn union(&self, other: &Self) -> Self {
+        let mut union = self.clone();
+        union.union_with(other);
+        union
+    }
+
+    /// Returns a set with the kinds that are in both this set and `other`.
+    pub fn intersection(&self, other: &Self) -> Self {
+        let mut intersection = Self {
+            words:   self.words.iter().zip(&other.words).map(|(bits, other)| bits & other).collect(),
+            _syntax: PhantomData,
+        };
+        intersection.trim();
+        intersection
+    }
+
+    /// Iterates over the kinds in the set, ordered by their raw values.
+    pub fn iter(&self) -> impl Iterator<Item = S> + '_ {
+        self.words.iter().enumerate().flat_map(|(word, &bits)| {
+            (0..64)
+                .filter(move |bit| bits & (1 << bit) != 0)
+                .map(move |bit| S::from_raw(RawSyntaxKind(word as u32 * 64 + bit)))
+        })
+    }
+
+    fn trim(&mut self) {
+        while self.words.last() == Some(&0) {
+            self.words.pop();
+        }
+    }
+}
+
+impl<S: Syntax> FromIterator<S> for SyntaxKindSet<S> {
+    fn from_iter<T: IntoIterator<Item = S>>(iter: T) -> Self {
+        let mut set = Self::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl<S: Syntax> Extend<S> for SyntaxKindSet<S> {
+    fn extend<T: IntoIterator<Item = S>>(&mut self, iter: T) {
+        for kind in iter {
+            self.insert(kind);
+        }
+    }
+}
+
+impl<S: Syntax> fmt::Debug for SyntaxKindSet<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter().map(KindName)).finish()
+    }
+}
+
+/// The kinds of the tokens that may follow each node kind, from which parsers compute where to stop skipping tokens
+/// after a syntax error. See the [module documentation](self) for an example.
+///
+/// Node kinds without a FOLLOW set do not stop recovery at any token.
+#[derive(Debug, Clone)]
+pub struct RecoverySets<S: Syntax> {
+    follow: FxHashMap<RawSyntaxKind, SyntaxKindSet<S>>,
+}
+
+impl<S: Syntax> Default for RecoverySets<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: Syntax> RecoverySets<S> {
+    /// Creates recovery sets without any FOLLOW sets.
+    pub fn new() -> Self {
+        Self {
+            follow: FxHashMap::default(),
+        }
+    }
+
+    /// Declares that the tokens of the given `kinds` may follow nodes of kind `node`, in addition to any kinds that
+    /// were declared before.
+    pub fn follow(mut self, node: S, kinds: impl IntoIterator<Item = S>) -> Self {
+        self.follow.entry(node.into_raw()).or_default().extend(kinds);
+        self
+    }
+
+    /// The kinds of the tokens that may follow nodes of kind `node`, if any were declared.
+    pub fn get(&self, node: S) -> Option<&SyntaxKindSet<S>> {
+        self.follow.get(&node.into_raw())
+    }
+
+    /// The union of the FOLLOW sets of all `nodes`, which is the set of tokens that ends error recovery while all of
+    /// `nodes` are open.
+    pub fn recovery_set(&self, nodes: impl IntoIterator<Item = S>) -> SyntaxKindSet<S> {
+        let mut set = SyntaxKindSet::new();
+        for node in nodes {
+            if let Some(follow) = self.get(node) {
+                set.union_with(follow);
+            }
+        }
+        set
+    }
+}
@@ -0,0 +1,342 @@
+//! Declaring the expected shape of the children of each node kind and validating trees against it.
+//!
+//! A [`Schema`] maps node kinds to a sequence of [`Child`] patterns, similar to the productions of a grammar. Checking
+//! a tree against a schema finds nodes produced by a parser that do not have the expected children, which catches
+//! parser bugs early. Since a schema can be printed in a grammar-like notation, it also documents the structure of
+//! the tree that the parser produces. A schema can also be
+//! [given to a builder](crate::build::GreenNodeBuilder::set_schema) to check every node as soon as it is built.
+//!
+//! # Example
+//! ```
+//! # use cstree::testing::*;
+//! use cstree::schema::{Child, Schema};
+//!
+//! let schema = Schema::new()
+//!     .shape(Root, [Child::kind(Operation).many()])
+//!     .shape(
+//!         Operation,
+//!         [
+//!             Child::one_of([Int, Float]),
+//!             Child::kind(Plus),
+//!             Child::one_of([Int, Float]),
+//!         ],
+//!     )
+//!     .ignore(|kind| kind == Whitespace);
+//! assert_eq!(
+//!     schema.to_string(),
+//!     "Root = Operation*\nOperation = (Int | Float) Plus (Int | Float)\n"
+//! );
+//!
+//! let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+//! builder.start_node(Root);
+//! builder.start_node(Operation);
+//! builder.token(Int, "1");
+//! builder.token(Whitespace, " ");
+//! builder.static_token(Plus);
+//! builder.finish_node();
+//! builder.finish_node();
+//! let (tree, _) = builder.finish();
+//! let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+//!
+//! let violations = schema.validate(&root);
+//! assert_eq!(violations.len(), 1);
+//! assert_eq!(violations[0].node, Operation);
+//! assert_eq!(violations[0].found, None);
+//! assert_eq!(
+//!     violations[0].to_string(),
+//!     "`Operation` at 0..3 is missing children at 3"
+//! );
+//! ```
+
+use std::fmt;
+
+use fxhash::FxHashMap;
+
+use crate::{
+    green::GreenNode,
+    syntax::SyntaxNode,
+    text::{TextRange, TextSize},
+    utility_types::KindName,
+    RawSyntaxKind, Syntax,
+};
+
+/// How many children in a row a [`Child`] pattern matches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Repeat {
+    One,
+    Optional,
+    Many,
+    AtLeastOne,
+}
+
+/// A pattern for one or more consecutive children of a node in a [`Schema`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Child<S: Syntax> {
+    /// The kinds matched by this pattern, or `None` if it matches any kind.
+    kinds:  Option<Vec<S>>,
+    repeat: Repeat,
+}
+
+impl<S: Syntax> Child<S> {
+    /// Matches a single child of the given `kind`.
+    pub fn kind(kind: S) -> Self {
+        Self::one_of([kind])
+    }
+
+    /// Matches a single child of any of the given `kinds`.
+    pub fn one_of(kinds: impl IntoIterator<Item = S>) -> Self {
+        Self {
+            kinds:  Some(kinds.into_iter().collect()),
+            repeat: Repeat::One,
+        }
+    }
+
+    /// Matches a single child of any kind.
+    pub fn any() -> Self {
+        Self {
+            kinds:  None,
+            repeat: Repeat::One,
+        }
+    }
+
+    /// Makes this pattern match zero or one children.
+    pub fn optional(mut self) -> Self {
+        self.repeat = Repeat::Optional;
+        self
+    }
+
+    /// Makes this pattern match any number of children, including none.
+    pub fn many(mut self) -> Self {
+        self.repeat = Repeat::Many;
+        self
+    }
+
+    /// Makes this pattern match one or more children.
+    pub fn at_least_one(mut self) -> Self {
+        self.repeat = Repeat::AtLeastOne;
+        self
+    }
+
+    fn matches(&self, kind: S) -> bool {
+        match &self.kinds {
+            Some(kinds) => kinds.contains(&kind),
+            None => true,
+        }
+    }
+}
+
+impl<S: Syntax> fmt::Display for Child<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kinds.as_deref() {
+            None => write!(f, "_")?,
+            Some([kind]) => write!(f, "{}", KindName(*kind))?,
+            Some(kinds) => {
+                write!(f, "(")?;
+                for (i, kind) in kinds.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " | ")?;
+                    }
+                    write!(f, "{}", KindName(*kind))?;
+                }
+                write!(f, ")")?;
+            }
+        }
+        match self.repeat {
+            Repeat::One => Ok(()),
+            Repeat::Optional => write!(f, "?"),
+            Repeat::Many => write!(f, "*"),
+            Repeat::AtLeastOne => write!(f, "+"),
+        }
+    }
+}
+
+/// The expected children of node kinds, against which trees can be [validated](Schema::validate).
+///
+/// Each node kind can be given a shape, which is a sequence of [`Child`] patterns that its children have to match
+/// in order. Nodes of kinds without a shape are not checked, but their descendants are. Children of the kinds given
+/// to [`ignore`](Schema::ignore), like whitespace and comments, are skipped before matching.
+///
+/// The [`Display`](fmt::Display) implementation prints the shapes in a grammar-like notation, one kind per line.
+/// See the [module documentation](self) for an example.
+#[derive(Debug, Clone)]
+pub struct Schema<S: Syntax> {
+    shapes: FxHashMap<RawSyntaxKind, (S, Vec<Child<S>>)>,
+    /// The order in which shapes were declared, used for printing.
+    order:  Vec<RawSyntaxKind>,
+    ignore: Option<fn(S) -> bool>,
+}
+
+impl<S: Syntax> Default for Schema<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: Syntax> Schema<S> {
+    /// Creates a schema without any shapes.
+    pub fn new() -> Self {
+        Self {
+            shapes: FxHashMap::default(),
+            order:  Vec::new(),
+            ignore: None,
+        }
+    }
+
+    /// Declares that the children of nodes of the given `kind` must match `children`.
+    /// Replaces any shape previously declared for `kind`.
+    pub fn shape(mut self, kind: S, children: impl IntoIterator<Item = Child<S>>) -> Self {
+        let raw = kind.into_raw();
+        if self
+            .shapes
+            .insert(raw, (kind, children.into_iter().collect()))
+            .is_none()
+        {
+            self.order.push(raw);
+        }
+        self
+    }
+
+    /// Skip children for which `ignore` returns `true` when matching shapes.
+    pub fn ignore(mut self, ignore: fn(S) -> bool) -> Self {
+        self.ignore = Some(ignore);
+        self
+    }
+
+    /// Checks `node` and all of its descendants against this schema, returning all nodes that do not match their
+    /// shape in preorder.
+    pub fn validate<D>(&self, node: &SyntaxNode<S, D>) -> Vec<Violation<S>> {
+        node.descendants().filter_map(|node| self.validate_node(node)).collect()
+    }
+
+    /// Checks the children of `node` against the shape of its kind, without looking at any descendants.
+    /// Returns `None` if the children match or if there is no shape for the kind of `node`.
+    pub fn validate_node<D>(&self, node: &SyntaxNode<S, D>) -> Option<Violation<S>> {
+        self.check(
+            node.kind(),
+            node.text_range(),
+            node.children_with_tokens()
+                .map(|child| (child.kind(), child.text_range())),
+        )
+    }
+
+    /// Checks the children of the green `node`, whose text starts at `offset`, like [`validate_node`].
+    ///
+    /// [`validate_node`]: Schema::validate_node
+    pub(crate) fn validate_green(&self, node: &GreenNode, offset: TextSize) -> Option<Violation<S>> {
+        let mut child_offset = offset;
+        self.check(
+            S::from_raw(node.kind()),
+            TextRange::at(offset, node.text_len()),
+            node.children().map(|child| {
+                let range = TextRange::at(child_offset, child.text_len());
+                child_offset = range.end();
+                (S::from_raw(child.kind()), range)
+            }),
+        )
+    }
+
+    fn check(
+        &self,
+        kind: S,
+        node_range: TextRange,
+        children: impl Iterator<Item = (S, TextRange)>,
+    ) -> Option<Violation<S>> {
+        let (_, shape) = self.shapes.get(&kind.into_raw())?;
+        let children: Vec<_> = children
+            .filter(|(kind, _)| !self.ignore.is_some_and(|ignore| ignore(*kind)))
+            .collect();
+        let kinds: Vec<_> = children.iter().map(|(kind, _)| *kind).collect();
+        let mut furthest = 0;
+        let mut failed = vec![false; shape.len() * (kinds.len() + 1)];
+        if matches(shape, &kinds, 0, 0, &mut furthest, &mut failed) {
+            return None;
+        }
+        let (range, found) = match children.get(furthest) {
+            Some(&(kind, range)) => (range, Some(kind)),
+            None => (TextRange::empty(node_range.end()), None),
+        };
+        Some(Violation {
+            node: kind,
+            node_range,
+            range,
+            found,
+        })
+    }
+}
+
+/// Returns whether `kinds[child..]` match `shape[pattern..]`, recording the furthest child that was reached.
+///
+/// `failed` records for each `(pattern, child)` whether it was already tried without a match, so that every pair is
+/// only tried once and the backtracking of several patterns that repeat does not take exponential time.
+fn matches<S: Syntax>(
+    shape: &[Child<S>],
+    kinds: &[S],
+    pattern: usize,
+    child: usize,
+    furthest: &mut usize,
+    failed: &mut [bool],
+) -> bool {
+    *furthest = (*furthest).max(child);
+    let Some(current) = shape.get(pattern) else {
+        return child == kinds.len();
+    };
+    let state = pattern * (kinds.len() + 1) + child;
+    if failed[state] {
+        return false;
+    }
+    // The number of children from `child` on that `current` could match.
+    let available = kinds[child..].iter().take_while(|&&kind| current.matches(kind)).count();
+    let (min, max) = match current.repeat {
+        Repeat::One => (1, 1),
+        Repeat::Optional => (0, 1),
+        Repeat::Many => (0, available),
+        Repeat::AtLeastOne => (1, available),
+    };
+    // Match greedily, backtracking to fewer repetitions if the rest of the shape does not match.
+    let matched = (min..=max.min(available))
+        .rev()
+        .any(|count| matches(shape, kinds, pattern + 1, child + count, furthest, failed));
+    failed[state] = !matched;
+    matched
+}
+
+impl<S: Syntax> fmt::Display for Schema<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for raw in &self.order {
+            let (kind, children) = &self.shapes[raw];
+            write!(f, "{} =", KindName(*kind))?;
+            for child in children {
+                write!(f, " {}", child)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// A node whose children do not match its shape in a [`Schema`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Violation<S: Syntax> {
+    /// The kind of the node.
+    pub node:       S,
+    /// The range of the node.
+    pub node_range: TextRange,
+    /// The range of the first child that could not be matched, or an empty range at the end of the node if the
+    /// node ended before its shape was complete.
+    pub range:      TextRange,
+    /// The kind of the first child that could not be matched, or `None` if the node ended before its shape was
+    /// complete.
+    pub found:      Option<S>,
+}
+
+impl<S: Syntax> fmt::Display for Violation<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}` at {:?} ", KindName(self.node), self.node_range)?;
+        match self.found {
+            Some(found) => write!(f, "has unexpected `{}` at {:?}", KindName(found), self.range),
+            None => write!(f, "is missing children at {:?}", self.range.start()),
+        }
+    }
+}
+
+impl<S: Syntax> std::error::Error for Violation<S> {}
@@ -1,15 +1,16 @@
 //! Serialization and Deserialization for syntax trees.
 
 use crate::{
-    build::GreenNodeBuilder,
-    interning::{Resolver, TokenKey},
+    build::{GreenNodeBuilder, NodeCache},
+    green::GreenNode,
+    interning::{Interner, Resolver, TokenKey},
     syntax::{ResolvedNode, SyntaxNode},
     traversal::WalkEvent,
     util::NodeOrToken,
     RawSyntaxKind, Syntax,
 };
 use serde::{
-    de::{Error, SeqAccess, Visitor},
+    de::{DeserializeSeed, Error, SeqAccess, Visitor},
     ser::SerializeTuple,
     Deserialize, Serialize,
 };
@@ -148,94 +149,215 @@ where
     S: Syntax,
     D: Deserialize<'de>,
 {
-    // Deserialization is done by walking down the deserialized event stream,
-    // which is the first element inside the tuple. The events
-    // are then passed to a `GreenNodeBuilder` which will do all
-    // the hard work for use.
-    //
-    // While walking the event stream, we also store a list of booleans,
-    // which indicate which node needs to set data. After creating the tree,
-    // we walk down the nodes, check if the bool at `data_list[idx]` is true,
-    // and if so, pop the first element of the data list and attach the data
-    // to the current node.
     fn deserialize<De>(deserializer: De) -> Result<Self, De::Error>
     where
         De: serde::Deserializer<'de>,
     {
-        struct EventVisitor<S: Syntax, D: 'static> {
-            _marker: PhantomData<fn() -> ResolvedNode<S, D>>,
+        let (tree, cache, nodes, data) = deserialize_tree(deserializer, GreenNodeBuilder::<S>::new())?;
+        let tree = ResolvedNode::new_root_with_resolver(tree, cache.unwrap().into_interner().unwrap());
+        attach_data(&tree, &nodes, data)?;
+        Ok(tree)
+    }
+}
+
+/// The green tree, the cache of the builder it was built with, the recorded [`NodeInfo`] and the data list of a
+/// deserialized tree.
+pub(crate) type DeserializedTree<'interner, I, D> =
+    (GreenNode, Option<NodeCache<'interner, I>>, Vec<NodeInfo>, VecDeque<D>);
+
+/// Deserializes a tree that was serialized by [`gen_serialize`], interning its texts with the interner of `builder`.
+///
+/// Deserialization is done by walking down the deserialized event stream, which is the first element inside the
+/// tuple. The events are then passed to the `builder`, which will do all the hard work for us. While walking the event
+/// stream, we record for each node whether it has data and where its subtree ends, which [`attach_data`] uses to
+/// attach the data list (the second element of the tuple) to the nodes of the tree.
+pub(crate) fn deserialize_tree<'de, 'cache, 'interner, De, S, D, I>(
+    deserializer: De,
+    builder: GreenNodeBuilder<'cache, 'interner, S, I>,
+) -> Result<DeserializedTree<'interner, I, D>, De::Error>
+where
+    De: serde::Deserializer<'de>,
+    S: Syntax,
+    D: Deserialize<'de>,
+    I: Interner<TokenKey>,
+{
+    struct TreeVisitor<'cache, 'interner, S: Syntax, D, I> {
+        builder: GreenNodeBuilder<'cache, 'interner, S, I>,
+        _marker: PhantomData<fn() -> D>,
+    }
+
+    impl<'de, 'cache, 'interner, S, D, I> Visitor<'de> for TreeVisitor<'cache, 'interner, S, D, I>
+    where
+        S: Syntax,
+        D: Deserialize<'de>,
+        I: Interner<TokenKey>,
+    {
+        type Value = DeserializedTree<'interner, I, D>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a list of tree events and a list of node data")
         }
 
-        impl<'de, S, D> Visitor<'de> for EventVisitor<S, D>
+        fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
         where
-            S: Syntax,
-            D: Deserialize<'de>,
+            A: SeqAccess<'de>,
         {
-            type Value = (ResolvedNode<S, D>, VecDeque<bool>);
+            let (tree, cache, nodes) = seq
+                .next_element_seed(EventsSeed { builder: self.builder })?
+                .ok_or_else(|| A::Error::custom("missing tree events"))?;
+            let data = seq
+                .next_element::<VecDeque<D>>()?
+                .ok_or_else(|| A::Error::custom("missing node data"))?;
+            Ok((tree, cache, nodes, data))
+        }
+    }
 
-            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("a list of tree events")
-            }
+    struct EventsSeed<'cache, 'interner, S: Syntax, I> {
+        builder: GreenNodeBuilder<'cache, 'interner, S, I>,
+    }
 
-            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
-            where
-                A: SeqAccess<'de>,
-            {
-                let mut builder: GreenNodeBuilder<S> = GreenNodeBuilder::new();
-                let mut data_indices = VecDeque::new();
-
-                while let Some(next) = seq.next_element::<Event<'_>>()? {
-                    match next {
-                        Event::EnterNode(kind, has_data) => {
-                            builder.start_node(S::from_raw(kind));
-                            data_indices.push_back(has_data);
-                        }
-                        Event::Token(kind, text) => builder.token(S::from_raw(kind), text),
-                        Event::LeaveNode => builder.finish_node(),
-                    }
-                }
+    impl<'de, 'interner, S, I> DeserializeSeed<'de> for EventsSeed<'_, 'interner, S, I>
+    where
+        S: Syntax,
+        I: Interner<TokenKey>,
+    {
+        type Value = (GreenNode, Option<NodeCache<'interner, I>>, Vec<NodeInfo>);
 
-                let (tree, cache) = builder.finish();
-                let tree = ResolvedNode::new_root_with_resolver(tree, cache.unwrap().into_interner().unwrap());
-                Ok((tree, data_indices))
-            }
+        fn deserialize<DE>(self, deserializer: DE) -> Result<Self::Value, DE::Error>
+        where
+            DE: serde::Deserializer<'de>,
+        {
+            deserializer.deserialize_seq(self)
+        }
+    }
+
+    impl<'de, 'interner, S, I> Visitor<'de> for EventsSeed<'_, 'interner, S, I>
+    where
+        S: Syntax,
+        I: Interner<TokenKey>,
+    {
+        type Value = (GreenNode, Option<NodeCache<'interner, I>>, Vec<NodeInfo>);
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a list of tree events")
         }
 
-        struct ProcessedEvents<S: Syntax, D: 'static>(ResolvedNode<S, D>, VecDeque<bool>);
-        impl<'de, S, D> Deserialize<'de> for ProcessedEvents<S, D>
+        fn visit_seq<A>(mut self, mut seq: A) -> Result<Self::Value, A::Error>
         where
-            S: Syntax,
-            D: Deserialize<'de>,
+            A: SeqAccess<'de>,
         {
-            fn deserialize<DE>(deserializer: DE) -> Result<Self, DE::Error>
-            where
-                DE: serde::Deserializer<'de>,
-            {
-                let (tree, ids) = deserializer.deserialize_seq(EventVisitor { _marker: PhantomData })?;
-                Ok(Self(tree, ids))
+            let builder = &mut self.builder;
+            let mut nodes = Vec::new();
+            // The preorder indices of all nodes that have been entered, but not left
+            let mut open = Vec::new();
+            // Corrupt input should result in an error instead of a panic in `S::from_raw`
+            let kind = |raw: RawSyntaxKind| {
+                S::try_from_raw(raw).ok_or_else(|| A::Error::custom(format_args!("invalid syntax kind: {}", raw.0)))
+            };
+
+            // Unbalanced events would make the builder panic, so they are checked for here
+            let mut finished = false;
+            while let Some(next) = seq.next_element::<Event<'_>>()? {
+                if finished {
+                    return Err(A::Error::custom("events after the end of the root node"));
+                }
+                match next {
+                    Event::EnterNode(raw, has_data) => {
+                        builder.start_node(kind(raw)?);
+                        open.push(nodes.len());
+                        nodes.push(NodeInfo {
+                            has_data,
+                            data_in_subtree: has_data,
+                            end: 0,
+                        });
+                    }
+                    Event::Token(_, _) if open.is_empty() => {
+                        return Err(A::Error::custom("token outside of the root node"));
+                    }
+                    Event::Token(raw, text) => builder.token(kind(raw)?, text),
+                    Event::LeaveNode => {
+                        let Some(index) = open.pop() else {
+                            return Err(A::Error::custom("unbalanced nodes"));
+                        };
+                        builder.finish_node();
+                        nodes[index].end = nodes.len();
+                        if let Some(&parent) = open.last() {
+                            nodes[parent].data_in_subtree |= nodes[index].data_in_subtree;
+                        }
+                        finished = open.is_empty();
+                    }
+                }
+            }
+            if !finished {
+                return Err(A::Error::custom("incomplete tree"));
             }
+
+            let (tree, cache) = self.builder.finish();
+            Ok((tree, cache, nodes))
         }
+    }
+
+    deserializer.deserialize_tuple(
+        2,
+        TreeVisitor {
+            builder,
+            _marker: PhantomData,
+        },
+    )
+}
 
-        let (ProcessedEvents(tree, data_indices), mut data) =
-            <(ProcessedEvents<S, D>, VecDeque<D>)>::deserialize(deserializer)?;
+/// What [`attach_data`] needs to know about a deserialized node.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct NodeInfo {
+    /// Whether the next element of the data list belongs to this node.
+    has_data: bool,
+    /// Whether this node or any of its descendants has data.
+    data_in_subtree: bool,
+    /// The preorder index of the first node after the subtree of this node.
+    end: usize,
+}
 
-        tree.descendants().zip(data_indices).try_for_each(|(node, has_data)| {
-            if has_data {
-                let data = data
-                    .pop_front()
-                    .ok_or_else(|| De::Error::custom("invalid serialized tree"))?;
-                node.set_data(data);
+/// Attaches the deserialized `data` to the nodes of `tree` as recorded in `nodes`, in preorder.
+///
+/// Only the nodes on the way to nodes with data are created in the red tree, the remaining nodes are created lazily
+/// when the tree is traversed later.
+pub(crate) fn attach_data<S, D, E>(tree: &SyntaxNode<S, D>, nodes: &[NodeInfo], mut data: VecDeque<D>) -> Result<(), E>
+where
+    S: Syntax,
+    E: Error,
+{
+    fn attach<S: Syntax, D, E: Error>(
+        node: &SyntaxNode<S, D>,
+        nodes: &[NodeInfo],
+        index: usize,
+        data: &mut VecDeque<D>,
+    ) -> Result<(), E> {
+        if nodes[index].has_data {
+            let data = data.pop_front().ok_or_else(|| E::custom("invalid serialized tree"))?;
+            node.set_data(data);
+        }
+        let mut child_index = index + 1;
+        let mut offset = node.text_range().start();
+        for (i, child) in node.green().children().enumerate() {
+            if let NodeOrToken::Node(child) = child {
+                if nodes[child_index].data_in_subtree {
+                    let child = node.get_or_add_node(child, i, offset);
+                    attach(child.as_node().unwrap(), nodes, child_index, data)?;
+                }
+                child_index = nodes[child_index].end;
             }
-            <Result<(), De::Error>>::Ok(())
-        })?;
-
-        if !data.is_empty() {
-            Err(De::Error::custom(
-                "serialized SyntaxNode contained too many data elements",
-            ))
-        } else {
-            Ok(tree)
+            offset += child.text_len();
         }
+        Ok(())
+    }
+
+    if nodes.first().is_some_and(|root| root.data_in_subtree) {
+        attach(tree, nodes, 0, &mut data)?;
+    }
+    if !data.is_empty() {
+        Err(E::custom("serialized SyntaxNode contained too many data elements"))
+    } else {
+        Ok(())
     }
 }
 
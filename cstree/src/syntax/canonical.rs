@@ -0,0 +1,139 @@
+//! Printing the tokens of a subtree without their formatting.
+
+use std::fmt;
+
+use super::*;
+use crate::{
+    interning::{Resolver, TokenKey},
+    Syntax,
+};
+
+/// Options to print the text of a subtree in a canonical form with [`SyntaxNode::canonical_text`], which is equal
+/// for trees that only differ in their formatting.
+///
+/// Which tokens are trivia is decided by the function given to [`Canonicalize::new`]. Trivia tokens are not printed.
+/// Instead, every run of trivia between two other tokens is replaced by a single space, and trivia before the first
+/// and after the last token are dropped. The space can be limited to the places where the two tokens would otherwise
+/// be read as one with [`needs_space`](Canonicalize::needs_space), which makes for the shortest canonical text.
+///
+/// # Example
+/// ```
+/// # use cstree::testing::*;
+/// use cstree::syntax::Canonicalize;
+///
+/// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+/// builder.start_node(Root);
+/// builder.token(Whitespace, "\n");
+/// builder.token(Identifier, "x");
+/// builder.token(Whitespace, "  ");
+/// builder.static_token(Plus);
+/// builder.token(Whitespace, "\n\t");
+/// builder.token(Int, "1");
+/// builder.finish_node();
+/// let (tree, cache) = builder.finish();
+/// let interner = cache.unwrap().into_interner().unwrap();
+/// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+///
+/// let options = Canonicalize::new(|kind| kind == Whitespace);
+/// assert_eq!(root.canonical_text(&options, &interner), "x + 1");
+/// let options = options.needs_space(|left, right| left != Plus && right != Plus);
+/// assert_eq!(root.canonical_text(&options, &interner), "x+1");
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Canonicalize<S: Syntax> {
+    is_trivia:   fn(S) -> bool,
+    needs_space: Option<fn(S, S) -> bool>,
+}
+
+impl<S: Syntax> Canonicalize<S> {
+    /// Creates the default options, see [`Canonicalize`]. Tokens for which `is_trivia` returns `true`, like whitespace
+    /// and comments, are not printed.
+    pub fn new(is_trivia: fn(S) -> bool) -> Self {
+        Self {
+            is_trivia,
+            needs_space: None,
+        }
+    }
+
+    /// Only replace trivia between a token of the first and a token of the second kind with a space if
+    /// `needs_space` returns `true` for the two kinds. Trivia are otherwise dropped without a replacement.
+    ///
+    /// Tokens that were not separated by trivia in the input are never separated in the canonical text.
+    pub fn needs_space(mut self, needs_space: fn(S, S) -> bool) -> Self {
+        self.needs_space = Some(needs_space);
+        self
+    }
+
+    /// Returns `true` if tokens of this kind are not printed.
+    pub fn is_trivia(&self, kind: S) -> bool {
+        (self.is_trivia)(kind)
+    }
+}
+
+impl<S: Syntax, D> SyntaxNode<S, D> {
+    /// Returns the text of the tokens in the subtree rooted at this node that are not trivia, separated as
+    /// configured by `options`. The text of the tokens is resolved with `resolver`.
+    ///
+    /// Two subtrees have the same canonical text if they consist of the same tokens and only differ in their trivia,
+    /// which makes it a suitable key for hashing or detecting duplicated code.
+    pub fn canonical_text<R>(&self, options: &Canonicalize<S>, resolver: &R) -> String
+    where
+        R: Resolver<TokenKey> + ?Sized,
+    {
+        let mut text = String::new();
+        self.write_canonical_text(options, resolver, &mut text)
+            .expect("writing to a `String` does not fail");
+        text
+    }
+
+    /// Writes the [canonical text](SyntaxNode::canonical_text) of the subtree rooted at this node to `out`, without
+    /// collecting it into a string first.
+    pub fn write_canonical_text<R, W>(&self, options: &Canonicalize<S>, resolver: &R, out: &mut W) -> fmt::Result
+    where
+        R: Resolver<TokenKey> + ?Sized,
+        W: fmt::Write + ?Sized,
+    {
+        let mut previous: Option<S> = None;
+        let mut separated = false;
+        for token in self.tokens() {
+            let kind = token.kind();
+            if options.is_trivia(kind) {
+                separated = true;
+                continue;
+            }
+            if let Some(previous) = previous {
+                if separated && options.needs_space.map_or(true, |needs_space| needs_space(previous, kind)) {
+                    out.write_char(' ')?;
+                }
+            }
+            out.write_str(token.resolve_text(resolver))?;
+            previous = Some(kind);
+            separated = false;
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if the subtrees rooted at this node and at `other` consist of the same tokens, of the same
+    /// kinds and with the same text, when ignoring the tokens for which `options` says that they are trivia. The
+    /// text of the tokens of this tree is resolved with `resolver`, and that of `other` with `other_resolver`.
+    ///
+    /// Unlike comparing the [canonical texts](SyntaxNode::canonical_text) of the subtrees, this does not allocate
+    /// and stops at the first difference. The structure of the nodes is not compared.
+    pub fn eq_ignoring_trivia<E, R1, R2>(
+        &self,
+        other: &SyntaxNode<S, E>,
+        options: &Canonicalize<S>,
+        resolver: &R1,
+        other_resolver: &R2,
+    ) -> bool
+    where
+        R1: Resolver<TokenKey> + ?Sized,
+        R2: Resolver<TokenKey> + ?Sized,
+    {
+        let tokens = self.tokens().filter(|token| !options.is_trivia(token.kind()));
+        let other_tokens = other.tokens().filter(|token| !options.is_trivia(token.kind()));
+        tokens
+            .map(|token| (token.kind(), token.resolve_text(resolver)))
+            .eq(other_tokens.map(|token| (token.kind(), token.resolve_text(other_resolver))))
+    }
+}
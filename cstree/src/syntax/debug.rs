@@ -0,0 +1,248 @@
+//! Configurable [`Debug`](fmt::Debug) rendering of syntax trees.
+
+use std::fmt;
+
+use super::*;
+use crate::{
+    interning::{Resolver, TokenKey},
+    util::NodeOrToken,
+    utility_types::KindName,
+    KindClass, Syntax,
+};
+
+/// One of the 8 standard ANSI terminal colors, used to highlight kinds in [`DebugOptions::colored`] output.
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AnsiColor {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+}
+
+impl AnsiColor {
+    /// The color that [`DebugOptions::color_by_class`] highlights kinds of the given class in, or `None` if they are
+    /// not highlighted by class.
+    ///
+    /// Errors are red, comments green, keywords magenta, identifiers cyan, literals yellow and expression, statement
+    /// and item nodes blue. Whitespace, punctuation and other kinds have no color of their own.
+    pub fn for_class(class: KindClass) -> Option<Self> {
+        match class {
+            KindClass::Error => Some(Self::Red),
+            KindClass::Comment | KindClass::DocComment => Some(Self::Green),
+            KindClass::Keyword => Some(Self::Magenta),
+            KindClass::Identifier => Some(Self::Cyan),
+            KindClass::Literal => Some(Self::Yellow),
+            KindClass::Expression | KindClass::Statement | KindClass::Item => Some(Self::Blue),
+            KindClass::Other | KindClass::Whitespace | KindClass::Punctuation => None,
+        }
+    }
+
+    fn code(self) -> u8 {
+        30 + self as u8
+    }
+}
+
+/// Options to control how a syntax tree is rendered by [`SyntaxNode::write_debug_with`].
+///
+/// The default options print the entire tree, with the full text of every token and the range of every element,
+/// and without any colors.
+///
+/// # Example
+/// ```
+/// # use cstree::testing::*;
+/// # use cstree::syntax::DebugOptions;
+/// # let mut builder: GreenNodeBuilder<TestSyntaxKind> = GreenNodeBuilder::new();
+/// # builder.start_node(Root);
+/// # builder.start_node(Operation);
+/// # builder.token(Int, "1000000");
+/// # builder.finish_node();
+/// # builder.finish_node();
+/// # let (tree, cache) = builder.finish();
+/// # let interner = cache.unwrap().into_interner().unwrap();
+/// # let root: SyntaxNode<TestSyntaxKind> = SyntaxNode::new_root(tree);
+/// let options = DebugOptions::new().max_depth(1).offsets(false);
+/// assert_eq!(
+///     root.debug_with(&interner, &options),
+///     "Root\n  Operation\n    ...\n"
+/// );
+///
+/// let options = DebugOptions::new().max_text_len(3).offsets(false);
+/// assert_eq!(
+///     root.debug_with(&interner, &options),
+///     "Root\n  Operation\n    Int \"100 ...\"\n"
+/// );
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct DebugOptions<S: Syntax> {
+    max_depth:    Option<usize>,
+    max_text_len: Option<usize>,
+    offsets:      bool,
+    node_color:   Option<AnsiColor>,
+    token_color:  Option<AnsiColor>,
+    kind_colors:  Option<fn(S) -> Option<AnsiColor>>,
+}
+
+impl<S: Syntax> Default for DebugOptions<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: Syntax> DebugOptions<S> {
+    /// Creates the default options, see [`DebugOptions`].
+    pub fn new() -> Self {
+        Self {
+            max_depth:    None,
+            max_text_len: None,
+            offsets:      true,
+            node_color:   None,
+            token_color:  None,
+            kind_colors:  None,
+        }
+    }
+
+    /// Only print elements up to `depth` levels below the node the tree is rendered from.
+    /// Deeper subtrees are replaced by a single `...` line.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Cut off token texts that are longer than `len` characters.
+    pub fn max_text_len(mut self, len: usize) -> Self {
+        self.max_text_len = Some(len);
+        self
+    }
+
+    /// Whether to print the text range of each element (on by default).
+    pub fn offsets(mut self, offsets: bool) -> Self {
+        self.offsets = offsets;
+        self
+    }
+
+    /// Highlight node kinds in `node` and token kinds in `token` using ANSI escape codes.
+    pub fn colored(mut self, node: AnsiColor, token: AnsiColor) -> Self {
+        self.node_color = Some(node);
+        self.token_color = Some(token);
+        self
+    }
+
+    /// Highlight kinds using ANSI escape codes, with `color` deciding the color of each kind.
+    /// Kinds for which `color` returns `None` use the colors given to [`colored`](DebugOptions::colored),
+    /// or are not highlighted if there are none.
+    pub fn color_kinds(mut self, color: fn(S) -> Option<AnsiColor>) -> Self {
+        self.kind_colors = Some(color);
+        self
+    }
+
+    /// Highlight kinds using ANSI escape codes in the color of their [class](Syntax::class), as given by
+    /// [`AnsiColor::for_class`]. Like with [`color_kinds`](DebugOptions::color_kinds), kinds of classes without a
+    /// color use the colors given to [`colored`](DebugOptions::colored), or are not highlighted if there are none.
+    pub fn color_by_class(self) -> Self {
+        self.color_kinds(|kind| AnsiColor::for_class(kind.class()))
+    }
+
+    fn write_kind(&self, kind: S, is_token: bool, target: &mut impl fmt::Write) -> fmt::Result {
+        let default = if is_token { self.token_color } else { self.node_color };
+        match self.kind_colors.and_then(|color| color(kind)).or(default) {
+            Some(color) => write!(target, "\x1b[{}m{}\x1b[0m", color.code(), KindName(kind)),
+            None => write!(target, "{}", KindName(kind)),
+        }
+    }
+
+    fn write_element<D, R>(
+        &self,
+        element: SyntaxElementRef<'_, S, D>,
+        resolver: &R,
+        target: &mut impl fmt::Write,
+    ) -> fmt::Result
+    where
+        R: Resolver<TokenKey> + ?Sized,
+    {
+        self.write_kind(element.kind(), element.as_token().is_some(), target)?;
+        if self.offsets {
+            write!(target, "@{:?}", element.text_range())?;
+        }
+        if let NodeOrToken::Token(token) = element {
+            let text = token.resolve_text(resolver);
+            match self.max_text_len.and_then(|len| text.char_indices().nth(len)) {
+                Some((cut, _)) => write!(target, " {:?}", format!("{} ...", &text[..cut]))?,
+                None => write!(target, " {:?}", text)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<S: Syntax, D> SyntaxNode<S, D> {
+    /// Writes a rendering of the subtree rooted in this node into the given `target`, as configured by `options`.
+    ///
+    /// Like the recursive [`write_debug`](SyntaxNode::write_debug), every element is written on its own line and
+    /// indented according to its depth.
+    pub fn write_debug_with<R>(
+        &self,
+        resolver: &R,
+        options: &DebugOptions<S>,
+        target: &mut impl fmt::Write,
+    ) -> fmt::Result
+    where
+        R: Resolver<TokenKey> + ?Sized,
+    {
+        options.write_element(self.into(), resolver, target)?;
+        writeln!(target)?;
+        if options.max_depth == Some(0) {
+            return write_elided(self, 1, target);
+        }
+        // Walk the tree with an explicit stack so that pruned subtrees are never visited.
+        let mut stack = vec![self.children_with_tokens()];
+        while let Some(children) = stack.last_mut() {
+            let Some(child) = children.next() else {
+                stack.pop();
+                continue;
+            };
+            let depth = stack.len();
+            for _ in 0..depth {
+                write!(target, "  ")?;
+            }
+            options.write_element(child, resolver, target)?;
+            writeln!(target)?;
+            if let NodeOrToken::Node(node) = child {
+                if options.max_depth.is_some_and(|max_depth| depth >= max_depth) {
+                    write_elided(node, depth + 1, target)?;
+                } else {
+                    stack.push(node.children_with_tokens());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns a rendering of the subtree rooted in this node as a string, as configured by `options`.
+    ///
+    /// To avoid allocating, see [`write_debug_with`](SyntaxNode::write_debug_with).
+    pub fn debug_with<R>(&self, resolver: &R, options: &DebugOptions<S>) -> String
+    where
+        R: Resolver<TokenKey> + ?Sized,
+    {
+        // NOTE: `fmt::Write` methods on `String` never fail
+        let mut res = String::new();
+        self.write_debug_with(resolver, options, &mut res).unwrap();
+        res
+    }
+}
+
+/// Marks the children of `node` as left out, if there are any.
+fn write_elided<S: Syntax, D>(node: &SyntaxNode<S, D>, depth: usize, target: &mut impl fmt::Write) -> fmt::Result {
+    if node.green().children().next().is_none() {
+        return Ok(());
+    }
+    for _ in 0..depth {
+        write!(target, "  ")?;
+    }
+    writeln!(target, "...")
+}
@@ -1,8 +1,8 @@
-use std::{fmt, sync::atomic::AtomicU32};
+use std::fmt;
 
 use text_size::{TextRange, TextSize};
 
-use super::*;
+use super::{node::RefCount, *};
 use crate::{
     green::GreenElementRef,
     interning::{Resolver, TokenKey},
@@ -163,7 +163,7 @@ impl<S: Syntax, D> SyntaxElement<S, D> {
         parent: &SyntaxNode<S, D>,
         index: u32,
         offset: TextSize,
-        ref_count: *mut AtomicU32,
+        ref_count: *mut RefCount,
     ) -> SyntaxElement<S, D> {
         match element {
             NodeOrToken::Node(node) => SyntaxNode::new_child(node, parent, index, offset, ref_count).into(),
@@ -180,6 +180,17 @@ impl<S: Syntax, D> SyntaxElement<S, D> {
         }
     }
 
+    /// The range this element covers in the source text, in bytes, relative to the start of `ancestor`.
+    ///
+    /// Returns `None` if `ancestor` is neither this element nor one of its ancestors.
+    #[inline]
+    pub fn range_in(&self, ancestor: &SyntaxNode<S, D>) -> Option<TextRange> {
+        match self {
+            NodeOrToken::Node(it) => it.range_in(ancestor),
+            NodeOrToken::Token(it) => it.range_in(ancestor),
+        }
+    }
+
     /// The internal representation of the kind of this element.
     #[inline]
     pub fn syntax_kind(&self) -> RawSyntaxKind {
@@ -251,6 +262,24 @@ impl<S: Syntax, D> SyntaxElement<S, D> {
             NodeOrToken::Token(it) => it.prev_sibling_or_token(),
         }
     }
+
+    /// Returns the first token after this element, in document order.
+    #[inline]
+    pub fn next_token(&self) -> Option<&SyntaxToken<S, D>> {
+        match self {
+            NodeOrToken::Node(it) => it.next_token(),
+            NodeOrToken::Token(it) => it.next_token(),
+        }
+    }
+
+    /// Returns the last token before this element, in document order.
+    #[inline]
+    pub fn prev_token(&self) -> Option<&SyntaxToken<S, D>> {
+        match self {
+            NodeOrToken::Node(it) => it.prev_token(),
+            NodeOrToken::Token(it) => it.prev_token(),
+        }
+    }
 }
 
 impl<'a, S: Syntax, D> SyntaxElementRef<'a, S, D> {
@@ -263,6 +292,17 @@ impl<'a, S: Syntax, D> SyntaxElementRef<'a, S, D> {
         }
     }
 
+    /// The range this element covers in the source text, in bytes, relative to the start of `ancestor`.
+    ///
+    /// Returns `None` if `ancestor` is neither this element nor one of its ancestors.
+    #[inline]
+    pub fn range_in(&self, ancestor: &SyntaxNode<S, D>) -> Option<TextRange> {
+        match self {
+            NodeOrToken::Node(it) => it.range_in(ancestor),
+            NodeOrToken::Token(it) => it.range_in(ancestor),
+        }
+    }
+
     /// The internal representation of the kind of this element.
     #[inline]
     pub fn syntax_kind(&self) -> RawSyntaxKind {
@@ -335,6 +375,40 @@ impl<'a, S: Syntax, D> SyntaxElementRef<'a, S, D> {
         }
     }
 
+    /// Returns the first token after this element, in document order.
+    ///
+    /// If this element is a node, this is the first token after its subtree. The token may belong to
+    /// a sibling of this element or one of its ancestors, but will always be further right in the tree.
+    pub fn next_token(&self) -> Option<&'a SyntaxToken<S, D>> {
+        let mut element = *self;
+        loop {
+            while let Some(sibling) = element.next_sibling_or_token() {
+                if let Some(token) = sibling.first_token() {
+                    return Some(token);
+                }
+                element = sibling;
+            }
+            element = element.parent()?.into();
+        }
+    }
+
+    /// Returns the last token before this element, in document order.
+    ///
+    /// If this element is a node, this is the last token before its subtree. The token may belong to
+    /// a sibling of this element or one of its ancestors, but will always be further left in the tree.
+    pub fn prev_token(&self) -> Option<&'a SyntaxToken<S, D>> {
+        let mut element = *self;
+        loop {
+            while let Some(sibling) = element.prev_sibling_or_token() {
+                if let Some(token) = sibling.last_token() {
+                    return Some(token);
+                }
+                element = sibling;
+            }
+            element = element.parent()?.into();
+        }
+    }
+
     #[inline]
     pub(super) fn token_at_offset(&self, offset: TextSize) -> TokenAtOffset<SyntaxToken<S, D>> {
         assert!(self.text_range().start() <= offset && offset <= self.text_range().end());
@@ -0,0 +1,167 @@
+//! Adjusting the indentation of a subtree, for example after moving it to a different nesting level.
+
+use super::*;
+use crate::{
+    green::GreenNode,
+    interning::{Interner, TokenKey},
+    Syntax,
+};
+
+/// The characters a line is indented with by [`Reindent`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IndentStyle {
+    /// One tab per indentation level. Columns that do not fill a whole tab are indented with spaces.
+    Tabs,
+    /// The given number of spaces per indentation level.
+    Spaces(u32),
+}
+
+/// Options to change the indentation of the lines in a subtree with [`SyntaxNode::reindent`].
+///
+/// Indentation is the run of spaces and tabs at the start of a line, which is expected to be part of a whitespace
+/// token. Which tokens count as whitespace is decided by the function given to [`Reindent::new`]. Every indented
+/// line in the subtree is shifted by the given number of levels and written in the given [`IndentStyle`], which
+/// also converts between tabs and spaces. Blank lines are left alone.
+///
+/// By default, lines are indented with 4 spaces per level, tabs are 4 columns wide and lines are not shifted.
+///
+/// # Example
+/// ```
+/// # use cstree::testing::*;
+/// use cstree::syntax::{IndentStyle, Reindent};
+///
+/// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+/// builder.start_node(Root);
+/// builder.token(Int, "1");
+/// builder.token(Whitespace, "\n\t");
+/// builder.static_token(Plus);
+/// builder.token(Whitespace, "\n\t\t");
+/// builder.token(Int, "2");
+/// builder.finish_node();
+/// let (tree, cache) = builder.finish();
+/// let mut interner = cache.unwrap().into_interner().unwrap();
+/// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+///
+/// let options = Reindent::new(|kind| kind == Whitespace)
+///     .style(IndentStyle::Spaces(2))
+///     .shift(1);
+/// let new_root: SyntaxNode<MySyntax> =
+///     SyntaxNode::new_root(root.reindent(&options, &mut interner));
+/// assert_eq!(new_root.resolve_text(&interner), "1\n      +\n          2");
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Reindent<S: Syntax> {
+    is_whitespace: fn(S) -> bool,
+    style:         IndentStyle,
+    tab_width:     u32,
+    shift:         i32,
+}
+
+impl<S: Syntax> Reindent<S> {
+    /// Creates the default options, see [`Reindent`]. Tokens for which `is_whitespace` returns `true` may contain
+    /// indentation.
+    pub fn new(is_whitespace: fn(S) -> bool) -> Self {
+        Self {
+            is_whitespace,
+            style: IndentStyle::Spaces(4),
+            tab_width: 4,
+            shift: 0,
+        }
+    }
+
+    /// Indent lines with `style`, and shift them by levels of that style.
+    pub fn style(mut self, style: IndentStyle) -> Self {
+        self.style = style;
+        self
+    }
+
+    /// The number of columns a tab advances to, both when reading existing indentation and when indenting with
+    /// [`IndentStyle::Tabs`].
+    ///
+    /// ## Panics
+    /// If `width` is 0.
+    pub fn tab_width(mut self, width: u32) -> Self {
+        assert!(width > 0, "tabs must be at least one column wide");
+        self.tab_width = width;
+        self
+    }
+
+    /// Shift every indented line by `levels` indentation levels, which moves them to the left if `levels` is
+    /// negative. Lines are never shifted past the start of the line.
+    pub fn shift(mut self, levels: i32) -> Self {
+        self.shift = levels;
+        self
+    }
+
+    /// Returns the adjusted form of `indent`, which must consist only of spaces and tabs.
+    fn reindent(&self, indent: &str) -> String {
+        let columns = indent.chars().fold(0, |columns, c| match c {
+            '\t' => (columns / self.tab_width + 1) * self.tab_width,
+            _ => columns + 1,
+        });
+        let level = match self.style {
+            IndentStyle::Tabs => self.tab_width,
+            IndentStyle::Spaces(width) => width,
+        };
+        let columns = (i64::from(columns) + i64::from(self.shift) * i64::from(level)).max(0) as usize;
+        match self.style {
+            IndentStyle::Tabs => {
+                let tab_width = self.tab_width as usize;
+                let mut res = "\t".repeat(columns / tab_width);
+                res.push_str(&" ".repeat(columns % tab_width));
+                res
+            }
+            IndentStyle::Spaces(_) => " ".repeat(columns),
+        }
+    }
+}
+
+impl<S: Syntax, D> SyntaxNode<S, D> {
+    /// Returns a green tree, equal to the green tree this node belongs to, except that the indentation of all lines
+    /// in the subtree rooted at this node is adjusted as configured by `options`. New whitespace texts are interned
+    /// with `interner`, which must also be able to resolve the text of all tokens in the subtree.
+    ///
+    /// A line is indented by the spaces and tabs at the end of a whitespace token that follow a line break, or make
+    /// up the entire token if the previous token ends in a line break. Since the first line of this node usually
+    /// starts before it, its indentation is not part of the subtree and is not changed. The same goes for
+    /// whitespace at the very end of the subtree, which indents whatever follows this node.
+    ///
+    /// Tokens of which the indentation does not change are shared with the original tree.
+    pub fn reindent<I>(&self, options: &Reindent<S>, interner: &mut I) -> GreenNode
+    where
+        I: Interner<TokenKey> + ?Sized,
+    {
+        let texts: Vec<_> = self.tokens().map(|token| token.resolve_text(interner)).collect();
+        let mut at_line_start = false;
+        let replacements: Vec<_> = self
+            .tokens()
+            .zip(&texts)
+            .enumerate()
+            .map(|(i, (token, text))| {
+                let line_start = at_line_start;
+                at_line_start = text.ends_with('\n');
+                if !(options.is_whitespace)(token.kind()) || token.green().text_key().is_none() {
+                    return None;
+                }
+                let start = match text.rfind('\n') {
+                    Some(newline) => newline + 1,
+                    None if line_start => 0,
+                    None => return None,
+                };
+                let indent = &text[start..];
+                let indents_content = texts.get(i + 1).is_some_and(|next| !next.starts_with(['\n', '\r']));
+                if !indents_content || !indent.chars().all(|c| c == ' ' || c == '\t') {
+                    return None;
+                }
+                let reindented = options.reindent(indent);
+                (reindented != indent).then(|| format!("{}{}", &text[..start], reindented))
+            })
+            .collect();
+        let mut replacements = replacements.into_iter();
+        self.map_tokens(|token| {
+            let text = replacements.next().unwrap()?;
+            let key = interner.get_or_intern(&text);
+            Some(token.with_text(key, &text))
+        })
+    }
+}
@@ -0,0 +1,262 @@
+//! Parsing code embedded in the tokens of a tree in a second language.
+
+use std::fmt;
+
+use text_size::{TextRange, TextSize};
+
+use super::*;
+use crate::{
+    green::GreenNode,
+    interning::{Resolver, TokenKey},
+    util::TokenAtOffset,
+    Syntax,
+};
+
+/// A contiguous part of the text of an [`Injection`] and the range of a host token it was taken from.
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    /// The offset of the part in the injected text.
+    injected: TextSize,
+    /// The document offset of the part in the host token.
+    host:     TextSize,
+    len:      TextSize,
+    /// The index of the host token in `Injection::hosts`.
+    token:    usize,
+}
+
+/// Code in a second language that is embedded in one or more tokens of a tree, like the contents of a template
+/// string or of a `<script>` element.
+///
+/// An injection concatenates the embedded parts of its host tokens into a single [text](Injection::text), which
+/// can be parsed by the parser of the embedded language. It remembers where each part came from, so offsets and
+/// ranges in the injected text can be mapped to document offsets and back. Once the text is parsed, the resulting
+/// tree can be [attached](Injection::attach) to the injection, which links its elements back to the host tokens.
+///
+/// # Examples
+/// ```
+/// # use cstree::testing::*;
+/// use cstree::{syntax::Injection, text::TextRange};
+///
+/// // The host language has string literals, whose contents are code in the embedded language.
+/// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+/// builder.start_node(Root);
+/// builder.token(Identifier, "eval");
+/// builder.token(Whitespace, " ");
+/// builder.token(Identifier, "\"1+2\"");
+/// builder.finish_node();
+/// let (tree, cache) = builder.finish();
+/// let interner = cache.unwrap().into_interner().unwrap();
+/// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+///
+/// let string = root.last_token().unwrap();
+/// let contents = TextRange::new(1.into(), 4.into()); // without the quotes
+/// let injection = Injection::new([(string, contents)], &interner);
+/// assert_eq!(injection.text(), "1+2");
+/// assert_eq!(injection.host_offset(2.into()), Some(8.into()));
+///
+/// // Parse the embedded code with the parser of the embedded language.
+/// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+/// builder.start_node(Operation);
+/// builder.token(Int, "1");
+/// builder.static_token(Plus);
+/// builder.token(Int, "2");
+/// builder.finish_node();
+/// let (embedded, _) = builder.finish();
+///
+/// let injected = injection.attach::<MySyntax, ()>(embedded);
+/// let plus = injected.token_at_host_offset(7.into()).right_biased().unwrap();
+/// assert_eq!(plus.kind(), Plus);
+/// assert_eq!(injected.host_token(plus.text_range().start()), Some(string));
+/// ```
+pub struct Injection<S: Syntax, D: 'static = ()> {
+    hosts:    Vec<SyntaxToken<S, D>>,
+    text:     String,
+    /// The parts of the text, ordered by their offsets, which are increasing in both the injected and the host text.
+    segments: Vec<Segment>,
+}
+
+impl<S: Syntax, D> Injection<S, D> {
+    /// Creates an injection from the given parts of host tokens, whose text is resolved with `resolver`. Each part is
+    /// a token and the range of its embedded code, relative to the start of the token.
+    ///
+    /// ## Panics
+    /// If a range is not contained in the text of its token or does not start and end at char boundaries, or if the
+    /// parts are not in the order in which they appear in the document.
+    pub fn new<'a, R>(parts: impl IntoIterator<Item = (&'a SyntaxToken<S, D>, TextRange)>, resolver: &R) -> Self
+    where
+        S: 'a,
+        R: Resolver<TokenKey> + ?Sized,
+    {
+        let mut injection = Self {
+            hosts:    Vec::new(),
+            text:     String::new(),
+            segments: Vec::new(),
+        };
+        for (token, range) in parts {
+            let host = token.text_range().start() + range.start();
+            if let Some(last) = injection.segments.last() {
+                assert!(
+                    last.host + last.len <= host,
+                    "injected parts are not in document order: {range:?} in {token:?}"
+                );
+            }
+            if injection.hosts.last() != Some(token) {
+                injection.hosts.push(token.clone());
+            }
+            injection.segments.push(Segment {
+                injected: TextSize::of(injection.text.as_str()),
+                host,
+                len: range.len(),
+                token: injection.hosts.len() - 1,
+            });
+            injection.text.push_str(&token.resolve_text(resolver)[range]);
+        }
+        injection
+    }
+
+    /// The embedded code, which is the concatenation of the embedded parts of the host tokens.
+    #[inline]
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The tokens that contain the embedded code, in order.
+    #[inline]
+    pub fn hosts(&self) -> &[SyntaxToken<S, D>] {
+        &self.hosts
+    }
+
+    /// The segment that contains the injected `offset`. Offsets at the boundary of two segments belong to the second
+    /// one.
+    fn segment(&self, offset: TextSize) -> Option<&Segment> {
+        let index = self.segments.partition_point(|segment| segment.injected <= offset);
+        let segment = self.segments.get(index.checked_sub(1)?)?;
+        (offset <= segment.injected + segment.len).then_some(segment)
+    }
+
+    /// Maps `offset` in the [injected text](Injection::text) to the document offset it was taken from.
+    ///
+    /// Returns `None` if `offset` is greater than the length of the injected text. Offsets at which two parts of
+    /// the injected text meet are mapped to the start of the second part.
+    pub fn host_offset(&self, offset: TextSize) -> Option<TextSize> {
+        let segment = self.segment(offset)?;
+        Some(segment.host + (offset - segment.injected))
+    }
+
+    /// Maps the document `offset` to the corresponding offset in the [injected text](Injection::text).
+    ///
+    /// Returns `None` if `offset` is not in one of the embedded parts of the host tokens (their ends are included).
+    pub fn injected_offset(&self, offset: TextSize) -> Option<TextSize> {
+        let index = self.segments.partition_point(|segment| segment.host <= offset);
+        let segment = self.segments.get(index.checked_sub(1)?)?;
+        (offset <= segment.host + segment.len).then(|| segment.injected + (offset - segment.host))
+    }
+
+    /// Maps `range` in the [injected text](Injection::text) to the document ranges it was taken from, one for each
+    /// embedded part that it overlaps. An empty range is mapped to a single empty range.
+    ///
+    /// ## Panics
+    /// If `range` ends after the injected text.
+    pub fn host_ranges(&self, range: TextRange) -> Vec<TextRange> {
+        assert!(
+            range.end() <= TextSize::of(self.text.as_str()),
+            "range {range:?} is outside of the injected text"
+        );
+        if range.is_empty() {
+            return self
+                .host_offset(range.start())
+                .map(TextRange::empty)
+                .into_iter()
+                .collect();
+        }
+        self.segments
+            .iter()
+            .filter_map(|segment| {
+                let part = TextRange::at(segment.injected, segment.len).intersect(range)?;
+                (!part.is_empty()).then(|| part - segment.injected + segment.host)
+            })
+            .collect()
+    }
+
+    /// The host token that the [injected text](Injection::text) at `offset` was taken from, chosen like in
+    /// [`host_offset`](Injection::host_offset).
+    pub fn host_token(&self, offset: TextSize) -> Option<&SyntaxToken<S, D>> {
+        let segment = self.segment(offset)?;
+        Some(&self.hosts[segment.token])
+    }
+
+    /// Links the green tree that a parser for the embedded language built from the [injected text](Injection::text)
+    /// back to this injection.
+    ///
+    /// ## Panics
+    /// If the length of the text of `tree` differs from the length of the injected text.
+    pub fn attach<T: Syntax, E>(self, tree: GreenNode) -> InjectedTree<S, T, D, E> {
+        assert_eq!(
+            tree.text_len(),
+            TextSize::of(self.text.as_str()),
+            "the injected tree does not cover the injected text"
+        );
+        InjectedTree {
+            injection: self,
+            root:      SyntaxNode::new_root(tree),
+        }
+    }
+}
+
+impl<S: Syntax, D> fmt::Debug for Injection<S, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Injection")
+            .field("hosts", &self.hosts)
+            .field("text", &self.text)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The syntax tree of the code in an [`Injection`], created with [`Injection::attach`].
+///
+/// The elements of the tree can be mapped back to the host tree through the injection, for example with
+/// [`Injection::host_ranges`] to highlight an element of the embedded code in the document.
+pub struct InjectedTree<S: Syntax, T: Syntax, D: 'static = (), E: 'static = ()> {
+    injection: Injection<S, D>,
+    root:      SyntaxNode<T, E>,
+}
+
+impl<S: Syntax, T: Syntax, D, E> InjectedTree<S, T, D, E> {
+    /// The injection that the tree was parsed from.
+    #[inline]
+    pub fn injection(&self) -> &Injection<S, D> {
+        &self.injection
+    }
+
+    /// The root of the tree of the embedded code.
+    #[inline]
+    pub fn root(&self) -> &SyntaxNode<T, E> {
+        &self.root
+    }
+
+    /// Finds the tokens of the embedded code at the document `offset`, like [`SyntaxNode::token_at_offset`].
+    ///
+    /// Returns [`TokenAtOffset::None`] if `offset` is not in one of the embedded parts of the host tokens.
+    pub fn token_at_host_offset(&self, offset: TextSize) -> TokenAtOffset<SyntaxToken<T, E>> {
+        match self.injection.injected_offset(offset) {
+            Some(offset) => self.root.token_at_offset(offset),
+            None => TokenAtOffset::None,
+        }
+    }
+
+    /// The host token that the [injected text](Injection::text) at `offset` was taken from. See
+    /// [`Injection::host_token`].
+    #[inline]
+    pub fn host_token(&self, offset: TextSize) -> Option<&SyntaxToken<S, D>> {
+        self.injection.host_token(offset)
+    }
+}
+
+impl<S: Syntax, T: Syntax, D, E> fmt::Debug for InjectedTree<S, T, D, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InjectedTree")
+            .field("injection", &self.injection)
+            .field("root", &self.root)
+            .finish()
+    }
+}
@@ -0,0 +1,116 @@
+//! An index for finding all elements of a tree that overlap a text range.
+
+use std::fmt;
+
+use text_size::TextRange;
+
+use super::{node::overlaps_range, *};
+use crate::{traversal::WalkEvent, util::NodeOrToken, Syntax};
+
+/// An index over the elements of a syntax tree that finds all nodes and tokens overlapping a given text range in
+/// `O(log n + d + k)` time, where `n` is the number of elements in the tree, `d` is the depth of the tree at the
+/// start of the range and `k` is the number of elements found.
+///
+/// Building the index walks the tree once, so it pays off when many ranges are queried against the same tree,
+/// for example when checking a large number of diagnostics against a large file.
+///
+/// # Example
+/// ```
+/// # use cstree::testing::*;
+/// use cstree::{syntax::IntervalIndex, text::TextRange};
+///
+/// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+/// builder.start_node(Root);
+/// builder.start_node(Operation);
+/// builder.token(Int, "1");
+/// builder.static_token(Plus);
+/// builder.token(Int, "2");
+/// builder.finish_node();
+/// builder.finish_node();
+/// let (tree, _) = builder.finish();
+/// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+///
+/// let index = IntervalIndex::new(&root);
+/// let kinds: Vec<_> = index
+///     .overlapping(TextRange::new(1.into(), 3.into()))
+///     .map(|element| element.kind())
+///     .collect();
+/// assert_eq!(kinds, vec![Root, Operation, Plus, Int]);
+/// ```
+pub struct IntervalIndex<S: Syntax, D: 'static = ()> {
+    /// All elements of the tree in preorder, which sorts them by the start of their range.
+    entries: Vec<Entry<S, D>>,
+}
+
+struct Entry<S: Syntax, D: 'static> {
+    range:   TextRange,
+    /// Index of the parent node's entry, if the parent is part of the index.
+    parent:  Option<usize>,
+    element: SyntaxElement<S, D>,
+}
+
+impl<S: Syntax, D> IntervalIndex<S, D> {
+    /// Builds an index of `node` and all of its descendants.
+    pub fn new(node: &SyntaxNode<S, D>) -> Self {
+        let mut entries = Vec::new();
+        let mut parents = Vec::new();
+        for event in node.preorder_with_tokens() {
+            match event {
+                WalkEvent::Enter(element) => {
+                    let parent = parents.last().copied();
+                    if element.as_node().is_some() {
+                        parents.push(entries.len());
+                    }
+                    entries.push(Entry {
+                        range: element.text_range(),
+                        parent,
+                        element: element.cloned(),
+                    });
+                }
+                WalkEvent::Leave(NodeOrToken::Node(_)) => {
+                    parents.pop();
+                }
+                WalkEvent::Leave(NodeOrToken::Token(_)) => {}
+            }
+        }
+        Self { entries }
+    }
+
+    /// Returns all indexed nodes and tokens that overlap `range`, in preorder.
+    ///
+    /// Elements that only touch `range` at one of its ends are not included, unless they are empty and lie within
+    /// `range`, like for [`SyntaxNode::descendants_intersecting`].
+    pub fn overlapping(&self, range: TextRange) -> impl Iterator<Item = SyntaxElementRef<'_, S, D>> {
+        let first = self
+            .entries
+            .partition_point(|entry| entry.range.start() < range.start());
+        // Elements that start before the range can only overlap it if they contain the start of the range.
+        // This makes them ancestors of the last element that starts before the range (or that element itself).
+        let mut enclosing = Vec::new();
+        let mut current = first.checked_sub(1);
+        while let Some(index) = current {
+            let entry = &self.entries[index];
+            if overlaps_range(entry.range, range) {
+                enclosing.push(entry);
+            }
+            current = entry.parent;
+        }
+        let starting_inside = self.entries[first..]
+            .iter()
+            .take_while(move |entry| entry.range.start() <= range.end())
+            .filter(move |entry| overlaps_range(entry.range, range));
+        enclosing
+            .into_iter()
+            .rev()
+            .chain(starting_inside)
+            .map(|entry| (&entry.element).into())
+    }
+}
+
+impl<S: Syntax, D> fmt::Debug for IntervalIndex<S, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IntervalIndex")
+            .field("elements", &self.entries.len())
+            .finish()
+    }
+}
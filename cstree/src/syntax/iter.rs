@@ -2,11 +2,12 @@
 
 use std::iter::FusedIterator;
 
-use text_size::TextSize;
+use text_size::{TextRange, TextSize};
 
 use crate::{
     green::{GreenElementRef, GreenNodeChildren},
     syntax::{SyntaxElementRef, SyntaxNode},
+    traversal::Direction,
     Syntax,
 };
 
@@ -166,3 +167,167 @@ impl<'n, S: Syntax, D> ExactSizeIterator for SyntaxElementChildren<'n, S, D> {
     }
 }
 impl<'n, S: Syntax, D> FusedIterator for SyntaxElementChildren<'n, S, D> {}
+
+/// Iterates over the green children of a node before or after the child at some index, with their indices and
+/// offsets.
+#[derive(Clone, Debug)]
+struct SiblingIter<'n> {
+    green:     GreenNodeChildren<'n>,
+    direction: Direction,
+    /// The index of the next child when iterating forwards, or the index of the previous child plus one when
+    /// iterating backwards.
+    index:     usize,
+    /// The start of the next child when iterating forwards, or the end of the previous child when iterating
+    /// backwards.
+    offset:    TextSize,
+}
+
+impl<'n> SiblingIter<'n> {
+    /// Starts iterating next to the child at `index` of `parent`, which has the text range `range`.
+    fn new<S: Syntax, D>(parent: &'n SyntaxNode<S, D>, index: usize, range: TextRange, direction: Direction) -> Self {
+        let mut green = parent.green().children();
+        let offset = match direction {
+            Direction::Next => {
+                green.nth(index);
+                range.end()
+            }
+            Direction::Prev => {
+                green.nth_back(green.len() - index - 1);
+                range.start()
+            }
+        };
+        let index = match direction {
+            Direction::Next => index + 1,
+            Direction::Prev => index,
+        };
+        Self {
+            green,
+            direction,
+            index,
+            offset,
+        }
+    }
+}
+
+impl<'n> Iterator for SiblingIter<'n> {
+    type Item = (GreenElementRef<'n>, usize, TextSize);
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.direction {
+            Direction::Next => self.green.next().map(|element| {
+                let (index, offset) = (self.index, self.offset);
+                self.index += 1;
+                self.offset += element.text_len();
+                (element, index, offset)
+            }),
+            Direction::Prev => self.green.next_back().map(|element| {
+                self.index -= 1;
+                self.offset -= element.text_len();
+                (element, self.index, self.offset)
+            }),
+        }
+    }
+
+    #[inline(always)]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.green.size_hint()
+    }
+}
+
+/// An iterator over a node or token and its siblings in one [`Direction`], including tokens.
+///
+/// See [`SyntaxNode::siblings_with_tokens`] and [`SyntaxToken::siblings_with_tokens`].
+///
+/// [`SyntaxToken::siblings_with_tokens`]: crate::syntax::SyntaxToken::siblings_with_tokens
+#[derive(Clone, Debug)]
+pub struct SyntaxElementSiblings<'n, S: Syntax, D: 'static = ()> {
+    first:    Option<SyntaxElementRef<'n, S, D>>,
+    siblings: Option<(SiblingIter<'n>, &'n SyntaxNode<S, D>)>,
+}
+
+impl<'n, S: Syntax, D> SyntaxElementSiblings<'n, S, D> {
+    /// Iterates from `first`, which is the child at `index` of `parent` if it has a parent.
+    #[inline]
+    pub(super) fn new(
+        first: SyntaxElementRef<'n, S, D>,
+        parent: Option<(&'n SyntaxNode<S, D>, usize)>,
+        direction: Direction,
+    ) -> Self {
+        Self {
+            first:    Some(first),
+            siblings: parent.map(|(parent, index)| {
+                (SiblingIter::new(parent, index, first.text_range(), direction), parent)
+            }),
+        }
+    }
+}
+
+impl<'n, S: Syntax, D> Iterator for SyntaxElementSiblings<'n, S, D> {
+    type Item = SyntaxElementRef<'n, S, D>;
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(first) = self.first.take() {
+            return Some(first);
+        }
+        let (siblings, parent) = self.siblings.as_mut()?;
+        siblings
+            .next()
+            .map(|(green, index, offset)| parent.get_or_add_element(green, index, offset))
+    }
+
+    #[inline(always)]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let first = self.first.is_some() as usize;
+        let siblings = self.siblings.as_ref().map_or(0, |(siblings, _)| siblings.green.len());
+        (first + siblings, Some(first + siblings))
+    }
+}
+
+impl<'n, S: Syntax, D> ExactSizeIterator for SyntaxElementSiblings<'n, S, D> {}
+impl<'n, S: Syntax, D> FusedIterator for SyntaxElementSiblings<'n, S, D> {}
+
+/// An iterator over a node and its sibling nodes in one [`Direction`], see [`SyntaxNode::siblings`].
+#[derive(Clone, Debug)]
+pub struct SyntaxNodeSiblings<'n, S: Syntax, D: 'static = ()> {
+    first:    Option<&'n SyntaxNode<S, D>>,
+    siblings: Option<(SiblingIter<'n>, &'n SyntaxNode<S, D>)>,
+}
+
+impl<'n, S: Syntax, D> SyntaxNodeSiblings<'n, S, D> {
+    /// Iterates from `first`, which is the child at `index` of `parent` if it has a parent.
+    #[inline]
+    pub(super) fn new(
+        first: &'n SyntaxNode<S, D>,
+        parent: Option<(&'n SyntaxNode<S, D>, usize)>,
+        direction: Direction,
+    ) -> Self {
+        Self {
+            first:    Some(first),
+            siblings: parent.map(|(parent, index)| {
+                (SiblingIter::new(parent, index, first.text_range(), direction), parent)
+            }),
+        }
+    }
+}
+
+impl<'n, S: Syntax, D> Iterator for SyntaxNodeSiblings<'n, S, D> {
+    type Item = &'n SyntaxNode<S, D>;
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(first) = self.first.take() {
+            return Some(first);
+        }
+        let (siblings, parent) = self.siblings.as_mut()?;
+        for (element, index, offset) in siblings {
+            if let Some(&node) = element.as_node() {
+                return Some(parent.get_or_add_node(node, index, offset).as_node().unwrap());
+            }
+        }
+        None
+    }
+}
+
+impl<'n, S: Syntax, D> FusedIterator for SyntaxNodeSiblings<'n, S, D> {}
@@ -8,17 +8,46 @@
 mod element;
 pub use element::{SyntaxElement, SyntaxElementRef};
 mod node;
-pub use node::SyntaxNode;
+pub use node::{SyntaxNode, TextChunks, WeakSyntaxNode};
 mod token;
 pub use token::SyntaxToken;
 mod resolved;
 pub use resolved::{ResolvedElement, ResolvedElementRef, ResolvedNode, ResolvedToken};
 mod iter;
-pub use iter::{SyntaxElementChildren, SyntaxNodeChildren};
+pub use iter::{SyntaxElementChildren, SyntaxElementSiblings, SyntaxNodeChildren, SyntaxNodeSiblings};
+mod slice;
+pub use slice::ChildSlice;
 
 mod text;
 pub use text::SyntaxText;
 
+mod debug;
+pub use debug::{AnsiColor, DebugOptions};
+
+mod interval_index;
+pub use interval_index::IntervalIndex;
+
+mod preorder_index;
+pub use preorder_index::PreorderIndex;
+
+mod indent;
+pub use indent::{IndentStyle, Reindent};
+
+mod relex;
+pub use relex::{Lookaround, RelexRange};
+
+mod trivia;
+pub use trivia::{CommentBlock, TriviaKind, TriviaMap, TriviaPolicy};
+
+mod zip;
+pub use zip::{Divergence, ZipEvent, ZipWalk};
+
+mod injection;
+pub use injection::{InjectedTree, Injection};
+
+mod canonical;
+pub use canonical::Canonicalize;
+
 // A note on `#[inline]` usage in this module:
 // In `rowan`, there are two layers of `SyntaxXY`s: the `cursor` layer and the `api` layer.
 // The `cursor` layer handles all of the actual methods on the tree, while the `api` layer is
@@ -47,6 +76,8 @@ mod tests {
         f::<SyntaxToken<TestSyntaxKind>>();
         f::<SyntaxElement<TestSyntaxKind>>();
         f::<SyntaxElementRef<'static, TestSyntaxKind>>();
+        f::<WeakSyntaxNode<TestSyntaxKind>>();
+        f::<ChildSlice<'static, TestSyntaxKind>>();
 
         f::<ResolvedNode<TestSyntaxKind>>();
         f::<ResolvedToken<TestSyntaxKind>>();
@@ -1,13 +1,19 @@
 use super::*;
 #[cfg(feature = "serialize")]
-use crate::serde_impls::{SerializeWithData, SerializeWithResolver};
 use crate::{
-    green::{GreenElementRef, GreenNode},
+    build::GreenNodeBuilder,
+    interning::Interner,
+    serde_impls::{SerializeWithData, SerializeWithResolver},
+};
+use crate::{
+    ast::AstNode,
+    green::{GreenElementRef, GreenNode, GreenNodeChildren, GreenToken},
     interning::{Resolver, TokenKey},
     text::*,
     traversal::*,
     util::*,
-    RawSyntaxKind, Syntax,
+    utility_types::KindName,
+    KindClass, RawSyntaxKind, Syntax,
 };
 use parking_lot::RwLock;
 use std::{
@@ -15,9 +21,10 @@ use std::{
     fmt,
     hash::{Hash, Hasher},
     iter,
+    marker::PhantomData,
     ptr::{self, NonNull},
     sync::{
-        atomic::{AtomicU32, Ordering},
+        atomic::{self, AtomicU32, AtomicUsize, Ordering},
         Arc as StdArc,
     },
 };
@@ -27,12 +34,23 @@ use triomphe::Arc;
 /// Syntax nodes can be shared between threads.
 /// Every syntax tree is reference counted as a whole and nodes are pointer-sized, so copying
 /// individual nodes is relatively cheap.
-#[derive(Debug)]
 #[repr(transparent)]
 pub struct SyntaxNode<S: Syntax, D: 'static = ()> {
     data: NonNull<NodeData<S, D>>,
 }
 
+/// Without a resolver associated with the tree, only the node's kind and range are written.
+/// Otherwise, this is the same as [`write_debug`](SyntaxNode::write_debug), printing the entire subtree in alternate
+/// mode (`{:#?}`).
+impl<S: Syntax, D> fmt::Debug for SyntaxNode<S, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.resolver() {
+            Some(resolver) => self.write_debug(&**resolver, f, f.alternate()),
+            None => write!(f, "{}@{:?}", KindName(self.kind()), self.text_range()),
+        }
+    }
+}
+
 unsafe impl<S: Syntax, D: 'static> Send for SyntaxNode<S, D> {}
 unsafe impl<S: Syntax, D: 'static> Sync for SyntaxNode<S, D> {}
 
@@ -62,7 +80,7 @@ impl<S: Syntax, D> SyntaxNode<S, D> {
             assert_eq!(level, 0);
             Ok(())
         } else {
-            write!(target, "{:?}@{:?}", self.kind(), self.text_range())
+            write!(target, "{}@{:?}", KindName(self.kind()), self.text_range())
         }
     }
 
@@ -138,8 +156,8 @@ impl<S: Syntax, D> Clone for SyntaxNode<S, D> {
     fn clone(&self) -> Self {
         // safety:: the ref count is only dropped when there are no more external references (see below)
         // since we are currently cloning such a reference, there is still at least one
-        let ref_count = unsafe { &mut *self.data().ref_count };
-        ref_count.fetch_add(1, Ordering::AcqRel);
+        let ref_count = unsafe { &*self.data().ref_count };
+        ref_count.strong.fetch_add(1, Ordering::AcqRel);
         self.clone_uncounted()
     }
 }
@@ -151,8 +169,11 @@ impl<S: Syntax, D> Drop for SyntaxNode<S, D> {
         // if we are the last external reference, we have not yet dropped the ref count
         // if we aren't we won't enter the `if` below
         let ref_count = unsafe { &*self.data().ref_count };
-        let refs = ref_count.fetch_sub(1, Ordering::AcqRel);
+        let refs = ref_count.strong.fetch_sub(1, Ordering::AcqRel);
         if refs == 1 {
+            // Dropping the tree below changes the strong count while the tree is torn down, which must not allow
+            // a `WeakSyntaxNode` to upgrade.
+            ref_count.strong.store(RefCount::DROPPED, Ordering::Release);
             // drop from parent
             // NOTE regarding drop orders: since `SyntaxNode<L>::drop` looks at the `ref_count`, we
             // need to first drop the `root` and only then its `root_data` and the contained
@@ -164,7 +185,8 @@ impl<S: Syntax, D> Drop for SyntaxNode<S, D> {
             let root_data = root.data;
             drop(root);
             unsafe { drop(Box::from_raw(root_data.as_ptr())) };
-            unsafe { drop(Box::from_raw(ref_count)) };
+            // safety: all strong references together hold one weak reference, which is released here
+            unsafe { RefCount::release_weak(ref_count) };
         }
     }
 }
@@ -191,6 +213,18 @@ impl<S: Syntax, D> SyntaxNode<S, D> {
         current
     }
 
+    /// Creates a [`WeakSyntaxNode`] pointing to this node, which does not keep the tree alive.
+    pub fn downgrade(&self) -> WeakSyntaxNode<S, D> {
+        let ref_count = self.data().ref_count;
+        // safety: the counts are alive as long as `self` is
+        unsafe { &*ref_count }.weak.fetch_add(1, Ordering::Relaxed);
+        WeakSyntaxNode {
+            data:      self.data,
+            // safety: created from a `Box`
+            ref_count: unsafe { NonNull::new_unchecked(ref_count) },
+        }
+    }
+
     fn drop_recursive(&mut self) {
         let data = self.data();
         for i in 0..data.children.len() {
@@ -236,6 +270,129 @@ impl<S: Syntax, D> Hash for SyntaxNode<S, D> {
     }
 }
 
+/// The reference counts shared by all nodes and tokens of a tree.
+///
+/// The strong count counts external references to nodes and tokens of the tree, which is dropped as a whole when the
+/// count reaches zero. The weak count counts [`WeakSyntaxNode`]s, plus one for all strong references together, and
+/// keeps only the counts themselves alive.
+pub(super) struct RefCount {
+    strong: AtomicU32,
+    weak:   AtomicU32,
+}
+
+impl RefCount {
+    /// The largest strong count of a tree that has not been dropped.
+    const ALIVE_MAX: u32 = u32::MAX >> 1;
+    /// The strong count of a tree that is being dropped. Dropping the nodes of the tree decrements the count, but
+    /// not far enough to fall below [`ALIVE_MAX`](Self::ALIVE_MAX).
+    const DROPPED: u32 = 3 << 30;
+
+    fn new() -> *mut Self {
+        Box::into_raw(Box::new(Self {
+            strong: AtomicU32::new(1),
+            weak:   AtomicU32::new(1),
+        }))
+    }
+
+    /// Increments the strong count, unless the tree has already been dropped.
+    fn try_upgrade(&self) -> bool {
+        let mut strong = self.strong.load(Ordering::Acquire);
+        loop {
+            if strong == 0 || strong > Self::ALIVE_MAX {
+                return false;
+            }
+            match self
+                .strong
+                .compare_exchange_weak(strong, strong + 1, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => return true,
+                Err(current) => strong = current,
+            }
+        }
+    }
+
+    /// Decrements the weak count of `this`, freeing it if this was the last weak reference.
+    ///
+    /// # Safety
+    /// `this` must have been created by [`RefCount::new`] and the caller must own one of its weak references.
+    unsafe fn release_weak(this: *mut Self) {
+        if (*this).weak.fetch_sub(1, Ordering::Release) == 1 {
+            atomic::fence(Ordering::Acquire);
+            drop(Box::from_raw(this));
+        }
+    }
+}
+
+/// A weak reference to a [`SyntaxNode`], which does not keep its tree alive.
+///
+/// Weak references can be stored in side tables to refer back to nodes without keeping the whole tree, including the
+/// green tree of its root, in memory. While any (strong) [`SyntaxNode`] or [`SyntaxToken`] of the tree exists,
+/// [`upgrade`](WeakSyntaxNode::upgrade) returns the node. Once the tree has been dropped, it returns `None`.
+///
+/// # Example
+/// ```
+/// # use cstree::testing::*;
+/// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+/// builder.start_node(Root);
+/// builder.start_node(Operation);
+/// builder.token(Int, "1");
+/// builder.finish_node();
+/// builder.finish_node();
+/// let (tree, _) = builder.finish();
+/// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+///
+/// let operation = root.first_child().unwrap().downgrade();
+/// assert_eq!(operation.upgrade().unwrap().kind(), Operation);
+/// drop(root);
+/// assert!(operation.upgrade().is_none());
+/// ```
+pub struct WeakSyntaxNode<S: Syntax, D: 'static = ()> {
+    data:      NonNull<NodeData<S, D>>,
+    ref_count: NonNull<RefCount>,
+}
+
+unsafe impl<S: Syntax, D: 'static> Send for WeakSyntaxNode<S, D> {}
+unsafe impl<S: Syntax, D: 'static> Sync for WeakSyntaxNode<S, D> {}
+
+impl<S: Syntax, D> WeakSyntaxNode<S, D> {
+    /// Returns the node this reference points to, or `None` if its tree has been dropped.
+    pub fn upgrade(&self) -> Option<SyntaxNode<S, D>> {
+        // safety: the counts are kept alive by this weak reference
+        let ref_count = unsafe { self.ref_count.as_ref() };
+        // safety: while the strong count is positive, the tree and all of its node data are alive
+        ref_count.try_upgrade().then(|| SyntaxNode { data: self.data })
+    }
+
+    /// Returns `true` if both references point to the same node. This also works if the tree has been dropped.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        self.data == other.data
+    }
+}
+
+impl<S: Syntax, D> Clone for WeakSyntaxNode<S, D> {
+    fn clone(&self) -> Self {
+        // safety: the counts are kept alive by this weak reference
+        unsafe { self.ref_count.as_ref() }.weak.fetch_add(1, Ordering::Relaxed);
+        Self {
+            data:      self.data,
+            ref_count: self.ref_count,
+        }
+    }
+}
+
+impl<S: Syntax, D> Drop for WeakSyntaxNode<S, D> {
+    fn drop(&mut self) {
+        // safety: we own one of the weak references
+        unsafe { RefCount::release_weak(self.ref_count.as_ptr()) };
+    }
+}
+
+impl<S: Syntax, D> fmt::Debug for WeakSyntaxNode<S, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(Weak)")
+    }
+}
+
 enum Kind<S: Syntax, D: 'static> {
     Root(GreenNode, Option<StdArc<dyn Resolver<TokenKey>>>),
     Child {
@@ -257,14 +414,14 @@ impl<S: Syntax, D> Kind<S, D> {
 pub(super) struct NodeData<S: Syntax, D: 'static> {
     kind:        Kind<S, D>,
     green:       NonNull<GreenNode>,
-    ref_count:   *mut AtomicU32,
+    ref_count:   *mut RefCount,
     data:        RwLock<Option<Arc<D>>>,
     children:    Vec<UnsafeCell<Option<SyntaxElement<S, D>>>>,
     child_locks: Vec<RwLock<()>>,
 }
 
 impl<S: Syntax, D> NodeData<S, D> {
-    fn new(kind: Kind<S, D>, green: NonNull<GreenNode>, ref_count: *mut AtomicU32, n_children: usize) -> NonNull<Self> {
+    fn new(kind: Kind<S, D>, green: NonNull<GreenNode>, ref_count: *mut RefCount, n_children: usize) -> NonNull<Self> {
         let mut children = Vec::with_capacity(n_children);
         let mut child_locks = Vec::with_capacity(n_children);
         children.extend((0..n_children).map(|_| Default::default()));
@@ -305,12 +462,11 @@ impl<S: Syntax, D> SyntaxNode<S, D> {
     }
 
     fn make_new_root(green: GreenNode, resolver: Option<StdArc<dyn Resolver<TokenKey>>>) -> Self {
-        let ref_count = Box::new(AtomicU32::new(1));
         let n_children = green.children().count();
         let data = NodeData::new(
             Kind::Root(green, resolver),
             NonNull::dangling(),
-            Box::into_raw(ref_count),
+            RefCount::new(),
             n_children,
         );
         let ret = Self::new(data);
@@ -355,6 +511,40 @@ impl<S: Syntax, D> SyntaxNode<S, D> {
         }
     }
 
+    /// Returns a new tree for the subtree rooted at this node, with `resolver` associated with the new tree. The
+    /// `resolver` must be able to resolve the texts of the tokens of this tree.
+    ///
+    /// The green subtree and the resolver are shared, so this only allocates the new root. The new root starts at
+    /// offset `0`, and data attached to the nodes of this tree is not copied. If this tree already has a resolver,
+    /// see [`ResolvedNode::freeze`].
+    ///
+    /// # Example
+    /// ```
+    /// # use cstree::testing::*;
+    /// use cstree::interning::{Resolver, TokenKey};
+    /// use std::sync::Arc;
+    ///
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// builder.start_node(Operation);
+    /// builder.token(Identifier, "x");
+    /// builder.finish_node();
+    /// builder.finish_node();
+    /// let (tree, cache) = builder.finish();
+    /// let interner: Arc<dyn Resolver<TokenKey>> = Arc::new(cache.unwrap().into_interner().unwrap());
+    /// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+    ///
+    /// let operation = root.first_child().unwrap().freeze_with_resolver(Arc::clone(&interner));
+    /// assert_eq!(operation.kind(), Operation);
+    /// assert_eq!(operation.text(), "x");
+    /// ```
+    #[inline]
+    pub fn freeze_with_resolver(&self, resolver: StdArc<dyn Resolver<TokenKey>>) -> ResolvedNode<S, D> {
+        ResolvedNode {
+            syntax: SyntaxNode::make_new_root(self.green().clone(), Some(resolver)),
+        }
+    }
+
     // Technically, unsafe, but private so that's OK.
     // Safety: `green` must be a descendent of `parent.green`
     pub(super) fn new_child(
@@ -362,7 +552,7 @@ impl<S: Syntax, D> SyntaxNode<S, D> {
         parent: &Self,
         index: u32,
         offset: TextSize,
-        ref_count: *mut AtomicU32,
+        ref_count: *mut RefCount,
     ) -> Self {
         let n_children = green.children().count();
         let data = NodeData::new(
@@ -421,49 +611,23 @@ impl<S: Syntax, D> SyntaxNode<S, D> {
         slot.as_ref().map(|elem| elem.into())
     }
 
-    fn try_write(&self, index: usize, elem: SyntaxElement<S, D>) {
+    /// Creates the child at `index` with `make` unless another thread created it since it was last read.
+    ///
+    /// The check and the creation happen under the write lock of the child's slot, so no child is ever created only
+    /// to be dropped again, which would have to allocate its `NodeData` and correct the tree's reference count.
+    fn init(&self, index: usize, make: impl FnOnce() -> SyntaxElement<S, D>) {
         // safety: children are pre-allocated and indices are determined internally
         let _write = unsafe { self.data().child_locks.get_unchecked(index).write() };
         // safety: we are the only writer and there are no readers as evidenced by the write lock
         let slot = unsafe { &mut *self.data().children.get_unchecked(index).get() };
         if slot.is_none() {
             // we are first to initialize the child
-            *slot = Some(elem);
-        } else {
-            // another thread got the write lock first and already initialized it
-            match elem {
-                SyntaxElement::Node(node) => {
-                    // There are three things to handle here:
-                    //   1) `node` was just created, which allocated `NodeData` that we now need to drop, and
-                    //   2) dropping `node` will decrement the global `ref_count`, even though the count was not
-                    //      incremented when creating `node` (because it is an internal reference). Thus, we need to
-                    //      bump the count up by one.
-                    //   3) dropping `node`'s `NodeData` will drop its `parent` reference, which will again decrement
-                    //      the `ref_count`. Thus, we have to offset by 2 overall.
-
-                    // safety: `node` was just created and has not been shared
-                    let ref_count = unsafe { &*node.data().ref_count };
-                    ref_count.fetch_add(2, Ordering::AcqRel);
-                    let node_data = node.data;
-                    drop(node);
-                    unsafe { drop(Box::from_raw(node_data.as_ptr())) };
-                }
-                SyntaxElement::Token(token) => {
-                    // We don't have to worry about `NodeData` or `SyntaxToken<L>`'s own `Drop` here,
-                    // but we will still drop `token`'s `parent`, which decreases the `ref_count`
-                    // by one.
-
-                    // safety: as above
-                    let ref_count = unsafe { &*token.parent().data().ref_count };
-                    ref_count.fetch_add(1, Ordering::AcqRel);
-                    drop(token);
-                }
-            }
+            *slot = Some(make());
         }
     }
 
     #[inline(always)]
-    pub(super) fn get_or_add_node(
+    pub(crate) fn get_or_add_node(
         &self,
         node: &GreenNode,
         index: usize,
@@ -473,10 +637,9 @@ impl<S: Syntax, D> SyntaxNode<S, D> {
             debug_assert_eq!(elem.text_range().start(), offset);
             return elem;
         }
-        self.try_write(
-            index,
-            Self::new_child(node, self, index as u32, offset, self.data().ref_count).into(),
-        );
+        self.init(index, || {
+            Self::new_child(node, self, index as u32, offset, self.data().ref_count).into()
+        });
         self.read(index).unwrap()
     }
 
@@ -491,10 +654,9 @@ impl<S: Syntax, D> SyntaxNode<S, D> {
             debug_assert_eq!(elem.text_range().start(), offset);
             return elem;
         }
-        self.try_write(
-            index,
-            SyntaxElement::new(element, self, index as u32, offset, self.data().ref_count),
-        );
+        self.init(index, || {
+            SyntaxElement::new(element, self, index as u32, offset, self.data().ref_count)
+        });
         self.read(index).unwrap()
     }
 
@@ -503,6 +665,40 @@ impl<S: Syntax, D> SyntaxNode<S, D> {
     /// of operation is proportional to the depth of the tree
     pub fn replace_with(&self, replacement: GreenNode) -> GreenNode {
         assert_eq!(self.syntax_kind(), replacement.kind());
+        self.replace_in_tree(replacement)
+    }
+
+    /// Returns a green tree, equal to the green tree this node belongs to, except that this node has the kind
+    /// `kind`. All children of this node, as well as all other parts of the tree that are not ancestors of this node,
+    /// are shared with the original tree.
+    ///
+    /// This is useful for passes that reclassify nodes after parsing, for example to turn a reference to a name into
+    /// a definition after name resolution.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// builder.start_node(Operation);
+    /// builder.token(Int, "1");
+    /// builder.finish_node();
+    /// builder.finish_node();
+    /// let (tree, _) = builder.finish();
+    /// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+    /// let operation = root.first_child().unwrap();
+    /// let new_root: SyntaxNode<MySyntax> = SyntaxNode::new_root(operation.with_kind(Root));
+    /// assert_eq!(new_root.first_child().unwrap().kind(), Root);
+    /// assert_eq!(
+    ///     new_root.first_token().unwrap().green(),
+    ///     root.first_token().unwrap().green()
+    /// );
+    /// ```
+    pub fn with_kind(&self, kind: S) -> GreenNode {
+        self.replace_in_tree(self.green().with_kind(S::into_raw(kind)))
+    }
+
+    fn replace_in_tree(&self, replacement: GreenNode) -> GreenNode {
         match self.data().kind.as_child() {
             None => replacement, // `None` means `self` is the root
             Some((parent, me, _offset)) => {
@@ -514,12 +710,67 @@ impl<S: Syntax, D> SyntaxNode<S, D> {
                         child.cloned()
                     }
                 });
-                let new_parent = GreenNode::new(parent.syntax_kind(), children);
+                let new_parent = parent.green().rebuild(parent.green().kind(), children);
                 parent.replace_with(new_parent)
             }
         }
     }
 
+    /// Returns a green tree, equal to the green tree this node belongs to, except with this node replaced by its
+    /// children. Like for [`replace_with`](SyntaxNode::replace_with), the complexity of the operation is proportional
+    /// to the depth of the tree.
+    ///
+    /// ## Panics
+    /// If this node is the root of the tree.
+    pub fn flatten(&self) -> GreenNode {
+        let Some((parent, me, _offset)) = self.data().kind.as_child() else {
+            panic!("cannot flatten the root of a tree");
+        };
+        parent.replace_with(parent.green().flatten_child(me as usize))
+    }
+
+    /// Returns a green tree, equal to the green tree this node belongs to, except that every token in the subtree
+    /// rooted at this node whose text was interned as `from` gets the text of `to` instead. `resolver` is used to
+    /// look up the length of the new text.
+    ///
+    /// Subtrees that contain no such token are shared with the original tree, as are all parts of the tree outside
+    /// of this node that are not its ancestors. This is the basis for renaming all uses of a name in one step.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// # use cstree::interning::Interner;
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// builder.token(Int, "1");
+    /// builder.static_token(Plus);
+    /// builder.token(Int, "1");
+    /// builder.finish_node();
+    /// let (tree, cache) = builder.finish();
+    /// let mut interner = cache.unwrap().into_interner().unwrap();
+    /// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+    /// let one = root.first_token().unwrap().text_key().unwrap();
+    /// let ten = interner.get_or_intern("10");
+    /// let new_root: SyntaxNode<MySyntax> =
+    ///     SyntaxNode::new_root(root.replace_token_keys(one, ten, &interner));
+    /// assert_eq!(new_root.resolve_text(&interner), "10+10");
+    /// ```
+    pub fn replace_token_keys<R>(&self, from: TokenKey, to: TokenKey, resolver: &R) -> GreenNode
+    where
+        R: Resolver<TokenKey> + ?Sized,
+    {
+        let text = resolver.resolve(to);
+        self.map_tokens(|token| (token.text_key() == Some(from)).then(|| token.with_text(to, text)))
+    }
+
+    /// Returns a green tree, equal to the green tree this node belongs to, except that every token in the subtree
+    /// rooted at this node for which `f` returns a replacement is replaced. Subtrees without replacements are shared
+    /// with the original tree.
+    pub(super) fn map_tokens(&self, mut f: impl FnMut(&GreenToken) -> Option<GreenToken>) -> GreenNode {
+        let replacement = self.green().map_tokens(&mut f).unwrap_or_else(|| self.green().clone());
+        self.replace_in_tree(replacement)
+    }
+
     /// The internal representation of the kind of this node.
     #[inline]
     pub fn syntax_kind(&self) -> RawSyntaxKind {
@@ -542,6 +793,85 @@ impl<S: Syntax, D> SyntaxNode<S, D> {
         TextRange::at(offset, self.green().text_len())
     }
 
+    /// The range this node covers in the source text, in bytes, relative to the start of `ancestor`.
+    ///
+    /// Returns `None` if `ancestor` is neither this node nor one of its ancestors.
+    /// This is useful to work with local coordinates in a subtree that is embedded in a larger tree.
+    #[inline]
+    pub fn range_in(&self, ancestor: &SyntaxNode<S, D>) -> Option<TextRange> {
+        self.ancestors()
+            .any(|node| node == ancestor)
+            .then(|| self.text_range() - ancestor.text_range().start())
+    }
+
+    /// The number of newlines (`\n`) in the text of this node.
+    ///
+    /// This is stored in the green tree and does not require resolving any text.
+    #[cfg(feature = "line_counts")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "line_counts")))]
+    #[inline]
+    pub fn newline_count(&self) -> u32 {
+        self.green().newline_count()
+    }
+
+    /// The payload that was given to the builder for this node, see [`GreenNodeHead::payload`].
+    ///
+    /// [`GreenNodeHead::payload`]: crate::green::GreenNodeHead::payload
+    #[cfg(feature = "node_payloads")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "node_payloads")))]
+    #[inline]
+    pub fn payload(&self) -> u32 {
+        self.green().payload()
+    }
+
+    /// Returns `true` if the text of this node spans multiple lines, i.e., if it contains a newline.
+    #[cfg(feature = "line_counts")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "line_counts")))]
+    #[inline]
+    pub fn is_multiline(&self) -> bool {
+        self.newline_count() > 0
+    }
+
+    /// The (0-based) line of the source text on which this node starts.
+    ///
+    /// This adds up the newlines stored in the green siblings that precede this node and its ancestors, so it takes
+    /// time proportional to the depth of the node and the number of children of its ancestors, but does not resolve
+    /// any text or create any red nodes.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// builder.token(Whitespace, "\n\n");
+    /// builder.start_node(Operation);
+    /// builder.token(Int, "1");
+    /// builder.token(Whitespace, "\n");
+    /// builder.finish_node();
+    /// builder.finish_node();
+    /// let (tree, _) = builder.finish();
+    /// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+    /// let operation = root.first_child().unwrap();
+    /// assert_eq!(operation.start_line(), 2);
+    /// assert!(operation.is_multiline());
+    /// assert_eq!(root.newline_count(), 3);
+    /// ```
+    #[cfg(feature = "line_counts")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "line_counts")))]
+    pub fn start_line(&self) -> u32 {
+        match self.data().kind.as_child() {
+            Some((parent, index, _)) => parent.line_of_child(index as usize),
+            None => 0,
+        }
+    }
+
+    /// The line on which the child of this node at `index` starts.
+    #[cfg(feature = "line_counts")]
+    pub(super) fn line_of_child(&self, index: usize) -> u32 {
+        let before: u32 = self.green().children().take(index).map(|child| child.newline_count()).sum();
+        self.start_line() + before
+    }
+
     /// Uses the provided resolver to return an efficient representation of all source text covered
     /// by this node, i.e. the combined text of all token leafs of the subtree originating in this
     /// node.
@@ -553,6 +883,91 @@ impl<S: Syntax, D> SyntaxNode<S, D> {
         SyntaxText::new(self, resolver)
     }
 
+    /// Returns an iterator over the text of every token in the subtree of this node, in order, together with the
+    /// range in the source text that the token covers.
+    ///
+    /// The text of tokens with [static text](Syntax::static_text) is taken from their kind, the text of all other
+    /// tokens is resolved with `resolver`. Since every token yields a chunk, there are no gaps between consecutive
+    /// ranges, which makes this a building block for streaming over the text of a node with exact offsets, for
+    /// example to hash, search or write it.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// # use cstree::text::TextRange;
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// builder.token(Int, "12");
+    /// builder.static_token(Plus);
+    /// builder.token(Int, "3");
+    /// builder.finish_node();
+    /// let (tree, cache) = builder.finish();
+    /// let interner = cache.unwrap().into_interner().unwrap();
+    /// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+    /// let chunks: Vec<_> = root.text_chunks(&interner).collect();
+    /// assert_eq!(
+    ///     chunks,
+    ///     [
+    ///         (TextRange::new(0.into(), 2.into()), "12"),
+    ///         (TextRange::new(2.into(), 3.into()), "+"),
+    ///         (TextRange::new(3.into(), 4.into()), "3"),
+    ///     ]
+    /// );
+    /// ```
+    #[inline]
+    pub fn text_chunks<'n, 'i, R>(&'n self, resolver: &'i R) -> TextChunks<'n, 'i, S, R>
+    where
+        R: Resolver<TokenKey> + ?Sized,
+    {
+        TextChunks::new(self.green(), self.text_range().start(), resolver)
+    }
+
+    /// Returns `true` if the text of this node, resolved with `resolver`, is equal to the text of `other`, resolved
+    /// with `other_resolver`.
+    ///
+    /// The two nodes can be from different trees that were built with different interners. The texts are compared
+    /// chunk by chunk without allocating, and without creating red nodes for the subtrees.
+    ///
+    /// # Example
+    /// ```
+    /// # use cstree::testing::*;
+    /// # use cstree::interning::Resolver;
+    /// fn build(text: &str) -> (SyntaxNode<MySyntax>, impl Resolver) {
+    ///     let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    ///     builder.start_node(Root);
+    ///     for (i, part) in text.split('+').enumerate() {
+    ///         if i > 0 {
+    ///             builder.static_token(Plus);
+    ///         }
+    ///         builder.token(Identifier, part);
+    ///     }
+    ///     builder.finish_node();
+    ///     let (tree, cache) = builder.finish();
+    ///     (
+    ///         SyntaxNode::new_root(tree),
+    ///         cache.unwrap().into_interner().unwrap(),
+    ///     )
+    /// }
+    /// let (a, resolver_a) = build("ab+c");
+    /// let (b, resolver_b) = build("a+bc");
+    /// let (c, resolver_c) = build("ab+c");
+    /// assert!(!a.text_eq(&b, &resolver_a, &resolver_b));
+    /// assert!(a.text_eq(&c, &resolver_a, &resolver_c));
+    /// ```
+    pub fn text_eq<S2, D2, R1, R2>(&self, other: &SyntaxNode<S2, D2>, resolver: &R1, other_resolver: &R2) -> bool
+    where
+        S2: Syntax,
+        R1: Resolver<TokenKey> + ?Sized,
+        R2: Resolver<TokenKey> + ?Sized,
+    {
+        if self.green().text_len() != other.green().text_len() {
+            return false;
+        }
+        let lhs = TextChunks::<S, R1>::new(self.green(), 0.into(), resolver).map(|(_, chunk)| chunk);
+        let rhs = TextChunks::<S2, R2>::new(other.green(), 0.into(), other_resolver).map(|(_, chunk)| chunk);
+        chunks_eq(lhs, rhs)
+    }
+
     /// Returns the unterlying green tree node of this node.
     #[inline]
     pub fn green(&self) -> &GreenNode {
@@ -588,6 +1003,30 @@ impl<S: Syntax, D> SyntaxNode<S, D> {
         iter::successors(Some(self), |&node| node.parent())
     }
 
+    /// Returns an iterator over all nodes along the chain of parents of this node (starting with this node) that can
+    /// be converted into the [`AstNode`] type `T`, converted into `T`.
+    ///
+    /// Nodes are only passed to [`AstNode::cast`] if [`AstNode::can_cast`] accepts their kind.
+    #[inline]
+    pub fn ancestors_of_type<T>(&self) -> impl Iterator<Item = T> + '_
+    where
+        T: AstNode<S, D>,
+    {
+        self.ancestors()
+            .filter(|node| T::can_cast(node.kind()))
+            .filter_map(|node| T::cast(node.clone()))
+    }
+
+    /// Returns the closest node along the chain of parents of this node (starting with this node) that can be
+    /// converted into the [`AstNode`] type `T`, for example the function or block that contains this node.
+    #[inline]
+    pub fn nearest_ancestor<T>(&self) -> Option<T>
+    where
+        T: AstNode<S, D>,
+    {
+        self.ancestors_of_type().next()
+    }
+
     /// Returns an iterator over all nodes that are children of this node.
     ///
     /// If you want to also consider leafs, see [`children_with_tokens`](SyntaxNode::children_with_tokens).
@@ -602,6 +1041,112 @@ impl<S: Syntax, D> SyntaxNode<S, D> {
         SyntaxElementChildren::new(self)
     }
 
+    /// Returns a view of all children of this node, including tokens, that allows accessing them by index and finding
+    /// them by offset in logarithmic time. See [`ChildSlice`].
+    ///
+    /// This computes the offsets of all children, so it takes time proportional to the number of children.
+    #[inline]
+    pub fn child_slice(&self) -> ChildSlice<'_, S, D> {
+        ChildSlice::new(self)
+    }
+
+    /// Creates the syntax nodes and tokens of all elements in the subtree of this node.
+    ///
+    /// Syntax nodes and tokens are created lazily, when they are first reached by navigating the tree, and are kept
+    /// until the whole tree is dropped. Materializing a subtree up front allows latency-sensitive passes to pay the
+    /// cost of allocating its nodes at a predictable time, for example on a background thread right after parsing,
+    /// instead of during the first traversal. The syntax nodes of a tree are allocated individually and freed together
+    /// with the tree, without any reuse between trees, so their allocation can only be moved, not avoided.
+    ///
+    /// Materializing an element that already exists does nothing, so this is cheap for subtrees that have already
+    /// been traversed.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// builder.start_node(Operation);
+    /// builder.token(Int, "1");
+    /// builder.static_token(Plus);
+    /// builder.token(Int, "2");
+    /// builder.finish_node();
+    /// builder.finish_node();
+    /// let (tree, _) = builder.finish();
+    /// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+    /// root.materialize();
+    /// // navigating the tree no longer allocates
+    /// assert_eq!(root.first_token().unwrap().kind(), Int);
+    /// ```
+    pub fn materialize(&self) {
+        self.preorder_with_tokens().for_each(drop);
+    }
+
+    /// [Materializes](SyntaxNode::materialize) the subtree of this node on as many threads as the system supports.
+    ///
+    /// The elements down to `depth` levels below this node are created on the current thread. The subtrees of the
+    /// nodes at `depth` are then distributed over the threads. A `depth` that yields many more nodes than there are
+    /// threads balances the work better if the subtrees differ in size.
+    ///
+    /// Every element is created exactly once, even if the subtree is traversed by other threads at the same time, so
+    /// this can be used to eagerly create the part of a tree that a language server is about to visit, for example
+    /// the [covering node](SyntaxNode::covering_element) of the visible part of a document, in the background.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// for _ in 0..100 {
+    ///     builder.start_node(Operation);
+    ///     builder.token(Int, "1");
+    ///     builder.static_token(Plus);
+    ///     builder.token(Int, "2");
+    ///     builder.finish_node();
+    /// }
+    /// builder.finish_node();
+    /// let (tree, _) = builder.finish();
+    /// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+    /// root.par_materialize(1);
+    /// assert_eq!(root.descendants_with_tokens().count(), 401);
+    /// ```
+    pub fn par_materialize(&self, depth: usize) {
+        let mut frontier = vec![self];
+        for _ in 0..depth {
+            frontier = frontier
+                .iter()
+                .flat_map(|node| node.children_with_tokens().filter_map(NodeOrToken::into_node))
+                .collect();
+        }
+        let threads = std::thread::available_parallelism()
+            .map_or(1, |threads| threads.get())
+            .min(frontier.len());
+        if threads <= 1 {
+            frontier.iter().for_each(|node| node.materialize());
+            return;
+        }
+        let next = AtomicUsize::new(0);
+        let work = || {
+            while let Some(node) = frontier.get(next.fetch_add(1, Ordering::Relaxed)) {
+                node.materialize();
+            }
+        };
+        std::thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(work);
+            }
+        });
+    }
+
+    /// Returns a view of the children of this node, including tokens, that overlap `range`, i.e., that share some
+    /// text with `range` or are empty and lie within `range`. See [`ChildSlice`].
+    ///
+    /// This computes the offsets of all children, so it takes time proportional to the number of children.
+    #[inline]
+    pub fn children_in_range(&self, range: TextRange) -> ChildSlice<'_, S, D> {
+        self.child_slice().in_range(range)
+    }
+
     /// The first child node of this node, if any.
     ///
     /// If you want to also consider leafs, see [`first_child_or_token`](SyntaxNode::first_child_or_token).
@@ -734,15 +1279,62 @@ impl<S: Syntax, D> SyntaxNode<S, D> {
     }
 
     /// Return the leftmost token in the subtree of this node
+    ///
+    /// This only materializes the nodes on the path to the token (plus any child nodes before it
+    /// that do not contain any tokens), so it takes time proportional to the depth of the tree.
     #[inline]
     pub fn first_token(&self) -> Option<&SyntaxToken<S, D>> {
-        self.first_child_or_token()?.first_token()
+        for (element, (index, offset)) in self.green().children_from(0, self.text_range().start()) {
+            if is_empty_node(element) {
+                continue;
+            }
+            match self.get_or_add_element(element, index, offset) {
+                NodeOrToken::Node(node) => match node.first_token() {
+                    Some(token) => return Some(token),
+                    None => continue,
+                },
+                NodeOrToken::Token(token) => return Some(token),
+            }
+        }
+        None
     }
 
     /// Return the rightmost token in the subtree of this node
+    ///
+    /// This only materializes the nodes on the path to the token (plus any child nodes after it
+    /// that do not contain any tokens), so it takes time proportional to the depth of the tree.
     #[inline]
     pub fn last_token(&self) -> Option<&SyntaxToken<S, D>> {
-        self.last_child_or_token()?.last_token()
+        let green = self.green();
+        for (element, (index, offset)) in green.children_to(green.children().len(), self.text_range().end()) {
+            if is_empty_node(element) {
+                continue;
+            }
+            match self.get_or_add_element(element, index, offset) {
+                NodeOrToken::Node(node) => match node.last_token() {
+                    Some(token) => return Some(token),
+                    None => continue,
+                },
+                NodeOrToken::Token(token) => return Some(token),
+            }
+        }
+        None
+    }
+
+    /// Returns the first token after the subtree of this node, in document order.
+    /// This token may belong to a sibling of this node or one of its ancestors, but will always be
+    /// further right in the tree.
+    #[inline]
+    pub fn next_token(&self) -> Option<&SyntaxToken<S, D>> {
+        SyntaxElementRef::from(self).next_token()
+    }
+
+    /// Returns the last token before the subtree of this node, in document order.
+    /// This token may belong to a sibling of this node or one of its ancestors, but will always be
+    /// further left in the tree.
+    #[inline]
+    pub fn prev_token(&self) -> Option<&SyntaxToken<S, D>> {
+        SyntaxElementRef::from(self).prev_token()
     }
 
     /// Returns an iterator over all sibling nodes of this node in the given `direction`, i.e. all of
@@ -751,23 +1343,26 @@ impl<S: Syntax, D> SyntaxNode<S, D> {
     ///
     /// If you want to also consider leafs, see [`siblings_with_tokens`](SyntaxNode::siblings_with_tokens).
     #[inline]
-    pub fn siblings(&self, direction: Direction) -> impl Iterator<Item = &SyntaxNode<S, D>> {
-        iter::successors(Some(self), move |node| match direction {
-            Direction::Next => node.next_sibling(),
-            Direction::Prev => node.prev_sibling(),
-        })
+    pub fn siblings(&self, direction: Direction) -> SyntaxNodeSiblings<'_, S, D> {
+        let parent = self
+            .data()
+            .kind
+            .as_child()
+            .map(|(parent, index, _)| (parent, index as usize));
+        SyntaxNodeSiblings::new(self, parent, direction)
     }
 
     /// Returns an iterator over all siblings of this node in the given `direction`, i.e. all of this
     /// node's parent's children from this node on to the left or the right.
     /// The first item in the iterator will always be this node.
     #[inline]
-    pub fn siblings_with_tokens(&self, direction: Direction) -> impl Iterator<Item = SyntaxElementRef<'_, S, D>> {
-        let me: SyntaxElementRef<'_, S, D> = self.into();
-        iter::successors(Some(me), move |el| match direction {
-            Direction::Next => el.next_sibling_or_token(),
-            Direction::Prev => el.prev_sibling_or_token(),
-        })
+    pub fn siblings_with_tokens(&self, direction: Direction) -> SyntaxElementSiblings<'_, S, D> {
+        let parent = self
+            .data()
+            .kind
+            .as_child()
+            .map(|(parent, index, _)| (parent, index as usize));
+        SyntaxElementSiblings::new(self.into(), parent, direction)
     }
 
     /// Returns an iterator over all nodes (!) in the subtree starting at this node, including this node.
@@ -790,6 +1385,145 @@ impl<S: Syntax, D> SyntaxNode<S, D> {
         })
     }
 
+    /// Returns an iterator over all tokens in the subtree starting at this node, in order.
+    ///
+    /// This is equivalent to filtering [`descendants_with_tokens`](SyntaxNode::descendants_with_tokens)
+    /// for tokens.
+    #[inline]
+    pub fn tokens(&self) -> impl Iterator<Item = &SyntaxToken<S, D>> {
+        self.descendants_with_tokens()
+            .filter_map(|element| element.into_token())
+    }
+
+    /// Returns an iterator over all [error tokens](Syntax::is_error_token) in the subtree starting at this node, in
+    /// order. These are the lexical errors in the text of this node.
+    #[inline]
+    pub fn error_tokens(&self) -> impl Iterator<Item = &SyntaxToken<S, D>> {
+        self.tokens().filter(|token| token.kind().is_error_token())
+    }
+
+    /// Returns an iterator over all tokens in the subtree starting at this node whose kind has the given
+    /// [class](Syntax::class), in order.
+    #[inline]
+    pub fn tokens_of_class(&self, class: KindClass) -> impl Iterator<Item = &SyntaxToken<S, D>> {
+        self.tokens().filter(move |token| token.kind().class() == class)
+    }
+
+    /// Returns an iterator over all tokens in the subtree starting at this node whose text range
+    /// overlaps with `range`, in order.
+    ///
+    /// Tokens that only touch `range` at one of its ends are not included, with the exception of empty
+    /// tokens, which are included if they lie within `range`. An empty `range` yields the token that
+    /// strictly contains its offset, if any. Subtrees that lie outside of `range` are skipped without
+    /// visiting their children.
+    #[inline]
+    pub fn tokens_in_range(&self, range: TextRange) -> impl Iterator<Item = &SyntaxToken<S, D>> {
+        self.descendants_intersecting(range)
+            .filter_map(|element| element.into_token())
+    }
+
+    /// Returns an iterator over all tokens in the subtree starting at this node whose text was interned as `key`, in
+    /// order, for example to find all uses of a name.
+    ///
+    /// With the `key_filters` feature, subtrees that cannot contain such a token according to
+    /// [`GreenNode::may_contain_key`] are skipped without visiting their children. Otherwise, this visits all tokens
+    /// of the subtree, but still does not need to resolve their text.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// # use cstree::interning::Interner;
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// let x = builder.interner_mut().get_or_intern("x");
+    /// builder.start_node(Root);
+    /// builder.token(Identifier, "x");
+    /// builder.static_token(Plus);
+    /// builder.token(Identifier, "y");
+    /// builder.static_token(Plus);
+    /// builder.token(Identifier, "x");
+    /// builder.finish_node();
+    /// let (tree, _) = builder.finish();
+    /// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+    /// let offsets: Vec<_> = root.tokens_with_key(x).map(|token| u32::from(token.text_range().start())).collect();
+    /// assert_eq!(offsets, [0, 4]);
+    /// ```
+    pub fn tokens_with_key(&self, key: TokenKey) -> impl Iterator<Item = &SyntaxToken<S, D>> {
+        let me: SyntaxElementRef<'_, S, D> = self.into();
+        iter::successors(Some(WalkEvent::Enter(me)), move |pos| {
+            let next = match pos {
+                WalkEvent::Enter(NodeOrToken::Node(node)) if may_contain_key(node.green(), key) => {
+                    match node.first_child_or_token() {
+                        Some(child) => WalkEvent::Enter(child),
+                        None => WalkEvent::Leave((*node).into()),
+                    }
+                }
+                WalkEvent::Enter(el) => WalkEvent::Leave(*el),
+                WalkEvent::Leave(el) => {
+                    if el == &me {
+                        return None;
+                    }
+                    match el.next_sibling_or_token() {
+                        Some(sibling) => WalkEvent::Enter(sibling),
+                        None => WalkEvent::Leave(el.parent().unwrap().into()),
+                    }
+                }
+            };
+            Some(next)
+        })
+        .filter_map(move |event| match event {
+            WalkEvent::Enter(NodeOrToken::Token(token)) if token.text_key() == Some(key) => Some(token),
+            _ => None,
+        })
+    }
+
+    /// Returns `false` if no token in the subtree of this node has the text `key`. If this returns `true`, such a
+    /// token may or may not exist. See [`GreenNode::may_contain_key`].
+    #[cfg(feature = "key_filters")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "key_filters")))]
+    #[inline]
+    pub fn may_contain_key(&self, key: TokenKey) -> bool {
+        self.green().may_contain_key(key)
+    }
+
+    /// Returns an iterator over all elements in the subtree starting at this node (including this node) whose text
+    /// range overlaps with `range`, in order.
+    ///
+    /// Like for [`tokens_in_range`](SyntaxNode::tokens_in_range), elements that only touch `range` at one of its
+    /// ends are not included, unless they are empty and lie within `range`. The iterator only descends into
+    /// children of nodes that overlap or touch `range`, and does not allocate.
+    pub fn descendants_intersecting(&self, range: TextRange) -> impl Iterator<Item = SyntaxElementRef<'_, S, D>> {
+        let me: SyntaxElementRef<'_, S, D> = self.into();
+        iter::successors(Some(WalkEvent::Enter(me)), move |pos| {
+            let next = match pos {
+                WalkEvent::Enter(el) => match el {
+                    NodeOrToken::Node(node) if node.text_range().intersect(range).is_some() => {
+                        match node.first_child_or_token() {
+                            Some(child) => WalkEvent::Enter(child),
+                            None => WalkEvent::Leave((*node).into()),
+                        }
+                    }
+                    _ => WalkEvent::Leave(*el),
+                },
+                WalkEvent::Leave(el) => {
+                    if el == &me {
+                        return None;
+                    }
+                    match el.next_sibling_or_token() {
+                        // everything after this point lies behind `range`
+                        Some(sibling) if sibling.text_range().start() > range.end() => return None,
+                        Some(sibling) => WalkEvent::Enter(sibling),
+                        None => WalkEvent::Leave(el.parent().unwrap().into()),
+                    }
+                }
+            };
+            Some(next)
+        })
+        .filter_map(move |event| match event {
+            WalkEvent::Enter(element) if overlaps_range(element.text_range(), range) => Some(element),
+            _ => None,
+        })
+    }
+
     /// Traverse the subtree rooted at the current node (including the current
     /// node) in preorder, excluding tokens.
     #[inline(always)]
@@ -842,6 +1576,37 @@ impl<S: Syntax, D> SyntaxNode<S, D> {
         })
     }
 
+    /// Traverse the subtree rooted at the current node (including the current node) in preorder, including tokens,
+    /// but only visiting elements whose text range overlaps with `range`.
+    ///
+    /// Elements are included like for [`descendants_intersecting`](SyntaxNode::descendants_intersecting), but every
+    /// included element is both entered and left, so the events are correctly nested. Subtrees that lie outside of
+    /// `range` are skipped without visiting their children, and the traversal ends after the last element that
+    /// overlaps `range`. If this node does not overlap `range`, there are no events.
+    pub fn preorder_with_tokens_in_range(
+        &self,
+        range: TextRange,
+    ) -> impl Iterator<Item = WalkEvent<SyntaxElementRef<'_, S, D>>> {
+        let me: SyntaxElementRef<'_, S, D> = self.into();
+        let start = overlaps_range(self.text_range(), range).then_some(WalkEvent::Enter(me));
+        iter::successors(start, move |pos| {
+            let (next, parent) = match pos {
+                WalkEvent::Enter(NodeOrToken::Node(node)) => (node.first_child_or_token(), *node),
+                WalkEvent::Enter(el) => return Some(WalkEvent::Leave(*el)),
+                WalkEvent::Leave(el) => {
+                    if el == &me {
+                        return None;
+                    }
+                    (el.next_sibling_or_token(), el.parent().unwrap())
+                }
+            };
+            Some(match next_overlapping(next, range) {
+                Some(el) => WalkEvent::Enter(el),
+                None => WalkEvent::Leave(parent.into()),
+            })
+        })
+    }
+
     /// Find a token in the subtree corresponding to this node, which covers the offset.
     /// Precondition: offset must be withing node's range.
     pub fn token_at_offset(&self, offset: TextSize) -> TokenAtOffset<SyntaxToken<S, D>> {
@@ -878,6 +1643,47 @@ impl<S: Syntax, D> SyntaxNode<S, D> {
         }
     }
 
+    /// Find the token(s) in the subtree corresponding to this node at the given line and column, with the column
+    /// counted in `unit`.
+    ///
+    /// `line_index` must have been computed from the text of the whole tree, for example with
+    /// `root.resolve_text(resolver).line_index()`. Returns [`TokenAtOffset::None`] if the position does not exist
+    /// in the text or lies outside of this node.
+    ///
+    /// # Example
+    /// ```
+    /// # use cstree::testing::*;
+    /// # use cstree::syntax::ResolvedNode;
+    /// # use cstree::text::{ColumnUnit, LineCol};
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// builder.token(Identifier, "a");
+    /// builder.token(Whitespace, "\n");
+    /// builder.token(Identifier, "bb");
+    /// builder.finish_node();
+    /// let (root, cache) = builder.finish();
+    /// let root: ResolvedNode<MySyntax> =
+    ///     SyntaxNode::new_root_with_resolver(root, cache.unwrap().into_interner().unwrap());
+    ///
+    /// let line_index = root.text().line_index();
+    /// let token = root
+    ///     .token_at_position(&line_index, LineCol { line: 1, col: 1 }, ColumnUnit::Utf16)
+    ///     .right_biased()
+    ///     .unwrap();
+    /// assert_eq!(token.text(), "bb");
+    /// ```
+    pub fn token_at_position(
+        &self,
+        line_index: &LineIndex,
+        position: LineCol,
+        unit: ColumnUnit,
+    ) -> TokenAtOffset<SyntaxToken<S, D>> {
+        match line_index.offset_in(position, unit) {
+            Some(offset) if self.text_range().contains_inclusive(offset) => self.token_at_offset(offset),
+            _ => TokenAtOffset::None,
+        }
+    }
+
     /// Return the deepest node or token in the current subtree that fully
     /// contains the range. If the range is empty and is contained in two leaf
     /// nodes, either one can be returned. Precondition: range must be contained
@@ -905,6 +1711,62 @@ impl<S: Syntax, D> SyntaxNode<S, D> {
             };
         }
     }
+
+    /// Return the deepest node in the current subtree that fully contains the range and has one of the given
+    /// `kinds`, for example the innermost expression or statement that encloses a selection.
+    ///
+    /// This is the same as looking for the first ancestor of the [`covering_element`](SyntaxNode::covering_element)
+    /// with one of the `kinds` (including the covering element itself), but only walks down the tree once.
+    /// Returns `None` if neither the covering element nor any of its ancestors in the current subtree has one of
+    /// the `kinds`. Precondition: range must be contained within the current node.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// # use cstree::text::TextRange;
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// builder.start_node(Operation);
+    /// builder.token(Int, "1");
+    /// builder.static_token(Plus);
+    /// builder.start_node(Operation);
+    /// builder.token(Int, "2");
+    /// builder.static_token(Plus);
+    /// builder.token(Int, "3");
+    /// builder.finish_node();
+    /// builder.finish_node();
+    /// builder.finish_node();
+    /// let (tree, _) = builder.finish();
+    /// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+    ///
+    /// let inner = root.covering_node_of_kind(TextRange::new(2.into(), 3.into()), &[Operation]).unwrap();
+    /// assert_eq!(inner.text_range(), TextRange::new(2.into(), 5.into()));
+    /// let outer = root.covering_node_of_kind(TextRange::new(0.into(), 3.into()), &[Operation]).unwrap();
+    /// assert_eq!(outer.text_range(), TextRange::new(0.into(), 5.into()));
+    /// assert_eq!(root.covering_node_of_kind(TextRange::new(2.into(), 3.into()), &[Root]), Some(&root));
+    /// ```
+    pub fn covering_node_of_kind(&self, range: TextRange, kinds: &[S]) -> Option<&SyntaxNode<S, D>> {
+        assert!(
+            self.text_range().contains_range(range),
+            "Bad range: node range {:?}, range {:?}",
+            self.text_range(),
+            range,
+        );
+        let mut node = self;
+        let mut found = None;
+        loop {
+            if kinds.contains(&node.kind()) {
+                found = Some(node);
+            }
+            match node
+                .children_with_tokens()
+                .find(|child| child.text_range().contains_range(range))
+            {
+                Some(NodeOrToken::Node(child)) => node = child,
+                Some(NodeOrToken::Token(_)) | None => return found,
+            }
+        }
+    }
 }
 
 #[cfg(feature = "serialize")]
@@ -932,6 +1794,55 @@ where
     ) -> impl serde::Serialize + 'node {
         SerializeWithResolver { node: self, resolver }
     }
+
+    /// Deserializes a tree that was serialized together with its data, interning the text of its tokens with the
+    /// given `interner`. Use this to load a tree into the interner of an existing session, which can then be used to
+    /// resolve the tree's texts. If the tree was serialized without data, no data is attached.
+    ///
+    /// Node data is attached to the deserialized tree right away. However, only the parts of the tree that lead to
+    /// nodes with data are created in memory, the rest of the tree is created lazily when it is traversed.
+    ///
+    /// # Example
+    /// ```
+    /// # use cstree::testing::*;
+    /// # use cstree::interning::new_interner;
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// builder.start_node(Operation);
+    /// builder.token(Int, "1");
+    /// builder.finish_node();
+    /// builder.finish_node();
+    /// let (tree, cache) = builder.finish();
+    /// let interner = cache.unwrap().into_interner().unwrap();
+    /// let root: SyntaxNode<MySyntax, String> = SyntaxNode::new_root(tree);
+    /// root.first_child().unwrap().set_data("checked".to_string());
+    /// let json =
+    ///     serde_json::to_string(&root.as_serialize_with_data_with_resolver(&interner)).unwrap();
+    ///
+    /// let mut interner = new_interner();
+    /// let mut deserializer = serde_json::Deserializer::from_str(&json);
+    /// let root: SyntaxNode<MySyntax, String> =
+    ///     SyntaxNode::deserialize_with_interner(&mut deserializer, &mut interner).unwrap();
+    /// assert_eq!(root.get_data(), None);
+    /// let operation = root.first_child().unwrap();
+    /// assert_eq!(
+    ///     operation.get_data().as_deref().map(String::as_str),
+    ///     Some("checked")
+    /// );
+    /// assert_eq!(operation.resolve_text(&interner), "1");
+    /// ```
+    pub fn deserialize_with_interner<'de, De, I>(deserializer: De, interner: &mut I) -> Result<Self, De::Error>
+    where
+        De: serde::Deserializer<'de>,
+        D: serde::Deserialize<'de>,
+        I: Interner<TokenKey>,
+    {
+        let builder = GreenNodeBuilder::<S, I>::with_interner(interner);
+        let (tree, _, nodes, data) = crate::serde_impls::deserialize_tree(deserializer, builder)?;
+        let tree = SyntaxNode::new_root(tree);
+        crate::serde_impls::attach_data(&tree, &nodes, data)?;
+        Ok(tree)
+    }
 }
 
 impl GreenNode {
@@ -940,7 +1851,7 @@ impl GreenNode {
         &self,
         start_index: usize,
         mut offset: TextSize,
-    ) -> impl Iterator<Item = (GreenElementRef, (usize, TextSize))> {
+    ) -> impl Iterator<Item = (GreenElementRef<'_>, (usize, TextSize))> {
         self.children()
             .skip(start_index)
             .enumerate()
@@ -956,7 +1867,7 @@ impl GreenNode {
         &self,
         end_index: usize,
         mut offset: TextSize,
-    ) -> impl Iterator<Item = (GreenElementRef, (usize, TextSize))> {
+    ) -> impl Iterator<Item = (GreenElementRef<'_>, (usize, TextSize))> {
         self.children()
             .take(end_index)
             .rev()
@@ -977,3 +1888,131 @@ fn filter_nodes<'a, I: Iterator<Item = (GreenElementRef<'a>, T)>, T>(
         NodeOrToken::Token(_) => None,
     })
 }
+
+/// Whether the subtree of `node` may contain a token with text `key`, using its key filter if there is one.
+#[cfg(feature = "key_filters")]
+#[inline(always)]
+fn may_contain_key(node: &GreenNode, key: TokenKey) -> bool {
+    node.may_contain_key(key)
+}
+
+/// Without key filters, every subtree may contain a token with text `key`.
+#[cfg(not(feature = "key_filters"))]
+#[inline(always)]
+fn may_contain_key(_node: &GreenNode, _key: TokenKey) -> bool {
+    true
+}
+
+/// Nodes without children cannot contain tokens, so there is no need to create red nodes for them when looking for
+/// tokens.
+fn is_empty_node(element: GreenElementRef<'_>) -> bool {
+    match element {
+        NodeOrToken::Node(node) => node.children().len() == 0,
+        NodeOrToken::Token(_) => false,
+    }
+}
+
+/// An iterator over the texts of all tokens in a subtree, in order, together with the range each text covers.
+///
+/// Returned by [`SyntaxNode::text_chunks`] and [`ResolvedNode::text_chunks`](super::ResolvedNode::text_chunks).
+pub struct TextChunks<'n, 'i, S, R: ?Sized> {
+    stack:    Vec<GreenNodeChildren<'n>>,
+    resolver: &'i R,
+    offset:   TextSize,
+    _syntax:  PhantomData<S>,
+}
+
+impl<'n, 'i, S, R: ?Sized> TextChunks<'n, 'i, S, R> {
+    fn new(node: &'n GreenNode, offset: TextSize, resolver: &'i R) -> Self {
+        Self {
+            stack: vec![node.children()],
+            resolver,
+            offset,
+            _syntax: PhantomData,
+        }
+    }
+}
+
+impl<'i, S: Syntax, R: Resolver<TokenKey> + ?Sized> Iterator for TextChunks<'_, 'i, S, R> {
+    type Item = (TextRange, &'i str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let Some(child) = self.stack.last_mut()?.next() else {
+                self.stack.pop();
+                continue;
+            };
+            match child {
+                NodeOrToken::Node(node) => self.stack.push(node.children()),
+                NodeOrToken::Token(token) => {
+                    let text = token.static_text::<S>().or_else(|| token.text(self.resolver));
+                    let range = TextRange::at(self.offset, token.text_len());
+                    self.offset = range.end();
+                    return Some((range, text.unwrap_or_default()));
+                }
+            }
+        }
+    }
+}
+
+impl<S: Syntax, R: Resolver<TokenKey> + ?Sized> iter::FusedIterator for TextChunks<'_, '_, S, R> {}
+
+impl<S, R: ?Sized> fmt::Debug for TextChunks<'_, '_, S, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TextChunks").field("offset", &self.offset).finish_non_exhaustive()
+    }
+}
+
+/// Compares two texts that are split into chunks at different positions.
+fn chunks_eq<'a, 'b>(mut lhs: impl Iterator<Item = &'a str>, mut rhs: impl Iterator<Item = &'b str>) -> bool {
+    let mut left: &[u8] = &[];
+    let mut right: &[u8] = &[];
+    loop {
+        if left.is_empty() {
+            match lhs.next() {
+                Some(chunk) => left = chunk.as_bytes(),
+                None => return right.is_empty() && rhs.all(str::is_empty),
+            }
+            continue;
+        }
+        if right.is_empty() {
+            match rhs.next() {
+                Some(chunk) => right = chunk.as_bytes(),
+                None => return false,
+            }
+            continue;
+        }
+        let len = left.len().min(right.len());
+        if left[..len] != right[..len] {
+            return false;
+        }
+        left = &left[len..];
+        right = &right[len..];
+    }
+}
+
+/// The first element of `first` and its following siblings that overlaps with `range`, if any.
+fn next_overlapping<'a, S: Syntax, D>(
+    mut next: Option<SyntaxElementRef<'a, S, D>>,
+    range: TextRange,
+) -> Option<SyntaxElementRef<'a, S, D>> {
+    while let Some(el) = next {
+        let el_range = el.text_range();
+        if overlaps_range(el_range, range) {
+            return Some(el);
+        }
+        if el_range.start() > range.end() {
+            // everything after this point lies behind `range`
+            return None;
+        }
+        next = el.next_sibling_or_token();
+    }
+    None
+}
+
+/// Whether an element covering `element` overlaps with `range`: if they share some text, or if the element is empty and
+/// lies within `range`.
+pub(super) fn overlaps_range(element: TextRange, range: TextRange) -> bool {
+    let overlaps = element.start() < range.end() && range.start() < element.end();
+    overlaps || range.contains_range(element)
+}
@@ -0,0 +1,131 @@
+//! A dense numbering of the elements of a tree in preorder.
+
+use std::{fmt, ops::Range};
+
+use text_size::TextSize;
+
+use super::*;
+use crate::{traversal::WalkEvent, util::NodeOrToken, Syntax};
+
+/// Numbers the nodes and tokens of a syntax tree in preorder and maps the numbers back to the elements.
+///
+/// The root of the indexed subtree has index `0`, and every node is followed by the elements of its subtree, so the
+/// indices of a subtree form a contiguous range (see [`subtree`](PreorderIndex::subtree)). This gives analyses a dense
+/// key space for the elements of a tree, which can be used to store per-element information in vectors or bitsets
+/// instead of hash maps keyed by nodes. Looking up an element by its index takes constant time, finding the index of
+/// an element takes `O(log n + d)` time, where `n` is the number of indexed elements and `d` is the depth of the
+/// tree.
+///
+/// Since the numbering only depends on the structure of the tree, equal trees are numbered the same.
+///
+/// # Example
+/// ```
+/// # use cstree::testing::*;
+/// use cstree::syntax::PreorderIndex;
+///
+/// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+/// builder.start_node(Root);
+/// builder.start_node(Operation);
+/// builder.token(Int, "1");
+/// builder.static_token(Plus);
+/// builder.token(Int, "2");
+/// builder.finish_node();
+/// builder.finish_node();
+/// let (tree, _) = builder.finish();
+/// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+///
+/// let index = PreorderIndex::new(&root);
+/// assert_eq!(index.len(), 5);
+/// let operation = root.first_child().unwrap();
+/// assert_eq!(index.index_of(operation), Some(1));
+/// assert_eq!(index.subtree(1), 1..5);
+/// assert_eq!(index.get(3).unwrap().kind(), Plus);
+/// ```
+pub struct PreorderIndex<S: Syntax, D: 'static = ()> {
+    /// All elements of the tree in preorder, which sorts them by the start of their range.
+    entries: Vec<Entry<S, D>>,
+}
+
+struct Entry<S: Syntax, D: 'static> {
+    start:   TextSize,
+    element: SyntaxElement<S, D>,
+}
+
+impl<S: Syntax, D> PreorderIndex<S, D> {
+    /// Numbers `node` and all of its descendants.
+    pub fn new(node: &SyntaxNode<S, D>) -> Self {
+        let len = node.green().node_count() as usize + node.green().token_count() as usize;
+        let mut entries = Vec::with_capacity(len);
+        for event in node.preorder_with_tokens() {
+            if let WalkEvent::Enter(element) = event {
+                entries.push(Entry {
+                    start: element.text_range().start(),
+                    element: element.cloned(),
+                });
+            }
+        }
+        Self { entries }
+    }
+
+    /// The number of indexed nodes and tokens.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if no elements are indexed. Since the root of the indexed subtree is always indexed, this is
+    /// never the case.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the element with the given preorder `index`, if there is one.
+    #[inline]
+    pub fn get(&self, index: usize) -> Option<SyntaxElementRef<'_, S, D>> {
+        self.entries.get(index).map(|entry| (&entry.element).into())
+    }
+
+    /// Returns the preorder index of `element`, or `None` if it is not part of the indexed subtree.
+    pub fn index_of<'a>(&self, element: impl Into<SyntaxElementRef<'a, S, D>>) -> Option<usize>
+    where
+        S: 'a,
+        D: 'a,
+    {
+        let element = element.into();
+        let start = element.text_range().start();
+        let first = self.entries.partition_point(|entry| entry.start < start);
+        // Elements that start at the same offset are nested or empty, so there are few of them.
+        self.entries[first..]
+            .iter()
+            .take_while(|entry| entry.start == start)
+            .position(|entry| SyntaxElementRef::from(&entry.element) == element)
+            .map(|position| first + position)
+    }
+
+    /// Returns the range of indices of the element with the given preorder `index` and all of its descendants.
+    ///
+    /// ## Panics
+    /// If `index` is out of bounds.
+    pub fn subtree(&self, index: usize) -> Range<usize> {
+        let len = match &self.entries[index].element {
+            NodeOrToken::Node(node) => node.green().node_count() as usize + node.green().token_count() as usize,
+            NodeOrToken::Token(_) => 1,
+        };
+        index..index + len
+    }
+
+    /// Returns an iterator over all indexed elements in preorder, i.e., ordered by their index.
+    #[inline]
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = SyntaxElementRef<'_, S, D>> {
+        self.entries.iter().map(|entry| (&entry.element).into())
+    }
+}
+
+impl<S: Syntax, D> fmt::Debug for PreorderIndex<S, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PreorderIndex")
+            .field("elements", &self.entries.len())
+            .finish()
+    }
+}
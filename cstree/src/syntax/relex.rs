@@ -0,0 +1,262 @@
+//! Finding and replacing the tokens that have to be re-lexed after an edit.
+
+use std::fmt;
+
+use text_size::TextRange;
+
+use super::*;
+use crate::{
+    green::{GreenNode, GreenToken},
+    interning::{Interner, Resolver, TokenKey},
+    text::TextEdit,
+    traversal::Direction,
+    util::{NodeOrToken, TokenAtOffset},
+    Syntax,
+};
+
+/// The number of neighboring tokens that have to be re-lexed together with a token of some kind, used by
+/// [`SyntaxNode::relex_range`].
+///
+/// Many lexers decide the kind or the extent of a token based on the text around it. For example, whether `/` starts
+/// a regular expression literal may depend on the token before it, and a lexer that merges adjacent whitespace into a
+/// single token has to look at the token after a whitespace token to know where it ends. If such a token is
+/// re-lexed, its context has to be re-lexed with it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Lookaround {
+    /// The number of tokens before a token of this kind that are re-lexed together with it.
+    pub behind: u32,
+    /// The number of tokens after a token of this kind that are re-lexed together with it.
+    pub ahead:  u32,
+}
+
+impl Lookaround {
+    /// No context is needed to lex tokens of this kind.
+    pub const NONE: Self = Self { behind: 0, ahead: 0 };
+
+    /// Creates a lookaround of `behind` tokens before and `ahead` tokens after a token.
+    pub const fn new(behind: u32, ahead: u32) -> Self {
+        Self { behind, ahead }
+    }
+}
+
+/// A contiguous range of tokens that has to be re-lexed after an edit, as found by [`SyntaxNode::relex_range`].
+///
+/// This is the lexer-level half of incremental parsing: instead of lexing the whole edited text again, only the
+/// [`text`](RelexRange::text) of these tokens with the edit applied is lexed, and the resulting tokens are
+/// [spliced](RelexRange::splice) into the tree in place of the old ones.
+pub struct RelexRange<S: Syntax, D: 'static = ()> {
+    first: SyntaxToken<S, D>,
+    last:  SyntaxToken<S, D>,
+    edit:  TextRange,
+}
+
+impl<S: Syntax, D> SyntaxNode<S, D> {
+    /// Finds the tokens in the subtree of this node that have to be re-lexed after the text in `edit` was replaced.
+    ///
+    /// The range starts with all tokens that overlap `edit` or touch it at one of its ends, since inserted text may
+    /// become part of the tokens around it. It is then extended until it contains the tokens before and after each
+    /// of its tokens that `lookaround` requires for the kind of that token. Returns `None` if this node contains no
+    /// tokens with text.
+    ///
+    /// ## Panics
+    /// If `edit` is not contained in the text range of this node.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// use cstree::{
+    ///     syntax::Lookaround,
+    ///     text::{TextEdit, TextRange},
+    /// };
+    ///
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// for token in ["1", " ", "+", " ", "23", " ", "+", " ", "4"] {
+    ///     match token {
+    ///         "+" => builder.static_token(Plus),
+    ///         " " => builder.token(Whitespace, " "),
+    ///         _ => builder.token(Int, token),
+    ///     }
+    /// }
+    /// builder.finish_node();
+    /// let (tree, cache) = builder.finish();
+    /// let mut interner = cache.unwrap().into_interner().unwrap();
+    /// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+    ///
+    /// // Insert a space into the middle of `23`.
+    /// let edit = TextEdit::insert(5.into(), " ");
+    /// let relex = root.relex_range(edit.range, |_| Lookaround::NONE).unwrap();
+    /// assert_eq!(relex.text_range(), TextRange::new(4.into(), 6.into()));
+    /// assert_eq!(relex.text(&edit, &interner), "2 3");
+    ///
+    /// // The lexer produces these tokens for the new text.
+    /// let new_tree = relex.splice(&[(Int, "2"), (Whitespace, " "), (Int, "3")], &mut interner).unwrap();
+    /// let new_root: SyntaxNode<MySyntax> = SyntaxNode::new_root(new_tree);
+    /// assert_eq!(new_root.resolve_text(&interner), "1 + 2 3 + 4");
+    /// ```
+    pub fn relex_range(&self, edit: TextRange, lookaround: impl Fn(S) -> Lookaround) -> Option<RelexRange<S, D>> {
+        let range = self.text_range();
+        assert!(range.contains_range(edit), "Bad edit: range {range:?} edit {edit:?}");
+        let mut first = match self.token_at_offset(edit.start()) {
+            TokenAtOffset::None => return None,
+            TokenAtOffset::Single(token) | TokenAtOffset::Between(token, _) => token,
+        };
+        let mut last = match self.token_at_offset(edit.end()) {
+            TokenAtOffset::None => return None,
+            TokenAtOffset::Single(token) | TokenAtOffset::Between(_, token) => token,
+        };
+        let in_subtree = |token: &SyntaxToken<S, D>| range.contains_range(token.text_range());
+        // The positions of the tokens before and after a token at `position` that are re-lexed with it.
+        let bounds = |position: i64, token: &SyntaxToken<S, D>| {
+            let Lookaround { behind, ahead } = lookaround(token.kind());
+            (position - i64::from(behind), position + i64::from(ahead))
+        };
+
+        // Positions are counted from the initial `first` token, `start` and `end` are the positions of `first` and
+        // `last`, and `needed_start` and `needed_end` the positions required by the tokens in between.
+        let (mut start, mut end) = (0, 0);
+        let (mut needed_start, mut needed_end) = bounds(0, &first);
+        let mut token = first.clone();
+        while token != last {
+            token = token.next_token().expect("`last` comes after `first`").clone();
+            end += 1;
+            let (behind, ahead) = bounds(end, &token);
+            needed_start = needed_start.min(behind);
+            needed_end = needed_end.max(ahead);
+        }
+        loop {
+            let (position, token) = if needed_start < start {
+                match first.prev_token().filter(|&token| in_subtree(token)) {
+                    Some(prev) => {
+                        first = prev.clone();
+                        start -= 1;
+                        (start, &first)
+                    }
+                    None => {
+                        needed_start = start;
+                        continue;
+                    }
+                }
+            } else if needed_end > end {
+                match last.next_token().filter(|&token| in_subtree(token)) {
+                    Some(next) => {
+                        last = next.clone();
+                        end += 1;
+                        (end, &last)
+                    }
+                    None => {
+                        needed_end = end;
+                        continue;
+                    }
+                }
+            } else {
+                break;
+            };
+            let (behind, ahead) = bounds(position, token);
+            needed_start = needed_start.min(behind);
+            needed_end = needed_end.max(ahead);
+        }
+        Some(RelexRange { first, last, edit })
+    }
+}
+
+impl<S: Syntax, D> RelexRange<S, D> {
+    /// The first token that has to be re-lexed.
+    #[inline]
+    pub fn first_token(&self) -> &SyntaxToken<S, D> {
+        &self.first
+    }
+
+    /// The last token that has to be re-lexed.
+    #[inline]
+    pub fn last_token(&self) -> &SyntaxToken<S, D> {
+        &self.last
+    }
+
+    /// The range of text covered by the tokens that have to be re-lexed, before the edit.
+    #[inline]
+    pub fn text_range(&self) -> TextRange {
+        TextRange::new(self.first.text_range().start(), self.last.text_range().end())
+    }
+
+    /// Returns an iterator over the tokens that have to be re-lexed, in order.
+    pub fn tokens(&self) -> impl Iterator<Item = &SyntaxToken<S, D>> {
+        let mut next = Some(&self.first);
+        std::iter::from_fn(move || {
+            let token = next?;
+            next = if *token == self.last {
+                None
+            } else {
+                token.next_token()
+            };
+            Some(token)
+        })
+    }
+
+    /// The text that has to be re-lexed: the text of the tokens in this range, with `edit` applied.
+    ///
+    /// ## Panics
+    /// If the range of `edit` is not the one this range was computed for, or if `resolver` cannot resolve the text of
+    /// a token.
+    pub fn text<R>(&self, edit: &TextEdit, resolver: &R) -> String
+    where
+        R: Resolver<TokenKey> + ?Sized,
+    {
+        assert_eq!(edit.range, self.edit, "edit does not match the range it was computed for");
+        let mut text = String::with_capacity(usize::from(self.text_range().len()) + edit.insert.len());
+        for token in self.tokens() {
+            text.push_str(token.resolve_text(resolver));
+        }
+        let start = self.text_range().start();
+        TextEdit::replace(self.edit - start, edit.insert.as_str()).apply(&mut text);
+        text
+    }
+
+    /// Returns a green tree, equal to the green tree these tokens belong to, except that they are replaced by
+    /// `tokens`, which are created with [`GreenToken::new_detached`] using `interner`.
+    ///
+    /// Returns `None` if the old tokens are not consecutive children of the same node, in which case the tokens
+    /// cannot be replaced without changing the structure of the tree, and the smallest node that contains them has
+    /// to be parsed again instead. The complexity of the operation is proportional to the depth of the tree.
+    pub fn splice<I>(&self, tokens: &[(S, &str)], interner: &mut I) -> Option<GreenNode>
+    where
+        I: Interner<TokenKey> + ?Sized,
+    {
+        let parent = self.first.parent();
+        let start = self.first.index() as usize;
+        let mut end = start;
+        for sibling in self.first.siblings_with_tokens(Direction::Next) {
+            match sibling {
+                NodeOrToken::Token(token) if *token == self.last => break,
+                NodeOrToken::Token(_) => end += 1,
+                NodeOrToken::Node(_) => return None,
+            }
+        }
+        if self.last.parent() != parent || self.last.index() as usize != end {
+            return None;
+        }
+
+        let new_tokens = tokens
+            .iter()
+            .map(|&(kind, text)| NodeOrToken::Token(GreenToken::new_detached(kind, text, interner)));
+        let old = parent.green().children();
+        let children: Vec<_> = old
+            .clone()
+            .take(start)
+            .map(|child| child.cloned())
+            .chain(new_tokens)
+            .chain(old.skip(end + 1).map(|child| child.cloned()))
+            .collect();
+        Some(parent.replace_with(parent.green().rebuild(parent.green().kind(), children)))
+    }
+}
+
+impl<S: Syntax, D> fmt::Debug for RelexRange<S, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RelexRange")
+            .field("first", &self.first)
+            .field("last", &self.last)
+            .field("edit", &self.edit)
+            .finish()
+    }
+}
@@ -10,15 +10,17 @@ use std::{
     sync::Arc as StdArc,
 };
 
+use fxhash::FxHashMap;
 use text_size::{TextRange, TextSize};
 
 use crate::{
     green::GreenNode,
-    interning::{Resolver, TokenKey},
+    interning::{new_interner, Interner, Resolver, TokenKey},
     syntax::*,
+    text::{ColumnUnit, LineCol, LineIndex},
     traversal::*,
     util::*,
-    RawSyntaxKind, Syntax,
+    KindClass, RawSyntaxKind, Syntax,
 };
 
 /// Syntax tree node that is guaranteed to belong to a tree that contains an associated
@@ -208,6 +210,102 @@ impl<S: Syntax, D> ResolvedNode<S, D> {
     pub fn text(&self) -> SyntaxText<'_, '_, dyn Resolver<TokenKey>, S, D> {
         SyntaxText::new(self, &**self.resolver())
     }
+
+    /// Uses the resolver associated with this tree to return an iterator over the text of every token in the
+    /// subtree of this node, together with its range. See [`SyntaxNode::text_chunks`].
+    #[inline]
+    pub fn text_chunks(&self) -> TextChunks<'_, '_, S, dyn Resolver<TokenKey>> {
+        self.syntax().text_chunks(&**self.resolver())
+    }
+}
+
+impl<S: Syntax, D> ResolvedNode<S, D> {
+    /// Uses the resolver associated with this tree to render the subtree rooted in this node as configured by
+    /// `options`. See [`SyntaxNode::debug_with`].
+    pub fn debug_tree(&self, options: &DebugOptions<S>) -> String {
+        self.syntax.debug_with(&**self.resolver(), options)
+    }
+
+    /// Returns a new tree for the subtree rooted at this node, which shares the resolver of this tree.
+    ///
+    /// Unlike [`compact`](ResolvedNode::compact), this does not copy any text, and the green subtree is shared with
+    /// this tree. This makes it cheap to hand out self-contained views of parts of a tree. The new root starts at
+    /// offset `0`, and data attached to the nodes of this tree is not copied.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// use cstree::syntax::ResolvedNode;
+    ///
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// builder.token(Int, "1");
+    /// builder.static_token(Plus);
+    /// builder.start_node(Operation);
+    /// builder.token(Int, "2");
+    /// builder.finish_node();
+    /// builder.finish_node();
+    /// let (tree, cache) = builder.finish();
+    /// let interner = cache.unwrap().into_interner().unwrap();
+    /// let root: ResolvedNode<MySyntax> = SyntaxNode::new_root_with_resolver(tree, interner);
+    ///
+    /// let operation = root.first_child().unwrap().freeze();
+    /// drop(root);
+    /// assert_eq!(operation.text(), "2");
+    /// assert_eq!(operation.text_range().start(), 0.into());
+    /// assert!(operation.parent().is_none());
+    /// ```
+    pub fn freeze(&self) -> ResolvedNode<S, D> {
+        self.syntax.freeze_with_resolver(StdArc::clone(self.resolver()))
+    }
+
+    /// Returns a new tree for the subtree rooted at this node, with its own resolver that contains only the texts of
+    /// the tokens in the subtree.
+    ///
+    /// Trees that are built with a shared interner keep all of its texts alive, even if they only need a few of them.
+    /// Compacting a tree that is kept around for a long time detaches it from the shared interner, which can then be
+    /// dropped. Since texts get new keys in the new resolver, all tokens of the subtree are re-created. Data attached
+    /// to the nodes of this tree is not copied.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// use cstree::syntax::ResolvedNode;
+    ///
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// builder.start_node(Operation);
+    /// builder.token(Int, "1");
+    /// builder.static_token(Plus);
+    /// builder.token(Int, "2");
+    /// builder.finish_node();
+    /// builder.finish_node();
+    /// let (tree, cache) = builder.finish();
+    /// let interner = cache.unwrap().into_interner().unwrap();
+    /// let root: ResolvedNode<MySyntax> = SyntaxNode::new_root_with_resolver(tree, interner);
+    ///
+    /// let operation = root.first_child().unwrap().compact();
+    /// drop(root);
+    /// assert_eq!(operation.kind(), Operation);
+    /// assert_eq!(operation.text(), "1+2");
+    /// ```
+    pub fn compact(&self) -> ResolvedNode<S, D> {
+        let resolver = self.resolver();
+        let mut interner = new_interner();
+        let mut keys = FxHashMap::default();
+        let green = self
+            .green()
+            .map_tokens(&mut |token| {
+                token.text_key()?;
+                Some(token.map_keys(|key| {
+                    *keys
+                        .entry(key)
+                        .or_insert_with(|| interner.get_or_intern(resolver.resolve(key)))
+                }))
+            })
+            .unwrap_or_else(|| self.green().clone());
+        SyntaxNode::new_root_with_resolver(green, interner)
+    }
 }
 
 impl<S: Syntax, D> fmt::Debug for ResolvedNode<S, D> {
@@ -231,6 +329,15 @@ impl<S: Syntax, D> ResolvedToken<S, D> {
             .or_else(|| self.green().text(&**self.resolver()))
             .unwrap()
     }
+
+    /// Uses the resolver associated with this tree to return the cooked value of this token. See
+    /// [`SyntaxToken::cooked_text`].
+    #[cfg(feature = "cooked_values")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "cooked_values")))]
+    #[inline]
+    pub fn cooked_text(&self) -> &str {
+        self.syntax().cooked_text(&**self.resolver())
+    }
 }
 
 impl<S: Syntax, D> fmt::Debug for ResolvedToken<S, D> {
@@ -457,6 +564,22 @@ impl<S: Syntax, D> ResolvedNode<S, D> {
         forward_token!(self.syntax.last_token())
     }
 
+    /// Returns the first token after the subtree of this node, in document order.
+    /// This token may belong to a sibling of this node or one of its ancestors, but will always be
+    /// further right in the tree.
+    #[inline]
+    pub fn next_token(&self) -> Option<&ResolvedToken<S, D>> {
+        forward_token!(self.syntax.next_token())
+    }
+
+    /// Returns the last token before the subtree of this node, in document order.
+    /// This token may belong to a sibling of this node or one of its ancestors, but will always be
+    /// further left in the tree.
+    #[inline]
+    pub fn prev_token(&self) -> Option<&ResolvedToken<S, D>> {
+        forward_token!(self.syntax.prev_token())
+    }
+
     /// Returns an iterator over all sibling nodes of this node in the given `direction`, i.e. all of
     /// this node's parent's child nodes (!) from this node on to the left or the right. The first
     /// item in the iterator will always be this node.
@@ -489,6 +612,43 @@ impl<S: Syntax, D> ResolvedNode<S, D> {
         forward_as_elem!(self.syntax.descendants_with_tokens())
     }
 
+    /// Returns an iterator over all tokens in the subtree starting at this node, in order.
+    #[inline]
+    pub fn tokens(&self) -> impl Iterator<Item = &ResolvedToken<S, D>> {
+        forward_token!(self.syntax.tokens())
+    }
+
+    /// Returns an iterator over all error tokens in the subtree starting at this node, in order.
+    #[inline]
+    pub fn error_tokens(&self) -> impl Iterator<Item = &ResolvedToken<S, D>> {
+        forward_token!(self.syntax.error_tokens())
+    }
+
+    /// Returns an iterator over all tokens in the subtree starting at this node whose kind has the given class, in
+    /// order.
+    #[inline]
+    pub fn tokens_of_class(&self, class: KindClass) -> impl Iterator<Item = &ResolvedToken<S, D>> {
+        forward_token!(self.syntax.tokens_of_class(class))
+    }
+
+    /// Returns an iterator over all tokens in the subtree starting at this node whose text range
+    /// overlaps with `range`, in order.
+    ///
+    /// See [`SyntaxNode::tokens_in_range`] for details.
+    #[inline]
+    pub fn tokens_in_range(&self, range: TextRange) -> impl Iterator<Item = &ResolvedToken<S, D>> {
+        forward_token!(self.syntax.tokens_in_range(range))
+    }
+
+    /// Returns an iterator over all elements in the subtree starting at this node (including this node) whose text
+    /// range overlaps with `range`, in order.
+    ///
+    /// See [`SyntaxNode::descendants_intersecting`] for details.
+    #[inline]
+    pub fn descendants_intersecting(&self, range: TextRange) -> impl Iterator<Item = ResolvedElementRef<'_, S, D>> {
+        forward_as_elem!(self.syntax.descendants_intersecting(range))
+    }
+
     /// Traverse the subtree rooted at the current node (including the current
     /// node) in preorder, excluding tokens.
     #[inline(always)]
@@ -507,6 +667,20 @@ impl<S: Syntax, D> ResolvedNode<S, D> {
             .map(|event| event.map(|elem| unsafe { ResolvedElementRef::coerce_ref(elem) }))
     }
 
+    /// Traverse the subtree rooted at the current node (including the current node) in preorder, including tokens,
+    /// but only visiting elements whose text range overlaps with `range`.
+    ///
+    /// See [`SyntaxNode::preorder_with_tokens_in_range`] for details.
+    #[inline]
+    pub fn preorder_with_tokens_in_range(
+        &self,
+        range: TextRange,
+    ) -> impl Iterator<Item = WalkEvent<ResolvedElementRef<'_, S, D>>> {
+        self.syntax
+            .preorder_with_tokens_in_range(range)
+            .map(|event| event.map(|elem| unsafe { ResolvedElementRef::coerce_ref(elem) }))
+    }
+
     /// Find a token in the subtree corresponding to this node, which covers the offset.
     /// Precondition: offset must be withing node's range.
     pub fn token_at_offset(&self, offset: TextSize) -> TokenAtOffset<ResolvedToken<S, D>> {
@@ -515,6 +689,21 @@ impl<S: Syntax, D> ResolvedNode<S, D> {
             .map(|token| ResolvedToken { syntax: token })
     }
 
+    /// Find the token(s) in the subtree corresponding to this node at the given line and column, with the column
+    /// counted in `unit`.
+    ///
+    /// See [`SyntaxNode::token_at_position`] for details.
+    pub fn token_at_position(
+        &self,
+        line_index: &LineIndex,
+        position: LineCol,
+        unit: ColumnUnit,
+    ) -> TokenAtOffset<ResolvedToken<S, D>> {
+        self.syntax
+            .token_at_position(line_index, position, unit)
+            .map(|token| ResolvedToken { syntax: token })
+    }
+
     /// Return the deepest node or token in the current subtree that fully
     /// contains the range. If the range is empty and is contained in two leaf
     /// nodes, either one can be returned. Precondition: range must be contained
@@ -522,6 +711,15 @@ impl<S: Syntax, D> ResolvedNode<S, D> {
     pub fn covering_element(&self, range: TextRange) -> ResolvedElementRef<'_, S, D> {
         unsafe { ResolvedElementRef::coerce_ref(self.syntax.covering_element(range)) }
     }
+
+    /// Return the deepest node in the current subtree that fully contains the range and has one of the given
+    /// `kinds`. See [`SyntaxNode::covering_node_of_kind`].
+    #[inline]
+    pub fn covering_node_of_kind(&self, range: TextRange, kinds: &[S]) -> Option<&ResolvedNode<S, D>> {
+        self.syntax
+            .covering_node_of_kind(range, kinds)
+            .map(|node| unsafe { Self::coerce_ref(node) })
+    }
 }
 
 impl<S: Syntax, D> ResolvedToken<S, D> {
@@ -601,6 +799,17 @@ impl<S: Syntax, D> ResolvedElement<S, D> {
         }
     }
 
+    /// The range this element covers in the source text, in bytes, relative to the start of `ancestor`.
+    ///
+    /// Returns `None` if `ancestor` is neither this element nor one of its ancestors.
+    #[inline]
+    pub fn range_in(&self, ancestor: &SyntaxNode<S, D>) -> Option<TextRange> {
+        match self {
+            NodeOrToken::Node(it) => it.range_in(ancestor),
+            NodeOrToken::Token(it) => it.range_in(ancestor),
+        }
+    }
+
     /// The internal representation of the kind of this element.
     #[inline]
     pub fn syntax_kind(&self) -> RawSyntaxKind {
@@ -672,6 +881,24 @@ impl<S: Syntax, D> ResolvedElement<S, D> {
             NodeOrToken::Token(it) => it.prev_sibling_or_token(),
         }
     }
+
+    /// Returns the first token after this element, in document order.
+    #[inline]
+    pub fn next_token(&self) -> Option<&ResolvedToken<S, D>> {
+        match self {
+            NodeOrToken::Node(it) => it.next_token(),
+            NodeOrToken::Token(it) => it.next_token(),
+        }
+    }
+
+    /// Returns the last token before this element, in document order.
+    #[inline]
+    pub fn prev_token(&self) -> Option<&ResolvedToken<S, D>> {
+        match self {
+            NodeOrToken::Node(it) => it.prev_token(),
+            NodeOrToken::Token(it) => it.prev_token(),
+        }
+    }
 }
 
 impl<'a, S: Syntax, D> ResolvedElementRef<'a, S, D> {
@@ -684,6 +911,17 @@ impl<'a, S: Syntax, D> ResolvedElementRef<'a, S, D> {
         }
     }
 
+    /// The range this element covers in the source text, in bytes, relative to the start of `ancestor`.
+    ///
+    /// Returns `None` if `ancestor` is neither this element nor one of its ancestors.
+    #[inline]
+    pub fn range_in(&self, ancestor: &SyntaxNode<S, D>) -> Option<TextRange> {
+        match self {
+            NodeOrToken::Node(it) => it.range_in(ancestor),
+            NodeOrToken::Token(it) => it.range_in(ancestor),
+        }
+    }
+
     /// The internal representation of the kind of this element.
     #[inline]
     pub fn syntax_kind(&self) -> RawSyntaxKind {
@@ -755,4 +993,22 @@ impl<'a, S: Syntax, D> ResolvedElementRef<'a, S, D> {
             NodeOrToken::Token(it) => it.prev_sibling_or_token(),
         }
     }
+
+    /// Returns the first token after this element, in document order.
+    #[inline]
+    pub fn next_token(&self) -> Option<&'a ResolvedToken<S, D>> {
+        match self {
+            NodeOrToken::Node(it) => it.next_token(),
+            NodeOrToken::Token(it) => it.next_token(),
+        }
+    }
+
+    /// Returns the last token before this element, in document order.
+    #[inline]
+    pub fn prev_token(&self) -> Option<&'a ResolvedToken<S, D>> {
+        match self {
+            NodeOrToken::Node(it) => it.prev_token(),
+            NodeOrToken::Token(it) => it.prev_token(),
+        }
+    }
 }
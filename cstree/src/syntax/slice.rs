@@ -0,0 +1,212 @@
+//! Random access to a range of the children of a node.
+
+use std::{fmt, ops::Range, sync::Arc as StdArc};
+
+use text_size::{TextRange, TextSize};
+
+use super::{node::overlaps_range, *};
+use crate::Syntax;
+
+/// A view of a contiguous range of the children (nodes and tokens) of a [`SyntaxNode`], which allows accessing them
+/// by index and finding them by offset in `O(log n)` time.
+///
+/// Creating the view for a node with [`SyntaxNode::child_slice`] computes the offsets of all of its children once.
+/// Afterwards, the view can be sliced and split without copying, which enables divide-and-conquer algorithms on very
+/// wide nodes, such as binary searching the children by offset or processing chunks of them on different threads.
+/// Indices passed to and returned from the methods of a view are relative to the start of the view.
+///
+/// # Example
+/// ```
+/// # use cstree::testing::*;
+/// use cstree::text::TextRange;
+///
+/// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+/// builder.start_node(Root);
+/// for _ in 0..10 {
+///     builder.token(Int, "12");
+///     builder.static_token(Plus);
+/// }
+/// builder.finish_node();
+/// let (tree, _) = builder.finish();
+/// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+///
+/// let children = root.child_slice();
+/// assert_eq!(children.len(), 20);
+/// assert_eq!(children.index_at_offset(7.into()), Some(4));
+/// let (left, right) = children.split_at(10);
+/// assert_eq!(right.text_range(), TextRange::new(15.into(), 30.into()));
+/// assert_eq!(right.get(1).unwrap().kind(), Plus);
+/// assert_eq!(left.slice(2..4).iter().map(|child| child.kind()).collect::<Vec<_>>(), [Int, Plus]);
+/// ```
+pub struct ChildSlice<'n, S: Syntax, D: 'static = ()> {
+    parent:  &'n SyntaxNode<S, D>,
+    /// The start offsets of all children of `parent`, followed by the end offset of `parent`.
+    offsets: StdArc<[TextSize]>,
+    /// The indices of the children in this view.
+    indices: Range<usize>,
+}
+
+impl<'n, S: Syntax, D> ChildSlice<'n, S, D> {
+    pub(super) fn new(parent: &'n SyntaxNode<S, D>) -> Self {
+        let mut offset = parent.text_range().start();
+        let offsets: StdArc<[TextSize]> = std::iter::once(offset)
+            .chain(parent.green().children().map(|child| {
+                offset += child.text_len();
+                offset
+            }))
+            .collect();
+        let indices = 0..offsets.len() - 1;
+        Self {
+            parent,
+            offsets,
+            indices,
+        }
+    }
+
+    /// The node whose children this view contains.
+    #[inline]
+    pub fn parent(&self) -> &'n SyntaxNode<S, D> {
+        self.parent
+    }
+
+    /// The number of children in this view.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    /// Returns `true` if this view does not contain any children.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    /// The index of the first child in this view among all children of the [`parent`](ChildSlice::parent).
+    #[inline]
+    pub fn start_index(&self) -> usize {
+        self.indices.start
+    }
+
+    /// The range of text covered by the children in this view.
+    #[inline]
+    pub fn text_range(&self) -> TextRange {
+        TextRange::new(self.offsets[self.indices.start], self.offsets[self.indices.end])
+    }
+
+    /// Returns the child at `index` in this view, or `None` if `index` is out of bounds.
+    #[inline]
+    pub fn get(&self, index: usize) -> Option<SyntaxElementRef<'n, S, D>> {
+        if index >= self.len() {
+            return None;
+        }
+        let index = self.indices.start + index;
+        let child = self.parent.green().children().nth(index)?;
+        Some(self.parent.get_or_add_element(child, index, self.offsets[index]))
+    }
+
+    /// The range of text covered by the child at `index` in this view, or `None` if `index` is out of bounds.
+    #[inline]
+    pub fn child_range(&self, index: usize) -> Option<TextRange> {
+        (index < self.len()).then(|| self.range_of(self.indices.start + index))
+    }
+
+    /// Returns a view of the children in the given `range` of indices of this view.
+    ///
+    /// ## Panics
+    /// If `range` is out of bounds.
+    pub fn slice(&self, range: Range<usize>) -> Self {
+        assert!(
+            range.start <= range.end && range.end <= self.len(),
+            "Bad range: {range:?} for a slice of {} children",
+            self.len()
+        );
+        self.with_indices(self.indices.start + range.start..self.indices.start + range.end)
+    }
+
+    /// Splits this view into the children before `mid` and the children starting at `mid`.
+    ///
+    /// ## Panics
+    /// If `mid > self.len()`.
+    pub fn split_at(&self, mid: usize) -> (Self, Self) {
+        (self.slice(0..mid), self.slice(mid..self.len()))
+    }
+
+    /// Returns an iterator over views of `chunk_size` consecutive children of this view each, in order. The last
+    /// view contains fewer children if the length of this view is not a multiple of `chunk_size`.
+    ///
+    /// ## Panics
+    /// If `chunk_size` is 0.
+    pub fn chunks(&self, chunk_size: usize) -> impl Iterator<Item = ChildSlice<'n, S, D>> + '_ {
+        assert!(chunk_size > 0, "chunk size must be greater than 0");
+        self.indices
+            .clone()
+            .step_by(chunk_size)
+            .map(move |start| self.with_indices(start..self.indices.end.min(start + chunk_size)))
+    }
+
+    /// Returns the index of the child in this view that contains `offset`, or `None` if no child does. Children
+    /// without text never contain an offset.
+    pub fn index_at_offset(&self, offset: TextSize) -> Option<usize> {
+        let index = self.partition_point(|range| range.end() <= offset);
+        (index < self.len() && self.range_of(self.indices.start + index).contains(offset)).then_some(index)
+    }
+
+    /// Returns a view of the children of this view that overlap `range`, i.e., that share some text with `range` or
+    /// are empty and lie within `range`.
+    pub fn in_range(&self, range: TextRange) -> Self {
+        let start = self.partition_point(|child| child.start() < range.start() && child.end() <= range.start());
+        let end = self.partition_point(|child| !(child.start() >= range.end() && child.end() > range.end()));
+        let end = end.max(start);
+        debug_assert!((start..end).all(|index| overlaps_range(self.range_of(self.indices.start + index), range)));
+        self.slice(start..end)
+    }
+
+    /// Returns an iterator over the children in this view, in order.
+    #[inline]
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = SyntaxElementRef<'n, S, D>> + ExactSizeIterator + '_ {
+        (0..self.len()).map(move |index| self.get(index).unwrap())
+    }
+
+    fn with_indices(&self, indices: Range<usize>) -> Self {
+        Self {
+            parent: self.parent,
+            offsets: StdArc::clone(&self.offsets),
+            indices,
+        }
+    }
+
+    #[inline]
+    fn range_of(&self, index: usize) -> TextRange {
+        TextRange::new(self.offsets[index], self.offsets[index + 1])
+    }
+
+    /// Returns the index of the first child in this view for which `pred` returns `false`, where `pred` must return
+    /// `true` for all children before it.
+    fn partition_point(&self, mut pred: impl FnMut(TextRange) -> bool) -> usize {
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if pred(self.range_of(self.indices.start + mid)) {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        low
+    }
+}
+
+impl<S: Syntax, D> Clone for ChildSlice<'_, S, D> {
+    fn clone(&self) -> Self {
+        self.with_indices(self.indices.clone())
+    }
+}
+
+impl<S: Syntax, D> fmt::Debug for ChildSlice<'_, S, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChildSlice")
+            .field("parent", self.parent)
+            .field("indices", &self.indices)
+            .finish()
+    }
+}
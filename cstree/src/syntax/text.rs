@@ -2,10 +2,19 @@
 
 use std::fmt;
 
+#[cfg(feature = "regex")]
+use regex_automata::{
+    dfa::{regex::Regex, Automaton},
+    util::start,
+    Anchored,
+};
+use smallvec::{smallvec, SmallVec};
+
 use crate::{
     interning::{Resolver, TokenKey},
+    line_index::LineIndexBuilder,
     syntax::{SyntaxNode, SyntaxToken},
-    text::{TextRange, TextSize},
+    text::{LineIndex, TextRange, TextSize},
     Syntax,
 };
 
@@ -84,6 +93,118 @@ impl<'n, 'i, I: Resolver<TokenKey> + ?Sized, S: Syntax, D> SyntaxText<'n, 'i, I,
         found(res)
     }
 
+    /// Returns `true` if `pattern` appears anywhere in this text, including across token boundaries.
+    pub fn contains_str(&self, pattern: &str) -> bool {
+        self.find_str(pattern).is_some()
+    }
+
+    /// If `self.contains_str(pattern)`, returns `Some(pos)`, where `pos` is the byte position of the
+    /// first appearance of `pattern`. Otherwise, returns `None`.
+    ///
+    /// Like [`find_char`](SyntaxText::find_char), this works on the token chunks directly and finds
+    /// occurrences that span multiple tokens without building the full text.
+    pub fn find_str(&self, pattern: &str) -> Option<TextSize> {
+        found(self.try_for_each_match(pattern, |range| Err(range.start())))
+    }
+
+    /// Applies the given function to the range of every non-overlapping occurrence of `pattern` in
+    /// this text, in order, as long as `f` completes successfully.
+    ///
+    /// The ranges passed to `f` are relative to the start of this text, so they can be passed to
+    /// [`slice`](SyntaxText::slice) directly. Matches may span any number of tokens. To get to the
+    /// tokens covering a match, offset the range by the start of the text's node, for example for
+    /// use with [`SyntaxNode::covering_element`].
+    ///
+    /// Like [`str::match_indices`], an empty `pattern` matches at every `char` boundary.
+    ///
+    /// If `f` returns `Err`, this method returns immediately and will not look for further matches.
+    ///
+    /// # Example
+    /// ```
+    /// # use cstree::testing::*;
+    /// # use cstree::text::TextRange;
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// builder.token(Identifier, "foo");
+    /// builder.token(Plus, "+");
+    /// builder.token(Identifier, "bar");
+    /// builder.finish_node();
+    /// let (root, cache) = builder.finish();
+    /// let resolver = cache.unwrap().into_interner().unwrap();
+    /// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(root);
+    ///
+    /// let text = root.resolve_text(&resolver);
+    /// let mut matches = Vec::new();
+    /// text.for_each_match("o+b", |range| matches.push(range));
+    /// assert_eq!(matches, [TextRange::new(2.into(), 5.into())]);
+    /// // The match starts in the first identifier and ends in the second one
+    /// let covering = root.covering_element(matches[0]);
+    /// assert_eq!(covering.as_node(), Some(&&root));
+    /// ```
+    pub fn try_for_each_match<F, E>(&self, pattern: &str, mut f: F) -> Result<(), E>
+    where
+        F: FnMut(TextRange) -> Result<(), E>,
+    {
+        if pattern.is_empty() {
+            let mut offset: TextSize = 0.into();
+            self.try_for_each_chunk(|chunk| {
+                for (pos, _) in chunk.char_indices() {
+                    f(TextRange::empty(offset + TextSize::from(pos as u32)))?;
+                }
+                offset += TextSize::of(chunk);
+                Ok(())
+            })?;
+            return f(TextRange::empty(self.len()));
+        }
+
+        // Knuth-Morris-Pratt, so we never have to look back into previous chunks.
+        let pattern_len = TextSize::of(pattern);
+        let pattern = pattern.as_bytes();
+        let failure = failure_table(pattern);
+        let mut matched = 0;
+        let mut offset: TextSize = 0.into();
+        self.try_for_each_chunk(|chunk| {
+            for (pos, &byte) in chunk.as_bytes().iter().enumerate() {
+                while matched > 0 && pattern[matched] != byte {
+                    matched = failure[matched - 1];
+                }
+                if pattern[matched] == byte {
+                    matched += 1;
+                }
+                if matched == pattern.len() {
+                    let end = offset + TextSize::from(pos as u32 + 1);
+                    f(TextRange::new(end - pattern_len, end))?;
+                    matched = 0;
+                }
+            }
+            offset += TextSize::of(chunk);
+            Ok(())
+        })
+    }
+
+    /// Applies the given function to the range of every non-overlapping occurrence of `pattern` in
+    /// this text, in order.
+    ///
+    /// See [`try_for_each_match`](SyntaxText::try_for_each_match) for details.
+    pub fn for_each_match<F: FnMut(TextRange)>(&self, pattern: &str, mut f: F) {
+        enum Void {}
+        if let Err(void) = self.try_for_each_match(pattern, |range| {
+            f(range);
+            Ok::<(), Void>(())
+        }) {
+            match void {}
+        }
+    }
+
+    /// Computes a [`LineIndex`] for this text without collecting it into a `String`.
+    ///
+    /// Offsets in the returned index are relative to the start of this text.
+    pub fn line_index(&self) -> LineIndex {
+        let mut builder = LineIndexBuilder::default();
+        self.for_each_chunk(|chunk| builder.push(chunk));
+        builder.finish()
+    }
+
     /// If `offset < self.len()`, returns `Some(c)`, where `c` is the first `char` at or after
     /// `offset` (in bytes). Otherwise, returns `None`.
     pub fn char_at(&self, offset: TextSize) -> Option<char> {
@@ -201,11 +322,303 @@ impl<'n, 'i, I: Resolver<TokenKey> + ?Sized, S: Syntax, D> SyntaxText<'n, 'i, I,
     }
 }
 
+#[cfg(feature = "regex")]
+#[cfg_attr(doc_cfg, doc(cfg(feature = "regex")))]
+impl<'n, 'i, I: Resolver<TokenKey> + ?Sized, S: Syntax, D> SyntaxText<'n, 'i, I, S, D> {
+    /// Returns the range of the first (leftmost-first) match of `regex` in this text, if any.
+    ///
+    /// The returned range is relative to the start of this text.
+    /// See [`try_for_each_regex_match`](SyntaxText::try_for_each_regex_match) for details.
+    pub fn find_regex<A: Automaton>(&self, regex: &Regex<A>) -> Option<TextRange> {
+        found(self.try_for_each_regex_match(regex, Err))
+    }
+
+    /// Returns `true` if `regex` matches anywhere in this text.
+    pub fn is_regex_match<A: Automaton>(&self, regex: &Regex<A>) -> bool {
+        self.find_regex(regex).is_some()
+    }
+
+    /// Applies the given function to the range of every non-overlapping match of `regex` in this
+    /// text, in order, as long as `f` completes successfully.
+    ///
+    /// Matches follow the same semantics as [`Regex::find_iter`] on the full text, but the search
+    /// runs directly over the token chunks, so matches may span any number of tokens without the
+    /// text ever being collected into a `String`. The ranges passed to `f` are relative to the
+    /// start of this text.
+    ///
+    /// If `f` returns `Err`, this method returns immediately and will not look for further matches.
+    ///
+    /// # Panics
+    /// If the DFAs of `regex` were built with quit bytes (for example, to heuristically support
+    /// Unicode word boundaries) and the search encounters one of them.
+    ///
+    /// # Example
+    /// ```
+    /// # use cstree::testing::*;
+    /// # use cstree::text::{regex_automata::dfa::regex::Regex, TextRange};
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// builder.token(Int, "12");
+    /// builder.token(Plus, "+");
+    /// builder.token(Int, "3");
+    /// builder.finish_node();
+    /// let (root, cache) = builder.finish();
+    /// let resolver = cache.unwrap().into_interner().unwrap();
+    /// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(root);
+    ///
+    /// let regex = Regex::new(r"\d\+\d").unwrap();
+    /// let text = root.resolve_text(&resolver);
+    /// assert_eq!(
+    ///     text.find_regex(&regex),
+    ///     Some(TextRange::new(1.into(), 4.into()))
+    /// );
+    /// ```
+    pub fn try_for_each_regex_match<A, F, E>(&self, regex: &Regex<A>, mut f: F) -> Result<(), E>
+    where
+        A: Automaton,
+        F: FnMut(TextRange) -> Result<(), E>,
+    {
+        let mut at: TextSize = 0.into();
+        // The chunk containing `at` (possibly at its end). This is only `None` if the text is empty.
+        let mut chunk = self.first_chunk();
+        let mut last_end = None;
+        loop {
+            let Some((end, end_chunk)) = self.regex_match_end(regex.forward(), at, chunk) else {
+                return Ok(());
+            };
+            let start = self.regex_match_start(regex.reverse(), at, end, end_chunk);
+            let range = TextRange::new(start, end);
+            // Like the `regex` crate, skip empty matches directly after a previous match.
+            if !(range.is_empty() && last_end == Some(end)) {
+                f(range)?;
+            }
+            last_end = Some(end);
+            at = end;
+            chunk = end_chunk;
+            if range.is_empty() {
+                // Step over the next character to not find the same empty match again.
+                let Some(mut c) = chunk else {
+                    return Ok(());
+                };
+                if at == c.range.end() {
+                    match self.next_chunk(c) {
+                        Some(next) => c = next,
+                        None => return Ok(()),
+                    }
+                }
+                let rest = &self.chunk_text(c)[usize::from(at - c.range.start())..];
+                at += TextSize::of(rest.chars().next().unwrap());
+                chunk = Some(c);
+            }
+        }
+    }
+
+    /// Applies the given function to the range of every non-overlapping match of `regex` in this
+    /// text, in order.
+    ///
+    /// See [`try_for_each_regex_match`](SyntaxText::try_for_each_regex_match) for details.
+    pub fn for_each_regex_match<A: Automaton, F: FnMut(TextRange)>(&self, regex: &Regex<A>, mut f: F) {
+        enum Void {}
+        if let Err(void) = self.try_for_each_regex_match(regex, |range| {
+            f(range);
+            Ok::<(), Void>(())
+        }) {
+            match void {}
+        }
+    }
+
+    /// Runs the forward DFA from `at` to find the end of the leftmost-first match.
+    fn regex_match_end<A: Automaton>(
+        &self,
+        dfa: &A,
+        at: TextSize,
+        chunk: Option<Chunk<'n, S, D>>,
+    ) -> Option<(TextSize, Option<Chunk<'n, S, D>>)> {
+        let look_behind = chunk.and_then(|c| self.byte_before(c, at));
+        let config = start::Config::new().anchored(Anchored::No).look_behind(look_behind);
+        let mut state = dfa.start_state(&config).expect("failed to compute regex start state");
+        let mut end = None;
+        let mut last = chunk;
+        let mut current = chunk;
+        while let Some(c) = current {
+            let skip = usize::from(at.max(c.range.start()) - c.range.start());
+            for (pos, &byte) in self.chunk_text(c).as_bytes().iter().enumerate().skip(skip) {
+                state = dfa.next_state(state, byte);
+                if dfa.is_special_state(state) {
+                    // Matches are reported with a delay of one byte.
+                    if dfa.is_match_state(state) {
+                        end = Some((c.range.start() + TextSize::from(pos as u32), Some(c)));
+                    } else if dfa.is_dead_state(state) {
+                        return end;
+                    } else if dfa.is_quit_state(state) {
+                        panic!("regex search encountered quit byte {byte:#x}");
+                    }
+                }
+            }
+            last = current;
+            current = self.next_chunk(c);
+        }
+        state = dfa.next_eoi_state(state);
+        if dfa.is_match_state(state) {
+            end = Some((self.len(), last));
+        }
+        end
+    }
+
+    /// Runs the reverse DFA backwards from `end` (but not past `at`) to find the start of the match.
+    fn regex_match_start<A: Automaton>(
+        &self,
+        dfa: &A,
+        at: TextSize,
+        end: TextSize,
+        chunk: Option<Chunk<'n, S, D>>,
+    ) -> TextSize {
+        let look_behind = chunk.and_then(|c| self.byte_at(c, end));
+        let config = start::Config::new().anchored(Anchored::Yes).look_behind(look_behind);
+        let mut state = dfa.start_state(&config).expect("failed to compute regex start state");
+        let mut start = None;
+        let mut current = chunk;
+        let mut hi = end;
+        while let Some(c) = current {
+            let lo = at.max(c.range.start());
+            let text = self.chunk_text(c).as_bytes();
+            for pos in (usize::from(lo - c.range.start())..usize::from(hi - c.range.start())).rev() {
+                state = dfa.next_state(state, text[pos]);
+                if dfa.is_special_state(state) {
+                    if dfa.is_match_state(state) {
+                        start = Some(c.range.start() + TextSize::from(pos as u32 + 1));
+                    } else if dfa.is_dead_state(state) {
+                        return start.expect("regex matched forwards, but not in reverse");
+                    } else if dfa.is_quit_state(state) {
+                        panic!("regex search encountered quit byte {:#x}", text[pos]);
+                    }
+                }
+            }
+            if c.range.start() <= at {
+                break;
+            }
+            hi = c.range.start();
+            current = self.prev_chunk(c);
+        }
+        state = match current.and_then(|c| self.byte_before(c, at)) {
+            Some(byte) => dfa.next_state(state, byte),
+            None => dfa.next_eoi_state(state),
+        };
+        if dfa.is_match_state(state) {
+            start = Some(at);
+        }
+        start.expect("regex matched forwards, but not in reverse")
+    }
+
+    fn first_chunk(&self) -> Option<Chunk<'n, S, D>> {
+        self.node
+            .descendants_with_tokens()
+            .filter_map(|element| element.into_token())
+            .find_map(|token| {
+                let range = self
+                    .range
+                    .intersect(token.text_range())
+                    .filter(|range| !range.is_empty())?;
+                let range = range - self.range.start();
+                Some(Chunk { token, range })
+            })
+    }
+
+    fn next_chunk(&self, chunk: Chunk<'n, S, D>) -> Option<Chunk<'n, S, D>> {
+        let mut token = chunk.token;
+        loop {
+            token = token.next_token()?;
+            let token_range = token.text_range();
+            if token_range.start() >= self.range.end() {
+                return None;
+            }
+            if let Some(range) = self.range.intersect(token_range).filter(|range| !range.is_empty()) {
+                let range = range - self.range.start();
+                return Some(Chunk { token, range });
+            }
+        }
+    }
+
+    fn prev_chunk(&self, chunk: Chunk<'n, S, D>) -> Option<Chunk<'n, S, D>> {
+        let mut token = chunk.token;
+        loop {
+            token = token.prev_token()?;
+            let token_range = token.text_range();
+            if token_range.end() <= self.range.start() {
+                return None;
+            }
+            if let Some(range) = self.range.intersect(token_range).filter(|range| !range.is_empty()) {
+                let range = range - self.range.start();
+                return Some(Chunk { token, range });
+            }
+        }
+    }
+
+    fn chunk_text(&self, chunk: Chunk<'n, S, D>) -> &'i str {
+        let range = chunk.range + self.range.start() - chunk.token.text_range().start();
+        &chunk.token.resolve_text(self.resolver)[range]
+    }
+
+    /// The byte at `offset`, given the chunk that contains `offset`.
+    fn byte_at(&self, chunk: Chunk<'n, S, D>, offset: TextSize) -> Option<u8> {
+        if offset < chunk.range.end() {
+            Some(self.chunk_text(chunk).as_bytes()[usize::from(offset - chunk.range.start())])
+        } else {
+            let next = self.next_chunk(chunk)?;
+            Some(self.chunk_text(next).as_bytes()[0])
+        }
+    }
+
+    /// The byte before `offset`, given the chunk that contains `offset`.
+    fn byte_before(&self, chunk: Chunk<'n, S, D>, offset: TextSize) -> Option<u8> {
+        if offset > chunk.range.start() {
+            Some(self.chunk_text(chunk).as_bytes()[usize::from(offset - chunk.range.start()) - 1])
+        } else {
+            let prev = self.prev_chunk(chunk)?;
+            self.chunk_text(prev).as_bytes().last().copied()
+        }
+    }
+}
+
+/// A non-empty part of a [`SyntaxText`] that belongs to a single token.
+#[cfg(feature = "regex")]
+struct Chunk<'n, S: Syntax, D: 'static> {
+    token: &'n SyntaxToken<S, D>,
+    /// The range of the chunk, relative to the start of the text.
+    range: TextRange,
+}
+
+#[cfg(feature = "regex")]
+impl<S: Syntax, D> Clone for Chunk<'_, S, D> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+#[cfg(feature = "regex")]
+impl<S: Syntax, D> Copy for Chunk<'_, S, D> {}
+
 fn found<T>(res: Result<(), T>) -> Option<T> {
-    match res {
-        Ok(()) => None,
-        Err(it) => Some(it),
+    res.err()
+}
+
+/// Computes the longest proper prefix of `pattern[..=i]` that is also a suffix of it, for every `i`.
+///
+/// The table is kept on the stack for patterns of up to 32 bytes, so that searching for short patterns does not
+/// allocate.
+fn failure_table(pattern: &[u8]) -> SmallVec<[usize; 32]> {
+    let mut table = smallvec![0; pattern.len()];
+    let mut len = 0;
+    for i in 1..pattern.len() {
+        while len > 0 && pattern[i] != pattern[len] {
+            len = table[len - 1];
+        }
+        if pattern[i] == pattern[len] {
+            len += 1;
+        }
+        table[i] = len;
     }
+    table
 }
 
 impl<I: Resolver<TokenKey> + ?Sized, S: Syntax, D> fmt::Debug for SyntaxText<'_, '_, I, S, D> {
@@ -451,4 +864,80 @@ mod tests {
         check(&["{", "abc", "}"], &["{", "123", "}", "{"]);
         check(&["{", "abc", "}ab"], &["{", "abc", "}", "ab"]);
     }
+
+    #[test]
+    fn test_find_str() {
+        fn check(chunks: &[&str], pattern: &str) {
+            let (node, resolver) = build_tree(chunks);
+            let text = node.resolve_text(&resolver);
+            let full = text.to_string();
+            let expected: Vec<_> = full
+                .match_indices(pattern)
+                .map(|(pos, m)| TextRange::at((pos as u32).into(), TextSize::of(m)))
+                .collect();
+            let mut actual = Vec::new();
+            text.for_each_match(pattern, |range| actual.push(range));
+            assert_eq!(expected, actual, "`{}` in {:?}", pattern, chunks);
+            assert_eq!(text.find_str(pattern), expected.first().map(|range| range.start()));
+            assert_eq!(text.contains_str(pattern), !expected.is_empty());
+        }
+
+        check(&[""], "");
+        check(&[""], "a");
+        check(&["abc"], "");
+        check(&["hello", "world"], "low");
+        check(&["hel", "lo", "wor", "ld"], "loworl");
+        check(&["aa", "a", "aab"], "aab");
+        check(&["ab", "a", "bab", "ab"], "abab");
+        check(&["{", "abc", "}", "{", "abc", "}"], "}{");
+        check(&["{", "abc", "}", "{", "abc", "}"], "{abc}");
+        check(&["{", "abc", "}"], "abcd");
+        check(&["é", "è", "é"], "èé");
+        // longer than the failure table kept on the stack
+        let long = "abcabcabcabcabcabcabcabcabcabcabcabd";
+        check(&["xabcabcabcabcabcab", "cabcabcabcabcabcabd", "abcabcabcabcabcabcabcabcabcabcabcabd"], long);
+    }
+
+    #[test]
+    fn test_find_str_in_slice() {
+        let (node, resolver) = build_tree(&["ab", "cab", "c"]);
+        let text = node.resolve_text(&resolver);
+        let slice = text.slice(TextSize::from(1)..TextSize::from(5));
+        assert_eq!(slice, "bcab");
+        assert_eq!(slice.find_str("ab"), Some(2.into()));
+        assert_eq!(slice.find_str("abc"), None);
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_find_regex() {
+        use regex_automata::dfa::regex::Regex;
+
+        fn check(chunks: &[&str], pattern: &str) {
+            let (node, resolver) = build_tree(chunks);
+            let regex = Regex::new(pattern).unwrap();
+            let text = node.resolve_text(&resolver);
+            let full = text.to_string();
+            let expected: Vec<_> = regex
+                .find_iter(full.as_bytes())
+                .map(|m| TextRange::new((m.start() as u32).into(), (m.end() as u32).into()))
+                .collect();
+            let mut actual = Vec::new();
+            text.for_each_regex_match(&regex, |range| actual.push(range));
+            assert_eq!(expected, actual, "`{}` in {:?}", pattern, chunks);
+            assert_eq!(text.find_regex(&regex), expected.first().copied());
+        }
+
+        check(&[""], "a*");
+        check(&[""], "a");
+        check(&["b"], "a*");
+        check(&["ab", "aab"], "a*");
+        check(&["hello", "world"], "l+");
+        check(&["hel", "lo", "wor", "ld"], "o.*?l");
+        check(&["foo", " ", "bar", "\n", "baz"], r"(?m)^\w+$");
+        check(&["foo", " ", "bar", "\n", "baz"], r"(?-u:\b)ba");
+        check(&["{", "abc", "}", "{", "abc", "}"], r"\}\{|c");
+        check(&["é", "è", "é"], r"è|\w");
+        check(&["a", "b", "", "c"], "");
+    }
 }
@@ -1,7 +1,6 @@
 use std::{
     fmt,
     hash::{Hash, Hasher},
-    iter,
     sync::Arc as StdArc,
 };
 
@@ -9,14 +8,15 @@ use text_size::{TextRange, TextSize};
 
 use super::*;
 use crate::{
+    ast::AstNode,
     green::{GreenNode, GreenToken},
-    interning::{Resolver, TokenKey},
+    interning::{Interner, Resolver, TokenKey},
     traversal::Direction,
+    utility_types::KindName,
     RawSyntaxKind, Syntax,
 };
 
 /// Syntax tree token.
-#[derive(Debug)]
 pub struct SyntaxToken<S: Syntax, D: 'static = ()> {
     parent: SyntaxNode<S, D>,
     index:  u32,
@@ -41,6 +41,21 @@ impl<S: Syntax, D> Hash for SyntaxToken<S, D> {
     }
 }
 
+/// Without a resolver associated with the tree, the token's text is only written if it is a
+/// [`static_text`](Syntax::static_text). Otherwise, this is the same as [`write_debug`](SyntaxToken::write_debug).
+impl<S: Syntax, D> fmt::Debug for SyntaxToken<S, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(resolver) = self.resolver() {
+            return self.write_debug(&**resolver, f);
+        }
+        write!(f, "{}@{:?}", KindName(self.kind()), self.text_range())?;
+        match self.static_text() {
+            Some(text) => write!(f, " {:?}", text),
+            None => Ok(()),
+        }
+    }
+}
+
 impl<S: Syntax, D> PartialEq for SyntaxToken<S, D> {
     fn eq(&self, other: &SyntaxToken<S, D>) -> bool {
         self.parent == other.parent && self.index == other.index && self.offset == other.offset
@@ -55,7 +70,7 @@ impl<S: Syntax, D> SyntaxToken<S, D> {
     where
         R: Resolver<TokenKey> + ?Sized,
     {
-        write!(target, "{:?}@{:?}", self.kind(), self.text_range())?;
+        write!(target, "{}@{:?}", KindName(self.kind()), self.text_range())?;
         let text = self.resolve_text(resolver);
         if text.len() < 25 {
             return write!(target, " {:?}", text);
@@ -136,6 +151,12 @@ impl<S: Syntax, D> SyntaxToken<S, D> {
         }
     }
 
+    /// The index of this token among the children of its parent.
+    #[inline]
+    pub(super) fn index(&self) -> u32 {
+        self.index
+    }
+
     /// Returns a green tree, equal to the green tree this token
     /// belongs two, except with this token substitute. The complexity
     /// of operation is proportional to the depth of the tree
@@ -152,10 +173,64 @@ impl<S: Syntax, D> SyntaxToken<S, D> {
                 child.cloned()
             }
         });
-        let new_parent = GreenNode::new(parent.syntax_kind(), children);
+        let new_parent = parent.green().rebuild(parent.green().kind(), children);
         parent.replace_with(new_parent)
     }
 
+    /// Returns a green tree, equal to the green tree this token belongs to, except that this token has the text
+    /// `text`, which is interned with `interner`. Like for [`replace_with`](SyntaxToken::replace_with), the
+    /// complexity of the operation is proportional to the depth of the tree.
+    ///
+    /// If the kind of this token has [static text](Syntax::static_text), the token is left unchanged.
+    ///
+    /// To change the text of many tokens at once, for example to rename all uses of a name, see
+    /// [`SyntaxNode::replace_token_keys`].
+    ///
+    /// ## Panics
+    /// In debug builds, if the kind of this token has static text that does not match `text`. Unlike the
+    /// [`GreenNodeBuilder`], this method has no [`StaticTextPolicy`], so the text is always checked with debug
+    /// assertions and never checked without them.
+    ///
+    /// [`GreenNodeBuilder`]: crate::build::GreenNodeBuilder
+    /// [`StaticTextPolicy`]: crate::build::StaticTextPolicy
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// builder.token(Int, "1");
+    /// builder.static_token(Plus);
+    /// builder.token(Int, "2");
+    /// builder.finish_node();
+    /// let (tree, cache) = builder.finish();
+    /// let mut interner = cache.unwrap().into_interner().unwrap();
+    /// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+    /// let one = root.first_token().unwrap();
+    /// let new_root: SyntaxNode<MySyntax> = SyntaxNode::new_root(one.with_text("100", &mut interner));
+    /// assert_eq!(new_root.resolve_text(&interner), "100+2");
+    /// ```
+    pub fn with_text<I>(&self, text: &str, interner: &mut I) -> GreenNode
+    where
+        I: Interner<TokenKey> + ?Sized,
+    {
+        let kind = self.kind();
+        let replacement = match S::static_text(kind) {
+            Some(static_text) => {
+                debug_assert_eq!(
+                    static_text, text,
+                    r#"Tried to change the text of a `{kind:?}` token, which should have text "{static_text}", to "{text}"."#
+                );
+                self.green().clone()
+            }
+            None => {
+                let key = interner.get_or_intern(text);
+                self.green().with_text(key, text)
+            }
+        };
+        self.replace_with(replacement)
+    }
+
     /// The internal representation of the kind of this token.
     #[inline]
     pub fn syntax_kind(&self) -> RawSyntaxKind {
@@ -174,6 +249,138 @@ impl<S: Syntax, D> SyntaxToken<S, D> {
         TextRange::at(self.offset, self.green().text_len())
     }
 
+    /// The range this token covers in the source text, in bytes, relative to the start of `ancestor`.
+    ///
+    /// Returns `None` if `ancestor` is not an ancestor of this token.
+    #[inline]
+    pub fn range_in(&self, ancestor: &SyntaxNode<S, D>) -> Option<TextRange> {
+        self.parent().range_in(ancestor)?;
+        Some(self.text_range() - ancestor.text_range().start())
+    }
+
+    /// Converts the document `offset` into an offset into the text of this token, which is resolved with `resolver`.
+    ///
+    /// Returns `None` if `offset` is outside of the [range](SyntaxToken::text_range) of this token (its end is
+    /// included), or if it is not at a char boundary of the token's text.
+    ///
+    /// # Examples
+    /// ```
+    /// # use cstree::testing::*;
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// builder.token(Whitespace, " ");
+    /// builder.token(Identifier, "größe");
+    /// builder.finish_node();
+    /// let (tree, cache) = builder.finish();
+    /// let interner = cache.unwrap().into_interner().unwrap();
+    /// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+    /// let ident = root.last_token().unwrap();
+    ///
+    /// assert_eq!(ident.offset_in_token(5.into(), &interner), Some(4.into()));
+    /// assert_eq!(ident.offset_in_token(4.into(), &interner), None); // inside `ö`
+    /// assert_eq!(ident.offset_in_token(0.into(), &interner), None); // before the token
+    /// assert_eq!(ident.char_index_at(5.into(), &interner), Some(3));
+    /// assert_eq!(ident.offset_of_char(3, &interner), Some(5.into()));
+    /// assert_eq!(ident.offset_in_document(4.into(), &interner), Some(5.into()));
+    /// ```
+    pub fn offset_in_token<I>(&self, offset: TextSize, resolver: &I) -> Option<TextSize>
+    where
+        I: Resolver<TokenKey> + ?Sized,
+    {
+        let range = self.text_range();
+        if !range.contains_inclusive(offset) {
+            return None;
+        }
+        let relative = offset - range.start();
+        self.resolve_text(resolver)
+            .is_char_boundary(relative.into())
+            .then_some(relative)
+    }
+
+    /// Converts `offset` into the text of this token, which is resolved with `resolver`, into a document offset.
+    /// This is the inverse of [`offset_in_token`](SyntaxToken::offset_in_token).
+    ///
+    /// Returns `None` if `offset` is greater than the length of the token's text, or if it is not at a char boundary
+    /// of the text.
+    pub fn offset_in_document<I>(&self, offset: TextSize, resolver: &I) -> Option<TextSize>
+    where
+        I: Resolver<TokenKey> + ?Sized,
+    {
+        self.resolve_text(resolver)
+            .is_char_boundary(offset.into())
+            .then(|| self.text_range().start() + offset)
+    }
+
+    /// Converts the document `offset` into the index of the char of this token's text that starts at `offset`, or
+    /// the number of chars in the text if `offset` is the end of this token. The text is resolved with `resolver`.
+    ///
+    /// Returns `None` under the same conditions as [`offset_in_token`](SyntaxToken::offset_in_token).
+    pub fn char_index_at<I>(&self, offset: TextSize, resolver: &I) -> Option<usize>
+    where
+        I: Resolver<TokenKey> + ?Sized,
+    {
+        let relative = self.offset_in_token(offset, resolver)?;
+        Some(self.resolve_text(resolver)[..relative.into()].chars().count())
+    }
+
+    /// Converts the `index` of a char of this token's text into the document offset at which that char starts. An
+    /// index equal to the number of chars in the text is converted into the end of this token. The text is resolved
+    /// with `resolver`.
+    ///
+    /// Returns `None` if `index` is greater than the number of chars in the text.
+    pub fn offset_of_char<I>(&self, index: usize, resolver: &I) -> Option<TextSize>
+    where
+        I: Resolver<TokenKey> + ?Sized,
+    {
+        let text = self.resolve_text(resolver);
+        let relative = match text.char_indices().nth(index) {
+            Some((relative, _)) => relative,
+            None if index == text.chars().count() => text.len(),
+            None => return None,
+        };
+        Some(self.text_range().start() + TextSize::try_from(relative).unwrap())
+    }
+
+    /// The number of newlines (`\n`) in the text of this token.
+    ///
+    /// This is stored in the green token and does not require resolving its text.
+    #[cfg(feature = "line_counts")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "line_counts")))]
+    #[inline]
+    pub fn newline_count(&self) -> u32 {
+        self.green().newline_count()
+    }
+
+    /// The length of the text of this token in `char`s, i.e., in Unicode scalar values.
+    ///
+    /// This is stored in the green tree and does not require resolving or decoding any text.
+    #[cfg(feature = "char_counts")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "char_counts")))]
+    #[inline]
+    pub fn text_char_len(&self) -> u32 {
+        self.green().text_char_len()
+    }
+
+    /// The id of the error message of this token if it is an [error token](Syntax::is_error_token), or `None`
+    /// otherwise. Error tokens that were not added with
+    /// [`GreenNodeBuilder::error_token`](crate::build::GreenNodeBuilder::error_token) have the message `0`.
+    #[cfg(feature = "error_messages")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "error_messages")))]
+    #[inline]
+    pub fn error_message(&self) -> Option<u32> {
+        self.kind().is_error_token().then(|| self.green().error_message())
+    }
+
+    /// The (0-based) line of the source text on which this token starts.
+    ///
+    /// See [`SyntaxNode::start_line`] for how this is computed.
+    #[cfg(feature = "line_counts")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "line_counts")))]
+    #[inline]
+    pub fn start_line(&self) -> u32 {
+        self.parent.line_of_child(self.index as usize)
+    }
+
     /// Uses the provided resolver to return the source text of this token.
     ///
     /// If no text is explicitly associated with the token, returns its [`static_text`](SyntaxToken::static_text)
@@ -325,6 +532,34 @@ impl<S: Syntax, D> SyntaxToken<S, D> {
         self.green().text_key()
     }
 
+    /// Returns the interned key of the cooked value of this token, if it has one. See
+    /// [`cooked_text`](SyntaxToken::cooked_text).
+    #[cfg(feature = "cooked_values")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "cooked_values")))]
+    #[inline]
+    pub fn cooked_key(&self) -> Option<TokenKey> {
+        self.green().cooked_key()
+    }
+
+    /// Uses the provided resolver to return the cooked value of this token, such as the unescaped contents of a
+    /// string literal, which was recorded when the tree was built by a builder with a
+    /// [cooker](crate::build::GreenNodeBuilder::set_cooker).
+    ///
+    /// If no cooked value was recorded, returns the [text](SyntaxToken::resolve_text) of the token instead. Cooked
+    /// values are not preserved when a tree is serialized, or by tokens that were created outside of a builder.
+    #[cfg(feature = "cooked_values")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "cooked_values")))]
+    #[inline]
+    pub fn cooked_text<'i, I>(&self, resolver: &'i I) -> &'i str
+    where
+        I: Resolver<TokenKey> + ?Sized,
+    {
+        match self.cooked_key() {
+            Some(key) => resolver.resolve(key),
+            None => self.resolve_text(resolver),
+        }
+    }
+
     /// Returns the unterlying green tree token of this token.
     #[inline]
     pub fn green(&self) -> &GreenToken {
@@ -349,6 +584,26 @@ impl<S: Syntax, D> SyntaxToken<S, D> {
         self.parent().ancestors()
     }
 
+    /// Returns an iterator over all ancestors of this token that can be converted into the [`AstNode`] type `T`,
+    /// converted into `T`. See [`SyntaxNode::ancestors_of_type`].
+    #[inline]
+    pub fn ancestors_of_type<T>(&self) -> impl Iterator<Item = T> + '_
+    where
+        T: AstNode<S, D>,
+    {
+        self.parent().ancestors_of_type()
+    }
+
+    /// Returns the closest ancestor of this token that can be converted into the [`AstNode`] type `T`. See
+    /// [`SyntaxNode::nearest_ancestor`].
+    #[inline]
+    pub fn nearest_ancestor<T>(&self) -> Option<T>
+    where
+        T: AstNode<S, D>,
+    {
+        self.parent().nearest_ancestor()
+    }
+
     /// The tree element to the right of this one, i.e. the next child of this token's parent after this token.
     #[inline]
     pub fn next_sibling_or_token(&self) -> Option<SyntaxElementRef<'_, S, D>> {
@@ -367,39 +622,21 @@ impl<S: Syntax, D> SyntaxToken<S, D> {
     /// token's parent's children from this token on to the left or the right.
     /// The first item in the iterator will always be this token.
     #[inline]
-    pub fn siblings_with_tokens(&self, direction: Direction) -> impl Iterator<Item = SyntaxElementRef<'_, S, D>> {
-        let me: SyntaxElementRef<'_, S, D> = self.into();
-        iter::successors(Some(me), move |el| match direction {
-            Direction::Next => el.next_sibling_or_token(),
-            Direction::Prev => el.prev_sibling_or_token(),
-        })
+    pub fn siblings_with_tokens(&self, direction: Direction) -> SyntaxElementSiblings<'_, S, D> {
+        SyntaxElementSiblings::new(self.into(), Some((self.parent(), self.index as usize)), direction)
     }
 
     /// Returns the next token in the tree.
     /// This is not necessary a direct sibling of this token, but will always be further right in the tree.
     #[inline]
     pub fn next_token(&self) -> Option<&SyntaxToken<S, D>> {
-        match self.next_sibling_or_token() {
-            Some(element) => element.first_token(),
-            None => self
-                .parent()
-                .ancestors()
-                .find_map(|it| it.next_sibling_or_token())
-                .and_then(|element| element.first_token()),
-        }
+        SyntaxElementRef::from(self).next_token()
     }
 
     /// Returns the previous token in the tree.
     /// This is not necessary a direct sibling of this token, but will always be further left in the tree.
     #[inline]
     pub fn prev_token(&self) -> Option<&SyntaxToken<S, D>> {
-        match self.prev_sibling_or_token() {
-            Some(element) => element.last_token(),
-            None => self
-                .parent()
-                .ancestors()
-                .find_map(|it| it.prev_sibling_or_token())
-                .and_then(|element| element.last_token()),
-        }
+        SyntaxElementRef::from(self).prev_token()
     }
 }
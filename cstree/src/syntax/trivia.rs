@@ -0,0 +1,393 @@
+//! Associating whitespace and comment tokens with the nodes and tokens they belong to.
+
+use std::{fmt, mem};
+
+use fxhash::FxHashMap;
+use text_size::TextRange;
+
+use super::*;
+use crate::{
+    interning::{Resolver, TokenKey},
+    util::NodeOrToken,
+    Syntax,
+};
+
+/// The kinds of trivia told apart by a [`TriviaPolicy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TriviaKind {
+    /// Whitespace, including line breaks.
+    Whitespace,
+    /// A comment.
+    Comment,
+    /// A documentation comment, which documents the item that follows it.
+    DocComment,
+}
+
+/// Rules for which element owns each trivia token, used to build a [`TriviaMap`].
+///
+/// Trivia are whitespace and comment tokens that appear as siblings of the nodes and tokens that make up the
+/// structure of a tree, which this documentation calls _items_. Which tokens are trivia is decided by the function
+/// given to [`TriviaPolicy::new`]. The trivia between two items are split up as follows:
+///  - Comments (including doc comments) on the same line as the preceding item, together with the whitespace before
+///    them, are _trailing_ trivia of that item. This can be turned off with [`trailing`](TriviaPolicy::trailing).
+///  - All other trivia are _leading_ trivia of the following item. If
+///    [`detach_at_blank_line`](TriviaPolicy::detach_at_blank_line) is set, trivia before a blank line are not attached
+///    to the following item.
+///
+/// Trivia after the last item of a node, except for trailing comments of that item, and trivia that are not attached
+/// to an item otherwise are _dangling_ trivia of the node.
+#[derive(Debug, Clone, Copy)]
+pub struct TriviaPolicy<S: Syntax> {
+    classify: fn(S) -> Option<TriviaKind>,
+    trailing: bool,
+    detach_at_blank_line: bool,
+}
+
+impl<S: Syntax> TriviaPolicy<S> {
+    /// Creates the default policy, see [`TriviaPolicy`]. `classify` returns the [`TriviaKind`] of tokens of a kind
+    /// that is trivia, and `None` for all other kinds.
+    pub fn new(classify: fn(S) -> Option<TriviaKind>) -> Self {
+        Self {
+            classify,
+            trailing: true,
+            detach_at_blank_line: false,
+        }
+    }
+
+    /// Creates the default policy with the trivia of the [classes](Syntax::class) of kinds, see
+    /// [`KindClass::trivia_kind`](crate::KindClass::trivia_kind).
+    pub fn from_classes() -> Self {
+        Self::new(|kind| kind.class().trivia_kind())
+    }
+
+    /// Whether comments on the same line as an item are trailing trivia of that item (on by default).
+    /// If turned off, they belong to the following item instead.
+    pub fn trailing(mut self, trailing: bool) -> Self {
+        self.trailing = trailing;
+        self
+    }
+
+    /// Whether trivia that are separated from the following item by a blank line are left dangling instead of being
+    /// attached to that item (off by default).
+    pub fn detach_at_blank_line(mut self, detach: bool) -> Self {
+        self.detach_at_blank_line = detach;
+        self
+    }
+
+    /// Returns the [`TriviaKind`] of `kind`, or `None` if tokens of this kind are not trivia.
+    pub fn classify(&self, kind: S) -> Option<TriviaKind> {
+        (self.classify)(kind)
+    }
+}
+
+/// The leading and trailing trivia of every item in a tree, as well as the dangling trivia of every node, as decided
+/// by a [`TriviaPolicy`].
+///
+/// Building the map walks the tree once, so that formatters and other tools can look up the trivia of any element
+/// without reimplementing the rules for which trivia belong to it.
+///
+/// # Example
+/// ```
+/// # use cstree::testing::*;
+/// use cstree::syntax::{TriviaKind, TriviaMap, TriviaPolicy};
+///
+/// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+/// builder.start_node(Root);
+/// builder.token(Int, "1");
+/// builder.token(Whitespace, " ");
+/// builder.token(Float, "# one");
+/// builder.token(Whitespace, "\n");
+/// builder.static_token(Plus);
+/// builder.finish_node();
+/// let (tree, cache) = builder.finish();
+/// let interner = cache.unwrap().into_interner().unwrap();
+/// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+///
+/// // For this example, pretend that `Float` tokens are comments.
+/// let policy = TriviaPolicy::new(|kind| match kind {
+///     Whitespace => Some(TriviaKind::Whitespace),
+///     Float => Some(TriviaKind::Comment),
+///     _ => None,
+/// });
+/// let trivia = TriviaMap::new(&root, &policy, &interner);
+/// let one = root.first_token().unwrap();
+/// let plus = root.last_token().unwrap();
+/// let trailing: Vec<_> = trivia
+///     .trailing(one.into())
+///     .iter()
+///     .map(|t| t.resolve_text(&interner))
+///     .collect();
+/// assert_eq!(trailing, [" ", "# one"]);
+/// let leading: Vec<_> = trivia
+///     .leading(plus.into())
+///     .iter()
+///     .map(|t| t.resolve_text(&interner))
+///     .collect();
+/// assert_eq!(leading, ["\n"]);
+/// ```
+pub struct TriviaMap<S: Syntax, D: 'static = ()> {
+    classify: fn(S) -> Option<TriviaKind>,
+    attached: FxHashMap<SyntaxElement<S, D>, Attached<S, D>>,
+    dangling: FxHashMap<SyntaxNode<S, D>, Vec<SyntaxToken<S, D>>>,
+}
+
+struct Attached<S: Syntax, D: 'static> {
+    leading:  Vec<SyntaxToken<S, D>>,
+    trailing: Vec<SyntaxToken<S, D>>,
+}
+
+impl<S: Syntax, D> Default for Attached<S, D> {
+    fn default() -> Self {
+        Self {
+            leading:  Vec::new(),
+            trailing: Vec::new(),
+        }
+    }
+}
+
+impl<S: Syntax, D> TriviaMap<S, D> {
+    /// Associates the trivia in the subtree rooted at `node` with their owners according to `policy`.
+    /// `resolver` is used to find line breaks in the trivia.
+    pub fn new<R>(node: &SyntaxNode<S, D>, policy: &TriviaPolicy<S>, resolver: &R) -> Self
+    where
+        R: Resolver<TokenKey> + ?Sized,
+    {
+        let mut map = Self {
+            classify: policy.classify,
+            attached: FxHashMap::default(),
+            dangling: FxHashMap::default(),
+        };
+        for parent in node.descendants() {
+            let mut previous: Option<SyntaxElement<S, D>> = None;
+            let mut trivia: Vec<(SyntaxToken<S, D>, TriviaKind)> = Vec::new();
+            for child in parent.children_with_tokens() {
+                if let NodeOrToken::Token(token) = child {
+                    if let Some(kind) = policy.classify(token.kind()) {
+                        trivia.push((token.clone(), kind));
+                        continue;
+                    }
+                }
+                let item = child.cloned();
+                let between = mem::take(&mut trivia);
+                map.attach(parent, previous.as_ref(), Some(&item), between, policy, resolver);
+                previous = Some(item);
+            }
+            map.attach(parent, previous.as_ref(), None, trivia, policy, resolver);
+        }
+        map
+    }
+
+    /// Attaches the `trivia` between `previous` and `next`, which are children of `parent`.
+    fn attach<R>(
+        &mut self,
+        parent: &SyntaxNode<S, D>,
+        previous: Option<&SyntaxElement<S, D>>,
+        next: Option<&SyntaxElement<S, D>>,
+        trivia: Vec<(SyntaxToken<S, D>, TriviaKind)>,
+        policy: &TriviaPolicy<S>,
+        resolver: &R,
+    ) where
+        R: Resolver<TokenKey> + ?Sized,
+    {
+        let line_breaks = |token: &SyntaxToken<S, D>| token.resolve_text(resolver).matches('\n').count();
+
+        // Trailing trivia end with the last comment before the first line break.
+        let mut trailing = 0;
+        if policy.trailing && previous.is_some() {
+            for (i, (token, kind)) in trivia.iter().enumerate() {
+                if *kind != TriviaKind::Whitespace {
+                    trailing = i + 1;
+                }
+                if line_breaks(token) > 0 {
+                    break;
+                }
+            }
+        }
+        // Leading trivia start after the last blank line.
+        let mut leading = trailing;
+        if next.is_none() {
+            leading = trivia.len();
+        } else if policy.detach_at_blank_line {
+            if let Some(blank) = trivia[trailing..]
+                .iter()
+                .rposition(|(token, kind)| *kind == TriviaKind::Whitespace && line_breaks(token) > 1)
+            {
+                leading = trailing + blank + 1;
+            }
+        }
+
+        let mut trivia = trivia.into_iter().map(|(token, _)| token);
+        if let Some(previous) = previous {
+            let trailing_trivia = trivia.by_ref().take(trailing).collect::<Vec<_>>();
+            if !trailing_trivia.is_empty() {
+                self.attached.entry(previous.clone()).or_default().trailing = trailing_trivia;
+            }
+        }
+        let dangling = trivia.by_ref().take(leading - trailing).collect::<Vec<_>>();
+        if !dangling.is_empty() {
+            self.dangling.entry(parent.clone()).or_default().extend(dangling);
+        }
+        if let Some(next) = next {
+            let leading_trivia = trivia.collect::<Vec<_>>();
+            if !leading_trivia.is_empty() {
+                self.attached.entry(next.clone()).or_default().leading = leading_trivia;
+            }
+        }
+    }
+
+    /// Returns the trivia before `element` that belong to it, in order.
+    pub fn leading(&self, element: SyntaxElementRef<'_, S, D>) -> &[SyntaxToken<S, D>] {
+        match self.attached.get(&element.cloned()) {
+            Some(attached) => &attached.leading,
+            None => &[],
+        }
+    }
+
+    /// Returns the trivia after `element` that belong to it, in order.
+    pub fn trailing(&self, element: SyntaxElementRef<'_, S, D>) -> &[SyntaxToken<S, D>] {
+        match self.attached.get(&element.cloned()) {
+            Some(attached) => &attached.trailing,
+            None => &[],
+        }
+    }
+
+    /// Returns the comments and doc comments among the leading trivia of `element`, or `None` if there are none.
+    pub fn leading_comments(&self, element: SyntaxElementRef<'_, S, D>) -> Option<CommentBlock<S, D>> {
+        self.comment_block(element, |_| true)
+    }
+
+    /// Returns the doc comments directly before `element` that document it, or `None` if there are none.
+    ///
+    /// These are the doc comments at the end of the leading trivia of `element`, up to the last regular comment.
+    /// Whitespace between the doc comments is skipped, so whether a blank line separates a doc comment from
+    /// `element` is decided by the [`TriviaPolicy`] the map was built with.
+    ///
+    /// # Example
+    /// ```
+    /// # use cstree::testing::*;
+    /// use cstree::{
+    ///     syntax::{TriviaKind, TriviaMap, TriviaPolicy},
+    ///     text::TextRange,
+    /// };
+    ///
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// builder.token(Float, "# comment");
+    /// builder.token(Whitespace, "\n");
+    /// builder.token(Int, "## first line");
+    /// builder.token(Whitespace, "\n");
+    /// builder.token(Int, "## second line");
+    /// builder.token(Whitespace, "\n");
+    /// builder.start_node(Operation);
+    /// builder.static_token(Plus);
+    /// builder.finish_node();
+    /// builder.finish_node();
+    /// let (tree, cache) = builder.finish();
+    /// let interner = cache.unwrap().into_interner().unwrap();
+    /// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+    ///
+    /// // For this example, pretend that `Float` tokens are comments and `Int` tokens are doc comments.
+    /// let policy = TriviaPolicy::new(|kind| match kind {
+    ///     Whitespace => Some(TriviaKind::Whitespace),
+    ///     Float => Some(TriviaKind::Comment),
+    ///     Int => Some(TriviaKind::DocComment),
+    ///     _ => None,
+    /// });
+    /// let trivia = TriviaMap::new(&root, &policy, &interner);
+    /// let operation = root.first_child().unwrap();
+    /// let docs = trivia.doc_comments(operation.into()).unwrap();
+    /// assert_eq!(docs.text_range(), TextRange::new(10.into(), 38.into()));
+    /// let lines: Vec<_> = docs
+    ///     .comments()
+    ///     .iter()
+    ///     .map(|t| t.resolve_text(&interner))
+    ///     .collect();
+    /// assert_eq!(lines, ["## first line", "## second line"]);
+    /// assert_eq!(
+    ///     trivia
+    ///         .leading_comments(operation.into())
+    ///         .unwrap()
+    ///         .comments()
+    ///         .len(),
+    ///     3
+    /// );
+    /// ```
+    pub fn doc_comments(&self, element: SyntaxElementRef<'_, S, D>) -> Option<CommentBlock<S, D>> {
+        self.comment_block(element, |kind| kind == TriviaKind::DocComment)
+    }
+
+    /// Collects the comments at the end of the leading trivia of `element` while `accept` returns `true` for them.
+    fn comment_block(
+        &self,
+        element: SyntaxElementRef<'_, S, D>,
+        accept: impl Fn(TriviaKind) -> bool,
+    ) -> Option<CommentBlock<S, D>> {
+        let mut comments = Vec::new();
+        for token in self.leading(element).iter().rev() {
+            match (self.classify)(token.kind()) {
+                Some(TriviaKind::Whitespace) => {}
+                Some(kind) if accept(kind) => comments.push(token.clone()),
+                _ => break,
+            }
+        }
+        if comments.is_empty() {
+            return None;
+        }
+        comments.reverse();
+        Some(CommentBlock { comments })
+    }
+
+    /// Returns the trivia among the children of `node` that are not attached to any of them, in order.
+    pub fn dangling(&self, node: &SyntaxNode<S, D>) -> &[SyntaxToken<S, D>] {
+        match self.dangling.get(node) {
+            Some(dangling) => dangling,
+            None => &[],
+        }
+    }
+}
+
+impl<S: Syntax, D> fmt::Debug for TriviaMap<S, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TriviaMap")
+            .field("attached", &self.attached.len())
+            .field("dangling", &self.dangling.len())
+            .finish()
+    }
+}
+
+/// A sequence of comment tokens that belong to the same element, as found by [`TriviaMap::leading_comments`] and
+/// [`TriviaMap::doc_comments`]. A block always contains at least one comment.
+pub struct CommentBlock<S: Syntax, D: 'static = ()> {
+    comments: Vec<SyntaxToken<S, D>>,
+}
+
+impl<S: Syntax, D> CommentBlock<S, D> {
+    /// The comment tokens in this block, in order. Whitespace between them is not included.
+    pub fn comments(&self) -> &[SyntaxToken<S, D>] {
+        &self.comments
+    }
+
+    /// The range from the start of the first comment to the end of the last comment in this block.
+    pub fn text_range(&self) -> TextRange {
+        let first = self.comments[0].text_range();
+        let last = self.comments[self.comments.len() - 1].text_range();
+        first.cover(last)
+    }
+}
+
+impl<S: Syntax, D> Clone for CommentBlock<S, D> {
+    fn clone(&self) -> Self {
+        Self {
+            comments: self.comments.clone(),
+        }
+    }
+}
+
+impl<S: Syntax, D> fmt::Debug for CommentBlock<S, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CommentBlock")
+            .field("range", &self.text_range())
+            .field("comments", &self.comments)
+            .finish()
+    }
+}
@@ -0,0 +1,176 @@
+//! Walking two syntax trees in parallel.
+
+use std::fmt;
+
+use super::*;
+use crate::{
+    interning::{Resolver, TokenKey},
+    util::NodeOrToken,
+    Syntax,
+};
+
+/// The reason why two corresponding elements of a [`ZipWalk`] differ.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Divergence {
+    /// One of the elements is a node and the other one a token.
+    Element,
+    /// The elements have different kinds.
+    Kind,
+    /// The elements are nodes with a different number of children.
+    Children,
+    /// The elements are tokens with different text.
+    Text,
+}
+
+/// An event of a [`ZipWalk`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZipEvent<'a, S: Syntax, D: 'static = ()> {
+    /// Two corresponding elements that are equal except for their children, which are visited next.
+    Enter(SyntaxElementRef<'a, S, D>, SyntaxElementRef<'a, S, D>),
+    /// All children of two entered elements have been visited.
+    Leave(SyntaxElementRef<'a, S, D>, SyntaxElementRef<'a, S, D>),
+    /// Two corresponding elements differ. Their children are not visited.
+    Diverge(SyntaxElementRef<'a, S, D>, SyntaxElementRef<'a, S, D>, Divergence),
+}
+
+/// An iterator that walks two syntax trees in preorder in parallel, created by [`SyntaxNode::zip_with`].
+///
+/// Starting with the two roots, the walk pairs up the elements at the same position in both trees. If two corresponding
+/// elements are equal except for their children, the walk enters them and pairs up their children in order.
+/// Otherwise, it reports how they [diverge](ZipEvent::Diverge) and continues with their next siblings, since their
+/// children may no longer correspond to each other. Two trees are equal if the walk reports no divergences.
+///
+/// The trees may use different interners, for example to compare the output of two versions of a parser. Tokens are
+/// compared by their resolved text.
+///
+/// # Examples
+/// ```
+/// # use cstree::testing::*;
+/// use cstree::{
+///     interning::TokenInterner,
+///     syntax::{Divergence, ZipEvent},
+/// };
+///
+/// fn parse(operands: &[&str]) -> (SyntaxNode<MySyntax>, TokenInterner) {
+///     let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+///     builder.start_node(Root);
+///     for (i, operand) in operands.iter().enumerate() {
+///         if i > 0 {
+///             builder.static_token(Plus);
+///         }
+///         builder.token(Int, operand);
+///     }
+///     builder.finish_node();
+///     let (tree, cache) = builder.finish();
+///     (SyntaxNode::new_root(tree), cache.unwrap().into_interner().unwrap())
+/// }
+///
+/// let (old, old_interner) = parse(&["1", "2"]);
+/// let (new, new_interner) = parse(&["1", "3"]);
+///
+/// let divergences: Vec<_> = old
+///     .zip_with(&old_interner, &new, &new_interner)
+///     .filter_map(|event| match event {
+///         ZipEvent::Diverge(left, right, divergence) => Some((left.text_range(), divergence)),
+///         _ => None,
+///     })
+///     .collect();
+/// assert_eq!(divergences, [(new.last_token().unwrap().text_range(), Divergence::Text)]);
+/// ```
+pub struct ZipWalk<'a, S: Syntax, D: 'static, R1: ?Sized, R2: ?Sized> {
+    left_resolver:  &'a R1,
+    right_resolver: &'a R2,
+    /// The entered pairs of elements that have not been left yet.
+    stack:          Vec<(SyntaxElementRef<'a, S, D>, SyntaxElementRef<'a, S, D>)>,
+    /// The pair of elements that is visited next, if the top of the stack has any more children.
+    next:           Option<(SyntaxElementRef<'a, S, D>, SyntaxElementRef<'a, S, D>)>,
+}
+
+impl<S: Syntax, D> SyntaxNode<S, D> {
+    /// Walks this tree and `other` in parallel, pairing up structurally corresponding elements and reporting where
+    /// they diverge. The text of tokens in this tree is resolved with `resolver`, the text of tokens in `other` with
+    /// `other_resolver`. See [`ZipWalk`].
+    pub fn zip_with<'a, R1, R2>(
+        &'a self,
+        resolver: &'a R1,
+        other: &'a SyntaxNode<S, D>,
+        other_resolver: &'a R2,
+    ) -> ZipWalk<'a, S, D, R1, R2>
+    where
+        R1: Resolver<TokenKey> + ?Sized,
+        R2: Resolver<TokenKey> + ?Sized,
+    {
+        ZipWalk {
+            left_resolver:  resolver,
+            right_resolver: other_resolver,
+            stack:          Vec::new(),
+            next:           Some((self.into(), other.into())),
+        }
+    }
+}
+
+impl<'a, S, D, R1, R2> ZipWalk<'a, S, D, R1, R2>
+where
+    S: Syntax,
+    R1: Resolver<TokenKey> + ?Sized,
+    R2: Resolver<TokenKey> + ?Sized,
+{
+    fn divergence(&self, left: SyntaxElementRef<'a, S, D>, right: SyntaxElementRef<'a, S, D>) -> Option<Divergence> {
+        if left.kind() != right.kind() {
+            return Some(Divergence::Kind);
+        }
+        match (left, right) {
+            (NodeOrToken::Node(left), NodeOrToken::Node(right)) => {
+                (left.green().children().len() != right.green().children().len()).then_some(Divergence::Children)
+            }
+            (NodeOrToken::Token(left), NodeOrToken::Token(right)) => {
+                let equal = left.static_text().is_some()
+                    || left.resolve_text(self.left_resolver) == right.resolve_text(self.right_resolver);
+                (!equal).then_some(Divergence::Text)
+            }
+            _ => Some(Divergence::Element),
+        }
+    }
+
+    /// Schedules the next siblings of `left` and `right` to be visited next, unless they are the roots.
+    fn advance(&mut self, left: SyntaxElementRef<'a, S, D>, right: SyntaxElementRef<'a, S, D>) {
+        if !self.stack.is_empty() {
+            self.next = left.next_sibling_or_token().zip(right.next_sibling_or_token());
+        }
+    }
+}
+
+impl<'a, S, D, R1, R2> Iterator for ZipWalk<'a, S, D, R1, R2>
+where
+    S: Syntax,
+    R1: Resolver<TokenKey> + ?Sized,
+    R2: Resolver<TokenKey> + ?Sized,
+{
+    type Item = ZipEvent<'a, S, D>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Some((left, right)) = self.next.take() else {
+            let (left, right) = self.stack.pop()?;
+            self.advance(left, right);
+            return Some(ZipEvent::Leave(left, right));
+        };
+        if let Some(divergence) = self.divergence(left, right) {
+            self.advance(left, right);
+            return Some(ZipEvent::Diverge(left, right, divergence));
+        }
+        self.stack.push((left, right));
+        if let (NodeOrToken::Node(left), NodeOrToken::Node(right)) = (left, right) {
+            self.next = left.first_child_or_token().zip(right.first_child_or_token());
+        }
+        Some(ZipEvent::Enter(left, right))
+    }
+}
+
+impl<S: Syntax, D, R1: ?Sized, R2: ?Sized> fmt::Debug for ZipWalk<'_, S, D, R1, R2> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ZipWalk")
+            .field("stack", &self.stack)
+            .field("next", &self.next)
+            .finish_non_exhaustive()
+    }
+}
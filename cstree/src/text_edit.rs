@@ -0,0 +1,131 @@
+//! A single replacement in a text, and the generation of random edits for fuzzing.
+
+use text_size::{TextRange, TextSize};
+
+#[cfg(feature = "arbitrary")]
+use crate::{
+    interning::{Resolver, TokenKey},
+    syntax::SyntaxNode,
+    Syntax,
+};
+
+/// A change to a text, which replaces the text in `range` with `insert`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TextEdit {
+    /// The range of text that is replaced.
+    pub range:  TextRange,
+    /// The text that replaces the text in `range`.
+    pub insert: String,
+}
+
+impl TextEdit {
+    /// Creates an edit that replaces the text in `range` with `insert`.
+    pub fn replace(range: TextRange, insert: impl Into<String>) -> Self {
+        Self {
+            range,
+            insert: insert.into(),
+        }
+    }
+
+    /// Creates an edit that inserts `text` at `offset`.
+    pub fn insert(offset: TextSize, text: impl Into<String>) -> Self {
+        Self::replace(TextRange::empty(offset), text)
+    }
+
+    /// Creates an edit that removes the text in `range`.
+    pub fn delete(range: TextRange) -> Self {
+        Self::replace(range, String::new())
+    }
+
+    /// Applies this edit to `text`.
+    ///
+    /// ## Panics
+    /// If the range of this edit is out of bounds of `text` or does not lie on `char` boundaries.
+    pub fn apply(&self, text: &mut String) {
+        text.replace_range(std::ops::Range::<usize>::from(self.range), &self.insert);
+    }
+
+    /// Generates a random edit of the text of the subtree rooted at `node` from the fuzzer input `u`, and returns it
+    /// together with the edited text. The range of the edit is relative to the start of `node`, and always lies
+    /// within its text on `char` boundaries.
+    ///
+    /// The edits are biased towards the structure of the tree, such as deleting, duplicating or replacing whole
+    /// nodes and tokens, inserting text at their boundaries, or inserting the text of other tokens in the tree.
+    /// This makes the edited text more likely to still be close to valid input, which is useful for testing that
+    /// incremental consumers of a tree, like incremental reparsing, give the same result as processing the edited
+    /// text from scratch.
+    ///
+    /// # Example
+    /// ```
+    /// # use cstree::testing::*;
+    /// use cstree::text::{arbitrary::Unstructured, TextEdit};
+    ///
+    /// let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    /// builder.start_node(Root);
+    /// builder.token(Int, "1");
+    /// builder.static_token(Plus);
+    /// builder.token(Int, "2");
+    /// builder.finish_node();
+    /// let (tree, cache) = builder.finish();
+    /// let interner = cache.unwrap().into_interner().unwrap();
+    /// let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+    ///
+    /// let mut u = Unstructured::new(&[3, 1, 4, 1, 5, 9, 2, 6, 5, 3, 5]);
+    /// let (edit, new_text) = TextEdit::arbitrary_for(&mut u, &root, &interner).unwrap();
+    /// let mut text = root.resolve_text(&interner).to_string();
+    /// edit.apply(&mut text);
+    /// assert_eq!(text, new_text);
+    /// ```
+    #[cfg(feature = "arbitrary")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "arbitrary")))]
+    pub fn arbitrary_for<S, D, R>(
+        u: &mut arbitrary::Unstructured<'_>,
+        node: &SyntaxNode<S, D>,
+        resolver: &R,
+    ) -> arbitrary::Result<(Self, String)>
+    where
+        S: Syntax,
+        R: Resolver<TokenKey> + ?Sized,
+    {
+        let start = node.text_range().start();
+        let relative = |range: TextRange| range - start;
+        let mut text = node.resolve_text(resolver).to_string();
+        let elements: Vec<_> = node.descendants_with_tokens().collect();
+        let tokens: Vec<_> = node.tokens().collect();
+        let element = *u.choose(&elements)?;
+        let range = relative(element.text_range());
+
+        let new_text = |u: &mut arbitrary::Unstructured<'_>| -> arbitrary::Result<String> {
+            if !tokens.is_empty() && u.ratio(1, 2)? {
+                Ok(u.choose(&tokens)?.resolve_text(resolver).to_string())
+            } else {
+                u.arbitrary()
+            }
+        };
+        let edit = match u.int_in_range(0..=5)? {
+            0 => TextEdit::delete(range),
+            1 => TextEdit::replace(range, new_text(u)?),
+            2 => TextEdit::insert(range.start(), new_text(u)?),
+            3 => TextEdit::insert(range.end(), new_text(u)?),
+            4 => TextEdit::insert(range.end(), &text[range]),
+            _ => {
+                // An arbitrary range within the element, which may split tokens.
+                let mut offsets = [
+                    u.int_in_range(range.start().into()..=range.end().into())?,
+                    u.int_in_range(range.start().into()..=range.end().into())?,
+                ];
+                offsets.sort_unstable();
+                let [from, to] = offsets.map(|offset: u32| {
+                    let mut offset = offset as usize;
+                    while !text.is_char_boundary(offset) {
+                        offset -= 1;
+                    }
+                    TextSize::from(offset as u32)
+                });
+                TextEdit::replace(TextRange::new(from, to), new_text(u)?)
+            }
+        };
+        edit.apply(&mut text);
+        Ok((edit, text))
+    }
+}
@@ -1,5 +1,7 @@
 use std::fmt;
 
+use crate::Syntax;
+
 /// Convenience type to represent tree elements which may either be a node or a token.
 ///
 /// Used for both red and green tree, references to elements, ...
@@ -195,3 +197,21 @@ impl<T> Iterator for TokenAtOffset<T> {
 }
 
 impl<T> ExactSizeIterator for TokenAtOffset<T> {}
+
+/// Displays a kind by its [`Syntax::kind_name`], falling back to its `Debug` representation.
+pub(crate) struct KindName<S>(pub(crate) S);
+
+impl<S: Syntax> fmt::Debug for KindName<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl<S: Syntax> fmt::Display for KindName<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match S::kind_name(self.0.into_raw()) {
+            Some(name) => f.write_str(name),
+            None => write!(f, "{:?}", self.0),
+        }
+    }
+}
@@ -83,6 +83,2763 @@ fn token_text_eq() {
     assert!(!leaf1_0.text_eq(leaf2_0));
 }
 
+#[test]
+fn tokens() {
+    let tree = two_level_tree();
+    let (tree, resolver) = build_tree::<()>(&tree);
+    let texts = |tokens: &mut dyn Iterator<Item = &SyntaxToken>| {
+        tokens.map(|token| token.resolve_text(&resolver)).collect::<Vec<_>>()
+    };
+    assert_eq!(texts(&mut tree.tokens()), ["0.0", "0.1", "1.0", "2.0", "2.1", "2.2"]);
+    assert_eq!(
+        texts(&mut tree.children().nth(2).unwrap().tokens()),
+        ["2.0", "2.1", "2.2"]
+    );
+
+    let in_range = |start: u32, end: u32| texts(&mut tree.tokens_in_range(TextRange::new(start.into(), end.into())));
+    assert_eq!(in_range(0, 18), ["0.0", "0.1", "1.0", "2.0", "2.1", "2.2"]);
+    assert_eq!(in_range(4, 10), ["0.1", "1.0", "2.0"]);
+    assert_eq!(in_range(6, 9), ["1.0"]);
+    assert_eq!(in_range(7, 7), ["1.0"]);
+    assert_eq!(in_range(18, 18), Vec::<&str>::new());
+}
+
+#[test]
+fn tokens_in_range_with_empty_tokens() {
+    use Element::*;
+    let tree = Node(vec![
+        Node(vec![Token("a"), Token("")]),
+        Node(vec![Token(""), Token("b")]),
+        Token(""),
+    ]);
+    let (tree, _) = build_tree::<()>(&tree);
+    let ranges = |start: u32, end: u32| {
+        tree.tokens_in_range(TextRange::new(start.into(), end.into()))
+            .map(|token| token.text_range())
+            .collect::<Vec<_>>()
+    };
+    let at = |start: u32, len: u32| TextRange::at(start.into(), len.into());
+    assert_eq!(ranges(1, 1), [at(1, 0), at(1, 0)]);
+    assert_eq!(ranges(1, 2), [at(1, 0), at(1, 0), at(1, 1), at(2, 0)]);
+    assert_eq!(ranges(0, 1), [at(0, 1), at(1, 0), at(1, 0)]);
+    assert_eq!(ranges(2, 2), [at(2, 0)]);
+}
+
+#[test]
+fn range_in_ancestor() {
+    let tree = two_level_tree();
+    let (tree, _) = build_tree::<()>(&tree);
+    let node2 = tree.children().nth(2).unwrap();
+    let leaf2_1 = node2.children_with_tokens().nth(1).unwrap();
+    let range = |start: u32, end: u32| Some(TextRange::new(start.into(), end.into()));
+
+    assert_eq!(node2.range_in(&tree), range(9, 18));
+    assert_eq!(node2.range_in(node2), range(0, 9));
+    assert_eq!(leaf2_1.range_in(&tree), range(12, 15));
+    assert_eq!(leaf2_1.range_in(node2), range(3, 6));
+    assert_eq!(leaf2_1.into_token().unwrap().range_in(node2), range(3, 6));
+
+    let node0 = tree.children().next().unwrap();
+    assert_eq!(leaf2_1.range_in(node0), None);
+    assert_eq!(tree.range_in(node2), None);
+}
+
+#[test]
+fn next_and_prev_token() {
+    use Element::*;
+    let tree = Node(vec![
+        Node(vec![Token("a"), Node(vec![Token("b")])]),
+        Node(vec![]),
+        Node(vec![Node(vec![]), Token("c")]),
+    ]);
+    let (tree, resolver) = build_tree::<()>(&tree);
+    let text = |token: Option<&SyntaxToken>| token.map(|token| token.resolve_text(&resolver));
+
+    let a = tree.first_token().unwrap();
+    let b = a.next_token().unwrap();
+    let c = b.next_token().unwrap();
+    assert_eq!(text(Some(b)), Some("b"));
+    assert_eq!(text(Some(c)), Some("c"));
+    assert_eq!(c.next_token(), None);
+    assert_eq!(c.prev_token(), Some(b));
+    assert_eq!(b.prev_token(), Some(a));
+    assert_eq!(a.prev_token(), None);
+
+    let node0 = tree.children().next().unwrap();
+    let empty = tree.children().nth(1).unwrap();
+    let node2 = tree.children().nth(2).unwrap();
+    assert_eq!(node0.next_token(), Some(c));
+    assert_eq!(node0.prev_token(), None);
+    assert_eq!(empty.next_token(), Some(c));
+    assert_eq!(empty.prev_token(), Some(b));
+    assert_eq!(node2.prev_token(), Some(b));
+    assert_eq!(node2.next_token(), None);
+    assert_eq!(tree.next_token(), None);
+    assert_eq!(SyntaxElementRef::from(b.parent()).next_token(), Some(c));
+}
+
+#[test]
+fn text_eq_across_trees() {
+    use Element::*;
+    let (tree, resolver) = build_tree::<()>(&two_level_tree());
+    let other = Node(vec![
+        Node(vec![Token("0.00"), Token(""), Token(".11.0")]),
+        Node(vec![Token("2.02.12.2")]),
+    ]);
+    let (other, other_resolver) = build_tree::<()>(&other);
+    assert!(tree.text_eq(&other, &resolver, &other_resolver));
+    assert!(other.text_eq(&tree, &other_resolver, &resolver));
+
+    let node2 = tree.children().nth(2).unwrap();
+    let other1 = other.children().nth(1).unwrap();
+    assert!(node2.text_eq(other1, &resolver, &other_resolver));
+    let other0 = other.children().next().unwrap();
+    assert!(!tree.text_eq(other0, &resolver, &other_resolver));
+    let node0 = tree.children().next().unwrap();
+    assert!(!node0.text_eq(node2, &resolver, &resolver));
+}
+
+#[test]
+fn elements_intersecting_range() {
+    use cstree::syntax::IntervalIndex;
+    use Element::*;
+    let tree = Node(vec![
+        Node(vec![Token("a"), Token(""), Token("bc")]),
+        Node(vec![]),
+        Node(vec![Token("d"), Node(vec![Token("ef")])]),
+    ]);
+    let (tree, _) = build_tree::<()>(&tree);
+    let index = IntervalIndex::new(&tree);
+    let overlaps = |element: TextRange, range: TextRange| {
+        let overlaps = element.start() < range.end() && range.start() < element.end();
+        overlaps || range.contains_range(element)
+    };
+    for start in 0..=6 {
+        for end in start..=6 {
+            let range = TextRange::new(start.into(), end.into());
+            let expected: Vec<_> = tree
+                .descendants_with_tokens()
+                .filter(|element| overlaps(element.text_range(), range))
+                .collect();
+            let found: Vec<_> = index.overlapping(range).collect();
+            assert_eq!(found, expected, "{range:?}");
+            let found: Vec<_> = tree.descendants_intersecting(range).collect();
+            assert_eq!(found, expected, "{range:?}");
+        }
+    }
+}
+
+#[test]
+fn flatten() {
+    let (tree, resolver) = build_tree::<()>(&two_level_tree());
+    let node2 = tree.children().nth(2).unwrap();
+    let flat = SyntaxNode::<()>::new_root(node2.flatten());
+    assert_eq!(flat.resolve_text(&resolver), tree.resolve_text(&resolver));
+    let kinds: Vec<_> = flat.children_with_tokens().map(|child| child.kind()).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            SyntaxKind(1),
+            SyntaxKind(4),
+            SyntaxKind(7),
+            SyntaxKind(8),
+            SyntaxKind(9)
+        ]
+    );
+    // The untouched children are shared with the original tree.
+    assert_eq!(flat.first_child().unwrap().green(), tree.first_child().unwrap().green());
+}
+
+#[test]
+fn rename_tokens() {
+    use cstree::interning::Interner;
+    let mut cache = NodeCache::new();
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::with_cache(&mut cache);
+    build_recursive(&tree_with_eq_tokens(), &mut builder, 0);
+    let (green, _) = builder.finish();
+    let mut interner = cache.into_interner().unwrap();
+    let tree = SyntaxNode::<()>::new_root(green);
+
+    let c = tree.children().nth(1).unwrap().first_token().unwrap();
+    let renamed = SyntaxNode::<()>::new_root(c.with_text("xyz", &mut interner));
+    assert_eq!(renamed.resolve_text(&interner), "abxyzabc");
+    assert_eq!(
+        renamed.children().nth(2).unwrap().text_range(),
+        TextRange::new(5.into(), 8.into())
+    );
+
+    // Only tokens below the given node are renamed, everything else is shared with the original tree.
+    let a = tree.first_token().unwrap().text_key().unwrap();
+    let x = interner.get_or_intern("x");
+    let node2 = tree.children().nth(2).unwrap();
+    let renamed = SyntaxNode::<()>::new_root(node2.replace_token_keys(a, x, &interner));
+    assert_eq!(renamed.resolve_text(&interner), "abcxbc");
+    assert_eq!(
+        renamed.first_child().unwrap().green(),
+        tree.first_child().unwrap().green()
+    );
+    let renamed = SyntaxNode::<()>::new_root(tree.replace_token_keys(a, x, &interner));
+    assert_eq!(renamed.resolve_text(&interner), "xbcxbc");
+    assert_eq!(
+        renamed.children().nth(1).unwrap().green(),
+        tree.children().nth(1).unwrap().green()
+    );
+
+    // The same works directly on green trees
+    assert_eq!(&tree.green().replace_key(a, x, &interner), renamed.green());
+    let unused = interner.get_or_intern("unused");
+    assert_eq!(&tree.green().replace_key(unused, x, &interner), tree.green());
+}
+
+#[test]
+fn reindent() {
+    use cstree::syntax::{IndentStyle, Reindent};
+    let mut cache = NodeCache::new();
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::with_cache(&mut cache);
+    builder.start_node(SyntaxKind(0));
+    builder.token(SyntaxKind(2), "a");
+    builder.token(SyntaxKind(1), "\n        ");
+    builder.start_node(SyntaxKind(3));
+    builder.token(SyntaxKind(2), "b");
+    builder.token(SyntaxKind(1), "\n\t  ");
+    builder.token(SyntaxKind(2), "c\n");
+    builder.token(SyntaxKind(1), "  ");
+    builder.token(SyntaxKind(2), "d");
+    builder.token(SyntaxKind(1), "\n    ");
+    builder.token(SyntaxKind(1), "\n    ");
+    builder.token(SyntaxKind(2), "e");
+    builder.finish_node();
+    builder.token(SyntaxKind(1), "\n    ");
+    builder.finish_node();
+    let (green, _) = builder.finish();
+    let mut interner = cache.into_interner().unwrap();
+    let tree = SyntaxNode::<()>::new_root(green);
+    let node = tree.first_child().unwrap();
+
+    let options = Reindent::new(|kind| kind == SyntaxKind(1)).shift(-1);
+    let dedented = SyntaxNode::<()>::new_root(node.reindent(&options, &mut interner));
+    // The blank line, the whitespace after `a` and the whitespace after the node are outside the subtree or do not
+    // indent anything, so they are left alone.
+    assert_eq!(dedented.resolve_text(&interner), "a\n        b\n  c\nd\n    \ne\n    ");
+
+    let options = Reindent::new(|kind| kind == SyntaxKind(1))
+        .style(IndentStyle::Tabs)
+        .tab_width(2)
+        .shift(2);
+    let indented = SyntaxNode::<()>::new_root(node.reindent(&options, &mut interner));
+    assert_eq!(
+        indented.resolve_text(&interner),
+        "a\n        b\n\t\t\t\tc\n\t\t\td\n    \n\t\t\t\te\n    "
+    );
+
+    // Indentation that already matches the options is shared with the original tree.
+    let options = Reindent::new(|kind| kind == SyntaxKind(1)).tab_width(2);
+    let normalized = SyntaxNode::<()>::new_root(node.reindent(&options, &mut interner));
+    assert_eq!(
+        normalized.resolve_text(&interner),
+        "a\n        b\n    c\n  d\n    \n    e\n    "
+    );
+    let tokens: Vec<_> = normalized
+        .first_child()
+        .unwrap()
+        .tokens()
+        .map(|t| t.green().clone())
+        .collect();
+    let original: Vec<_> = node.tokens().map(|t| t.green().clone()).collect();
+    assert_ne!(tokens[1], original[1]);
+    assert_eq!(tokens[3], original[3]);
+}
+
+#[test]
+fn trivia() {
+    use cstree::syntax::{TriviaKind, TriviaMap, TriviaPolicy};
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+    builder.start_node(SyntaxKind(0));
+    builder.token(SyntaxKind(5), "// a");
+    builder.token(SyntaxKind(1), "\n\n");
+    builder.token(SyntaxKind(5), "// b");
+    builder.token(SyntaxKind(1), "\n");
+    builder.start_node(SyntaxKind(3));
+    builder.token(SyntaxKind(2), "x");
+    builder.token(SyntaxKind(1), " ");
+    builder.token(SyntaxKind(5), "// x");
+    builder.token(SyntaxKind(1), "\n");
+    builder.token(SyntaxKind(2), "y");
+    builder.token(SyntaxKind(1), "\n");
+    builder.token(SyntaxKind(5), "// tail");
+    builder.finish_node();
+    builder.token(SyntaxKind(1), " ");
+    builder.token(SyntaxKind(5), "// end");
+    builder.finish_node();
+    let (green, cache) = builder.finish();
+    let resolver = cache.unwrap().into_interner().unwrap();
+    let tree = SyntaxNode::<()>::new_root(green);
+    let node = tree.first_child().unwrap();
+    let x = node.first_token().unwrap();
+    let y = node
+        .tokens()
+        .filter(|token| token.kind() == SyntaxKind(2))
+        .nth(1)
+        .unwrap();
+    let texts = |tokens: &[cstree::syntax::SyntaxToken<SyntaxKind>]| -> Vec<String> {
+        tokens
+            .iter()
+            .map(|token| token.resolve_text(&resolver).to_string())
+            .collect()
+    };
+
+    let policy = TriviaPolicy::new(|kind: SyntaxKind| match kind.0 {
+        1 => Some(TriviaKind::Whitespace),
+        5 => Some(TriviaKind::Comment),
+        _ => None,
+    });
+    let trivia = TriviaMap::new(&tree, &policy, &resolver);
+    assert_eq!(texts(trivia.leading(node.into())), ["// a", "\n\n", "// b", "\n"]);
+    assert_eq!(texts(trivia.trailing(node.into())), [" ", "// end"]);
+    assert_eq!(texts(trivia.trailing(x.into())), [" ", "// x"]);
+    assert_eq!(texts(trivia.leading(y.into())), ["\n"]);
+    assert!(trivia.trailing(y.into()).is_empty());
+    assert_eq!(texts(trivia.dangling(node)), ["\n", "// tail"]);
+    assert!(trivia.dangling(&tree).is_empty());
+    let comments = trivia.leading_comments(node.into()).unwrap();
+    assert_eq!(texts(comments.comments()), ["// a", "// b"]);
+    assert_eq!(comments.text_range(), TextRange::new(0.into(), 10.into()));
+    assert!(trivia.doc_comments(node.into()).is_none());
+
+    let trivia = TriviaMap::new(&tree, &policy.detach_at_blank_line(true), &resolver);
+    assert_eq!(texts(trivia.leading(node.into())), ["// b", "\n"]);
+    assert_eq!(texts(trivia.dangling(&tree)), ["// a", "\n\n"]);
+
+    let trivia = TriviaMap::new(&tree, &policy.trailing(false), &resolver);
+    assert!(trivia.trailing(x.into()).is_empty());
+    assert_eq!(texts(trivia.leading(y.into())), [" ", "// x", "\n"]);
+    assert_eq!(texts(trivia.dangling(&tree)), [" ", "// end"]);
+}
+
+#[test]
+fn lowering() {
+    use cstree::lower::{Lower, LowerError, Lowering, Spanned};
+
+    #[derive(Debug, PartialEq)]
+    struct Root(Vec<Spanned<Child>>);
+    #[derive(Debug, PartialEq)]
+    struct Child(Vec<String>);
+
+    impl Lower<SyntaxKind> for Root {
+        type Error = &'static str;
+
+        fn lower(node: &SyntaxNode, cx: &mut Lowering<'_, Self::Error>) -> Option<Self> {
+            Some(Root(cx.lower_all(node.children())))
+        }
+    }
+
+    impl Lower<SyntaxKind> for Child {
+        type Error = &'static str;
+
+        fn lower(node: &SyntaxNode, cx: &mut Lowering<'_, Self::Error>) -> Option<Self> {
+            if node.kind() == SyntaxKind(4) {
+                cx.error("unexpected node");
+                return None;
+            }
+            let texts = node.children_with_tokens().filter_map(|child| child.into_token());
+            Some(Child(texts.map(|token| cx.text(token).to_string()).collect()))
+        }
+    }
+
+    let (tree, resolver) = build_tree::<()>(&two_level_tree());
+    let mut cx = Lowering::new(&resolver);
+    let root = cx.lower::<Root, _, _>(&tree).unwrap();
+    assert_eq!(root.range, TextRange::up_to(18.into()));
+    assert_eq!(
+        root.value.0,
+        [
+            Spanned {
+                value: Child(vec!["0.0".to_string(), "0.1".to_string()]),
+                range: TextRange::new(0.into(), 6.into()),
+            },
+            Spanned {
+                value: Child(vec!["2.0".to_string(), "2.1".to_string(), "2.2".to_string()]),
+                range: TextRange::new(9.into(), 18.into()),
+            },
+        ]
+    );
+    assert_eq!(
+        cx.finish(),
+        [LowerError {
+            error: "unexpected node",
+            range: TextRange::new(6.into(), 9.into()),
+        }]
+    );
+}
+
+#[test]
+fn schema() {
+    use cstree::schema::{Child, Schema};
+    let (tree, _) = build_tree::<()>(&two_level_tree());
+    let kind = |kind| Child::kind(SyntaxKind(kind));
+    let schema = Schema::new()
+        .shape(
+            SyntaxKind(0),
+            [kind(1), kind(4).optional(), Child::any().at_least_one()],
+        )
+        .shape(SyntaxKind(1), [kind(2), kind(3), kind(3).optional()])
+        // Needs to backtrack to leave the last child for the second pattern.
+        .shape(SyntaxKind(4), [Child::any().many(), kind(5)])
+        .shape(SyntaxKind(6), [kind(7).many(), kind(9)]);
+    assert_eq!(
+        schema.to_string().lines().next(),
+        Some("SyntaxKind(0) = SyntaxKind(1) SyntaxKind(4)? _+")
+    );
+
+    let violations = schema.validate(&tree);
+    assert_eq!(violations.len(), 1);
+    let violation = &violations[0];
+    assert_eq!(violation.node, SyntaxKind(6));
+    assert_eq!(violation.node_range, TextRange::new(9.into(), 18.into()));
+    assert_eq!(violation.found, Some(SyntaxKind(8)));
+    assert_eq!(violation.range, TextRange::new(12.into(), 15.into()));
+
+    let schema = schema.shape(SyntaxKind(4), [kind(5), kind(5)]);
+    let node4 = tree.children().nth(1).unwrap();
+    let violation = schema.validate_node(node4).unwrap();
+    assert_eq!(violation.found, None);
+    assert_eq!(violation.range, TextRange::empty(9.into()));
+}
+
+#[test]
+fn schema_wide_node() {
+    use cstree::schema::{Child, Schema};
+
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+    builder.start_node(SyntaxKind(0));
+    for _ in 0..1000 {
+        builder.token(SyntaxKind(1), "x");
+    }
+    builder.finish_node();
+    let (green, _) = builder.finish();
+    let tree = SyntaxNode::<()>::new_root(green);
+
+    // Every split of the children between the repeated patterns fails, which must not be tried one by one.
+    let any = || Child::any().many();
+    let schema = Schema::new().shape(SyntaxKind(0), [any(), any(), any(), Child::kind(SyntaxKind(2))]);
+    let violation = schema.validate_node(&tree).unwrap();
+    assert_eq!(violation.found, None);
+    assert_eq!(violation.range, TextRange::empty(1000.into()));
+}
+
+#[test]
+fn checked_builder() {
+    use cstree::schema::{Child, Schema};
+    use std::sync::Arc;
+
+    let build = |schema: Schema<SyntaxKind>| {
+        std::panic::catch_unwind(|| {
+            let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+            builder.set_schema(Arc::new(schema));
+            build_recursive(&two_level_tree(), &mut builder, 0);
+            builder.finish().0
+        })
+    };
+    let schema = Schema::new().shape(SyntaxKind(4), [Child::kind(SyntaxKind(5))]);
+    let (tree, _) = build_tree::<()>(&two_level_tree());
+    assert_eq!(&build(schema.clone()).unwrap(), tree.green());
+
+    let schema = schema.shape(SyntaxKind(6), [Child::kind(SyntaxKind(7)).many(), Child::kind(SyntaxKind(9))]);
+    let panic = build(schema).unwrap_err();
+    assert_eq!(
+        panic.downcast_ref::<String>().unwrap(),
+        "node does not match the schema: `SyntaxKind(6)` at 9..18 has unexpected `SyntaxKind(8)` at 12..15"
+    );
+}
+
+#[test]
+fn checked_builder_offsets() {
+    use cstree::{
+        build::NodeCaching,
+        schema::{Child, Schema},
+        util::NodeOrToken,
+    };
+    use std::sync::Arc;
+
+    let panic = std::panic::catch_unwind(|| {
+        let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+        builder.set_schema(Arc::new(Schema::new().shape(SyntaxKind(4), [Child::kind(SyntaxKind(5))])));
+        builder.set_node_hook(|_, children, _| {
+            children.retain(|child| !matches!(child, NodeOrToken::Token(token) if token.kind() == RawSyntaxKind(2)));
+            NodeCaching::Cache
+        });
+        builder.start_node(SyntaxKind(0));
+        let checkpoint = builder.checkpoint();
+        builder.token(SyntaxKind(3), "ab");
+        builder.insert_token_at(checkpoint, SyntaxKind(3), "123");
+        for kind in [1, 4] {
+            builder.start_node(SyntaxKind(kind));
+            builder.token(SyntaxKind(2), "drop");
+            builder.token(SyntaxKind(6), "x");
+            builder.finish_node();
+        }
+        builder.finish_node();
+    })
+    .unwrap_err();
+    assert_eq!(
+        panic.downcast_ref::<String>().unwrap(),
+        "node does not match the schema: `SyntaxKind(4)` at 6..7 has unexpected `SyntaxKind(6)` at 6..7"
+    );
+}
+
+#[test]
+fn text_edit() {
+    use cstree::text::TextEdit;
+    let mut text = "0.00.11.0".to_string();
+    TextEdit::replace(TextRange::new(3.into(), 6.into()), "xyz").apply(&mut text);
+    assert_eq!(text, "0.0xyz1.0");
+    TextEdit::insert(0.into(), "_").apply(&mut text);
+    TextEdit::delete(TextRange::new(4.into(), 7.into())).apply(&mut text);
+    assert_eq!(text, "_0.01.0");
+}
+
+#[test]
+#[cfg(feature = "arbitrary")]
+fn arbitrary_text_edits() {
+    use cstree::text::{arbitrary::Unstructured, TextEdit, TextSize};
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+    builder.start_node(SyntaxKind(20));
+    builder.token(SyntaxKind(21), "x");
+    builder.start_node(SyntaxKind(22));
+    build_recursive(&two_level_tree(), &mut builder, 0);
+    builder.token(SyntaxKind(23), "äöü");
+    builder.finish_node();
+    builder.finish_node();
+    let (green, cache) = builder.finish();
+    let resolver = cache.unwrap().into_interner().unwrap();
+    let tree = SyntaxNode::<()>::new_root(green);
+    // Edits are relative to the start of the node.
+    let node = tree.first_child().unwrap();
+    let original = node.resolve_text(&resolver).to_string();
+
+    let mut state = 0x2545_f491_u32;
+    let bytes: Vec<u8> = (0..4096)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect();
+    for start in 0..512 {
+        let mut u = Unstructured::new(&bytes[start..]);
+        let (edit, new_text) = TextEdit::arbitrary_for(&mut u, node, &resolver).unwrap();
+        assert!(edit.range.end() <= TextSize::of(original.as_str()));
+        let mut text = original.clone();
+        edit.apply(&mut text);
+        assert_eq!(text, new_text);
+    }
+}
+
+#[test]
+fn green_heads() {
+    use cstree::green::{GreenNode, GreenNodeHead, GreenToken, GreenTokenData};
+    // Heads only depend on the contents of nodes, so they can be used to cache nodes across trees and caches.
+    let (tree1, _) = build_tree::<()>(&two_level_tree());
+    let (tree2, _) = build_tree::<()>(&two_level_tree());
+    let (green1, green2) = (tree1.green(), tree2.green());
+    assert_eq!(green1.head(), green2.head());
+    assert_eq!(green1.head(), &GreenNodeHead::new(RawSyntaxKind(0), green2.children()));
+    assert_eq!(green1.head().text_len(), 18.into());
+
+    let token = tree1.first_token().unwrap().green();
+    let data = GreenTokenData::new(token.kind(), token.text_key(), token.text_len());
+    assert_eq!(token.data(), &data);
+    assert_eq!(&GreenToken::new(data), token);
+
+    let node = tree1.first_child().unwrap().green();
+    let children: Vec<_> = node
+        .children()
+        .map(|child| match child {
+            cstree::util::NodeOrToken::Node(node) => cstree::util::NodeOrToken::Node(node.clone()),
+            cstree::util::NodeOrToken::Token(token) => cstree::util::NodeOrToken::Token(token.clone()),
+        })
+        .collect();
+    assert_eq!(&GreenNode::from_head_and_children(*node.head(), children), node);
+}
+
+#[test]
+fn green_caches() {
+    use cstree::{
+        build::{DedupCache, GreenCache, NoCache},
+        green::{GreenNode, GreenNodeHead, GreenToken, GreenTokenData},
+        interning::TokenInterner,
+        util::NodeOrToken,
+    };
+
+    #[derive(Default)]
+    struct CountingCache {
+        inner:  NoCache,
+        nodes:  usize,
+        tokens: usize,
+    }
+
+    impl GreenCache for CountingCache {
+        fn node<C>(&mut self, head: GreenNodeHead, children: C) -> GreenNode
+        where
+            C: IntoIterator<Item = NodeOrToken<GreenNode, GreenToken>>,
+            C::IntoIter: ExactSizeIterator,
+        {
+            self.nodes += 1;
+            self.inner.node(head, children)
+        }
+
+        fn token(&mut self, data: GreenTokenData) -> GreenToken {
+            self.tokens += 1;
+            self.inner.token(data)
+        }
+    }
+
+    let cache = NodeCache::new().with_green_cache(CountingCache::default());
+    let mut builder: GreenNodeBuilder<SyntaxKind, TokenInterner, CountingCache> = GreenNodeBuilder::from_cache(cache);
+    build_recursive(&two_level_tree(), &mut builder, 0);
+    let (tree, cache) = builder.finish();
+    let cache = cache.unwrap();
+    assert_eq!(cache.green_cache().nodes, 4);
+    assert_eq!(cache.green_cache().tokens, 6);
+    let (expected, _) = build_tree::<()>(&two_level_tree());
+    assert_eq!(&tree, expected.green());
+
+    // Switching the green cache keeps the interner, so the tree's text can still be resolved.
+    let mut cache = cache.with_green_cache(DedupCache::new());
+    let mut builder = GreenNodeBuilder::with_cache(&mut cache);
+    build_recursive(&two_level_tree(), &mut builder, 0);
+    let (tree, _) = builder.finish();
+    cache.green_cache_mut().clear();
+    let interner = cache.into_interner().unwrap();
+    let tree: SyntaxNode = SyntaxNode::new_root(tree);
+    assert_eq!(tree.resolve_text(&interner), "0.00.11.02.02.12.2");
+}
+
+#[test]
+fn canonicalize() {
+    use cstree::build::{DedupCache, NoCache};
+    use Element::*;
+
+    let subtree = || Node(vec![Token("a"), Token("b"), Token("c"), Token("d")]);
+    let tree = Node(vec![subtree(), Token(" "), subtree()]);
+    let mut cache = NodeCache::new().with_green_cache(NoCache);
+    let mut build = || {
+        let mut builder: GreenNodeBuilder<SyntaxKind, _, NoCache> = GreenNodeBuilder::with_cache(&mut cache);
+        build_recursive(&tree, &mut builder, 0);
+        builder.finish().0
+    };
+    let (first, second) = (build(), build());
+    assert_eq!(first, second);
+    assert!(!first.ptr_eq(&second));
+
+    let mut canonical = DedupCache::canonical();
+    let first = first.canonicalize(&mut canonical);
+    let second = second.canonicalize(&mut canonical);
+    assert!(first.ptr_eq(&second));
+    assert!(first.canonicalize(&mut canonical).ptr_eq(&first));
+    assert_eq!(canonical.node_count(), 3);
+    let children: Vec<_> = first.children().filter_map(|child| child.into_node()).collect();
+    assert_eq!(children.len(), 2);
+    assert_eq!(children[0].kind(), RawSyntaxKind(1));
+}
+
+// 64-bit child hashes are too wide to find a collision in a test
+#[cfg(not(feature = "wide_child_hash"))]
+#[test]
+fn dedup_cache_compares_children() {
+    use cstree::{
+        build::{DedupCache, GreenCache, ThreadedNodeCache, TieredCache},
+        green::{GreenNode, GreenNodeHead, GreenToken, GreenTokenData},
+        util::NodeOrToken,
+    };
+    use std::collections::HashMap;
+
+    fn token(kind: u32) -> GreenToken {
+        GreenToken::new(GreenTokenData::new(RawSyntaxKind(kind), None, 1.into()))
+    }
+    fn children((first, second): (u32, u32)) -> [NodeOrToken<GreenNode, GreenToken>; 2] {
+        [NodeOrToken::Token(token(first)), NodeOrToken::Token(token(second))]
+    }
+    fn head((first, second): (u32, u32)) -> GreenNodeHead {
+        let (first, second) = (token(first), token(second));
+        GreenNodeHead::new(RawSyntaxKind(0), [NodeOrToken::Token(&first), NodeOrToken::Token(&second)])
+    }
+    fn check(cache: &mut impl GreenCache, a: (u32, u32), b: (u32, u32)) {
+        let first = cache.node(head(a), children(a));
+        let second = cache.node(head(b), children(b));
+        assert!(!first.ptr_eq(&second));
+        assert_ne!(first, second);
+        assert_eq!(second.children().next().unwrap().kind(), RawSyntaxKind(b.0));
+        // both nodes are found again
+        assert!(cache.node(head(a), children(a)).ptr_eq(&first));
+        assert!(cache.node(head(b), children(b)).ptr_eq(&second));
+    }
+
+    // find two nodes with different children, but the same head
+    let mut heads = HashMap::new();
+    let mut state = 1_u64;
+    let (a, b) = loop {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        let kinds = ((state >> 32) as u32, state as u32);
+        if let Some(other) = heads.insert(head(kinds), kinds) {
+            if other != kinds {
+                break (other, kinds);
+            }
+        }
+    };
+    assert_eq!(head(a), head(b));
+
+    for mut cache in [DedupCache::new(), DedupCache::canonical()] {
+        check(&mut cache, a, b);
+        assert_eq!(cache.node_count(), 2);
+    }
+    let threaded = ThreadedNodeCache::new();
+    check(&mut &threaded, a, b);
+    assert_eq!(threaded.stats().node_hits, 2);
+    // the hot tier only holds one of the nodes, the other one is found in the cold tier
+    let mut tiered = TieredCache::new(DedupCache::new(), 16);
+    check(&mut tiered, a, b);
+    assert_eq!(tiered.cold().node_count(), 2);
+}
+
+#[cfg(feature = "wide_child_hash")]
+#[test]
+fn wide_child_hash() {
+    use cstree::binary;
+
+    let (tree, resolver) = build_tree::<()>(&two_level_tree());
+    let hash = tree.green().head().child_hash();
+    assert!(hash > u64::from(u32::MAX));
+
+    // decoded trees have the same wide hashes
+    let bytes = binary::to_vec(&tree, &resolver);
+    let restored: ResolvedNode = binary::from_slice(&bytes).unwrap();
+    assert_eq!(restored.green().head().child_hash(), hash);
+    assert_eq!(restored.green(), tree.green());
+
+    #[cfg(feature = "serialize")]
+    {
+        let json = serde_json::to_string(&tree.as_serialize_with_resolver(&resolver)).unwrap();
+        let restored: ResolvedNode = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.green().head().child_hash(), hash);
+        assert_eq!(restored.green(), tree.green());
+    }
+}
+
+#[test]
+fn remap_kinds() {
+    use cstree::util::NodeOrToken;
+
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+    build_recursive(&two_level_tree(), &mut builder, 0);
+    let (tree, cache) = builder.finish();
+    let interner = cache.unwrap().into_interner().unwrap();
+    assert_eq!(tree.remap_kinds(|kind| kind), tree);
+
+    let migrated = tree.remap_kinds(|kind| RawSyntaxKind(kind.0 + 100));
+    assert_ne!(migrated, tree);
+    assert_eq!(migrated.kind(), RawSyntaxKind(100));
+    assert_eq!(migrated.text_len(), tree.text_len());
+    let kinds = |node: &GreenNode| -> Vec<_> {
+        node.children()
+            .flat_map(|child| match child {
+                NodeOrToken::Node(node) => std::iter::once(node.kind())
+                    .chain(node.children().map(|child| child.kind()))
+                    .collect(),
+                NodeOrToken::Token(token) => vec![token.kind()],
+            })
+            .collect()
+    };
+    let expected: Vec<_> = kinds(&tree).into_iter().map(|kind| RawSyntaxKind(kind.0 + 100)).collect();
+    assert_eq!(kinds(&migrated), expected);
+    let root: SyntaxNode = SyntaxNode::new_root(migrated);
+    assert_eq!(root.resolve_text(&interner), "0.00.11.02.02.12.2");
+}
+
+#[test]
+fn remap_kinds_shares_tokens() {
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+    builder.start_node(SyntaxKind(0));
+    builder.token(SyntaxKind(1), "x");
+    builder.start_node(SyntaxKind(2));
+    builder.token(SyntaxKind(1), "x");
+    builder.finish_node();
+    builder.finish_node();
+    let (tree, _) = builder.finish();
+
+    let migrated = tree.remap_kinds(|kind| RawSyntaxKind(kind.0 + 1));
+    let first = migrated.children().next().unwrap().into_token().unwrap();
+    let node = migrated.children().nth(1).unwrap().into_node().unwrap();
+    let second = node.children().next().unwrap().into_token().unwrap();
+    assert_eq!(first.kind(), RawSyntaxKind(2));
+    assert!(std::ptr::eq(first.data(), second.data()));
+}
+
+#[test]
+fn tokens_intern_as_batch() {
+    use cstree::{
+        interning::{Interner, TokenInterner, TokenKey},
+        testing::MySyntax,
+    };
+    use smallvec::SmallVec;
+
+    struct Batches {
+        interner: TokenInterner,
+        batches:  Vec<usize>,
+    }
+
+    impl Resolver for Batches {
+        fn try_resolve(&self, key: TokenKey) -> Option<&str> {
+            self.interner.try_resolve(key)
+        }
+    }
+
+    impl Interner for Batches {
+        type Error = <TokenInterner as Interner>::Error;
+
+        fn try_get_or_intern(&mut self, text: &str) -> Result<TokenKey, Self::Error> {
+            self.batches.push(1);
+            self.interner.try_get_or_intern(text)
+        }
+
+        fn try_get_or_intern_many(&mut self, texts: &[&str]) -> Result<SmallVec<[TokenKey; 16]>, Self::Error> {
+            self.batches.push(texts.len());
+            self.interner.try_get_or_intern_many(texts)
+        }
+    }
+
+    let interner = Batches {
+        interner: new_interner(),
+        batches:  Vec::new(),
+    };
+    let mut builder: GreenNodeBuilder<MySyntax, Batches> = GreenNodeBuilder::from_interner(interner);
+    builder.start_node(MySyntax::Root);
+    builder.tokens(&[(MySyntax::Int, "1"), (MySyntax::Plus, "+"), (MySyntax::Int, "2")]);
+    builder.finish_node();
+    let (tree, cache) = builder.finish();
+    let interner = cache.unwrap().into_interner().unwrap();
+    // the texts without static text were interned in a single batch
+    assert_eq!(interner.batches, [2]);
+    let tree = cstree::syntax::SyntaxNode::<MySyntax>::new_root(tree);
+    assert_eq!(tree.resolve_text(&interner), "1+2");
+}
+
+#[test]
+fn key_counts() {
+    use cstree::interning::{KeyCounts, Resolver};
+
+    let mut cache = NodeCache::new().with_key_counts();
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::with_cache(&mut cache);
+    build_recursive(&tree_with_eq_tokens(), &mut builder, 0);
+    let (tree, _) = builder.finish();
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::with_cache(&mut cache);
+    builder.start_node(SyntaxKind(0));
+    builder.token_n(SyntaxKind(1), "c", 3);
+    builder.tokens(&[(SyntaxKind(1), "d"), (SyntaxKind(1), "a")]);
+    builder.finish_node();
+    builder.finish();
+
+    let counts = cache.key_counts().unwrap();
+    assert_eq!(counts.total(), 11);
+    let interner = cache.interner();
+    let common: Vec<_> = counts
+        .most_common(10)
+        .into_iter()
+        .map(|(key, count)| (interner.resolve(key), count))
+        .collect();
+    assert_eq!(common, [("c", 5), ("a", 3), ("b", 2), ("d", 1)]);
+
+    let mut from_tree = KeyCounts::new();
+    from_tree.add_tree(&tree);
+    from_tree.add_tree(&tree);
+    assert_eq!(from_tree.total(), 12);
+    let (a, _) = from_tree.iter().find(|&(key, _)| interner.resolve(key) == "a").unwrap();
+    assert_eq!(from_tree.get(a), 4);
+    assert_eq!(from_tree.iter().count(), 3);
+    cache.key_counts_mut().unwrap().clear();
+    assert_eq!(cache.key_counts().unwrap().total(), 0);
+}
+
+#[test]
+fn spilling_interner() {
+    use cstree::interning::{FileSpillStore, Interner, SpillingInterner};
+
+    let path = std::env::temp_dir().join(format!("cstree-spill-test-{}", std::process::id()));
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+        .unwrap();
+    let mut interner = SpillingInterner::new(FileSpillStore::new(file).unwrap(), 40).with_min_len(8);
+    let short = interner.get_or_intern("short");
+    let long: Vec<_> = (0..8).map(|i| interner.get_or_intern(&format!("long text {i}"))).collect();
+    assert!(interner.resident_bytes() <= 40);
+    assert!(interner.spilled() > 0);
+    assert!(!interner.store().is_empty());
+
+    // Spilled texts are read back on demand and deduplicated against.
+    for (i, &key) in long.iter().enumerate() {
+        assert_eq!(interner.resolve(key), format!("long text {i}"));
+    }
+    assert_eq!(interner.resolve(short), "short");
+    assert_eq!(interner.get_or_intern("long text 0"), long[0]);
+    interner.trim();
+    assert!(interner.resident_bytes() <= 40);
+
+    let mut builder: GreenNodeBuilder<SyntaxKind, _> = GreenNodeBuilder::from_interner(interner);
+    build_recursive(&two_level_tree(), &mut builder, 0);
+    let (green, cache) = builder.finish();
+    let interner = cache.unwrap().into_interner().unwrap();
+    let node: SyntaxNode = SyntaxNode::new_root(green);
+    assert_eq!(node.resolve_text(&interner), "0.00.11.02.02.12.2");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn weak_nodes() {
+    let (tree, _) = build_tree::<()>(&two_level_tree());
+    let root = tree.downgrade();
+    let child = tree.children().nth(2).unwrap().downgrade();
+    assert!(child.ptr_eq(&child.clone()));
+    assert!(!child.ptr_eq(&root));
+    assert_eq!(root.upgrade().unwrap(), tree);
+    assert_eq!(
+        child.upgrade().unwrap().text_range(),
+        TextRange::new(9.into(), 18.into())
+    );
+
+    // Any node or token keeps the tree alive.
+    let token = tree.first_token().unwrap().clone();
+    drop(tree);
+    let upgraded = child.upgrade().unwrap();
+    assert_eq!(upgraded.parent().unwrap(), &root.upgrade().unwrap());
+    drop(upgraded);
+    drop(token);
+    assert!(root.upgrade().is_none());
+    assert!(child.clone().upgrade().is_none());
+}
+
+#[test]
+fn slice_green() {
+    use cstree::{green::GreenNode, interning::TokenInterner};
+    let mut cache = NodeCache::new();
+    let mut builder = GreenNodeBuilder::with_cache(&mut cache);
+    build_recursive(&two_level_tree(), &mut builder, 0);
+    let (tree, _) = builder.finish();
+    let mut interner = cache.into_interner().unwrap();
+    let text =
+        |node: GreenNode, interner: &TokenInterner| SyntaxNode::<()>::new_root(node).resolve_text(interner).to_string();
+
+    // Whole nodes and tokens only
+    let slice = tree.slice(TextRange::new(3.into(), 12.into())).unwrap();
+    assert_eq!(slice.kind(), tree.kind());
+    assert_eq!(text(slice.clone(), &interner), "0.11.02.0");
+    assert_eq!(slice.children().nth(1).unwrap(), tree.children().nth(1).unwrap());
+    assert_eq!(tree.slice(TextRange::up_to(tree.text_len())).unwrap(), tree);
+
+    let error = tree.slice(TextRange::new(4.into(), 10.into())).unwrap_err();
+    assert_eq!(error.token, TextRange::new(3.into(), 6.into()));
+    let error = tree.slice(TextRange::new(6.into(), 10.into())).unwrap_err();
+    assert_eq!(error.token, TextRange::new(9.into(), 12.into()));
+
+    let slice = tree
+        .slice_splitting(TextRange::new(4.into(), 10.into()), &mut interner)
+        .unwrap();
+    assert_eq!(slice.text_len(), 6.into());
+    assert_eq!(text(slice, &interner), ".11.02");
+}
+
+#[test]
+fn insert_at_checkpoint() {
+    let mut cache = NodeCache::new();
+    let mut builder = GreenNodeBuilder::with_cache(&mut cache);
+    build_recursive(&tree_with_eq_tokens(), &mut builder, 0);
+    let (inserted, _) = builder.finish();
+
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::with_cache(&mut cache);
+    builder.start_node(SyntaxKind(0));
+    let first = builder.checkpoint();
+    builder.token(SyntaxKind(1), "b");
+    let second = builder.checkpoint();
+    builder.start_node(SyntaxKind(2));
+    builder.token(SyntaxKind(3), "d");
+    builder.finish_node();
+    builder.insert_token_at(first, SyntaxKind(4), "a");
+    builder.insert_node_at(second, inserted);
+    assert_eq!(builder.current_offset(), 9.into());
+    assert_eq!(builder.tokens_since(first), 9);
+    builder.finish_node();
+    let (tree, _) = builder.finish();
+    let interner = cache.into_interner().unwrap();
+    let tree: SyntaxNode = SyntaxNode::new_root(tree);
+    // `second` was created before the token at `first` was inserted, so it points before "b"
+    assert_eq!(tree.resolve_text(&interner), "aabcabcbd");
+    let kinds: Vec<_> = tree.children_with_tokens().map(|child| child.kind()).collect();
+    assert_eq!(kinds, [SyntaxKind(4), SyntaxKind(0), SyntaxKind(1), SyntaxKind(2)]);
+}
+
+#[test]
+#[should_panic(expected = "checkpoint is not in the current node")]
+fn insert_at_checkpoint_outside_current_node() {
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+    builder.start_node(SyntaxKind(0));
+    let checkpoint = builder.checkpoint();
+    builder.token(SyntaxKind(1), "a");
+    builder.start_node(SyntaxKind(2));
+    builder.insert_token_at(checkpoint, SyntaxKind(1), "b");
+}
+
+#[test]
+#[should_panic(expected = "checkpoint is not in the current node (checkpoint `operand`)")]
+fn named_checkpoint_outside_current_node() {
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+    builder.start_node(SyntaxKind(0));
+    let checkpoint = builder.named_checkpoint("operand");
+    assert_eq!(checkpoint.name(), Some("operand"));
+    builder.token(SyntaxKind(1), "a");
+    builder.start_node(SyntaxKind(2));
+    builder.insert_token_at(checkpoint, SyntaxKind(1), "b");
+}
+
+#[test]
+fn reset_builder() {
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+    builder.start_node(SyntaxKind(0));
+    builder.token(SyntaxKind(1), "abandoned");
+    builder.reset();
+    assert_eq!(builder.depth(), 0);
+    assert_eq!(builder.current_offset(), 0.into());
+    build_recursive(&two_level_tree(), &mut builder, 0);
+    let (tree, cache) = builder.finish();
+    let interner = cache.unwrap().into_interner().unwrap();
+    let tree: SyntaxNode = SyntaxNode::new_root(tree);
+    assert_eq!(tree.resolve_text(&interner), "0.00.11.02.02.12.2");
+}
+
+#[test]
+fn cancellation() {
+    use cstree::build::{CancellationToken, Cancelled};
+
+    let token = CancellationToken::new();
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+    builder.set_cancellation_token(token.clone());
+    builder.start_node(SyntaxKind(0));
+    builder.token(SyntaxKind(1), "a");
+    assert_eq!(builder.check_cancelled(), Ok(()));
+    token.clone().cancel();
+    assert!(token.is_cancelled());
+    assert_eq!(builder.check_cancelled(), Err(Cancelled));
+    // adding tokens is not a cancellation point
+    builder.token(SyntaxKind(1), "b");
+    let result = Cancelled::catch(|| builder.start_node(SyntaxKind(2)));
+    assert_eq!(result, Err(Cancelled));
+    assert_eq!(builder.depth(), 1);
+
+    // node guards finish their nodes while a cancellation unwinds through them
+    let result = Cancelled::catch(|| {
+        builder.reset();
+        let token = CancellationToken::new();
+        builder.set_cancellation_token(token.clone());
+        let mut root = builder.enter_node(SyntaxKind(0));
+        root.token(SyntaxKind(1), "a");
+        token.cancel();
+        root.check_cancelled().unwrap_or_else(|_| Cancelled::throw());
+    });
+    assert_eq!(result, Err(Cancelled));
+    assert_eq!(builder.depth(), 0);
+
+    // other panics are propagated
+    let panic = std::panic::catch_unwind(|| Cancelled::catch(|| panic!("not cancelled")));
+    assert!(panic.is_err());
+}
+
+#[test]
+fn replay() {
+    use cstree::build::{BuildEvent, Progress};
+
+    let events = [
+        BuildEvent::StartNode(SyntaxKind(0)),
+        BuildEvent::StartNode(SyntaxKind(1)),
+        BuildEvent::Token(SyntaxKind(2), "0.0"),
+        BuildEvent::Token(SyntaxKind(3), "0.1"),
+        BuildEvent::FinishNode,
+        BuildEvent::Token(SyntaxKind(4), "1.0"),
+        BuildEvent::FinishNode,
+    ];
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+    let mut iter = events.into_iter();
+    assert_eq!(builder.replay(&mut iter, 0), Progress::Paused);
+    assert_eq!(builder.replay(&mut iter, 3), Progress::Paused);
+    assert_eq!(builder.depth(), 2);
+    // a budget that ends exactly with the events does not know yet that there are none left
+    assert_eq!(builder.replay(&mut iter, 4), Progress::Paused);
+    assert_eq!(builder.replay(&mut iter, 4), Progress::Done);
+    let (replayed, _) = builder.finish();
+
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+    for event in events {
+        builder.apply(event);
+    }
+    let (applied, _) = builder.finish();
+    assert_eq!(replayed, applied);
+    let root: SyntaxNode = SyntaxNode::new_root(applied);
+    assert_eq!(root.first_child().unwrap().kind(), SyntaxKind(1));
+    assert_eq!(root.text_range(), TextRange::up_to(9.into()));
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "checkpoint is from a different builder, or from before the builder was reset")]
+fn checkpoint_after_reset() {
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+    builder.start_node(SyntaxKind(0));
+    let checkpoint = builder.checkpoint();
+    builder.reset();
+    builder.start_node(SyntaxKind(0));
+    builder.start_node_at(checkpoint, SyntaxKind(1));
+}
+
+#[test]
+fn compact_resolved() {
+    let mut cache = NodeCache::new();
+    let mut builder = GreenNodeBuilder::with_cache(&mut cache);
+    build_recursive(&two_level_tree(), &mut builder, 0);
+    let (tree, _) = builder.finish();
+    let root: ResolvedNode = SyntaxNode::new_root_with_resolver(tree, cache.into_interner().unwrap());
+
+    let node = root.children().nth(2).unwrap().compact();
+    assert_eq!(node.text(), "2.02.12.2");
+    assert_eq!(node.kind(), SyntaxKind(6));
+    let keys: Vec<_> = node
+        .children_with_tokens()
+        .map(|token| token.as_token().unwrap().text_key().unwrap())
+        .collect();
+    assert!(keys.iter().all(|&key| node.resolver().try_resolve(key).is_some()));
+
+    let compacted = root.compact();
+    assert_eq!(compacted.text(), root.text());
+    assert_eq!(compacted.children().count(), 3);
+}
+
+#[test]
+fn recovery_sets() {
+    use cstree::recovery::{RecoverySets, SyntaxKindSet};
+
+    let mut set: SyntaxKindSet<SyntaxKind> = [SyntaxKind(3), SyntaxKind(130)].into_iter().collect();
+    assert!(set.contains(SyntaxKind(130)) && !set.contains(SyntaxKind(2)));
+    assert!(!set.insert(SyntaxKind(3)));
+    assert_eq!(set.len(), 2);
+    assert!(set.remove(SyntaxKind(130)));
+    assert_eq!(set.iter().collect::<Vec<_>>(), [SyntaxKind(3)]);
+    assert_eq!(set, SyntaxKindSet::from_iter([SyntaxKind(3)]));
+    let other = SyntaxKindSet::from_iter([SyntaxKind(3), SyntaxKind(70)]);
+    assert_eq!(set.union(&other), other);
+    assert_eq!(other.intersection(&SyntaxKindSet::from_iter([SyntaxKind(70)])).len(), 1);
+    assert!(set.intersection(&SyntaxKindSet::from_iter([SyntaxKind(70)])).is_empty());
+
+    let sets = RecoverySets::new()
+        .follow(SyntaxKind(0), [SyntaxKind(10)])
+        .follow(SyntaxKind(1), [SyntaxKind(11)])
+        .follow(SyntaxKind(1), [SyntaxKind(12)]);
+    let tokens = [
+        (SyntaxKind(20), "a"),
+        (SyntaxKind(21), "b"),
+        (SyntaxKind(10), ";"),
+        (SyntaxKind(22), "c"),
+    ];
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+    builder.start_node(SyntaxKind(0));
+    builder.start_node(SyntaxKind(1));
+    let recovery = builder.recovery_set(&sets);
+    assert_eq!(recovery.iter().collect::<Vec<_>>(), [10, 11, 12].map(SyntaxKind));
+    assert_eq!(builder.recover(SyntaxKind(99), &tokens, &recovery), 2);
+    assert_eq!(builder.recover(SyntaxKind(99), &tokens[2..], &recovery), 0);
+    builder.finish_node();
+    assert_eq!(builder.recovery_set(&sets), SyntaxKindSet::from_iter([SyntaxKind(10)]));
+    builder.token(SyntaxKind(10), ";");
+    // without a token in the recovery set, all remaining tokens are skipped
+    assert_eq!(builder.recover(SyntaxKind(99), &tokens[3..], &recovery), 1);
+    builder.finish_node();
+    let (tree, _) = builder.finish();
+    let root: SyntaxNode = SyntaxNode::new_root(tree);
+    let errors: Vec<_> = root
+        .descendants()
+        .filter(|node| node.kind() == SyntaxKind(99))
+        .map(|node| node.text_range())
+        .collect();
+    assert_eq!(errors, [TextRange::new(0.into(), 2.into()), TextRange::new(3.into(), 4.into())]);
+}
+
+#[test]
+fn freeze_subtree() {
+    let (tree, interner) = build_tree::<()>(&two_level_tree());
+    let root: ResolvedNode = SyntaxNode::new_root_with_resolver(tree.green().clone(), interner);
+
+    let node = root.children().nth(1).unwrap().freeze();
+    assert!(std::sync::Arc::ptr_eq(node.resolver(), root.resolver()));
+    assert!(std::ptr::eq(node.green().head(), root.children().nth(1).unwrap().green().head()));
+    drop(root);
+    assert_eq!(node.text(), "1.0");
+    assert_eq!(node.kind(), SyntaxKind(4));
+    assert_eq!(node.text_range(), TextRange::up_to(3.into()));
+    assert!(node.parent().is_none());
+
+    // a tree without a resolver can be frozen with a shared one
+    let resolver = node.resolver().clone();
+    let last = tree.children().nth(2).unwrap().freeze_with_resolver(resolver);
+    assert_eq!(last.text(), "2.02.12.2");
+}
+
+#[test]
+fn siblings() {
+    use cstree::traversal::Direction;
+
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+    builder.start_node(SyntaxKind(0));
+    builder.token(SyntaxKind(1), "a");
+    builder.start_node(SyntaxKind(2));
+    builder.token(SyntaxKind(3), "b");
+    builder.finish_node();
+    builder.token(SyntaxKind(4), "c");
+    builder.start_node(SyntaxKind(5));
+    builder.finish_node();
+    builder.token(SyntaxKind(6), "d");
+    builder.finish_node();
+    let (tree, _) = builder.finish();
+    let root: SyntaxNode = SyntaxNode::new_root(tree);
+    let kinds = |elements: &mut dyn Iterator<Item = cstree::syntax::SyntaxElementRef<'_, SyntaxKind>>| {
+        elements.map(|element| element.kind().0).collect::<Vec<_>>()
+    };
+
+    let first = root.first_child().unwrap();
+    assert_eq!(kinds(&mut first.siblings_with_tokens(Direction::Next)), [2, 4, 5, 6]);
+    assert_eq!(kinds(&mut first.siblings_with_tokens(Direction::Prev)), [2, 1]);
+    assert_eq!(first.siblings_with_tokens(Direction::Next).len(), 4);
+    let nodes: Vec<_> = first.siblings(Direction::Next).map(|node| node.kind().0).collect();
+    assert_eq!(nodes, [2, 5]);
+    let last = root.last_child().unwrap();
+    let nodes: Vec<_> = last.siblings(Direction::Prev).map(|node| node.kind().0).collect();
+    assert_eq!(nodes, [5, 2]);
+
+    // the elements and their ranges are the same as the ones of the children of the parent
+    let token = root.last_token().unwrap();
+    let backwards: Vec<_> = token.siblings_with_tokens(Direction::Prev).collect();
+    let children: Vec<_> = root.children_with_tokens().collect();
+    assert!(backwards.iter().rev().eq(children.iter()));
+    assert_eq!(backwards[1].text_range(), TextRange::new(3.into(), 3.into()));
+
+    // the root has no siblings
+    assert_eq!(kinds(&mut root.siblings_with_tokens(Direction::Prev)), [0]);
+    assert_eq!(root.siblings(Direction::Next).count(), 1);
+}
+
+#[test]
+fn par_materialize() {
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+    builder.start_node(SyntaxKind(0));
+    for i in 0..64 {
+        builder.start_node(SyntaxKind(1));
+        for _ in 0..i % 5 {
+            builder.start_node(SyntaxKind(2));
+            builder.token(SyntaxKind(3), "x");
+            builder.finish_node();
+        }
+        builder.finish_node();
+    }
+    builder.token(SyntaxKind(3), "y");
+    builder.finish_node();
+    let (tree, _) = builder.finish();
+    let root: SyntaxNode = SyntaxNode::new_root(tree);
+
+    // materialize parts of the tree concurrently with the parallel materialization
+    std::thread::scope(|scope| {
+        scope.spawn(|| root.par_materialize(1));
+        scope.spawn(|| root.last_child().unwrap().materialize());
+        scope.spawn(|| root.par_materialize(3));
+    });
+    let addresses = || {
+        root.descendants_with_tokens()
+            .map(|element| match element {
+                cstree::util::NodeOrToken::Node(node) => node as *const SyntaxNode as usize,
+                cstree::util::NodeOrToken::Token(token) => token as *const _ as usize,
+            })
+            .collect::<Vec<_>>()
+    };
+    let materialized = addresses();
+    let count = root.green().node_count() + root.green().token_count();
+    assert_eq!(materialized.len(), count as usize);
+    // traversing the tree again returns the elements that were already created
+    assert_eq!(addresses(), materialized);
+}
+
+#[test]
+fn clone_into_cache() {
+    let (tree, old_interner) = build_tree::<()>(&tree_with_eq_tokens());
+
+    let mut cache = NodeCache::new();
+    // Texts are interned in a different order than in the original interner
+    cache.interner_mut().get_or_intern("c");
+    let mut builder = GreenNodeBuilder::with_cache(&mut cache);
+    build_recursive(&tree_with_eq_tokens(), &mut builder, 0);
+    let (expected, _) = builder.finish();
+    let copy = tree.green().clone_into(&mut cache, &old_interner);
+    assert_eq!(copy, expected);
+
+    let interner = cache.into_interner().unwrap();
+    let copy: SyntaxNode = SyntaxNode::new_root(copy);
+    assert_eq!(copy.resolve_text(&interner), "abcabc");
+    assert_ne!(
+        copy.first_token().unwrap().text_key(),
+        tree.first_token().unwrap().text_key()
+    );
+}
+
+#[test]
+fn local_node_cache() {
+    use cstree::build::ThreadedNodeCache;
+
+    let shared = ThreadedNodeCache::new();
+    let mut interner = cstree::interning::new_interner();
+    let mut cache = NodeCache::with_interner(&mut interner).with_green_cache(shared.local());
+    let mut build = || {
+        let mut builder = GreenNodeBuilder::<SyntaxKind, _, _>::with_cache(&mut cache);
+        build_recursive(&tree_with_eq_tokens(), &mut builder, 0);
+        builder.finish().0
+    };
+    let first = build();
+    let second = build();
+    assert_eq!(first, second);
+    // local hits are only counted in the shared cache once they are synced
+    assert_eq!(shared.stats().token_hits, 0);
+    drop(cache);
+
+    // the second tree found all of its tokens in the local cache
+    let stats = shared.stats();
+    assert_eq!(stats.token_misses, 6);
+    assert_eq!(stats.token_hits, 6);
+    assert_eq!(stats.token_hit_rate(), 0.5);
+}
+
+#[test]
+fn convert_threaded_cache() {
+    use cstree::build::ThreadedNodeCache;
+
+    let mut cache = NodeCache::new();
+    let green = build_tree_with_cache(&two_level_tree(), &mut cache);
+    let nodes = cache.green_cache().node_count();
+    let tokens = cache.green_cache().token_count();
+    let (threaded, interner) = cache.into_threaded();
+    let mut interner = interner.unwrap();
+
+    // the threaded cache finds every node and token of the tree
+    let mut cache = NodeCache::with_interner(&mut interner).with_green_cache(&threaded);
+    let mut builder = GreenNodeBuilder::<SyntaxKind, _, &ThreadedNodeCache>::with_cache(&mut cache);
+    build_recursive(&two_level_tree(), &mut builder, 0);
+    assert!(builder.finish().0.ptr_eq(&green));
+    let stats = threaded.stats();
+    assert_eq!((stats.node_misses, stats.token_misses), (0, 0));
+    assert_eq!(stats.node_hits, nodes as u64);
+
+    // and so does the single-threaded cache converted back from it
+    let mut cache = NodeCache::from_threaded(threaded, interner);
+    assert_eq!(cache.green_cache().node_count(), nodes);
+    assert_eq!(cache.green_cache().token_count(), tokens);
+    let other = build_tree_with_cache(&two_level_tree(), &mut cache);
+    assert!(other.ptr_eq(&green));
+    let tree = SyntaxNode::<()>::new_root(other);
+    assert_eq!(tree.resolve_text(cache.interner()), "0.00.11.02.02.12.2");
+}
+
+#[test]
+fn tiered_cache() {
+    use cstree::build::{DedupCache, GreenCache, NoCache, TieredCache};
+
+    let mut cache = NodeCache::new().with_green_cache(TieredCache::new(DedupCache::new(), 16));
+    let first = {
+        let mut builder = GreenNodeBuilder::<SyntaxKind, _, _>::with_cache(&mut cache);
+        build_recursive(&two_level_tree(), &mut builder, 0);
+        builder.finish().0
+    };
+    let tiered = cache.green_cache();
+    // 6 tokens and 4 nodes
+    assert_eq!((tiered.hot_len(), tiered.hot_hits()), (10, 0));
+    let cold_nodes = tiered.cold().node_count();
+
+    // elements from the hot and the cold tier are the same as the ones of the first tree
+    let second = {
+        let mut builder = GreenNodeBuilder::<SyntaxKind, _, _>::with_cache(&mut cache);
+        build_recursive(&two_level_tree(), &mut builder, 0);
+        builder.finish().0
+    };
+    assert_eq!(first, second);
+    assert!(std::ptr::eq(first.head(), second.head()));
+    let tiered = cache.green_cache();
+    assert_eq!(tiered.hot_hits(), 10);
+    assert_eq!(tiered.cold().node_count(), cold_nodes);
+
+    // a token that stays in use is not evicted by a stream of new ones
+    let mut tiered = TieredCache::new(NoCache, 4);
+    let data = |i: u32| cstree::green::GreenTokenData::new(RawSyntaxKind(i), None, 1.into());
+    let hot = tiered.token(data(0));
+    for i in 1..20 {
+        assert_eq!(tiered.token(data(0)), hot);
+        tiered.token(data(i));
+    }
+    assert_eq!(tiered.hot_hits(), 19);
+    assert!(tiered.hot_len() <= 4);
+}
+
+#[test]
+fn adaptive_dedup() {
+    use cstree::build::DedupCache;
+
+    let mut cache = NodeCache::new().with_green_cache(DedupCache::adaptive(0.5));
+    let mut builder = GreenNodeBuilder::<SyntaxKind, _, _>::with_cache(&mut cache);
+    builder.start_node(SyntaxKind(0));
+    for i in 0..2000 {
+        // nodes of kind 1 are all different, nodes of kind 2 are all the same
+        builder.start_node(SyntaxKind(1));
+        builder.token(SyntaxKind(3), &i.to_string());
+        builder.finish_node();
+        builder.start_node(SyntaxKind(2));
+        builder.token(SyntaxKind(3), "x");
+        builder.finish_node();
+    }
+    builder.finish_node();
+    let (tree, _) = builder.finish();
+    assert_eq!(tree.children().len(), 4000);
+
+    // only the first 1024 nodes of kind 1 were cached
+    assert_eq!(cache.green_cache().node_count(), 1024 + 1);
+    assert_eq!(cache.green_cache().token_count(), 2000 + 1);
+    cache.green_cache_mut().clear();
+    assert_eq!(cache.green_cache().node_count(), 0);
+}
+
+#[test]
+fn node_store() {
+    use cstree::build::NodeStore;
+
+    let store = NodeStore::new();
+    let mut interner = cstree::interning::new_interner();
+    let mut cache = NodeCache::with_interner(&mut interner).with_green_cache(&store);
+    let mut builder = GreenNodeBuilder::<SyntaxKind, _, _>::with_cache(&mut cache);
+    build_recursive(&two_level_tree(), &mut builder, 0);
+    let (built, _) = builder.finish();
+    let (nodes, tokens) = (store.node_count(), store.token_count());
+    assert_eq!(nodes, 4);
+    assert_eq!(tokens, 6);
+
+    // a tree built with a different cache is replaced by the stored one
+    let mut cache = NodeCache::with_interner(&mut interner);
+    let other = build_tree_with_cache(&two_level_tree(), &mut cache);
+    assert_eq!(store.intern(&other), built);
+    assert_eq!(store.node_count(), nodes);
+    assert_eq!(store.token_count(), tokens);
+
+    let other = build_tree_with_cache(&two_level_tree(), &mut cache);
+    let bigger = store.intern(&other.wrap_in(RawSyntaxKind(20)));
+    assert_eq!(bigger.children().next().unwrap().into_node(), Some(&built));
+    assert_eq!(store.node_count(), nodes + 1);
+
+    store.clear();
+    assert_eq!(store.node_count(), 0);
+    assert!(std::ptr::eq(NodeStore::global(), NodeStore::global()));
+}
+
+#[test]
+fn green_counts() {
+    let (tree, _) = build_tree::<()>(&two_level_tree());
+    let green = tree.green();
+    assert_eq!(green.token_count(), 6);
+    assert_eq!(green.node_count(), 4);
+    #[cfg(feature = "subtree_counts")]
+    assert_eq!(green.head().token_count(), 6);
+    assert_eq!(tree.children().nth(2).unwrap().green().token_count(), 3);
+    assert_eq!(green.wrap_in(RawSyntaxKind(20)).node_count(), 5);
+    assert_eq!(
+        green.token_count() as usize,
+        tree.descendants_with_tokens()
+            .filter(|e| e.as_token().is_some())
+            .count()
+    );
+}
+
+#[test]
+#[cfg(feature = "line_counts")]
+fn line_counts() {
+    use Element::*;
+    let tree = Node(vec![
+        Node(vec![Token("a\n"), Token("b")]),
+        Token("\n\n"),
+        Node(vec![Token("c"), Node(vec![Token("d\ne\n")])]),
+    ]);
+    let (tree, resolver) = build_tree::<()>(&tree);
+    assert_eq!(tree.newline_count(), 5);
+    for token in tree.tokens() {
+        let text = token.resolve_text(&resolver);
+        assert_eq!(token.newline_count() as usize, text.matches('\n').count());
+        let before = &tree.resolve_text(&resolver).to_string()[..usize::from(token.text_range().start())];
+        assert_eq!(token.start_line() as usize, before.matches('\n').count());
+    }
+    let last = tree.last_child().unwrap();
+    assert_eq!(last.start_line(), 3);
+    assert!(last.is_multiline());
+    assert_eq!(last.last_child().unwrap().start_line(), 3);
+
+    let renamed = tree.replace_token_keys(
+        last.first_token().unwrap().text_key().unwrap(),
+        tree.first_token().unwrap().text_key().unwrap(),
+        &resolver,
+    );
+    assert_eq!(renamed.newline_count(), 6);
+}
+
+#[test]
+#[cfg(feature = "char_counts")]
+fn char_counts() {
+    use cstree::green::{GreenToken, GreenTokenData};
+    use Element::*;
+
+    let tree = Node(vec![Token("abc"), Node(vec![Token("äöü"), Token("日本")]), Token("🦀\n")]);
+    let (tree, resolver) = build_tree::<()>(&tree);
+    for token in tree.tokens() {
+        let text = token.resolve_text(&resolver);
+        assert_eq!(token.text_char_len() as usize, text.chars().count());
+        assert_eq!(token.green().text_char_len(), token.green().data().text_char_len());
+    }
+    assert_eq!(tree.last_token().unwrap().text_char_len(), 2);
+
+    // without the text, the length in bytes is used
+    let data = GreenTokenData::new(RawSyntaxKind(0), None, 3.into());
+    assert_eq!(GreenToken::new(data).text_char_len(), 3);
+}
+
+#[test]
+fn text_chunks() {
+    let (tree, resolver) = build_tree::<()>(&two_level_tree());
+    let node = tree.children().nth(2).unwrap();
+    let chunks: Vec<_> = node.text_chunks(&resolver).collect();
+    assert_eq!(
+        chunks,
+        [
+            (TextRange::new(9.into(), 12.into()), "2.0"),
+            (TextRange::new(12.into(), 15.into()), "2.1"),
+            (TextRange::new(15.into(), 18.into()), "2.2"),
+        ]
+    );
+
+    let tree: ResolvedNode = SyntaxNode::new_root_with_resolver(tree.green().clone(), resolver);
+    let text: String = tree.text_chunks().map(|(_, chunk)| chunk).collect();
+    assert_eq!(tree.text(), text.as_str());
+    for ((range, chunk), token) in tree.text_chunks().zip(tree.tokens()) {
+        assert_eq!(range, token.text_range());
+        assert_eq!(chunk, token.text());
+    }
+}
+
+#[test]
+fn covering_node_of_kind() {
+    let (tree, _) = build_tree::<()>(&two_level_tree());
+    let range = TextRange::new(10.into(), 11.into());
+    let leaf = tree.children().nth(2).unwrap();
+    assert_eq!(tree.covering_node_of_kind(range, &[SyntaxKind(6)]), Some(leaf));
+    assert_eq!(tree.covering_node_of_kind(range, &[SyntaxKind(0)]), Some(&tree));
+    assert_eq!(tree.covering_node_of_kind(range, &[SyntaxKind(0), SyntaxKind(6)]), Some(leaf));
+    assert_eq!(tree.covering_node_of_kind(range, &[SyntaxKind(1)]), None);
+    // the range spans two children, so only the root covers it
+    let range = TextRange::new(8.into(), 10.into());
+    assert_eq!(tree.covering_node_of_kind(range, &[SyntaxKind(6)]), None);
+}
+
+#[test]
+fn ancestors_of_type() {
+    use cstree::ast::AstNode;
+
+    /// Nodes with an even kind.
+    struct Even(SyntaxNode);
+
+    impl AstNode<SyntaxKind> for Even {
+        fn can_cast(kind: SyntaxKind) -> bool {
+            kind.0 % 2 == 0
+        }
+
+        fn cast(node: SyntaxNode) -> Option<Self> {
+            assert!(Self::can_cast(node.kind()));
+            Some(Even(node))
+        }
+
+        fn syntax(&self) -> &SyntaxNode {
+            &self.0
+        }
+    }
+
+    let (tree, _) = build_tree::<()>(&two_level_tree());
+    let leaf = tree.children().nth(2).unwrap();
+    let kinds: Vec<_> = leaf.ancestors_of_type::<Even>().map(|it| it.syntax().kind()).collect();
+    assert_eq!(kinds, [SyntaxKind(6), SyntaxKind(0)]);
+    let token = leaf.first_token().unwrap();
+    assert_eq!(token.nearest_ancestor::<Even>().unwrap().syntax(), leaf);
+    let odd = tree.children().next().unwrap();
+    assert_eq!(odd.nearest_ancestor::<Even>().unwrap().syntax(), &tree);
+}
+
+#[test]
+fn ast_node_accessors() {
+    use cstree::ast::AstNode;
+
+    const ROOT: SyntaxKind = SyntaxKind(0);
+    const PAIR: SyntaxKind = SyntaxKind(1);
+    const NAME: SyntaxKind = SyntaxKind(2);
+    const SEPARATOR: SyntaxKind = SyntaxKind(3);
+
+    cstree::ast_node! {
+        struct Root: SyntaxKind = ROOT {
+            fn first -> child Pair;
+            fn second -> node PAIR[1];
+            fn third -> child Pair[2];
+            fn separator -> token SEPARATOR;
+            fn pairs -> children Pair;
+        }
+    }
+
+    cstree::ast_node! {
+        struct Pair: SyntaxKind = PAIR {
+            fn key -> token NAME;
+            fn value -> token NAME[1];
+        }
+    }
+
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+    builder.start_node(ROOT);
+    for (key, value) in [("a", Some("b")), ("c", None)] {
+        builder.start_node(PAIR);
+        builder.token(NAME, key);
+        builder.token(SEPARATOR, "=");
+        if let Some(value) = value {
+            builder.token(NAME, value);
+        }
+        builder.finish_node();
+    }
+    builder.finish_node();
+    let (tree, cache) = builder.finish();
+    let interner = cache.unwrap().into_interner().unwrap();
+    let root: SyntaxNode = SyntaxNode::new_root(tree);
+
+    let root = Root::cast(root).unwrap();
+    assert!(Pair::cast(root.syntax().clone()).is_none());
+    let first = root.first().unwrap();
+    assert_eq!(first.key().unwrap().resolve_text(&interner), "a");
+    assert_eq!(first.value().unwrap().resolve_text(&interner), "b");
+    let second = Pair::cast(root.second().unwrap().clone()).unwrap();
+    assert_eq!(second.key().unwrap().resolve_text(&interner), "c");
+    assert!(second.value().is_none());
+    assert!(root.third().is_none());
+    assert!(root.separator().is_none());
+    assert_eq!(root.pairs().collect::<Vec<_>>(), [first, second]);
+}
+
+#[test]
+fn ast_node_with_data() {
+    use cstree::ast::AstNode;
+
+    const ROOT: SyntaxKind = SyntaxKind(0);
+    const LEAF: SyntaxKind = SyntaxKind(1);
+
+    cstree::ast_node! {
+        struct Root: SyntaxKind, u32 = ROOT {
+            fn leaf -> node LEAF;
+        }
+    }
+
+    let (tree, _) = build_tree::<u32>(&two_level_tree());
+    let root = Root::cast(tree).unwrap();
+    let leaf = root.leaf().unwrap();
+    leaf.set_data(7);
+    assert_eq!(root.syntax().first_child().unwrap().get_data().as_deref(), Some(&7));
+}
+
+#[test]
+fn child_slice() {
+    use cstree::syntax::ChildSlice;
+    use Element::*;
+    let tree = Node(vec![
+        Token("ab"),
+        Node(vec![]),
+        Token("cde"),
+        Node(vec![Token("f")]),
+        Token(""),
+        Token("gh"),
+    ]);
+    let (tree, _) = build_tree::<()>(&tree);
+    let children = tree.child_slice();
+    assert_eq!(children.len(), 6);
+    assert_eq!(children.text_range(), tree.text_range());
+    for (index, child) in tree.children_with_tokens().enumerate() {
+        assert_eq!(children.get(index), Some(child));
+        assert_eq!(children.child_range(index), Some(child.text_range()));
+    }
+    assert_eq!(children.get(6), None);
+
+    assert_eq!(children.index_at_offset(0.into()), Some(0));
+    assert_eq!(children.index_at_offset(2.into()), Some(2));
+    assert_eq!(children.index_at_offset(6.into()), Some(5));
+    assert_eq!(children.index_at_offset(8.into()), None);
+
+    let (left, right) = children.split_at(3);
+    assert_eq!(left.len(), 3);
+    assert_eq!(right.start_index(), 3);
+    assert_eq!(right.text_range(), TextRange::new(5.into(), 8.into()));
+    assert_eq!(right.index_at_offset(6.into()), Some(2));
+    assert_eq!(right.get(0), children.get(3));
+
+    let chunks: Vec<_> = children.chunks(4).map(|chunk| chunk.len()).collect();
+    assert_eq!(chunks, [4, 2]);
+
+    let indices = |slice: ChildSlice<'_, SyntaxKind>| slice.start_index()..slice.start_index() + slice.len();
+    assert_eq!(indices(tree.children_in_range(TextRange::new(1.into(), 3.into()))), 0..3);
+    assert_eq!(indices(tree.children_in_range(TextRange::new(2.into(), 2.into()))), 1..2);
+    assert_eq!(indices(tree.children_in_range(TextRange::new(6.into(), 6.into()))), 4..5);
+    assert_eq!(indices(tree.children_in_range(TextRange::new(8.into(), 8.into()))), 6..6);
+    assert_eq!(indices(right.in_range(TextRange::new(0.into(), 6.into()))), 3..5);
+    for child in tree.children_in_range(TextRange::new(3.into(), 7.into())).iter() {
+        assert!(child.text_range().intersect(TextRange::new(3.into(), 7.into())).is_some());
+    }
+}
+
+#[test]
+fn preorder_index() {
+    use cstree::{syntax::PreorderIndex, util::NodeOrToken};
+    use Element::*;
+    let tree = Node(vec![
+        Node(vec![]),
+        Node(vec![Token("a"), Node(vec![Token(""), Token("b")])]),
+        Token(""),
+        Node(vec![Token("c")]),
+    ]);
+    let (tree, _) = build_tree::<()>(&tree);
+    let index = PreorderIndex::new(&tree);
+    assert_eq!(index.len(), tree.descendants_with_tokens().count());
+    for (i, element) in tree.descendants_with_tokens().enumerate() {
+        assert_eq!(index.get(i), Some(element));
+        assert_eq!(index.index_of(element), Some(i));
+        let subtree = index.subtree(i);
+        let expected = match element {
+            NodeOrToken::Node(node) => node.descendants_with_tokens().count(),
+            NodeOrToken::Token(_) => 1,
+        };
+        assert_eq!(subtree, i..i + expected);
+    }
+    assert_eq!(index.get(index.len()), None);
+
+    let inner = PreorderIndex::new(tree.children().nth(1).unwrap());
+    assert_eq!(inner.len(), 5);
+    assert_eq!(inner.index_of(&tree), None);
+    assert_eq!(inner.index_of(tree.last_child().unwrap()), None);
+}
+
+#[test]
+fn tokens_with_key() {
+    use cstree::interning::Interner;
+
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+    build_recursive(&tree_with_eq_tokens(), &mut builder, 0);
+    let (tree, cache) = builder.finish();
+    let mut interner = cache.unwrap().into_interner().unwrap();
+    let tree: SyntaxNode = SyntaxNode::new_root(tree);
+    let a = interner.get_or_intern("a");
+    let starts: Vec<u32> = tree.tokens_with_key(a).map(|t| t.text_range().start().into()).collect();
+    assert_eq!(starts, [0, 3]);
+    let b = interner.get_or_intern("b");
+    assert_eq!(tree.children().nth(1).unwrap().tokens_with_key(b).count(), 0);
+    let missing = interner.get_or_intern("missing");
+    assert_eq!(tree.tokens_with_key(missing).count(), 0);
+
+    #[cfg(feature = "key_filters")]
+    {
+        assert!(tree.may_contain_key(a));
+        assert!(tree.first_child().unwrap().may_contain_key(b));
+        for node in tree.descendants() {
+            for token in node.tokens() {
+                assert!(node.may_contain_key(token.text_key().unwrap()));
+            }
+        }
+    }
+}
+
+#[test]
+fn token_sub_offsets() {
+    use Element::*;
+
+    let (tree, resolver) = build_tree::<()>(&Node(vec![Token("ab"), Token("çd€"), Token("")]));
+    let token = tree.first_token().unwrap().next_token().unwrap();
+    assert_eq!(token.text_range(), TextRange::new(2.into(), 8.into()));
+    let in_token: Vec<_> = (0..10)
+        .map(|offset| token.offset_in_token(offset.into(), &resolver).map(u32::from))
+        .collect();
+    assert_eq!(
+        in_token,
+        [None, None, Some(0), None, Some(2), Some(3), None, None, Some(6), None]
+    );
+    for offset in [0, 2, 3, 6] {
+        let document = token.offset_in_document(offset.into(), &resolver).unwrap();
+        assert_eq!(token.offset_in_token(document, &resolver), Some(offset.into()));
+    }
+    assert_eq!(token.offset_in_document(1.into(), &resolver), None);
+    assert_eq!(token.offset_in_document(7.into(), &resolver), None);
+
+    let chars: Vec<_> = (0..=3).map(|index| token.offset_of_char(index, &resolver)).collect();
+    assert_eq!(chars, [Some(2.into()), Some(4.into()), Some(5.into()), Some(8.into())]);
+    assert_eq!(token.offset_of_char(4, &resolver), None);
+    for (index, offset) in chars.into_iter().enumerate() {
+        assert_eq!(token.char_index_at(offset.unwrap(), &resolver), Some(index));
+    }
+    assert_eq!(token.char_index_at(3.into(), &resolver), None);
+
+    let empty = tree.last_token().unwrap();
+    assert_eq!(empty.offset_in_token(8.into(), &resolver), Some(0.into()));
+    assert_eq!(empty.offset_of_char(0, &resolver), Some(8.into()));
+    assert_eq!(empty.char_index_at(8.into(), &resolver), Some(0));
+}
+
+#[test]
+fn injection() {
+    use cstree::syntax::Injection;
+    use Element::*;
+
+    // `<a>` and `<b>` are embedded in `"<a>x<b>"` and `"<c>"`, with the quotes and `x` belonging to the host.
+    let (tree, resolver) = build_tree::<()>(&Node(vec![Token("\"<a>x<b>\""), Token(" "), Token("\"<c>\"")]));
+    let tokens: Vec<_> = tree.tokens().collect();
+    let parts = [
+        (tokens[0], TextRange::new(1.into(), 4.into())),
+        (tokens[0], TextRange::new(5.into(), 8.into())),
+        (tokens[2], TextRange::new(1.into(), 4.into())),
+    ];
+    let injection = Injection::new(parts, &resolver);
+    assert_eq!(injection.text(), "<a><b><c>");
+    assert_eq!(injection.hosts(), [tokens[0].clone(), tokens[2].clone()]);
+
+    let host: Vec<_> = (0..=9).map(|offset| injection.host_offset(offset.into()).map(u32::from)).collect();
+    assert_eq!(host, [1, 2, 3, 5, 6, 7, 11, 12, 13, 14].map(Some));
+    assert_eq!(injection.host_offset(10.into()), None);
+    for offset in [0, 9, 10, 15, 16] {
+        assert_eq!(injection.injected_offset(offset.into()), None);
+    }
+    assert_eq!(injection.injected_offset(4.into()), Some(3.into()));
+    assert_eq!(injection.injected_offset(5.into()), Some(3.into()));
+    assert_eq!(injection.injected_offset(8.into()), Some(6.into()));
+    assert_eq!(injection.injected_offset(11.into()), Some(6.into()));
+
+    assert_eq!(
+        injection.host_ranges(TextRange::new(1.into(), 8.into())),
+        [
+            TextRange::new(2.into(), 4.into()),
+            TextRange::new(5.into(), 8.into()),
+            TextRange::new(11.into(), 13.into()),
+        ]
+    );
+    assert_eq!(injection.host_ranges(TextRange::empty(3.into())), [TextRange::empty(5.into())]);
+    assert_eq!(injection.host_token(2.into()), Some(tokens[0]));
+    assert_eq!(injection.host_token(6.into()), Some(tokens[2]));
+
+    // The embedded language has one token per tag.
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+    builder.start_node(SyntaxKind(0));
+    for tag in ["<a>", "<b>", "<c>"] {
+        builder.token(SyntaxKind(1), tag);
+    }
+    builder.finish_node();
+    let (embedded, _) = builder.finish();
+    let injected = injection.attach::<SyntaxKind, ()>(embedded);
+    let token = injected.token_at_host_offset(13.into()).right_biased().unwrap();
+    assert_eq!(token.text_range(), TextRange::new(6.into(), 9.into()));
+    assert_eq!(injected.host_token(token.text_range().start()), Some(tokens[2]));
+    assert_eq!(injected.token_at_host_offset(10.into()).count(), 0);
+    assert_eq!(injected.root().text_range().len(), 9.into());
+}
+
+#[test]
+#[should_panic = "not in document order"]
+fn injection_out_of_order() {
+    use cstree::syntax::Injection;
+
+    let (tree, resolver) = build_tree::<()>(&two_level_tree());
+    let tokens: Vec<_> = tree.tokens().collect();
+    let range = TextRange::new(0.into(), 3.into());
+    Injection::new([(tokens[1], range), (tokens[0], range)], &resolver);
+}
+
+#[test]
+fn remap_keys() {
+    use cstree::interning::{new_interner, KeyMap, Resolver};
+
+    let mut master = new_interner();
+    master.get_or_intern("unrelated");
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+    build_recursive(&tree_with_eq_tokens(), &mut builder, 0);
+    let (tree, cache) = builder.finish();
+    let scratch = cache.unwrap().into_interner().unwrap();
+    let keys: Vec<_> = tree.token_keys().collect();
+    assert_eq!(keys.len(), tree.token_count() as usize);
+
+    let map = KeyMap::reintern(tree.token_keys(), &scratch, &mut master);
+    assert_eq!(map.len(), 3);
+    let remapped = tree.remap_keys(&map);
+    assert_eq!(remapped.text_len(), tree.text_len());
+    let before: SyntaxNode = SyntaxNode::new_root(tree);
+    let after: SyntaxNode = SyntaxNode::new_root(remapped.clone());
+    assert_eq!(before.debug(&scratch, true), after.debug(&master, true));
+    for (old, new) in keys.iter().zip(remapped.token_keys()) {
+        assert_eq!(scratch.resolve(*old), master.resolve(new));
+    }
+    // Remapping to the same keys leaves the tree unchanged.
+    let mut identity = KeyMap::new();
+    for key in remapped.token_keys() {
+        identity.insert(key, key);
+    }
+    assert_eq!(remapped.remap_keys(&identity), remapped);
+}
+
+#[test]
+fn static_text_policy() {
+    use cstree::{build::StaticTextPolicy, interning::Interner, testing::MySyntax};
+
+    let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    builder.set_static_text_policy(StaticTextPolicy::Ignore);
+    builder.start_node(MySyntax::Root);
+    builder.token(MySyntax::Plus, "-");
+    let (sender, receiver) = std::sync::mpsc::channel();
+    builder.set_static_text_policy(StaticTextPolicy::Warn(Box::new(move |kind, text| {
+        sender.send((kind, text.to_string())).unwrap();
+    })));
+    assert!(matches!(builder.static_text_policy(), StaticTextPolicy::Warn(_)));
+    let minus = builder.interner_mut().get_or_intern("-");
+    let plus = builder.interner_mut().get_or_intern("+");
+    builder.tokens(&[(MySyntax::Plus, "+"), (MySyntax::Plus, "plus")]);
+    builder.tokens_with_keys(&[(MySyntax::Plus, plus), (MySyntax::Plus, minus)]);
+    builder.insert_token_at(builder.checkpoint(), MySyntax::Plus, "?");
+    builder.finish_node();
+    let (tree, _) = builder.finish();
+    // Mismatched tokens are added with their static text.
+    assert_eq!(tree.text_len(), 6.into());
+    assert_eq!(tree.children().collect::<std::collections::HashSet<_>>().len(), 1);
+    let mismatches: Vec<_> = receiver.try_iter().collect();
+    assert_eq!(
+        mismatches,
+        [
+            (MySyntax::Plus, "plus".to_string()),
+            (MySyntax::Plus, "-".to_string()),
+            (MySyntax::Plus, "?".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn token_uses_static_text() {
+    use cstree::testing::MySyntax;
+
+    let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    builder.start_node(MySyntax::Root);
+    builder.token(MySyntax::Plus, "+");
+    builder.static_token(MySyntax::Plus);
+    builder.token(MySyntax::Int, "1");
+    builder.finish_node();
+    let (tree, _) = builder.finish();
+    let tokens: Vec<_> = tree.children().map(|child| child.into_token().unwrap()).collect();
+    // only the text of the integer is interned
+    assert_eq!(tokens[0], tokens[1]);
+    assert_eq!(tokens[0].text_key(), None);
+    assert!(tokens[2].text_key().is_some());
+}
+
+#[test]
+#[should_panic = "should have text"]
+fn static_text_policy_panic() {
+    use cstree::{build::StaticTextPolicy, testing::MySyntax};
+
+    let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    builder.set_static_text_policy(StaticTextPolicy::Panic);
+    builder.start_node(MySyntax::Root);
+    builder.token(MySyntax::Plus, "-");
+}
+
+#[test]
+fn finish_node_as() {
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+    builder.start_node(SyntaxKind(0));
+    builder.start_node(SyntaxKind(1));
+    builder.token(SyntaxKind(5), "a");
+    builder.start_node(SyntaxKind(1));
+    builder.token(SyntaxKind(5), "b");
+    builder.finish_node_as(SyntaxKind(3));
+    builder.finish_node_as(SyntaxKind(2));
+    builder.start_node(SyntaxKind(2));
+    builder.token(SyntaxKind(5), "a");
+    builder.start_node(SyntaxKind(3));
+    builder.token(SyntaxKind(5), "b");
+    builder.finish_node();
+    builder.finish_node();
+    builder.finish_node();
+    let (tree, _) = builder.finish();
+    let root: SyntaxNode = SyntaxNode::new_root(tree);
+    let kinds: Vec<_> = root.descendants().map(|node| node.kind()).collect();
+    assert_eq!(kinds, [SyntaxKind(0), SyntaxKind(2), SyntaxKind(3), SyntaxKind(2), SyntaxKind(3)]);
+    // the renamed node is the same as the one started with its final kind
+    let children: Vec<_> = root.green().children().map(|child| child.into_node().unwrap()).collect();
+    assert!(std::ptr::eq(children[0].head(), children[1].head()));
+}
+
+#[test]
+#[should_panic(expected = "no node to finish")]
+fn finish_node_as_without_node() {
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+    builder.finish_node_as(SyntaxKind(0));
+}
+
+#[test]
+fn kind_classes() {
+    use cstree::{
+        syntax::{TriviaMap, TriviaPolicy},
+        testing::*,
+        KindClass,
+    };
+
+    let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    builder.start_node(Root);
+    builder.token(Identifier, "x");
+    builder.token(Whitespace, " ");
+    builder.static_token(Plus);
+    builder.token(Whitespace, " ");
+    builder.token(Int, "1");
+    builder.finish_node();
+    let (tree, cache) = builder.finish();
+    let resolver = cache.unwrap().into_interner().unwrap();
+    let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+
+    let literals: Vec<_> = root
+        .tokens_of_class(KindClass::Literal)
+        .map(|token| token.resolve_text(&resolver))
+        .collect();
+    assert_eq!(literals, ["1"]);
+    assert_eq!(root.tokens_of_class(KindClass::Whitespace).count(), 2);
+    assert_eq!(Operation.class(), KindClass::Expression);
+    assert_eq!(cstree::testing::AnyKind(0).class(), KindClass::Other);
+
+    // the trivia of the classes are attached to the following tokens
+    let map = TriviaMap::new(&root, &TriviaPolicy::from_classes(), &resolver);
+    let plus = root.tokens().nth(2).unwrap();
+    assert_eq!(map.leading(plus.into()).len(), 1);
+}
+
+#[test]
+fn children_from_offset() {
+    let (tree, _) = build_tree::<()>(&two_level_tree());
+    let green = tree.green();
+    let starts = |offset: u32| -> Vec<u32> {
+        green
+            .children_from_offset(offset.into())
+            .map(|(_, start)| start.into())
+            .collect()
+    };
+    // children cover 0..6, 6..9 and 9..18
+    assert_eq!(starts(0), [0, 6, 9]);
+    assert_eq!(starts(5), [0, 6, 9]);
+    assert_eq!(starts(6), [6, 9]);
+    assert_eq!(starts(17), [9]);
+    assert!(starts(18).is_empty());
+    assert!(starts(100).is_empty());
+
+    // resuming continues with the next child
+    let mut children = green.children_from_offset(7.into());
+    let (child, start) = children.next().unwrap();
+    assert_eq!((child.text_len(), start), (3.into(), 6.into()));
+    assert_eq!((children.offset(), children.len()), (9.into(), 1));
+    let (child, start) = children.next().unwrap();
+    assert_eq!(child.as_node().unwrap().children().count(), 3);
+    assert_eq!(start, 9.into());
+    assert_eq!(children.offset(), green.text_len());
+    assert!(children.next().is_none());
+}
+
+#[test]
+fn green_visitor() {
+    use cstree::{
+        green::{GreenNode, GreenToken, GreenVisitor, Visit},
+        interning::TokenInterner,
+    };
+    use std::ops::ControlFlow;
+
+    struct Events {
+        events:   Vec<String>,
+        skip:     Option<RawSyntaxKind>,
+        stop_at:  Option<RawSyntaxKind>,
+        resolver: TokenInterner,
+    }
+
+    impl GreenVisitor for Events {
+        fn enter_node(&mut self, node: &GreenNode) -> Visit {
+            self.events.push(format!("enter {}", node.kind().0));
+            if Some(node.kind()) == self.skip {
+                Visit::SkipChildren
+            } else {
+                Visit::Continue
+            }
+        }
+
+        fn leave_node(&mut self, node: &GreenNode) -> ControlFlow<()> {
+            self.events.push(format!("leave {}", node.kind().0));
+            ControlFlow::Continue(())
+        }
+
+        fn token(&mut self, token: &GreenToken) -> ControlFlow<()> {
+            self.events.push(token.text(&self.resolver).unwrap().to_string());
+            if Some(token.kind()) == self.stop_at {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        }
+    }
+
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+    build_recursive(&two_level_tree(), &mut builder, 0);
+    let (tree, cache) = builder.finish();
+    let mut visitor = Events {
+        events:   Vec::new(),
+        skip:     None,
+        stop_at:  None,
+        resolver: cache.unwrap().into_interner().unwrap(),
+    };
+    assert!(tree.visit(&mut visitor).is_continue());
+    assert_eq!(
+        visitor.events,
+        [
+            "enter 0", "enter 1", "0.0", "0.1", "leave 1", "enter 4", "1.0", "leave 4", "enter 6", "2.0", "2.1", "2.2",
+            "leave 6", "leave 0"
+        ]
+    );
+
+    visitor.events.clear();
+    visitor.skip = Some(RawSyntaxKind(1));
+    visitor.stop_at = Some(RawSyntaxKind(8));
+    assert!(tree.visit(&mut visitor).is_break());
+    assert_eq!(
+        visitor.events,
+        ["enter 0", "enter 1", "leave 1", "enter 4", "1.0", "leave 4", "enter 6", "2.0", "2.1"]
+    );
+}
+
+#[test]
+fn node_hook() {
+    use cstree::{build::NodeCaching, util::NodeOrToken};
+
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+    // Drop the tokens of kind 2 and do not cache nodes of kind 4.
+    builder.set_node_hook(|kind, children, _| {
+        children.retain(|child| !matches!(child, NodeOrToken::Token(token) if token.kind() == RawSyntaxKind(2)));
+        if kind == SyntaxKind(4) {
+            NodeCaching::Bypass
+        } else {
+            NodeCaching::Cache
+        }
+    });
+    let checkpoint = builder.checkpoint();
+    builder.start_node(SyntaxKind(0));
+    for _ in 0..2 {
+        for kind in [1, 4] {
+            builder.start_node(SyntaxKind(kind));
+            builder.token(SyntaxKind(2), "drop");
+            builder.token(SyntaxKind(3), "keep");
+            builder.finish_node();
+        }
+    }
+    assert_eq!(builder.text_since(checkpoint), 16.into());
+    assert_eq!(builder.tokens_since(checkpoint), 4);
+    builder.remove_node_hook();
+    builder.finish_node();
+    let (tree, cache) = builder.finish();
+    assert_eq!(tree.text_len(), 16.into());
+    assert_eq!(tree.token_count(), 4);
+    assert!(tree.children().all(|child| child.as_node().unwrap().children().count() == 1));
+    // Only the nodes of kind 1 were added to the cache, the root has too many children.
+    assert_eq!(cache.unwrap().green_cache().node_count(), 1);
+}
+
+#[test]
+fn relex_range() {
+    use cstree::{
+        syntax::Lookaround,
+        text::{TextEdit, TextSize},
+    };
+
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+    build_recursive(&two_level_tree(), &mut builder, 0);
+    let (tree, cache) = builder.finish();
+    let mut resolver = cache.unwrap().into_interner().unwrap();
+    let tree: SyntaxNode = SyntaxNode::new_root(tree);
+    let range = |start: u32, end: u32| TextRange::new(start.into(), end.into());
+    let no_lookaround = |_| Lookaround::NONE;
+
+    let edit = TextEdit::insert(4.into(), "x");
+    let relex = tree.relex_range(edit.range, no_lookaround).unwrap();
+    assert_eq!(relex.text_range(), range(3, 6));
+    assert_eq!(relex.text(&edit, &resolver), "0x.1");
+    let new_tree = relex.splice(&[(SyntaxKind(3), "0x.1")], &mut resolver).unwrap();
+    let new_tree: SyntaxNode = SyntaxNode::new_root(new_tree);
+    assert_eq!(new_tree.resolve_text(&resolver), "0.00x.11.02.02.12.2");
+    assert_eq!(new_tree.children().nth(1).unwrap().green(), tree.children().nth(1).unwrap().green());
+
+    // Tokens that touch the edit are re-lexed, too.
+    let edit = TextEdit::insert(3.into(), "x");
+    let relex = tree.relex_range(edit.range, no_lookaround).unwrap();
+    assert_eq!(relex.text_range(), range(0, 6));
+    assert_eq!(relex.text(&edit, &resolver), "0.0x0.1");
+    let relex = tree.relex_range(TextRange::empty(TextSize::from(18)), no_lookaround).unwrap();
+    assert_eq!(relex.text_range(), range(15, 18));
+
+    // Tokens with different parents cannot be spliced.
+    let edit = TextEdit::delete(range(7, 10));
+    let relex = tree.relex_range(edit.range, no_lookaround).unwrap();
+    assert_eq!(relex.tokens().count(), 2);
+    assert_eq!(relex.text(&edit, &resolver), "1.0");
+    assert!(relex.splice(&[(SyntaxKind(5), "1.0")], &mut resolver).is_none());
+
+    let lookaround = |kind: SyntaxKind| match kind.0 {
+        8 => Lookaround::new(1, 1),
+        9 => Lookaround::new(1, 0),
+        _ => Lookaround::NONE,
+    };
+    let relex = tree.relex_range(range(16, 16), lookaround).unwrap();
+    assert_eq!(relex.text_range(), range(9, 18));
+    assert_eq!(relex.first_token().kind(), SyntaxKind(7));
+    let relex = tree.relex_range(range(10, 10), lookaround).unwrap();
+    assert_eq!(relex.text_range(), range(9, 12));
+    let node = tree.children().nth(2).unwrap();
+    let relex = node.relex_range(range(13, 13), |_| Lookaround::new(5, 5)).unwrap();
+    assert_eq!(relex.text_range(), node.text_range());
+}
+
+#[test]
+fn repeated_tokens() {
+    use cstree::testing::MySyntax;
+
+    let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    builder.start_node(MySyntax::Root);
+    let checkpoint = builder.checkpoint();
+    builder.token_n(MySyntax::Whitespace, "\t", 4);
+    builder.token_n(MySyntax::Identifier, "unused", 0);
+    builder.static_token_n(MySyntax::Plus, 3);
+    assert_eq!(builder.text_since(checkpoint), 7.into());
+    assert_eq!(builder.tokens_since(checkpoint), 7);
+    builder.finish_node();
+    let (tree, cache) = builder.finish();
+    let interner = cache.unwrap().into_interner().unwrap();
+    assert_eq!(tree.token_count(), 7);
+    let tree = cstree::syntax::SyntaxNode::<MySyntax>::new_root(tree);
+    assert_eq!(tree.resolve_text(&interner), "\t\t\t\t+++");
+    let first = tree.first_token().unwrap();
+    assert!(tree.children_with_tokens().take(4).all(|child| child.as_token().unwrap().green() == first.green()));
+}
+
+#[test]
+#[should_panic(expected = "repeated token run exceeds u32 text length")]
+fn repeated_tokens_overflow() {
+    use cstree::testing::MySyntax;
+
+    let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    builder.start_node(MySyntax::Root);
+    builder.token_n(MySyntax::Whitespace, "  ", 1 << 31);
+}
+
+#[test]
+fn build_flat() {
+    use cstree::build::NodeCaching;
+
+    let tokens = [(SyntaxKind(1), "a"), (SyntaxKind(2), " "), (SyntaxKind(1), "bc"), (SyntaxKind(2), "\n")];
+    let builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+    let (tree, cache) = builder.build_flat(SyntaxKind(0), tokens);
+    let interner = cache.unwrap().into_interner().unwrap();
+    assert_eq!(tree.token_count(), 4);
+    let root: SyntaxNode = SyntaxNode::new_root(tree);
+    assert_eq!(root.resolve_text(&interner), "a bc\n");
+    let ranges: Vec<_> = root.tokens().map(|token| token.text_range()).collect();
+    assert_eq!(ranges[2], TextRange::new(2.into(), 4.into()));
+
+    // The hook is called for the root node.
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+    builder.set_node_hook(|_, children, _| {
+        children.retain(|child| child.kind() != RawSyntaxKind(2));
+        NodeCaching::Cache
+    });
+    let (tree, _) = builder.build_flat(SyntaxKind(0), tokens);
+    assert_eq!(tree.token_count(), 2);
+    assert_eq!(tree.text_len(), 3.into());
+}
+
+#[test]
+#[should_panic = "already contains elements"]
+fn build_flat_after_start() {
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+    builder.start_node(SyntaxKind(0));
+    builder.build_flat(SyntaxKind(0), [(SyntaxKind(1), "a")]);
+}
+
+#[test]
+fn concat() {
+    use cstree::green::GreenNode;
+
+    let mut cache = NodeCache::new();
+    let first = build_tree_with_cache(&two_level_tree(), &mut cache);
+    let second = build_tree_with_cache(&tree_with_eq_tokens(), &mut cache);
+    let resolver = cache.into_interner().unwrap();
+    let joined = GreenNode::concat(RawSyntaxKind(42), &[first.clone(), second.clone(), first.clone()]);
+    assert_eq!(joined.kind(), RawSyntaxKind(42));
+    assert_eq!(joined.token_count(), 2 * first.token_count() + second.token_count());
+    assert_eq!(joined.node_count(), 1 + 2 * first.node_count() + second.node_count());
+
+    let joined: SyntaxNode = SyntaxNode::new_root(joined);
+    assert_eq!(joined.resolve_text(&resolver), "0.00.11.02.02.12.2abcabc0.00.11.02.02.12.2");
+    let parts: Vec<_> = joined.children().collect();
+    assert_eq!(parts[2].green(), &first);
+    assert_eq!(parts[1].text_range(), TextRange::new(18.into(), 24.into()));
+    assert_eq!(parts[2].first_token().unwrap().text_range().start(), 24.into());
+
+    let empty = GreenNode::concat(RawSyntaxKind(0), &[]);
+    assert_eq!(empty.text_len(), 0.into());
+}
+
+#[test]
+fn detached_elements() {
+    use cstree::green::{GreenNode, GreenToken};
+
+    let (tree, mut interner) = {
+        let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+        build_recursive(&two_level_tree(), &mut builder, 0);
+        let (tree, cache) = builder.finish();
+        (tree, cache.unwrap().into_interner().unwrap())
+    };
+    let token = GreenToken::new_detached(SyntaxKind(2), "0.0", &mut interner);
+    let original = tree.children().next().unwrap().into_node().unwrap();
+    assert_eq!(original.children().next().unwrap().into_token(), Some(&token));
+    let other = GreenToken::new_detached(SyntaxKind(3), "new", &mut interner);
+    assert_eq!(other.text(&interner), Some("new"));
+
+    let node = GreenNode::new_detached(SyntaxKind(1), [token.into(), other.into()]);
+    assert_eq!(node.kind(), RawSyntaxKind(1));
+    assert_eq!(node.text_len(), 6.into());
+    let tree: SyntaxNode = SyntaxNode::new_root(tree);
+    let new_tree: SyntaxNode = SyntaxNode::new_root(tree.first_child().unwrap().replace_with(node));
+    assert_eq!(new_tree.resolve_text(&interner), "0.0new1.02.02.12.2");
+}
+
+#[test]
+fn preorder_with_tokens_in_range() {
+    use cstree::traversal::WalkEvent;
+
+    let (tree, _) = build_tree::<()>(&two_level_tree());
+    let events = |range: TextRange| -> Vec<String> {
+        tree.preorder_with_tokens_in_range(range)
+            .map(|event| match event {
+                WalkEvent::Enter(element) => format!("+{}", element.kind().0),
+                WalkEvent::Leave(element) => format!("-{}", element.kind().0),
+            })
+            .collect()
+    };
+    assert_eq!(
+        events(TextRange::new(4.into(), 10.into())),
+        ["+0", "+1", "+3", "-3", "-1", "+4", "+5", "-5", "-4", "+6", "+7", "-7", "-6", "-0"]
+    );
+    assert_eq!(
+        events(TextRange::new(12.into(), 15.into())),
+        ["+0", "+6", "+8", "-8", "-6", "-0"]
+    );
+    assert_eq!(events(TextRange::new(3.into(), 3.into())), ["+0", "+1", "-1", "-0"]);
+    assert_eq!(events(TextRange::new(4.into(), 4.into())), ["+0", "+1", "+3", "-3", "-1", "-0"]);
+
+    let node = tree.children().nth(1).unwrap();
+    assert_eq!(node.preorder_with_tokens_in_range(TextRange::new(0.into(), 3.into())).count(), 0);
+}
+
+#[test]
+fn zip_with() {
+    use cstree::syntax::{Divergence, ZipEvent};
+    use Element::*;
+
+    let (tree, resolver) = build_tree::<()>(&two_level_tree());
+    let (same, same_resolver) = build_tree::<()>(&two_level_tree());
+    let events: Vec<_> = tree.zip_with(&resolver, &same, &same_resolver).collect();
+    assert_eq!(events.len(), 20);
+    assert!(events.iter().all(|event| !matches!(event, ZipEvent::Diverge(..))));
+    assert_eq!(events[0], ZipEvent::Enter((&tree).into(), (&same).into()));
+    assert_eq!(events[19], ZipEvent::Leave((&tree).into(), (&same).into()));
+
+    let divergences = |other: &Element<'static>| -> Vec<(u32, Divergence)> {
+        let (other, other_resolver) = build_tree::<()>(other);
+        tree.zip_with(&resolver, &other, &other_resolver)
+            .filter_map(|event| match event {
+                ZipEvent::Diverge(left, _right, divergence) => Some((left.kind().0, divergence)),
+                _ => None,
+            })
+            .collect()
+    };
+    let texts = divergences(&tree_with_eq_tokens());
+    assert_eq!(texts.len(), 6);
+    assert!(texts.iter().all(|&(_, divergence)| divergence == Divergence::Text));
+    let other = Node(vec![
+        Node(vec![Token("0.0"), Token("0.1")]),
+        Node(vec![Token("1.0"), Token("1.1")]),
+        Node(vec![Token("2.0"), Token("2.1"), Token("2.2")]),
+    ]);
+    assert_eq!(divergences(&other), [(4, Divergence::Children), (6, Divergence::Kind)]);
+    assert_eq!(divergences(&Node(vec![Token("0")])), [(0, Divergence::Children)]);
+}
+
+#[test]
+fn materialize() {
+    let (tree, resolver) = build_tree::<()>(&two_level_tree());
+    let node = tree.children().nth(2).unwrap();
+    node.materialize();
+    let tokens: Vec<_> = node.tokens().collect();
+    assert_eq!(tokens.len(), 3);
+    assert!(tokens.iter().all(|token| token.parent() == node));
+    tree.materialize();
+    assert_eq!(tree.descendants_with_tokens().count(), 10);
+    assert_eq!(tree.last_token().unwrap(), *tokens.last().unwrap());
+    assert_eq!(tree.resolve_text(&resolver), "0.00.11.02.02.12.2");
+}
+
+#[test]
+#[cfg(feature = "cooked_values")]
+fn cooked_values() {
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+    builder.set_cooker(|kind, text| (kind.0 % 2 == 0).then(|| text.replace('.', "")));
+    build_recursive(&two_level_tree(), &mut builder, 0);
+    let (tree, cache) = builder.finish();
+    let interner = cache.unwrap().into_interner().unwrap();
+    let tree: SyntaxNode = SyntaxNode::new_root(tree);
+
+    let cooked: Vec<_> = tree.tokens().map(|token| token.cooked_text(&interner)).collect();
+    assert_eq!(cooked, ["00", "0.1", "1.0", "2.0", "21", "2.2"]);
+    let first = tree.first_token().unwrap();
+    assert_eq!(first.resolve_text(&interner), "0.0");
+    assert_eq!(first.green().cooked_text(&interner), Some("00"));
+    assert!(tree.green().token_keys().any(|key| Some(key) == first.cooked_key()));
+
+    // cooked values are kept when the tree is moved to a new interner
+    let resolved: ResolvedNode = SyntaxNode::new_root_with_resolver(tree.green().clone(), interner).compact();
+    assert_eq!(resolved.first_token().unwrap().cooked_text(), "00");
+}
+
+#[test]
+#[cfg(feature = "stable_hash")]
+fn stable_hash() {
+    use cstree::interning::Interner;
+
+    let (tree, interner) = build_tree::<()>(&two_level_tree());
+    let mut cache = NodeCache::new();
+    cache.interner_mut().get_or_intern("2.2");
+    let other = build_tree_with_cache(&two_level_tree(), &mut cache);
+    assert_ne!(tree.green(), &other);
+    let hash = tree.green().stable_hash(&interner);
+    assert_eq!(other.stable_hash(cache.interner()), hash);
+    // The hash must not change between runs or versions.
+    assert_eq!(hash, 10387803947524052799);
+
+    let first = tree.first_token().unwrap().green();
+    assert_ne!(first.stable_hash(&interner), tree.last_token().unwrap().green().stable_hash(&interner));
+    let changed = tree.green().remap_kinds(|kind| RawSyntaxKind(kind.0 + 1));
+    assert_ne!(changed.stable_hash(&interner), hash);
+}
+
+#[test]
+fn binary_roundtrip() {
+    use cstree::binary;
+    let (tree, resolver) = build_tree::<()>(&tree_with_eq_tokens());
+    let bytes = binary::to_vec(&tree, &resolver);
+    let restored: ResolvedNode = binary::from_slice(&bytes).unwrap();
+    assert_eq!(
+        restored.debug(restored.resolver().as_ref(), true),
+        tree.debug(&resolver, true)
+    );
+
+    // Reading from a stream leaves the data after the tree untouched.
+    let node2 = tree.children().nth(2).unwrap();
+    let mut stream = bytes.clone();
+    binary::write(node2, &resolver, &mut stream).unwrap();
+    let mut reader = &stream[..];
+    let first: ResolvedNode = binary::read(&mut reader).unwrap();
+    let second: ResolvedNode = binary::read(&mut reader).unwrap();
+    assert!(reader.is_empty());
+    assert_eq!(first.text(), "abcabc");
+    // The subtree becomes the root of its own tree.
+    assert_eq!(second.green(), node2.green());
+    assert_eq!(second.text_range(), TextRange::up_to(node2.text_range().len()));
+}
+
+#[test]
+fn binary_range() {
+    use cstree::binary;
+    let (tree, resolver) = build_tree::<()>(&two_level_tree());
+    let bytes = binary::to_vec(&tree, &resolver);
+    let read = |start: u32, end: u32| {
+        let (node, offset): (ResolvedNode, _) =
+            binary::from_slice_range(&bytes, TextRange::new(start.into(), end.into())).unwrap();
+        (node.kind(), node.text().to_string(), u32::from(offset))
+    };
+
+    assert_eq!(read(6, 9), (SyntaxKind(4), "1.0".to_string(), 6));
+    assert_eq!(read(13, 14), (SyntaxKind(6), "2.02.12.2".to_string(), 9));
+    assert_eq!(read(2, 7), (SyntaxKind(0), "0.00.11.02.02.12.2".to_string(), 0));
+    assert_eq!(read(0, 18), (SyntaxKind(0), "0.00.11.02.02.12.2".to_string(), 0));
+}
+
+#[test]
+fn binary_mapped() {
+    use cstree::binary::{self, DecodeError, MappedTree};
+    let (tree, resolver) = build_tree::<()>(&two_level_tree());
+    let bytes = binary::to_vec_mapped(&tree, &resolver);
+    // the data does not have to be aligned in memory
+    let mut unaligned = vec![0];
+    unaligned.extend_from_slice(&bytes);
+    let mapped = MappedTree::<SyntaxKind>::new(&unaligned[1..]).unwrap();
+    mapped.verify().unwrap();
+    assert_eq!(mapped.len(), 10);
+
+    let root = mapped.root();
+    assert_eq!(root.kind(), SyntaxKind(0));
+    assert_eq!(root.parent(), None);
+    assert_eq!(root.text_range(), tree.text_range());
+    let kinds: Vec<_> = root.children().map(|child| child.kind()).collect();
+    assert_eq!(kinds, [SyntaxKind(1), SyntaxKind(4), SyntaxKind(6)]);
+    let last = root.children().last().unwrap();
+    let texts: Vec<_> = last
+        .children_with_tokens()
+        .map(|child| child.into_token().unwrap().text())
+        .collect();
+    assert_eq!(texts, ["2.0", "2.1", "2.2"]);
+    let token = last.first_child_or_token().unwrap().into_token().unwrap();
+    assert_eq!(token.text_range(), TextRange::new(9.into(), 12.into()));
+    assert_eq!(token.parent(), last);
+    assert_eq!(token.parent().parent(), Some(root));
+    let next = token.next_sibling_or_token().unwrap().into_token().unwrap();
+    assert_eq!(next.text(), "2.1");
+    assert!(last.next_sibling_or_token().is_none());
+    let middle = root.first_child_or_token().unwrap().into_node().unwrap().next_sibling_or_token();
+    assert_eq!(middle.unwrap().into_node().unwrap().kind(), SyntaxKind(4));
+    assert_eq!(root.covering_node(TextRange::new(13.into(), 14.into())), last);
+
+    // the regular readers understand the mapped layout
+    let restored: ResolvedNode = binary::from_slice(&bytes).unwrap();
+    assert_eq!(restored.green(), tree.green());
+    let read: ResolvedNode = binary::read(&bytes[..]).unwrap();
+    assert_eq!(read.green(), tree.green());
+    let (node, offset): (ResolvedNode, _) =
+        binary::from_slice_range(&bytes, TextRange::new(6.into(), 9.into())).unwrap();
+    assert_eq!((node.kind(), u32::from(offset)), (SyntaxKind(4), 6));
+    assert_eq!(mapped.root().to_tree::<()>().green(), tree.green());
+
+    // creating the tree only checks the header, verifying it checks the data
+    let mut corrupted = bytes.clone();
+    let text = bytes.len() - 1;
+    corrupted[text] ^= 1;
+    let mapped = MappedTree::<SyntaxKind>::new(&corrupted).unwrap();
+    assert!(matches!(mapped.verify(), Err(DecodeError::ChecksumMismatch)));
+    assert!(matches!(
+        MappedTree::<SyntaxKind>::new(&bytes[..bytes.len() - 1]),
+        Err(DecodeError::Truncated)
+    ));
+    let regular = binary::to_vec(&tree, &resolver);
+    assert!(matches!(MappedTree::<SyntaxKind>::new(&regular), Err(DecodeError::Corrupted(_))));
+}
+
+#[test]
+fn binary_static_text_with_text() {
+    use cstree::binary::{self, DecodeError, MappedTree};
+
+    // the same kinds as `SyntaxKind`, but every token has static text
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct StaticKind(u32);
+    impl Syntax for StaticKind {
+        fn from_raw(raw: RawSyntaxKind) -> Self {
+            Self(raw.0)
+        }
+
+        fn into_raw(self) -> RawSyntaxKind {
+            RawSyntaxKind(self.0)
+        }
+
+        fn static_text(self) -> Option<&'static str> {
+            Some("x")
+        }
+    }
+
+    // the data passes the checksum, but stores text for tokens of kinds with static text
+    let (tree, resolver) = build_tree::<()>(&two_level_tree());
+    let corrupted = |error| matches!(error, DecodeError::Corrupted("static-text kind with text"));
+    let bytes = binary::to_vec(&tree, &resolver);
+    assert!(corrupted(binary::from_slice::<StaticKind, ()>(&bytes).unwrap_err()));
+    assert!(corrupted(binary::read::<StaticKind, ()>(&bytes[..]).unwrap_err()));
+    let range = TextRange::new(6.into(), 9.into());
+    assert!(corrupted(binary::from_slice_range::<StaticKind, ()>(&bytes, range).unwrap_err()));
+    let mapped = binary::to_vec_mapped(&tree, &resolver);
+    assert!(corrupted(MappedTree::<StaticKind>::new(&mapped).unwrap().verify().unwrap_err()));
+}
+
+#[test]
+fn binary_errors() {
+    use cstree::binary::{self, DecodeError};
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct SmallKind(u32);
+    impl Syntax for SmallKind {
+        const RAW_KINDS: std::ops::RangeInclusive<u32> = 0..=9;
+
+        fn from_raw(raw: RawSyntaxKind) -> Self {
+            Self(raw.0)
+        }
+
+        fn into_raw(self) -> RawSyntaxKind {
+            RawSyntaxKind(self.0)
+        }
+
+        fn static_text(self) -> Option<&'static str> {
+            None
+        }
+    }
+
+    let (tree, resolver) = build_tree::<()>(&tree_with_eq_tokens());
+    let bytes = binary::to_vec(&tree, &resolver);
+    let decode = |bytes: &[u8]| binary::from_slice::<SyntaxKind, ()>(bytes).unwrap_err();
+    let read = |bytes: &[u8]| binary::read::<SyntaxKind, ()>(bytes).unwrap_err();
+
+    assert!(matches!(
+        decode(b"not a syntax tree, but long enough"),
+        DecodeError::NotATree
+    ));
+    assert!(matches!(
+        binary::from_slice::<SmallKind, ()>(&bytes).unwrap_err(),
+        DecodeError::SyntaxMismatch
+    ));
+
+    let mut other_version = bytes.clone();
+    other_version[6..8].copy_from_slice(&2u16.to_ne_bytes());
+    assert!(matches!(decode(&other_version), DecodeError::UnsupportedVersion(2)));
+
+    for len in [10, 40, bytes.len() - 1] {
+        assert!(matches!(decode(&bytes[..len]), DecodeError::Truncated));
+        assert!(matches!(read(&bytes[..len]), DecodeError::Truncated));
+    }
+
+    let mut corrupted = bytes.clone();
+    let text = 32 + corrupted[32..].iter().position(|&b| b == b'c').unwrap();
+    corrupted[text] = b'x';
+    assert!(matches!(decode(&corrupted), DecodeError::ChecksumMismatch));
+    assert!(matches!(read(&corrupted), DecodeError::ChecksumMismatch));
+
+    let mut trailing = bytes.clone();
+    trailing.push(0);
+    assert!(matches!(decode(&trailing), DecodeError::Corrupted(_)));
+
+    let mut flags = bytes.clone();
+    flags[5] = 0x80;
+    assert!(matches!(decode(&flags), DecodeError::Corrupted(_)));
+    #[cfg(not(feature = "zstd"))]
+    {
+        flags[5] = 1;
+        assert!(matches!(decode(&flags), DecodeError::Compressed));
+    }
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn binary_compressed() {
+    use cstree::binary::{self, DecodeError};
+    use Element::*;
+    let tree = Node((0..1000).map(|_| Token("token")).collect());
+    let (tree, resolver) = build_tree::<()>(&tree);
+    let bytes = binary::to_vec(&tree, &resolver);
+    let compressed = binary::to_vec_compressed(&tree, &resolver, 3);
+    assert!(compressed.len() < bytes.len() / 4);
+
+    let restored: ResolvedNode = binary::from_slice(&compressed).unwrap();
+    assert_eq!(restored.green(), tree.green());
+    let mut stream = compressed.clone();
+    stream.extend_from_slice(&bytes);
+    let mut reader = &stream[..];
+    let first: ResolvedNode = binary::read(&mut reader).unwrap();
+    assert_eq!(reader, &bytes[..]);
+    assert_eq!(first.green(), tree.green());
+
+    let (node, offset): (ResolvedNode, _) =
+        binary::from_slice_range(&compressed, TextRange::new(5.into(), 7.into())).unwrap();
+    assert_eq!(
+        (node.kind(), node.text().to_string(), offset),
+        (SyntaxKind(0), "token".repeat(1000), 0.into())
+    );
+
+    let truncated = &compressed[..compressed.len() - 1];
+    assert!(matches!(
+        binary::from_slice::<SyntaxKind, ()>(truncated).unwrap_err(),
+        DecodeError::Truncated
+    ));
+    assert!(matches!(
+        binary::read::<SyntaxKind, ()>(truncated).unwrap_err(),
+        DecodeError::Truncated
+    ));
+}
+
+#[test]
+fn node_guard_finishes_on_panic() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+    builder.start_node(SyntaxKind(0));
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        builder.node(SyntaxKind(1), |builder| {
+            builder.token(SyntaxKind(2), "a");
+            builder.start_node(SyntaxKind(3));
+            panic!("parser bug");
+        })
+    }));
+    assert!(result.is_err());
+    builder.token(SyntaxKind(2), "b");
+    builder.finish_node();
+    let (green, _) = builder.finish();
+    let tree = SyntaxNode::<()>::new_root(green);
+    let kinds: Vec<_> = tree.descendants().map(|node| node.kind()).collect();
+    assert_eq!(kinds, [SyntaxKind(0), SyntaxKind(1), SyntaxKind(3)]);
+}
+
+#[test]
+#[cfg(feature = "multi_threaded_interning")]
+fn convert_cache_interner() {
+    use cstree::interning::{MultiThreadedTokenInterner, TokenInterner};
+
+    let mut cache = NodeCache::new();
+    let green = build_tree_with_cache(&two_level_tree(), &mut cache);
+    let tree = SyntaxNode::<()>::new_root(green);
+    let mut cache: NodeCache<'static, MultiThreadedTokenInterner> =
+        cache.map_interner(MultiThreadedTokenInterner::from).unwrap();
+    assert_eq!(tree.resolve_text(cache.interner()), "0.00.11.02.02.12.2");
+
+    // nodes and tokens are still shared with the new interner
+    let green = build_tree_with_cache(&two_level_tree(), &mut cache);
+    assert!(green.children().zip(tree.green().children()).all(|(a, b)| a == b));
+    let other = build_tree_with_cache(&tree_with_eq_tokens(), &mut cache);
+
+    let cache: NodeCache<'static, TokenInterner> = cache.map_interner(TokenInterner::from).unwrap();
+    let other = SyntaxNode::<()>::new_root(other);
+    assert_eq!(other.resolve_text(cache.interner()), "abcabc");
+    assert_eq!(tree.resolve_text(cache.interner()), "0.00.11.02.02.12.2");
+
+    let mut interner = cstree::interning::new_interner();
+    let cache = NodeCache::with_interner(&mut interner);
+    assert!(cache.map_interner(MultiThreadedTokenInterner::from).is_err());
+}
+
 #[test]
 fn data() {
     let tree = two_level_tree();
@@ -170,6 +2927,119 @@ fn inline_resolver() {
     }
 }
 
+#[test]
+fn debug_with_options() {
+    use cstree::syntax::{AnsiColor, DebugOptions};
+
+    let tree = two_level_tree();
+    let (tree, resolver) = build_tree::<()>(&tree);
+    let options = DebugOptions::new();
+    assert_eq!(tree.debug_with(&resolver, &options), tree.debug(&resolver, true));
+
+    let options = DebugOptions::new().max_depth(1).offsets(false);
+    assert_eq!(
+        tree.debug_with(&resolver, &options),
+        "SyntaxKind(0)\n  SyntaxKind(1)\n    ...\n  SyntaxKind(4)\n    ...\n  SyntaxKind(6)\n    ...\n"
+    );
+    let options = DebugOptions::new().max_depth(0);
+    assert_eq!(tree.debug_with(&resolver, &options), "SyntaxKind(0)@0..18\n  ...\n");
+
+    let node = tree.children().nth(1).unwrap();
+    let options = DebugOptions::new()
+        .max_text_len(1)
+        .offsets(false)
+        .colored(AnsiColor::Blue, AnsiColor::Green);
+    assert_eq!(
+        node.debug_with(&resolver, &options),
+        "\x1b[34mSyntaxKind(4)\x1b[0m\n  \x1b[32mSyntaxKind(5)\x1b[0m \"1 ...\"\n"
+    );
+    let options = options.color_kinds(|kind| (kind == SyntaxKind(5)).then_some(AnsiColor::Red));
+    assert_eq!(
+        node.debug_with(&resolver, &options),
+        "\x1b[34mSyntaxKind(4)\x1b[0m\n  \x1b[31mSyntaxKind(5)\x1b[0m \"1 ...\"\n"
+    );
+}
+
+#[test]
+fn debug_color_by_class() {
+    use cstree::{
+        syntax::{AnsiColor, DebugOptions},
+        testing::*,
+    };
+
+    let mut builder: GreenNodeBuilder<TestSyntaxKind> = GreenNodeBuilder::new();
+    builder.start_node(Root);
+    builder.start_node(Operation);
+    builder.token(Int, "1");
+    builder.static_token(Plus);
+    builder.token(Error, "?");
+    builder.finish_node();
+    builder.finish_node();
+    let (green, cache) = builder.finish();
+    let interner = cache.unwrap().into_interner().unwrap();
+    let root = SyntaxNode::<TestSyntaxKind>::new_root(green);
+
+    let options = DebugOptions::new().offsets(false).color_by_class();
+    assert_eq!(
+        root.debug_with(&interner, &options),
+        concat!(
+            "Root\n",
+            "  \x1b[34mOperation\x1b[0m\n",
+            "    \x1b[33mInt\x1b[0m \"1\"\n",
+            "    Plus \"+\"\n",
+            "    \x1b[31mError\x1b[0m \"?\"\n",
+        )
+    );
+    // kinds without a class color fall back to the default colors
+    let options = options.colored(AnsiColor::White, AnsiColor::Black);
+    assert!(root.debug_with(&interner, &options).starts_with("\x1b[37mRoot\x1b[0m\n"));
+    assert!(root.debug_with(&interner, &options).contains("\x1b[30mPlus\x1b[0m"));
+    assert_eq!(AnsiColor::for_class(cstree::KindClass::Keyword), Some(AnsiColor::Magenta));
+}
+
+#[test]
+fn debug_kind_names_without_resolver() {
+    use cstree::syntax::{AnsiColor, DebugOptions};
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, cstree::Syntax)]
+    #[repr(u32)]
+    enum Kind {
+        Root,
+        Ident,
+        #[static_text(";")]
+        Semicolon,
+    }
+    assert_eq!(Kind::kind_name(RawSyntaxKind(1)), Some("Ident"));
+    assert_eq!(Kind::kind_name(RawSyntaxKind(3)), None);
+
+    let mut builder: GreenNodeBuilder<Kind> = GreenNodeBuilder::new();
+    builder.start_node(Kind::Root);
+    builder.token(Kind::Ident, "foo");
+    builder.static_token(Kind::Semicolon);
+    builder.finish_node();
+    let (green, cache) = builder.finish();
+    let interner = cache.unwrap().into_interner().unwrap();
+    assert_eq!(format!("{:?}", green.debug_kinds::<Kind>()), "Root@0..4");
+    assert_eq!(
+        format!("{:#?}", green.debug_kinds::<Kind>()),
+        "Root@0..4\n  Ident@0..3\n  Semicolon@3..4 \";\"\n"
+    );
+
+    let root = cstree::syntax::SyntaxNode::<Kind>::new_root(green);
+    assert_eq!(format!("{:?}", root), "Root@0..4");
+    let tokens: Vec<_> = root.tokens().map(|token| format!("{:?}", token)).collect();
+    assert_eq!(tokens, ["Ident@0..3", "Semicolon@3..4 \";\""]);
+    let options = DebugOptions::new().offsets(false).colored(AnsiColor::Blue, AnsiColor::Green);
+    assert_eq!(
+        root.debug_with(&interner, &options),
+        "\x1b[34mRoot\x1b[0m\n  \x1b[32mIdent\x1b[0m \"foo\"\n  \x1b[32mSemicolon\x1b[0m \";\"\n"
+    );
+
+    // without a `kind_name`, the `Debug` representation of the kind is used
+    let (tree, _) = build_tree::<()>(&two_level_tree());
+    assert_eq!(format!("{:?}", tree), "SyntaxKind(0)@0..18");
+}
+
 #[test]
 fn assert_debug_display() {
     use std::fmt;
@@ -184,3 +3054,220 @@ fn assert_debug_display() {
     fn dbg<T: fmt::Debug>() {}
     dbg::<GreenNodeBuilder<'static, 'static, SyntaxKind>>();
 }
+
+#[test]
+fn pretty_print() {
+    use cstree::pretty::{Doc, Format};
+
+    struct Keep;
+    impl Format<SyntaxKind> for Keep {}
+
+    let (root, resolver) = build_tree::<()>(&two_level_tree());
+    let rendered = Doc::from_tree(&root, &mut Keep, &resolver).render(4);
+    assert_eq!(rendered.text(), "0.00.11.02.02.12.2");
+    for mapping in rendered.mappings() {
+        assert_eq!(mapping.output, mapping.input);
+    }
+    let child = root.children().nth(1).unwrap();
+    assert_eq!(rendered.output_range(child.text_range()), Some(child.text_range()));
+    assert_eq!(rendered.source_at(7.into()).unwrap().input, TextRange::new(6.into(), 9.into()));
+
+    let list = |items: &[&str]| {
+        Doc::concat([
+            Doc::text("["),
+            Doc::concat([
+                Doc::softline(),
+                Doc::intersperse(items.iter().map(|&item| Doc::text(item)), Doc::concat([",".into(), Doc::line()])),
+            ])
+            .nest(2),
+            Doc::softline(),
+            Doc::text("]"),
+        ])
+        .group()
+    };
+    let doc = Doc::concat([list(&["a", "b"]), Doc::text(";")]);
+    assert_eq!(doc.render(7).text(), "[a, b];");
+    // the text after the group counts towards its width
+    assert_eq!(doc.render(6).text(), "[\n  a,\n  b\n];");
+    let nested = Doc::concat([
+        list(&["a"]),
+        Doc::hardline(),
+        Doc::hardline(),
+        list(&["b"]).source(TextRange::up_to(1.into())),
+    ])
+    .nest(4);
+    // empty lines have no indentation, and mappings start after the indentation
+    let rendered = nested.render(80);
+    assert_eq!(rendered.text(), "[a]\n\n    [b]");
+    assert_eq!(rendered.output_range(TextRange::up_to(1.into())), Some(TextRange::new(9.into(), 12.into())));
+    // a hard line breaks the enclosing group
+    let forced = Doc::concat([Doc::text("a"), Doc::line(), Doc::hardline().nest(1)]).group();
+    assert_eq!(forced.render(80).text(), "a\n\n");
+}
+
+#[test]
+fn canonical_text() {
+    use cstree::syntax::Canonicalize;
+
+    const WS: SyntaxKind = SyntaxKind(9);
+    let build = |tokens: &[(u32, &str)], cache: &mut NodeCache| {
+        let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::with_cache(cache);
+        builder.start_node(SyntaxKind(0));
+        for &(kind, text) in tokens {
+            builder.token(SyntaxKind(kind), text);
+        }
+        builder.finish_node();
+        SyntaxNode::<()>::new_root(builder.finish().0)
+    };
+    let mut cache = NodeCache::new();
+    let spaced = build(&[(9, " "), (1, "a"), (9, "\n  "), (9, "// b"), (2, "+"), (1, "c"), (9, "\n")], &mut cache);
+    let dense = build(&[(1, "a"), (2, "+"), (9, " "), (1, "c")], &mut cache);
+    let other = build(&[(1, "a"), (2, "-"), (1, "c")], &mut cache);
+    let resolver = cache.into_interner().unwrap();
+
+    let options = Canonicalize::new(|kind| kind == WS);
+    assert_eq!(spaced.canonical_text(&options, &resolver), "a +c");
+    assert_eq!(dense.canonical_text(&options, &resolver), "a+ c");
+    let options = options.needs_space(|left, right| left == right);
+    assert_eq!(spaced.canonical_text(&options, &resolver), "a+c");
+    assert_eq!(dense.canonical_text(&options, &resolver), "a+c");
+    assert_eq!(other.canonical_text(&options, &resolver), "a-c");
+
+    assert!(spaced.eq_ignoring_trivia(&dense, &options, &resolver, &resolver));
+    assert!(!spaced.eq_ignoring_trivia(&other, &options, &resolver, &resolver));
+    assert!(!spaced.eq_ignoring_trivia(&dense, &Canonicalize::new(|_| false), &resolver, &resolver));
+}
+
+#[test]
+fn tree_report() {
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+    builder.start_node(SyntaxKind(0));
+    for _ in 0..3 {
+        builder.start_node(SyntaxKind(1));
+        builder.start_node(SyntaxKind(2));
+        builder.token(SyntaxKind(3), "abc");
+        builder.finish_node();
+        builder.token(SyntaxKind(4), "de");
+        builder.finish_node();
+    }
+    builder.token(SyntaxKind(4), "de");
+    builder.finish_node();
+    let (tree, cache) = builder.finish();
+    let resolver = cache.unwrap().into_interner().unwrap();
+
+    let report = tree.report(&resolver);
+    assert_eq!(report.text_len, 17);
+    assert_eq!((report.nodes, report.unique_nodes), (7, 3));
+    assert_eq!((report.tokens, report.unique_tokens), (7, 2));
+    assert_eq!(report.node_kinds[&RawSyntaxKind(2)], 3);
+    assert_eq!(report.token_kinds[&RawSyntaxKind(4)], 4);
+    assert_eq!(report.depth_histogram, [1, 3, 3]);
+    assert_eq!(report.max_depth(), 2);
+    assert_eq!((report.interned_texts, report.interned_bytes), (2, 5));
+    assert_eq!(report.node_dedup_ratio(), 4.0 / 7.0);
+    assert!(report.estimated_memory > 0);
+
+    // a subtree only reports its own part of the tree
+    let subtree = tree.children().next().unwrap().into_node().unwrap().report(&resolver);
+    assert_eq!((subtree.nodes, subtree.tokens, subtree.token_dedup_ratio()), (2, 2, 0.0));
+
+    #[cfg(feature = "serialize")]
+    {
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["nodes"], 7);
+        assert_eq!(json["node_kinds"]["2"], 3);
+    }
+}
+
+#[cfg(feature = "node_payloads")]
+#[test]
+fn node_payloads() {
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+    builder.start_node(SyntaxKind(0));
+    for payload in [7, 7, 3] {
+        builder.start_node(SyntaxKind(1));
+        builder.token(SyntaxKind(2), "x");
+        builder.finish_node_with_payload(payload);
+    }
+    builder.finish_node();
+    let (tree, _) = builder.finish();
+    let children: Vec<_> = tree.children().map(|child| child.into_node().unwrap()).collect();
+    assert_eq!(children.iter().map(|child| child.payload()).collect::<Vec<_>>(), [7, 7, 3]);
+    assert_eq!(tree.payload(), 0);
+
+    // nodes with the same payload are deduplicated, nodes with different payloads are not
+    assert!(std::ptr::eq(children[0].head(), children[1].head()));
+    assert_ne!(children[0], children[2]);
+    assert_eq!(children[0].head().with_payload(3), *children[2].head());
+
+    // edits keep the payload
+    assert_eq!(children[0].with_kind(RawSyntaxKind(5)).payload(), 7);
+    let root: SyntaxNode = SyntaxNode::new_root(tree);
+    let third = root.children().nth(2).unwrap();
+    let token = third.first_token().unwrap();
+    let edited = token.replace_with(cstree::green::GreenToken::new_detached(SyntaxKind(2), "y", &mut new_interner()));
+    assert_eq!(edited.children().nth(2).unwrap().into_node().unwrap().payload(), 3);
+}
+
+#[test]
+fn error_tokens() {
+    use cstree::testing::*;
+
+    let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    builder.start_node(Root);
+    builder.token(Error, "$");
+    builder.start_node(Operation);
+    builder.token(Int, "1");
+    builder.static_token(Plus);
+    builder.token(Error, "#");
+    builder.finish_node();
+    builder.finish_node();
+    let (tree, cache) = builder.finish();
+    let resolver = cache.unwrap().into_interner().unwrap();
+    let root = SyntaxNode::<MySyntax>::new_root_with_resolver(tree, resolver);
+
+    let errors: Vec<_> = root.error_tokens().map(|token| (token.text(), token.text_range())).collect();
+    assert_eq!(
+        errors,
+        [("$", TextRange::new(0.into(), 1.into())), ("#", TextRange::new(3.into(), 4.into()))]
+    );
+    let operation = root.first_child().unwrap();
+    assert_eq!(operation.error_tokens().count(), 1);
+    #[cfg(feature = "error_messages")]
+    assert_eq!(operation.error_tokens().next().unwrap().error_message(), Some(0));
+}
+
+#[cfg(feature = "error_messages")]
+#[test]
+fn error_messages() {
+    use cstree::testing::*;
+
+    let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    builder.start_node(Root);
+    builder.error_token(Error, "$", 1);
+    builder.error_token(Error, "$", 2);
+    builder.token(Error, "$");
+    builder.token(Int, "1");
+    builder.finish_node();
+    let (tree, _) = builder.finish();
+    let root: SyntaxNode<MySyntax> = SyntaxNode::new_root(tree);
+
+    let messages: Vec<_> = root.error_tokens().map(|token| token.error_message()).collect();
+    assert_eq!(messages, [Some(1), Some(2), Some(0)]);
+    assert_eq!(root.last_token().unwrap().error_message(), None);
+    // tokens with different messages are not deduplicated
+    let tokens: Vec<_> = root.green().children().map(|child| child.into_token().unwrap()).collect();
+    assert_ne!(tokens[0], tokens[1]);
+    assert_eq!(tokens[0].error_message(), 1);
+}
+
+#[cfg(feature = "error_messages")]
+#[test]
+#[should_panic(expected = "not an error token kind")]
+fn error_message_on_non_error_kind() {
+    use cstree::testing::*;
+
+    let mut builder: GreenNodeBuilder<MySyntax> = GreenNodeBuilder::new();
+    builder.start_node(Root);
+    builder.error_token(Int, "1", 1);
+}
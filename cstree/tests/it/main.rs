@@ -5,7 +5,7 @@ mod sendsync;
 mod serde;
 
 use cstree::{
-    build::{GreenNodeBuilder, NodeCache},
+    build::{GreenCache, GreenNodeBuilder, NodeCache},
     green::GreenNode,
     interning::Interner,
     RawSyntaxKind, Syntax,
@@ -56,13 +56,14 @@ where
     node
 }
 
-pub fn build_recursive<I>(
+pub fn build_recursive<I, C>(
     root: &Element<'_>,
-    builder: &mut GreenNodeBuilder<'_, '_, SyntaxKind, I>,
+    builder: &mut GreenNodeBuilder<'_, '_, SyntaxKind, I, C>,
     mut from: u32,
 ) -> u32
 where
     I: Interner,
+    C: GreenCache,
 {
     match root {
         Element::Node(children) => {
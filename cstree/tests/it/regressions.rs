@@ -19,3 +19,36 @@ fn empty_tree_arc() {
     let root: SyntaxNode<SyntaxKind> = SyntaxNode::new_root(green);
     assert_eq!(root.kind(), SyntaxKind::Root);
 }
+
+#[test]
+fn first_and_last_token_skip_empty_nodes() {
+    use cstree::{build::GreenNodeBuilder, syntax::SyntaxNode};
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Syntax)]
+    #[repr(u32)]
+    enum SyntaxKind {
+        Root,
+        Error,
+        Ident,
+    }
+
+    let mut builder: GreenNodeBuilder<SyntaxKind> = GreenNodeBuilder::new();
+    builder.start_node(SyntaxKind::Root);
+    builder.start_node(SyntaxKind::Error);
+    builder.finish_node();
+    builder.token(SyntaxKind::Ident, "a");
+    builder.token(SyntaxKind::Ident, "b");
+    builder.start_node(SyntaxKind::Error);
+    builder.start_node(SyntaxKind::Error);
+    builder.finish_node();
+    builder.finish_node();
+    builder.finish_node();
+    let (green, cache) = builder.finish();
+    let resolver = cache.unwrap().into_interner().unwrap();
+    let root: SyntaxNode<SyntaxKind> = SyntaxNode::new_root(green);
+
+    assert_eq!(root.first_token().unwrap().resolve_text(&resolver), "a");
+    assert_eq!(root.last_token().unwrap().resolve_text(&resolver), "b");
+    let error = root.first_child().unwrap();
+    assert_eq!(error.first_token(), None);
+    assert_eq!(error.last_token(), None);
+}
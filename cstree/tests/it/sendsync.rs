@@ -163,3 +163,37 @@ fn drop_sync() {
     thread::sleep(Duration::from_millis(500));
     drop(tree);
 }
+
+#[test]
+#[cfg_attr(miri, ignore)]
+#[cfg(feature = "multi_threaded_interning")]
+fn parse_all() {
+    use cstree::{build::ThreadedNodeCache, parallel::parse_all};
+
+    use Element::*;
+
+    let other = || Node(vec![Node(vec![Token("a"), Token("b")]), Token("c")]);
+    let inputs: Vec<_> = (0..32)
+        .map(|i| if i % 2 == 0 { two_level_tree() } else { other() })
+        .collect();
+    let cache = ThreadedNodeCache::new();
+    let interner = new_threaded_interner();
+    let parsed = parse_all(&inputs, &cache, &interner, |input, builder| {
+        build_recursive(input, builder, 0)
+    });
+    assert_eq!(parsed.trees.len(), inputs.len());
+    for (i, (tree, _)) in parsed.trees.iter().enumerate() {
+        let expected = if i % 2 == 0 { "0.00.11.02.02.12.2" } else { "abc" };
+        assert_eq!(
+            SyntaxNode::<()>::new_root(tree.clone()).resolve_text(&interner),
+            expected
+        );
+        // small subtrees are shared between all trees, even if they were built on different threads
+        assert!(tree
+            .children()
+            .zip(parsed.trees[i % 2].0.children())
+            .all(|(a, b)| a == b));
+    }
+    assert!(parsed.stats.token_hits > 0);
+    assert_eq!(parsed.stats, cache.stats());
+}
@@ -5,6 +5,7 @@ use cstree::{
     build::{GreenNodeBuilder, NodeCache},
     interning::new_interner,
     util::NodeOrToken,
+    RawSyntaxKind, Syntax,
 };
 use serde_test::Token;
 use std::fmt;
@@ -287,3 +288,49 @@ fn serialize_tree_without_data() {
 
     serde_test::assert_tokens(&tree, three_level_tree_tokens().as_slice());
 }
+
+#[test]
+fn deserialize_invalid_kind() {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Syntax)]
+    #[repr(u32)]
+    enum Kind {
+        Root,
+        Ident,
+    }
+    assert_eq!(RawSyntaxKind::try_from_u32::<Kind>(1), Some(RawSyntaxKind(1)));
+    assert_eq!(RawSyntaxKind::try_from_u32::<Kind>(2), None);
+    assert_eq!(Kind::try_from_raw(RawSyntaxKind(0)), Some(Kind::Root));
+    assert_eq!(Kind::try_from_raw(RawSyntaxKind(1)), Some(Kind::Ident));
+
+    let tokens = event_tokens!(tuple(2), seq(3), node(0, false), token(2, "foo"));
+    serde_test::assert_de_tokens_error::<cstree::syntax::ResolvedNode<Kind>>(&tokens, "invalid syntax kind: 2");
+}
+
+#[test]
+fn deserialize_unbalanced_events() {
+    let tokens = event_tokens!(tuple(2), seq(2), leave_node());
+    serde_test::assert_de_tokens_error::<ResolvedNode>(&tokens, "unbalanced nodes");
+
+    let tokens = event_tokens!(tuple(2), seq(1), node(0, false), seq_end());
+    serde_test::assert_de_tokens_error::<ResolvedNode>(&tokens, "incomplete tree");
+}
+
+#[test]
+fn deserialize_with_interner() {
+    let tree = build_tree(three_level_tree());
+    // Only some nodes have data, so the other subtrees are not created on load
+    let inner = tree.first_child().unwrap().first_child().unwrap();
+    inner.set_data("inner".to_string());
+    tree.last_child().unwrap().set_data("last".to_string());
+    let serialized = serde_json::to_string(&tree.as_serialize_with_data()).unwrap();
+
+    let mut interner = new_interner();
+    let mut deserializer = serde_json::Deserializer::from_str(&serialized);
+    let deserialized = SyntaxNode::<String>::deserialize_with_interner(&mut deserializer, &mut interner).unwrap();
+    assert_eq!(deserialized.resolve_text(&interner), "foobarbazpubfntree");
+    let data: Vec<_> = deserialized
+        .descendants()
+        .map(|node| node.get_data().map(|data| data.to_string()))
+        .collect();
+    assert_eq!(data, [None, None, Some("inner".to_string()), Some("last".to_string())]);
+}
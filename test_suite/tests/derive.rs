@@ -20,3 +20,46 @@ fn basic() {
     assert!(MySyntax::static_text(SyntaxKind::A).is_none());
     assert_eq!(MySyntax::static_text(SyntaxKind::B), Some("b"));
 }
+
+#[test]
+fn error_tokens() {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Syntax)]
+    #[repr(u32)]
+    pub enum SyntaxKind {
+        A,
+        #[error_token]
+        Unknown,
+        #[error_token]
+        #[static_text("\"")]
+        UnterminatedString,
+    }
+
+    assert!(!SyntaxKind::A.is_error_token());
+    assert!(SyntaxKind::Unknown.is_error_token());
+    assert!(SyntaxKind::UnterminatedString.is_error_token());
+    assert_eq!(SyntaxKind::static_text(SyntaxKind::UnterminatedString), Some("\""));
+}
+
+#[test]
+fn classes() {
+    use cstree::KindClass;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Syntax)]
+    #[repr(u32)]
+    pub enum SyntaxKind {
+        #[class(Keyword)]
+        #[static_text("fn")]
+        Fn,
+        #[class(Whitespace)]
+        Whitespace,
+        #[error_token]
+        Unknown,
+        Root,
+    }
+
+    assert_eq!(SyntaxKind::Fn.class(), KindClass::Keyword);
+    assert_eq!(SyntaxKind::Whitespace.class(), KindClass::Whitespace);
+    assert!(SyntaxKind::Whitespace.class().is_trivia());
+    assert_eq!(SyntaxKind::Unknown.class(), KindClass::Error);
+    assert_eq!(SyntaxKind::Root.class(), KindClass::Other);
+}
@@ -0,0 +1,11 @@
+use cstree::Syntax;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Syntax)]
+#[repr(u32)]
+pub enum SyntaxKind {
+    A,
+    #[class]
+    B,
+}
+
+fn main() {}